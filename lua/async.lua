@@ -0,0 +1,86 @@
+local load_fn = _VERSION == 'Lua 5.1' and loadstring or load
+local unpack = unpack or table.unpack
+
+-- Lua 5.1 can't yield across pcall, copas ships coxpcall for this
+local has_coxpcall, coxpcall = pcall(require, 'coxpcall')
+local protect = has_coxpcall and type(coxpcall) == 'table' and coxpcall.pcall or pcall
+
+local function pack(...)
+   return { n = select('#', ...), ... }
+end
+
+local loops = {}
+
+-- each driver runs `fn` inside a coroutine owned by the event loop and steps
+-- the loop until it finishes, so calls that yield on I/O behave like `await`
+
+function loops.copas(fn)
+   local copas = require('copas')
+   local done = false
+
+   copas.addthread(function()
+      fn()
+      done = true
+   end)
+
+   while not done do
+      copas.step(0.1)
+   end
+end
+
+function loops.cqueues(fn)
+   local cqueues = require('cqueues')
+   local cq = cqueues.new()
+
+   cq:wrap(fn)
+
+   assert(cq:loop())
+end
+
+function loops.luv(fn)
+   local uv = require('luv')
+   local co = coroutine.create(fn)
+
+   assert(coroutine.resume(co))
+
+   while coroutine.status(co) ~= 'dead' and uv.run('once') do end
+end
+
+local function detect(name)
+   if name ~= 'auto' then
+      return loops[name]
+   end
+
+   for _, candidate in ipairs({ 'copas', 'cqueues', 'luv' }) do
+      if package and package.loaded[candidate] then
+         return loops[candidate]
+      end
+   end
+end
+
+function __manen_await(code, name)
+   -- a trailing expression already has its `return` from the REPL
+   local fn = assert(load_fn(code, '=repl'))
+
+   local driver = detect(name)
+
+   if not driver then
+      return fn()
+   end
+
+   local results
+
+   driver(function()
+      results = pack(protect(fn))
+   end)
+
+   if not results then
+      error('event loop finished before the evaluation completed', 0)
+   end
+
+   if not results[1] then
+      error(results[2], 0)
+   end
+
+   return unpack(results, 2, results.n)
+end
@@ -0,0 +1,76 @@
+local load_fn = _VERSION == 'Lua 5.1' and loadstring or load
+-- kept before `manen.sandbox` can remove `debug`, states without it get the message only
+local traceback = type(debug) == 'table' and debug.traceback
+
+-- input is always source, a binary chunk could break out of the sandbox even though
+-- `load_fn` is the one from before it replaced `load`
+local function load_input(code)
+   if _VERSION ~= 'Lua 5.1' then
+      return load_fn(code, '=repl', 't')
+   end
+
+   if code:byte(1) == 27 then
+      return nil, 'attempt to load a binary chunk'
+   end
+
+   return load_fn(code, '=repl')
+end
+
+-- what `.resume` steps, the last input that yielded or the suspended coroutine an input
+-- returned, until it finishes or errors
+local current
+
+local function step(co, ok, ...)
+   if not ok then
+      if co == current then
+         current = nil
+      end
+
+      -- the coroutine is gone once this returns, so its stack is read here
+      if traceback then
+         error(traceback(co, (...)), 0)
+      end
+
+      error((...), 0)
+   end
+
+   local first = ...
+
+   if coroutine.status(co) == 'suspended' then
+      current = co
+   elseif co == current then
+      current = nil
+   elseif type(first) == 'thread' and coroutine.status(first) == 'suspended' then
+      current = first
+   end
+
+   return ...
+end
+
+-- wraps what an input returned, the first value is kept as `_` while internal code
+-- leaves it alone
+function __manen_last(...)
+   if (...) ~= nil then
+      _G._ = (...)
+   end
+
+   return ...
+end
+
+-- each input runs in its own coroutine, so one that yields can be resumed later
+function __manen_eval(code)
+   local fn = assert(load_input(code))
+   local co = coroutine.create(fn)
+
+   return step(co, coroutine.resume(co))
+end
+
+function __manen_resume(...)
+   if not current then
+      error('no suspended coroutine to resume', 0)
+   end
+
+   local co = current
+
+   return step(co, coroutine.resume(co, ...))
+end
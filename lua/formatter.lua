@@ -0,0 +1,35 @@
+manen = manen or {}
+
+-- keyed by metatable, so they are collected with it and never show in its fields
+local formatters = setmetatable({}, { __mode = 'k' })
+
+-- `manen.formatter(MyClass, function(value) return ... end)` prints tables whose
+-- metatable is MyClass with the string the function returns, nil removes it
+function manen.formatter(metatable, format)
+   if type(metatable) ~= 'table' then
+      error('expected a metatable as the first argument to manen.formatter', 2)
+   end
+
+   if format ~= nil and type(format) ~= 'function' then
+      error('expected a function or nil as the second argument to manen.formatter', 2)
+   end
+
+   formatters[metatable] = format
+end
+
+-- `manen.class(Dog, 'Dog')` names Dog in the `: extends Dog` of tables inheriting
+-- from it when `manen.show_inheritance` is set
+function manen.class(class, name)
+   if type(class) ~= 'table' then
+      error('expected a table as the first argument to manen.class', 2)
+   end
+
+   if name ~= nil and type(name) ~= 'string' then
+      error('expected a string or nil as the second argument to manen.class', 2)
+   end
+
+   rawset(class, '__manen_class', name)
+end
+
+-- kept by an embedded state to look formatters up while printing
+return formatters
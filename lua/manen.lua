@@ -0,0 +1,459 @@
+manen = manen or {}
+
+local concat = table.concat
+local floor = math.floor
+local char = string.char
+
+local json = {}
+
+local escapes = {
+   ['"'] = '\\"',
+   ['\\'] = '\\\\',
+   ['\b'] = '\\b',
+   ['\f'] = '\\f',
+   ['\n'] = '\\n',
+   ['\r'] = '\\r',
+   ['\t'] = '\\t',
+}
+
+local function encode_string(s)
+   return '"' .. s:gsub('[%c"\\]', function(c)
+      return escapes[c] or string.format('\\u%04x', c:byte())
+   end) .. '"'
+end
+
+-- length of `t` when its keys are exactly 1 to n, `nil` when it is an object
+local function array_length(t)
+   local count = 0
+
+   for _ in pairs(t) do
+      count = count + 1
+   end
+
+   for i = 1, count do
+      if rawget(t, i) == nil then
+         return nil
+      end
+   end
+
+   return count
+end
+
+local function encode(value, seen, out)
+   local kind = type(value)
+
+   if value == nil then
+      out[#out + 1] = 'null'
+   elseif kind == 'boolean' then
+      out[#out + 1] = tostring(value)
+   elseif kind == 'number' then
+      if value ~= value or value == math.huge or value == -math.huge then
+         error('cannot encode ' .. tostring(value) .. ' as JSON', 0)
+      end
+
+      if floor(value) == value and value >= -2 ^ 53 and value <= 2 ^ 53 then
+         out[#out + 1] = string.format('%d', value)
+      else
+         out[#out + 1] = string.format('%.17g', value)
+      end
+   elseif kind == 'string' then
+      out[#out + 1] = encode_string(value)
+   elseif kind == 'table' then
+      if seen[value] then
+         error('cannot encode a table that contains itself as JSON', 0)
+      end
+
+      seen[value] = true
+
+      local length = array_length(value)
+
+      if length then
+         out[#out + 1] = '['
+
+         for i = 1, length do
+            if i > 1 then
+               out[#out + 1] = ','
+            end
+
+            encode(value[i], seen, out)
+         end
+
+         out[#out + 1] = ']'
+      else
+         -- number keys become strings, the original key is kept to read the value
+         local keys, originals = {}, {}
+
+         for key in pairs(value) do
+            if type(key) ~= 'string' and type(key) ~= 'number' then
+               error('cannot encode a ' .. type(key) .. ' key as JSON', 0)
+            end
+
+            local name = tostring(key)
+
+            if originals[name] ~= nil then
+               error('cannot encode both ' .. name .. ' and "' .. name .. '" as JSON keys', 0)
+            end
+
+            keys[#keys + 1] = name
+            originals[name] = key
+         end
+
+         table.sort(keys)
+
+         out[#out + 1] = '{'
+
+         for i, name in ipairs(keys) do
+            if i > 1 then
+               out[#out + 1] = ','
+            end
+
+            out[#out + 1] = encode_string(name)
+            out[#out + 1] = ':'
+
+            encode(value[originals[name]], seen, out)
+         end
+
+         out[#out + 1] = '}'
+      end
+
+      seen[value] = nil
+   else
+      error('cannot encode a ' .. kind .. ' as JSON', 0)
+   end
+end
+
+-- `json.encode({ a = { 1, 2 } })` gives `{"a":[1,2]}`, keys are sorted and tables whose
+-- keys are 1 to n, including empty ones, become arrays
+function json.encode(value)
+   local out = {}
+
+   encode(value, {}, out)
+
+   return concat(out)
+end
+
+local function utf8_char(code)
+   if code < 0x80 then
+      return char(code)
+   elseif code < 0x800 then
+      return char(0xc0 + floor(code / 0x40), 0x80 + code % 0x40)
+   elseif code < 0x10000 then
+      return char(
+         0xe0 + floor(code / 0x1000),
+         0x80 + floor(code / 0x40) % 0x40,
+         0x80 + code % 0x40
+      )
+   end
+
+   return char(
+      0xf0 + floor(code / 0x40000),
+      0x80 + floor(code / 0x1000) % 0x40,
+      0x80 + floor(code / 0x40) % 0x40,
+      0x80 + code % 0x40
+   )
+end
+
+local unescapes = {
+   ['"'] = '"',
+   ['\\'] = '\\',
+   ['/'] = '/',
+   b = '\b',
+   f = '\f',
+   n = '\n',
+   r = '\r',
+   t = '\t',
+}
+
+local decode_value
+
+local function decode_error(text, pos, message)
+   error(string.format('invalid JSON at character %d: %s', pos, message), 0)
+end
+
+local function skip(text, pos)
+   return text:find('[^ \t\r\n]', pos) or #text + 1
+end
+
+local function decode_string(text, pos)
+   local parts = {}
+   local i = pos + 1
+
+   while true do
+      local start, stop = text:find('["\\]', i)
+
+      if not start then
+         decode_error(text, pos, 'unterminated string')
+      end
+
+      parts[#parts + 1] = text:sub(i, start - 1)
+
+      if text:sub(start, stop) == '"' then
+         return concat(parts), start + 1
+      end
+
+      local escape = text:sub(start + 1, start + 1)
+
+      if escape == 'u' then
+         local code = tonumber(text:sub(start + 2, start + 5), 16)
+
+         if not code then
+            decode_error(text, start, 'invalid \\u escape')
+         end
+
+         i = start + 6
+
+         -- a surrogate pair is one character
+         local low = code >= 0xd800 and code < 0xdc00 and text:match('^\\u(%x%x%x%x)', i)
+
+         if low then
+            code = 0x10000 + (code - 0xd800) * 0x400 + (tonumber(low, 16) - 0xdc00)
+            i = i + 6
+         end
+
+         parts[#parts + 1] = utf8_char(code)
+      elseif unescapes[escape] then
+         parts[#parts + 1] = unescapes[escape]
+         i = start + 2
+      else
+         decode_error(text, start, 'invalid escape')
+      end
+   end
+end
+
+local literals = { ['true'] = true, ['false'] = false }
+
+function decode_value(text, pos)
+   pos = skip(text, pos)
+
+   local c = text:sub(pos, pos)
+
+   if c == '{' then
+      local object = {}
+
+      pos = skip(text, pos + 1)
+
+      if text:sub(pos, pos) == '}' then
+         return object, pos + 1
+      end
+
+      while true do
+         if text:sub(pos, pos) ~= '"' then
+            decode_error(text, pos, 'expected a string key')
+         end
+
+         local key
+         key, pos = decode_string(text, pos)
+         pos = skip(text, pos)
+
+         if text:sub(pos, pos) ~= ':' then
+            decode_error(text, pos, "expected ':'")
+         end
+
+         object[key], pos = decode_value(text, pos + 1)
+         pos = skip(text, pos)
+
+         local next_char = text:sub(pos, pos)
+
+         if next_char == '}' then
+            return object, pos + 1
+         elseif next_char ~= ',' then
+            decode_error(text, pos, "expected ',' or '}'")
+         end
+
+         pos = skip(text, pos + 1)
+      end
+   elseif c == '[' then
+      local array = {}
+      local n = 0
+
+      pos = skip(text, pos + 1)
+
+      if text:sub(pos, pos) == ']' then
+         return array, pos + 1
+      end
+
+      while true do
+         n = n + 1
+         array[n], pos = decode_value(text, pos)
+         pos = skip(text, pos)
+
+         local next_char = text:sub(pos, pos)
+
+         if next_char == ']' then
+            return array, pos + 1
+         elseif next_char ~= ',' then
+            decode_error(text, pos, "expected ',' or ']'")
+         end
+
+         pos = pos + 1
+      end
+   elseif c == '"' then
+      return decode_string(text, pos)
+   end
+
+   local number = text:match('^-?%d+%.?%d*[eE]?[-+]?%d*', pos)
+
+   if number and tonumber(number) then
+      return tonumber(number), pos + #number
+   end
+
+   for word, value in pairs(literals) do
+      if text:sub(pos, pos + #word - 1) == word then
+         return value, pos + #word
+      end
+   end
+
+   if text:sub(pos, pos + 3) == 'null' then
+      return nil, pos + 4
+   end
+
+   decode_error(text, pos, 'unexpected ' .. (c == '' and 'end' or "'" .. c .. "'"))
+end
+
+-- `json.decode('{"a":[1,2]}')` gives `{ a = { 1, 2 } }`, `null` becomes `nil`
+function json.decode(text)
+   local value, pos = decode_value(text, 1)
+
+   pos = skip(text, pos)
+
+   if pos <= #text then
+      decode_error(text, pos, 'trailing characters')
+   end
+
+   return value
+end
+
+manen.json = json
+
+-- `io` is looked up on each call, so `manen.sandbox` keeps these from reaching files
+function manen.readfile(path)
+   local file = assert(io.open(path, 'rb'))
+   local content = file:read('*a')
+
+   file:close()
+
+   return content
+end
+
+function manen.writefile(path, content)
+   local file = assert(io.open(path, 'wb'))
+
+   file:write(content)
+   file:close()
+end
+
+local stringx = {}
+
+-- `stringx.split('a,b,,c', ',')` gives `{ 'a', 'b', '', 'c' }`, whitespace runs
+-- without a separator
+function stringx.split(s, sep)
+   local parts = {}
+
+   if sep == nil then
+      for part in s:gmatch('%S+') do
+         parts[#parts + 1] = part
+      end
+
+      return parts
+   end
+
+   local start = 1
+
+   while true do
+      local from, to = s:find(sep, start, true)
+
+      if not from or sep == '' then
+         parts[#parts + 1] = s:sub(start)
+         return parts
+      end
+
+      parts[#parts + 1] = s:sub(start, from - 1)
+      start = to + 1
+   end
+end
+
+function stringx.trim(s)
+   return (s:gsub('^%s+', ''):gsub('%s+$', ''))
+end
+
+function stringx.startswith(s, prefix)
+   return s:sub(1, #prefix) == prefix
+end
+
+function stringx.endswith(s, suffix)
+   return suffix == '' or s:sub(-#suffix) == suffix
+end
+
+function stringx.lines(s)
+   local lines = {}
+
+   for line in (s .. '\n'):gmatch('(.-)\r?\n') do
+      lines[#lines + 1] = line
+   end
+
+   return lines
+end
+
+manen.stringx = stringx
+
+-- the `inspect` of `manen run`, or a plain one for runtimes that don't have it
+if type(inspect) == 'function' then
+   manen.inspect = inspect
+else
+   local function dump(value, indent, seen)
+      if type(value) == 'string' then
+         return string.format('%q', value)
+      elseif type(value) ~= 'table' then
+         return tostring(value)
+      elseif seen[value] then
+         return '<cycle>'
+      end
+
+      seen[value] = true
+
+      local keys = {}
+
+      for key in pairs(value) do
+         keys[#keys + 1] = key
+      end
+
+      table.sort(keys, function(a, b)
+         return tostring(a) < tostring(b)
+      end)
+
+      local inner = indent .. '   '
+      local lines = {}
+
+      for _, key in ipairs(keys) do
+         local name = type(key) == 'string' and key:match('^[%a_][%w_]*$') and key
+            or '[' .. dump(key, inner, seen) .. ']'
+
+         lines[#lines + 1] = inner .. name .. ' = ' .. dump(value[key], inner, seen) .. ','
+      end
+
+      seen[value] = nil
+
+      if #lines == 0 then
+         return '{}'
+      end
+
+      return '{\n' .. concat(lines, '\n') .. '\n' .. indent .. '}'
+   end
+
+   function manen.inspect(value)
+      print(dump(value, '', {}))
+   end
+end
+
+-- `require('manen')` gives the same table, also once something cleared `package.loaded`
+if type(package) == 'table' then
+   if type(package.preload) == 'table' then
+      package.preload.manen = function()
+         return manen
+      end
+   end
+
+   if type(package.loaded) == 'table' then
+      package.loaded.manen = manen
+   end
+end
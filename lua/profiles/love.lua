@@ -0,0 +1,327 @@
+-- Headless subset of the LÖVE API for exploring game logic without the engine
+--
+-- filesystem, math and timer work against the real machine, graphics, audio and
+-- input are inert stubs so modules that touch them at load time still run
+
+love = {}
+
+function love.getVersion()
+   return 11, 5, 0, 'Mysterious Mysteries'
+end
+
+-- stubs
+
+local function stub_object(name)
+   return setmetatable({}, {
+      __index = function(_, key)
+         if key:match('^get.*[Ww]idth$') or key:match('^get.*[Hh]eight$') then
+            return function() return 0 end
+         end
+
+         return function() end
+      end,
+      __tostring = function() return name .. ' (stub)' end,
+   })
+end
+
+local function stub_module(defaults)
+   return setmetatable(defaults or {}, {
+      __index = function(_, key)
+         if key:match('^new') then
+            return function() return stub_object(key:sub(4)) end
+         end
+
+         return function() end
+      end,
+   })
+end
+
+love.graphics = stub_module({
+   getWidth = function() return 800 end,
+   getHeight = function() return 600 end,
+   getDimensions = function() return 800, 600 end,
+   getColor = function() return 1, 1, 1, 1 end,
+   isActive = function() return false end,
+})
+
+love.audio = stub_module()
+love.window = stub_module({
+   isOpen = function() return false end,
+   getMode = function() return 800, 600, {} end,
+})
+love.keyboard = stub_module({
+   isDown = function() return false end,
+})
+love.mouse = stub_module({
+   getPosition = function() return 0, 0 end,
+   getX = function() return 0 end,
+   getY = function() return 0 end,
+   isDown = function() return false end,
+})
+love.event = stub_module({
+   poll = function() return function() end end,
+})
+
+-- timer
+
+local start = os.clock()
+local last_step = start
+local delta = 0
+
+love.timer = {}
+
+function love.timer.getTime()
+   return os.clock() - start
+end
+
+function love.timer.step()
+   local now = os.clock()
+   delta = now - last_step
+   last_step = now
+   return delta
+end
+
+function love.timer.getDelta()
+   return delta
+end
+
+function love.timer.getAverageDelta()
+   return delta
+end
+
+function love.timer.getFPS()
+   return delta > 0 and math.floor(1 / delta) or 0
+end
+
+function love.timer.sleep(seconds)
+   local deadline = os.clock() + seconds
+
+   while os.clock() < deadline do end
+end
+
+-- math
+
+love.math = {}
+
+local RandomGenerator = {}
+RandomGenerator.__index = RandomGenerator
+
+-- Park-Miller generator so seeded sequences are reproducible across Lua versions
+function RandomGenerator:random(min, max)
+   self.state = (self.state * 16807) % 2147483647
+
+   local value = (self.state - 1) / 2147483646
+
+   if min and max then
+      return math.floor(min + value * (max - min + 1))
+   elseif min then
+      return math.floor(1 + value * min)
+   end
+
+   return value
+end
+
+function RandomGenerator:randomNormal(stddev, mean)
+   local u1, u2 = self:random(), self:random()
+   local z = math.sqrt(-2 * math.log(math.max(u1, 1e-12))) * math.cos(2 * math.pi * u2)
+
+   return z * (stddev or 1) + (mean or 0)
+end
+
+function RandomGenerator:setSeed(seed)
+   self.seed = math.floor(seed) % 2147483646 + 1
+   self.state = self.seed
+end
+
+function RandomGenerator:getSeed()
+   return self.seed
+end
+
+function RandomGenerator:getState()
+   return tostring(self.state)
+end
+
+function RandomGenerator:setState(state)
+   self.state = tonumber(state)
+end
+
+function love.math.newRandomGenerator(seed)
+   local generator = setmetatable({}, RandomGenerator)
+   generator:setSeed(seed or os.time())
+   return generator
+end
+
+local global_generator = love.math.newRandomGenerator()
+
+function love.math.random(min, max)
+   return global_generator:random(min, max)
+end
+
+function love.math.randomNormal(stddev, mean)
+   return global_generator:randomNormal(stddev, mean)
+end
+
+function love.math.setRandomSeed(seed)
+   global_generator:setSeed(seed)
+end
+
+function love.math.getRandomSeed()
+   return global_generator:getSeed()
+end
+
+local function gamma_to_linear(c)
+   if c <= 0.04045 then
+      return c / 12.92
+   end
+
+   return ((c + 0.055) / 1.055) ^ 2.4
+end
+
+local function linear_to_gamma(c)
+   if c <= 0.0031308 then
+      return c * 12.92
+   end
+
+   return 1.055 * c ^ (1 / 2.4) - 0.055
+end
+
+function love.math.gammaToLinear(r, g, b, ...)
+   return gamma_to_linear(r), gamma_to_linear(g), gamma_to_linear(b), ...
+end
+
+function love.math.linearToGamma(r, g, b, ...)
+   return linear_to_gamma(r), linear_to_gamma(g), linear_to_gamma(b), ...
+end
+
+function love.math.colorFromBytes(r, g, b, a)
+   return r / 255, g / 255, b / 255, a and a / 255
+end
+
+function love.math.colorToBytes(r, g, b, a)
+   local function byte(c)
+      return math.floor(math.min(math.max(c, 0), 1) * 255 + 0.5)
+   end
+
+   return byte(r), byte(g), byte(b), a and byte(a)
+end
+
+function love.math.isConvex(vertices, ...)
+   if type(vertices) ~= 'table' then
+      vertices = { vertices, ... }
+   end
+
+   local n = #vertices / 2
+   local sign
+
+   for i = 0, n - 1 do
+      local ax, ay = vertices[i * 2 + 1], vertices[i * 2 + 2]
+      local j, k = (i + 1) % n, (i + 2) % n
+      local bx, by = vertices[j * 2 + 1], vertices[j * 2 + 2]
+      local cx, cy = vertices[k * 2 + 1], vertices[k * 2 + 2]
+
+      local cross = (bx - ax) * (cy - by) - (by - ay) * (cx - bx)
+
+      if cross ~= 0 then
+         if sign == nil then
+            sign = cross > 0
+         elseif sign ~= (cross > 0) then
+            return false
+         end
+      end
+   end
+
+   return true
+end
+
+-- filesystem, relative to the working directory
+
+love.filesystem = {}
+
+local identity = 'manen'
+
+function love.filesystem.setIdentity(name)
+   identity = name
+end
+
+function love.filesystem.getIdentity()
+   return identity
+end
+
+function love.filesystem.getWorkingDirectory()
+   return os.getenv('PWD') or '.'
+end
+
+love.filesystem.getSource = love.filesystem.getWorkingDirectory
+love.filesystem.getSaveDirectory = love.filesystem.getWorkingDirectory
+
+function love.filesystem.read(name, size)
+   local file, err = io.open(name, 'rb')
+
+   if not file then
+      return nil, err
+   end
+
+   local contents = file:read(size or '*a') or ''
+   file:close()
+
+   return contents, #contents
+end
+
+local function write_file(name, data, mode)
+   local file, err = io.open(name, mode)
+
+   if not file then
+      return false, err
+   end
+
+   file:write(data)
+   file:close()
+
+   return true
+end
+
+function love.filesystem.write(name, data)
+   return write_file(name, data, 'wb')
+end
+
+function love.filesystem.append(name, data)
+   return write_file(name, data, 'ab')
+end
+
+function love.filesystem.lines(name)
+   return io.lines(name)
+end
+
+function love.filesystem.remove(name)
+   return os.remove(name) ~= nil
+end
+
+function love.filesystem.getInfo(name)
+   local file = io.open(name, 'rb')
+
+   if not file then
+      return nil
+   end
+
+   local size = file:seek('end')
+   file:close()
+
+   -- io.open succeeds on directories on most platforms but seek does not
+   if size == nil then
+      return { type = 'directory' }
+   end
+
+   return { type = 'file', size = size }
+end
+
+function love.filesystem.load(name)
+   return loadfile(name)
+end
+
+function love.filesystem.createDirectory()
+   error('love.filesystem.createDirectory is not implemented in the manen love profile', 2)
+end
+
+function love.filesystem.getDirectoryItems()
+   error('love.filesystem.getDirectoryItems is not implemented in the manen love profile', 2)
+end
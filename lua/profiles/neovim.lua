@@ -0,0 +1,432 @@
+-- Neovim-flavored environment for prototyping plugin code outside the editor
+--
+-- Pure helpers from `vim.*` are implemented, `vim.api` and `vim.fn` expose the
+-- bundled function metadata so they complete, but calling them raises an error
+
+vim = {}
+
+local api_signatures = {
+   nvim_buf_attach = 'buffer, send_buffer, opts',
+   nvim_buf_call = 'buffer, fun',
+   nvim_buf_clear_namespace = 'buffer, ns_id, line_start, line_end',
+   nvim_buf_create_user_command = 'buffer, name, command, opts',
+   nvim_buf_del_extmark = 'buffer, ns_id, id',
+   nvim_buf_del_keymap = 'buffer, mode, lhs',
+   nvim_buf_del_var = 'buffer, name',
+   nvim_buf_delete = 'buffer, opts',
+   nvim_buf_get_extmarks = 'buffer, ns_id, start, end, opts',
+   nvim_buf_get_keymap = 'buffer, mode',
+   nvim_buf_get_lines = 'buffer, start, end, strict_indexing',
+   nvim_buf_get_mark = 'buffer, name',
+   nvim_buf_get_name = 'buffer',
+   nvim_buf_get_offset = 'buffer, index',
+   nvim_buf_get_text = 'buffer, start_row, start_col, end_row, end_col, opts',
+   nvim_buf_get_var = 'buffer, name',
+   nvim_buf_is_loaded = 'buffer',
+   nvim_buf_is_valid = 'buffer',
+   nvim_buf_line_count = 'buffer',
+   nvim_buf_set_extmark = 'buffer, ns_id, line, col, opts',
+   nvim_buf_set_keymap = 'buffer, mode, lhs, rhs, opts',
+   nvim_buf_set_lines = 'buffer, start, end, strict_indexing, replacement',
+   nvim_buf_set_mark = 'buffer, name, line, col, opts',
+   nvim_buf_set_name = 'buffer, name',
+   nvim_buf_set_text = 'buffer, start_row, start_col, end_row, end_col, replacement',
+   nvim_buf_set_var = 'buffer, name, value',
+   nvim_call_function = 'fn, args',
+   nvim_command = 'command',
+   nvim_create_augroup = 'name, opts',
+   nvim_create_autocmd = 'event, opts',
+   nvim_create_buf = 'listed, scratch',
+   nvim_create_namespace = 'name',
+   nvim_create_user_command = 'name, command, opts',
+   nvim_del_augroup_by_id = 'id',
+   nvim_del_augroup_by_name = 'name',
+   nvim_del_autocmd = 'id',
+   nvim_del_keymap = 'mode, lhs',
+   nvim_del_user_command = 'name',
+   nvim_del_var = 'name',
+   nvim_echo = 'chunks, history, opts',
+   nvim_eval = 'expr',
+   nvim_exec2 = 'src, opts',
+   nvim_exec_autocmds = 'event, opts',
+   nvim_feedkeys = 'keys, mode, escape_ks',
+   nvim_get_autocmds = 'opts',
+   nvim_get_current_buf = '',
+   nvim_get_current_line = '',
+   nvim_get_current_tabpage = '',
+   nvim_get_current_win = '',
+   nvim_get_hl = 'ns_id, opts',
+   nvim_get_keymap = 'mode',
+   nvim_get_mode = '',
+   nvim_get_option_value = 'name, opts',
+   nvim_get_var = 'name',
+   nvim_get_vvar = 'name',
+   nvim_input = 'keys',
+   nvim_list_bufs = '',
+   nvim_list_tabpages = '',
+   nvim_list_wins = '',
+   nvim_open_win = 'buffer, enter, config',
+   nvim_put = 'lines, type, after, follow',
+   nvim_replace_termcodes = 'str, from_part, do_lt, special',
+   nvim_set_current_buf = 'buffer',
+   nvim_set_current_line = 'line',
+   nvim_set_current_win = 'window',
+   nvim_set_hl = 'ns_id, name, val',
+   nvim_set_keymap = 'mode, lhs, rhs, opts',
+   nvim_set_option_value = 'name, value, opts',
+   nvim_set_var = 'name, value',
+   nvim_tabpage_get_win = 'tabpage',
+   nvim_tabpage_list_wins = 'tabpage',
+   nvim_win_close = 'window, force',
+   nvim_win_get_buf = 'window',
+   nvim_win_get_config = 'window',
+   nvim_win_get_cursor = 'window',
+   nvim_win_get_height = 'window',
+   nvim_win_get_width = 'window',
+   nvim_win_is_valid = 'window',
+   nvim_win_set_buf = 'window, buffer',
+   nvim_win_set_config = 'window, config',
+   nvim_win_set_cursor = 'window, pos',
+   nvim_win_set_height = 'window, height',
+   nvim_win_set_width = 'window, width',
+}
+
+local fn_signatures = {
+   abs = 'expr',
+   bufname = 'buf',
+   bufnr = 'buf, create',
+   col = 'expr, winid',
+   executable = 'expr',
+   exists = 'expr',
+   expand = 'string, nosuf, list',
+   filereadable = 'file',
+   fnamemodify = 'fname, mods',
+   getbufline = 'buf, lnum, end',
+   getcwd = 'winnr, tabnr',
+   getline = 'lnum, end',
+   getpos = 'expr',
+   glob = 'expr, nosuf, list, alllinks',
+   has = 'feature',
+   input = 'prompt, text, completion',
+   isdirectory = 'directory',
+   jobstart = 'cmd, opts',
+   jobstop = 'id',
+   json_decode = 'expr',
+   json_encode = 'expr',
+   line = 'expr, winid',
+   mkdir = 'name, flags, prot',
+   mode = 'expr',
+   readfile = 'fname, type, max',
+   reltime = 'start, end',
+   setline = 'lnum, text',
+   shellescape = 'string, special',
+   stdpath = 'what',
+   strftime = 'format, time',
+   strwidth = 'string',
+   system = 'cmd, input',
+   systemlist = 'cmd, input, keepempty',
+   tempname = '',
+   winnr = 'arg',
+   writefile = 'object, fname, flags',
+}
+
+-- parameter names by full path, shown by manen's completion for the stubs
+_MANEN_SIGNATURES = _MANEN_SIGNATURES or {}
+
+for name, params in pairs(api_signatures) do
+   _MANEN_SIGNATURES['vim.api.' .. name] = params
+end
+
+for name, params in pairs(fn_signatures) do
+   _MANEN_SIGNATURES['vim.fn.' .. name] = params
+end
+
+local function unavailable(path)
+   return function()
+      error(path .. ' is not available outside Neovim (manen neovim profile)', 2)
+   end
+end
+
+vim.api = {}
+
+for name in pairs(api_signatures) do
+   vim.api[name] = unavailable('vim.api.' .. name)
+end
+
+vim.fn = setmetatable({}, {
+   __index = function(_, name)
+      return unavailable('vim.fn.' .. tostring(name))
+   end,
+})
+
+for name in pairs(fn_signatures) do
+   rawset(vim.fn, name, unavailable('vim.fn.' .. name))
+end
+
+vim.g, vim.b, vim.w, vim.t, vim.v, vim.env = {}, {}, {}, {}, {}, {}
+vim.o, vim.bo, vim.wo, vim.go, vim.opt = {}, {}, {}, {}, {}
+
+vim.log = { levels = { TRACE = 0, DEBUG = 1, INFO = 2, WARN = 3, ERROR = 4, OFF = 5 } }
+
+function vim.notify(msg, level)
+   io.stderr:write(msg, '\n')
+end
+
+vim.cmd = unavailable('vim.cmd')
+vim.keymap = { set = unavailable('vim.keymap.set'), del = unavailable('vim.keymap.del') }
+
+-- pure helpers
+
+vim.inspect = function(value)
+   return tostring(value)
+end
+
+function vim.is_callable(f)
+   if type(f) == 'function' then
+      return true
+   end
+
+   local mt = getmetatable(f)
+
+   return mt ~= nil and type(mt.__call) == 'function'
+end
+
+function vim.startswith(s, prefix)
+   return s:sub(1, #prefix) == prefix
+end
+
+function vim.endswith(s, suffix)
+   return #suffix == 0 or s:sub(-#suffix) == suffix
+end
+
+function vim.trim(s)
+   return (s:gsub('^%s*(.-)%s*$', '%1'))
+end
+
+function vim.pesc(s)
+   return (s:gsub('[%(%)%.%%%+%-%*%?%[%]%^%$]', '%%%0'))
+end
+
+function vim.gsplit(s, sep, opts)
+   local plain = type(opts) == 'boolean' and opts or (type(opts) == 'table' and opts.plain)
+   local start, done = 1, false
+
+   return function()
+      if done then
+         return nil
+      end
+
+      if sep == '' then
+         if start > #s then
+            done = true
+            return nil
+         end
+
+         start = start + 1
+         return s:sub(start - 1, start - 1)
+      end
+
+      local first, last = s:find(sep, start, plain)
+
+      if not first then
+         done = true
+         return s:sub(start)
+      end
+
+      local piece = s:sub(start, first - 1)
+      start = last + 1
+
+      return piece
+   end
+end
+
+function vim.split(s, sep, opts)
+   local pieces = {}
+
+   for piece in vim.gsplit(s, sep, opts) do
+      pieces[#pieces + 1] = piece
+   end
+
+   if type(opts) == 'table' and opts.trimempty then
+      while pieces[1] == '' do
+         table.remove(pieces, 1)
+      end
+
+      while pieces[#pieces] == '' do
+         table.remove(pieces)
+      end
+   end
+
+   return pieces
+end
+
+function vim.deepcopy(value, seen)
+   if type(value) ~= 'table' then
+      return value
+   end
+
+   seen = seen or {}
+
+   if seen[value] then
+      return seen[value]
+   end
+
+   local copy = {}
+   seen[value] = copy
+
+   for k, v in pairs(value) do
+      copy[vim.deepcopy(k, seen)] = vim.deepcopy(v, seen)
+   end
+
+   return setmetatable(copy, getmetatable(value))
+end
+
+function vim.tbl_keys(t)
+   local keys = {}
+
+   for k in pairs(t) do
+      keys[#keys + 1] = k
+   end
+
+   return keys
+end
+
+function vim.tbl_values(t)
+   local values = {}
+
+   for _, v in pairs(t) do
+      values[#values + 1] = v
+   end
+
+   return values
+end
+
+function vim.tbl_map(f, t)
+   local mapped = {}
+
+   for k, v in pairs(t) do
+      mapped[k] = f(v)
+   end
+
+   return mapped
+end
+
+function vim.tbl_filter(f, t)
+   local filtered = {}
+
+   for _, v in pairs(t) do
+      if f(v) then
+         filtered[#filtered + 1] = v
+      end
+   end
+
+   return filtered
+end
+
+function vim.tbl_contains(t, value)
+   for _, v in pairs(t) do
+      if v == value then
+         return true
+      end
+   end
+
+   return false
+end
+
+function vim.tbl_isempty(t)
+   return next(t) == nil
+end
+
+function vim.tbl_count(t)
+   local count = 0
+
+   for _ in pairs(t) do
+      count = count + 1
+   end
+
+   return count
+end
+
+function vim.tbl_islist(t)
+   if type(t) ~= 'table' then
+      return false
+   end
+
+   local count = 0
+
+   for k in pairs(t) do
+      if type(k) ~= 'number' or k <= 0 or k % 1 ~= 0 then
+         return false
+      end
+
+      count = count + 1
+   end
+
+   return count == #t
+end
+
+vim.islist = vim.tbl_islist
+
+local function extend(behavior, deep, ...)
+   if behavior ~= 'error' and behavior ~= 'keep' and behavior ~= 'force' then
+      error('invalid "behavior": ' .. tostring(behavior), 3)
+   end
+
+   local result = {}
+
+   for i = 1, select('#', ...) do
+      for k, v in pairs(select(i, ...) or {}) do
+         if deep and type(v) == 'table' and type(result[k]) == 'table' and not vim.tbl_islist(v) then
+            result[k] = extend(behavior, true, result[k], v)
+         elseif behavior == 'error' and result[k] ~= nil then
+            error('key found in more than one map: ' .. tostring(k), 3)
+         elseif behavior == 'force' or result[k] == nil then
+            result[k] = deep and vim.deepcopy(v) or v
+         end
+      end
+   end
+
+   return result
+end
+
+function vim.tbl_extend(behavior, ...)
+   return extend(behavior, false, ...)
+end
+
+function vim.tbl_deep_extend(behavior, ...)
+   return extend(behavior, true, ...)
+end
+
+function vim.list_extend(dst, src, start, finish)
+   for i = start or 1, finish or #src do
+      dst[#dst + 1] = src[i]
+   end
+
+   return dst
+end
+
+function vim.list_slice(list, start, finish)
+   local slice = {}
+
+   for i = start or 1, finish or #list do
+      slice[#slice + 1] = list[i]
+   end
+
+   return slice
+end
+
+function vim.validate(spec)
+   for name, rule in pairs(spec) do
+      local value, expected, optional = rule[1], rule[2], rule[3]
+
+      if not (optional and value == nil) then
+         if type(expected) == 'function' then
+            if not expected(value) then
+               error(name .. ': invalid value', 2)
+            end
+         elseif type(value) ~= expected and not (expected == 'callable' and vim.is_callable(value)) then
+            error(('%s: expected %s, got %s'):format(name, expected, type(value)), 2)
+         end
+      end
+   end
+end
@@ -0,0 +1,418 @@
+-- Stub implementation of the OpenResty `ngx` API for exercising snippets outside nginx
+--
+-- Anything not stubbed here raises an error naming the missing API
+
+local function unimplemented(path)
+   return setmetatable({}, {
+      __index = function(_, key)
+         error(path .. '.' .. tostring(key) .. ' is not implemented in the manen openresty profile', 2)
+      end,
+   })
+end
+
+ngx = setmetatable({}, getmetatable(unimplemented('ngx')))
+
+ngx.null = setmetatable({}, { __tostring = function() return 'ngx.null' end })
+ngx.var = {}
+ngx.ctx = {}
+ngx.header = {}
+ngx.status = 200
+
+ngx.OK = 0
+ngx.ERROR = -1
+ngx.AGAIN = -2
+ngx.DONE = -4
+ngx.DECLINED = -5
+
+ngx.STDERR = 0
+ngx.EMERG = 1
+ngx.ALERT = 2
+ngx.CRIT = 3
+ngx.ERR = 4
+ngx.WARN = 5
+ngx.NOTICE = 6
+ngx.INFO = 7
+ngx.DEBUG = 8
+
+ngx.HTTP_OK = 200
+ngx.HTTP_CREATED = 201
+ngx.HTTP_NO_CONTENT = 204
+ngx.HTTP_MOVED_PERMANENTLY = 301
+ngx.HTTP_MOVED_TEMPORARILY = 302
+ngx.HTTP_NOT_MODIFIED = 304
+ngx.HTTP_BAD_REQUEST = 400
+ngx.HTTP_UNAUTHORIZED = 401
+ngx.HTTP_FORBIDDEN = 403
+ngx.HTTP_NOT_FOUND = 404
+ngx.HTTP_NOT_ALLOWED = 405
+ngx.HTTP_INTERNAL_SERVER_ERROR = 500
+ngx.HTTP_SERVICE_UNAVAILABLE = 503
+
+local level_names = {
+   [0] = 'stderr', 'emerg', 'alert', 'crit', 'error', 'warn', 'notice', 'info', 'debug',
+}
+
+local function concat_args(...)
+   local parts = {}
+
+   for i = 1, select('#', ...) do
+      local value = select(i, ...)
+      parts[#parts + 1] = value == nil and 'nil' or tostring(value)
+   end
+
+   return table.concat(parts)
+end
+
+function ngx.log(level, ...)
+   io.stderr:write('[', level_names[level] or tostring(level), '] ', concat_args(...), '\n')
+end
+
+function ngx.print(...)
+   io.write(concat_args(...))
+   return 1
+end
+
+function ngx.say(...)
+   io.write(concat_args(...), '\n')
+   return 1
+end
+
+function ngx.flush()
+   io.flush()
+   return 1
+end
+
+function ngx.exit(status)
+   ngx.status = status
+   error({ ngx_exit = status }, 2)
+end
+
+-- time
+
+local cached_time = os.time()
+
+function ngx.update_time()
+   cached_time = os.time()
+end
+
+function ngx.now()
+   return cached_time
+end
+
+function ngx.time()
+   return cached_time
+end
+
+function ngx.today()
+   return os.date('%Y-%m-%d', cached_time)
+end
+
+function ngx.localtime()
+   return os.date('%Y-%m-%d %H:%M:%S', cached_time)
+end
+
+function ngx.utctime()
+   return os.date('!%Y-%m-%d %H:%M:%S', cached_time)
+end
+
+function ngx.http_time(sec)
+   return os.date('!%a, %d %b %Y %H:%M:%S GMT', sec)
+end
+
+function ngx.cookie_time(sec)
+   return os.date('!%a, %d-%b-%y %H:%M:%S GMT', sec)
+end
+
+function ngx.sleep(seconds)
+   local deadline = os.clock() + seconds
+
+   while os.clock() < deadline do end
+
+   ngx.update_time()
+end
+
+-- escaping
+
+function ngx.escape_uri(s)
+   return (tostring(s):gsub('[^%w%-%._~]', function(c)
+      return string.format('%%%02X', c:byte())
+   end))
+end
+
+function ngx.unescape_uri(s)
+   return (tostring(s):gsub('%+', ' '):gsub('%%(%x%x)', function(hex)
+      return string.char(tonumber(hex, 16))
+   end))
+end
+
+function ngx.encode_args(args)
+   local parts = {}
+
+   for key, value in pairs(args) do
+      if value == true then
+         parts[#parts + 1] = ngx.escape_uri(key)
+      elseif type(value) == 'table' then
+         for _, item in ipairs(value) do
+            parts[#parts + 1] = ngx.escape_uri(key) .. '=' .. ngx.escape_uri(item)
+         end
+      elseif value then
+         parts[#parts + 1] = ngx.escape_uri(key) .. '=' .. ngx.escape_uri(value)
+      end
+   end
+
+   table.sort(parts)
+
+   return table.concat(parts, '&')
+end
+
+function ngx.decode_args(query)
+   local args = {}
+
+   for pair in tostring(query):gmatch('[^&]+') do
+      local key, value = pair:match('^([^=]*)=(.*)$')
+
+      if key then
+         key, value = ngx.unescape_uri(key), ngx.unescape_uri(value)
+      else
+         key, value = ngx.unescape_uri(pair), true
+      end
+
+      if args[key] == nil then
+         args[key] = value
+      elseif type(args[key]) == 'table' then
+         table.insert(args[key], value)
+      else
+         args[key] = { args[key], value }
+      end
+   end
+
+   return args
+end
+
+function ngx.quote_sql_str(s)
+   local escapes = {
+      ['\0'] = '\\0', ['\b'] = '\\b', ['\n'] = '\\n', ['\r'] = '\\r',
+      ['\t'] = '\\t', ['\26'] = '\\Z', ['\\'] = '\\\\', ["'"] = "\\'", ['"'] = '\\"',
+   }
+
+   return "'" .. tostring(s):gsub('.', escapes) .. "'"
+end
+
+local base64_chars = 'ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/'
+
+function ngx.encode_base64(s, no_padding)
+   local out = {}
+
+   for i = 1, #s, 3 do
+      local a, b, c = s:byte(i, i + 2)
+      local n = a * 65536 + (b or 0) * 256 + (c or 0)
+
+      for j = 3, 0, -1 do
+         local index = math.floor(n / 64 ^ j) % 64 + 1
+         out[#out + 1] = base64_chars:sub(index, index)
+      end
+
+      if not c then
+         out[#out] = '='
+      end
+
+      if not b then
+         out[#out - 1] = '='
+      end
+   end
+
+   local encoded = table.concat(out)
+
+   if no_padding then
+      encoded = encoded:gsub('=+$', '')
+   end
+
+   return encoded
+end
+
+function ngx.decode_base64(s)
+   s = s:gsub('[^%w%+/]', '')
+
+   local out = {}
+
+   for i = 1, #s, 4 do
+      local n, count = 0, 0
+
+      for j = i, math.min(i + 3, #s) do
+         n = n * 64 + base64_chars:find(s:sub(j, j), 1, true) - 1
+         count = count + 1
+      end
+
+      n = n * 64 ^ (4 - count)
+
+      local bytes = { math.floor(n / 65536) % 256, math.floor(n / 256) % 256, n % 256 }
+
+      for j = 1, count - 1 do
+         out[#out + 1] = string.char(bytes[j])
+      end
+   end
+
+   return table.concat(out)
+end
+
+-- shared dictionaries
+
+local SharedDict = {}
+SharedDict.__index = SharedDict
+
+local function expired(entry)
+   return entry.expires and entry.expires <= os.time()
+end
+
+function SharedDict:get(key)
+   local entry = self.entries[key]
+
+   if not entry or expired(entry) then
+      self.entries[key] = nil
+      return nil
+   end
+
+   return entry.value, entry.flags
+end
+
+function SharedDict:get_stale(key)
+   local entry = self.entries[key]
+
+   if not entry then
+      return nil
+   end
+
+   return entry.value, entry.flags, expired(entry) or false
+end
+
+function SharedDict:set(key, value, exptime, flags)
+   if value == nil then
+      self.entries[key] = nil
+   else
+      self.entries[key] = {
+         value = value,
+         flags = flags,
+         expires = exptime and exptime > 0 and os.time() + exptime or nil,
+      }
+   end
+
+   return true, nil, false
+end
+
+SharedDict.safe_set = SharedDict.set
+
+function SharedDict:add(key, value, exptime, flags)
+   if self:get(key) ~= nil then
+      return false, 'exists', false
+   end
+
+   return self:set(key, value, exptime, flags)
+end
+
+SharedDict.safe_add = SharedDict.add
+
+function SharedDict:replace(key, value, exptime, flags)
+   if self:get(key) == nil then
+      return false, 'not found', false
+   end
+
+   return self:set(key, value, exptime, flags)
+end
+
+function SharedDict:incr(key, value, init, init_ttl)
+   local current = self:get(key)
+
+   if current == nil then
+      if init == nil then
+         return nil, 'not found'
+      end
+
+      self:set(key, init, init_ttl)
+      current = init
+   end
+
+   if type(current) ~= 'number' then
+      return nil, 'not a number'
+   end
+
+   self.entries[key].value = current + value
+
+   return current + value
+end
+
+function SharedDict:delete(key)
+   self.entries[key] = nil
+end
+
+function SharedDict:ttl(key)
+   local entry = self.entries[key]
+
+   if not entry or expired(entry) then
+      return nil, 'not found'
+   end
+
+   return entry.expires and entry.expires - os.time() or 0
+end
+
+function SharedDict:expire(key, exptime)
+   local entry = self.entries[key]
+
+   if not entry or expired(entry) then
+      return nil, 'not found'
+   end
+
+   entry.expires = exptime > 0 and os.time() + exptime or nil
+
+   return true
+end
+
+function SharedDict:flush_all()
+   for _, entry in pairs(self.entries) do
+      entry.expires = 0
+   end
+end
+
+function SharedDict:flush_expired()
+   local count = 0
+
+   for key, entry in pairs(self.entries) do
+      if expired(entry) then
+         self.entries[key] = nil
+         count = count + 1
+      end
+   end
+
+   return count
+end
+
+function SharedDict:get_keys(max_count)
+   local keys = {}
+
+   for key, entry in pairs(self.entries) do
+      if not expired(entry) then
+         keys[#keys + 1] = key
+
+         if max_count and max_count > 0 and #keys >= max_count then
+            break
+         end
+      end
+   end
+
+   return keys
+end
+
+ngx.shared = setmetatable({}, {
+   __index = function(shared, name)
+      local dict = setmetatable({ name = name, entries = {} }, SharedDict)
+      rawset(shared, name, dict)
+      return dict
+   end,
+})
+
+ngx.re = unimplemented('ngx.re')
+ngx.req = unimplemented('ngx.req')
+ngx.resp = unimplemented('ngx.resp')
+ngx.socket = unimplemented('ngx.socket')
+ngx.thread = unimplemented('ngx.thread')
+ngx.timer = unimplemented('ngx.timer')
+ngx.location = unimplemented('ngx.location')
@@ -1,231 +1,203 @@
-local serpent
+local unpack = unpack or table.unpack
+local floor = math.floor
+-- still used by the RPC once `manen.sandbox` removed them
+local io, os, debug, loadfile = io, os, debug, loadfile
+
+-- MessagePack read by `src/msgpack.rs`, written with `string.pack` where it exists and
+-- with arithmetic on Lua 5.1 and 5.2
+local encode
 do
-   --[[
-   Serpent source is released under the MIT License
-
-   Copyright (c) 2012-2018 Paul Kulchenko (paul@kulchenko.com)
-
-   Permission is hereby granted, free of charge, to any person obtaining a copy
-   of this software and associated documentation files (the "Software"), to deal
-   in the Software without restriction, including without limitation the rights
-   to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
-   copies of the Software, and to permit persons to whom the Software is
-   furnished to do so, subject to the following conditions:
-
-   The above copyright notice and this permission notice shall be included in
-   all copies or substantial portions of the Software.
-
-   THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
-   IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
-   FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
-   AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
-   LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
-   OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
-   THE SOFTWARE.
-   ]]
-   local n, v = "serpent", "0.303" -- (C) 2012-18 Paul Kulchenko; MIT License
-   local c, d = "Paul Kulchenko", "Lua serializer and pretty printer"
-   local snum = {
-      [tostring(1 / 0)] = '1/0 --[[math.huge]]',
-      [tostring(-1 / 0)] = '-1/0 --[[-math.huge]]',
-      [tostring(0 / 0)] = '0/0'
-   }
-   local badtype = { thread = true, userdata = true, cdata = true }
-   local getmetatable = debug and debug.getmetatable or getmetatable
-   local pairs = function(t) return next, t end -- avoid using __pairs in Lua 5.2+
-   local keyword, globals, G = {}, {}, (_G or _ENV)
-   for _, k in ipairs({ 'and', 'break', 'do', 'else', 'elseif', 'end', 'false',
-      'for', 'function', 'goto', 'if', 'in', 'local', 'nil', 'not', 'or', 'repeat',
-      'return', 'then', 'true', 'until', 'while' }) do keyword[k] = true end
-   for k, v in pairs(G) do globals[v] = k end -- build func to name mapping
-   for _, g in ipairs({ 'coroutine', 'debug', 'io', 'math', 'string', 'table', 'os' }) do
-      for k, v in pairs(type(G[g]) == 'table' and G[g] or {}) do globals[v] = g .. '.' .. k end
-   end
-
-   local function s(t, opts)
-      local name, indent, fatal, maxnum = opts.name, opts.indent, opts.fatal, opts.maxnum
-      local sparse, custom, huge = opts.sparse, opts.custom, not opts.nohuge
-      local space, maxl = (opts.compact and '' or ' '), (opts.maxlevel or math.huge)
-      local maxlen, metatostring = tonumber(opts.maxlength), opts.metatostring
-      local iname, comm = '_' .. (name or ''), opts.comment and (tonumber(opts.comment) or math.huge)
-      local numformat = opts.numformat or "%.17g"
-      local seen, sref, syms, symn = {}, { 'local ' .. iname .. '={}' }, {}, 0
-      local function gensym(val)
-         return '_' .. (tostring(tostring(val)):gsub("[^%w]", ""):gsub("(%d%w+)",
-            -- tostring(val) is needed because __tostring may return a non-string value
-            function(s)
-               if not syms[s] then
-                  symn = symn + 1; syms[s] = symn
-               end
-               return tostring(syms[s])
-            end))
-      end
-      local function safestr(s)
-         return type(s) == "number" and (huge and snum[tostring(s)] or numformat:format(s))
-             or type(s) ~= "string" and tostring(s) -- escape NEWLINE/010 and EOF/026
-             or ("%q"):format(s):gsub("\010", "n"):gsub("\026", "\\026")
+   local char = string.char
+   local pack_bytes = string.pack
+
+   local function uint(n, size)
+      local bytes = {}
+
+      for i = size, 1, -1 do
+         bytes[i] = n % 256
+         n = floor(n / 256)
       end
-      -- handle radix changes in some locales
-      if opts.fixradix and (".1f"):format(1.2) ~= "1.2" then
-         local origsafestr = safestr
-         safestr = function(s)
-            return type(s) == "number"
-                and (nohuge and snum[tostring(s)] or numformat:format(s):gsub(",", ".")) or origsafestr(s)
-         end
+
+      return char(unpack(bytes))
+   end
+
+   -- a header with the length in its first byte below `small`, or in 1, 2 or 4 more
+   local function sized(len, fix, small, one, two, four)
+      if fix and len < small then
+         return char(fix + len)
+      elseif one and len < 256 then
+         return char(one, len)
+      elseif len < 65536 then
+         return char(two) .. uint(len, 2)
       end
-      local function comment(s, l)
-         return comm and (l or 0) < comm and ' --[[' .. select(2, pcall(tostring, s)) .. ']]' or
-             ''
+
+      return char(four) .. uint(len, 4)
+   end
+
+   local is_integer = math.type and function(n)
+      return math.type(n) == 'integer'
+   end or function(n)
+      return n % 1 == 0 and n >= -2 ^ 53 and n <= 2 ^ 53
+   end
+
+   local function integer(n)
+      if n >= 0 and n < 128 then
+         return char(n)
+      elseif n < 0 and n >= -32 then
+         return char(n + 256)
+      elseif pack_bytes then
+         return '\211' .. pack_bytes('>i8', n)
       end
-      local function globerr(s, l)
-         return globals[s] and globals[s] .. comment(s, l) or not fatal
-             and safestr(select(2, pcall(tostring, s))) or error("Can't serialize " .. tostring(s))
+
+      -- two's complement, as two unsigned halves
+      return '\211' .. uint(floor(n / 4294967296) % 4294967296, 4) .. uint(n % 4294967296, 4)
+   end
+
+   local function float(n)
+      if pack_bytes then
+         return '\203' .. pack_bytes('>d', n)
+      elseif n ~= n then
+         return '\203\127\248\0\0\0\0\0\0'
       end
-      local function safename(path, name) -- generates foo.bar, foo[3], or foo['b a r']
-         local n = name == nil and '' or name
-         local plain = type(n) == "string" and n:match("^[%l%u_][%w_]*$") and not keyword[n]
-         local safe = plain and n or '[' .. safestr(n) .. ']'
-         return (path or '') .. (plain and path and '.' or '') .. safe, safe
+
+      local sign = 0
+
+      if n < 0 or (n == 0 and 1 / n < 0) then
+         sign, n = 128, -n
       end
-      local alphanumsort = type(opts.sortkeys) == 'function' and opts.sortkeys or
-          function(k, o, n) -- k=keys, o=originaltable, n=padding
-             local maxn, to = tonumber(n) or 12, { number = 'a', string = 'b' }
-             local function padnum(d) return ("%0" .. tostring(maxn) .. "d"):format(tonumber(d)) end
-             table.sort(k, function(a, b)
-                -- sort numeric keys first: k[key] is not nil for numerical keys
-                return (k[a] ~= nil and 0 or to[type(a)] or 'z') .. (tostring(a):gsub("%d+", padnum))
-                    < (k[b] ~= nil and 0 or to[type(b)] or 'z') .. (tostring(b):gsub("%d+", padnum))
-             end)
-          end
-      local function val2str(t, name, indent, insref, path, plainindex, level)
-         local ttype, level, mt = type(t), (level or 0), getmetatable(t)
-         local spath, sname = safename(path, name)
-         local tag = plainindex and
-             ((type(name) == "number") and '' or name .. space .. '=' .. space) or
-             (name ~= nil and sname .. space .. '=' .. space or '')
-         if seen[t] then -- already seen this element
-            sref[#sref + 1] = spath .. space .. '=' .. space .. seen[t]
-            return tag .. 'nil' .. comment('ref', level)
-         end
-         -- protect from those cases where __tostring may fail
-         if type(mt) == 'table' and metatostring ~= false then
-            local to, tr = pcall(function() return mt.__tostring(t) end)
-            local so, sr = pcall(function() return mt.__serialize(t) end)
-            if (to or so) then -- knows how to serialize itself
-               seen[t] = insref or spath
-               t = so and sr or tr
-               ttype = type(t)
-            end -- new value falls through to be serialized
+
+      local mantissa, exponent
+
+      if n == math.huge then
+         mantissa, exponent = 0, 2047
+      elseif n == 0 then
+         mantissa, exponent = 0, 0
+      else
+         local m, e = math.frexp(n)
+         exponent = e + 1022
+
+         if exponent <= 0 then
+            mantissa, exponent = m * 2 ^ (e + 1074), 0
+         else
+            mantissa = (m * 2 - 1) * 2 ^ 52
          end
-         if ttype == "table" then
-            if level >= maxl then return tag .. '{}' .. comment('maxlvl', level) end
-            seen[t] = insref or spath
-            if next(t) == nil then return tag .. '{}' .. comment(t, level) end -- table empty
-            if maxlen and maxlen < 0 then return tag .. '{}' .. comment('maxlen', level) end
-            local maxn, o, out = math.min(#t, maxnum or #t), {}, {}
-            for key = 1, maxn do o[key] = key end
-            if not maxnum or #o < maxnum then
-               local n = #o -- n = n + 1; o[n] is much faster than o[#o+1] on large tables
-               for key in pairs(t) do
-                  if o[key] ~= key then
-                     n = n + 1; o[n] = key
-                  end
-               end
+      end
+
+      -- the sign, 11 bits of exponent, then 52 of mantissa
+      local top = floor(mantissa / 2 ^ 48)
+
+      return '\203' .. char(sign + floor(exponent / 16), exponent % 16 * 16 + top)
+         .. uint(mantissa % 2 ^ 48, 6)
+   end
+
+   function encode(value)
+      local out, tables, count = {}, {}, 0
+
+      local function add(v)
+         local ty = type(v)
+
+         if ty == 'nil' then
+            out[#out + 1] = '\192'
+         elseif ty == 'boolean' then
+            out[#out + 1] = v and '\195' or '\194'
+         elseif ty == 'number' then
+            out[#out + 1] = is_integer(v) and integer(v) or float(v)
+         elseif ty == 'string' then
+            out[#out + 1] = sized(#v, 160, 32, 217, 218, 219)
+            out[#out + 1] = v
+         elseif ty == 'table' then
+            -- shared and cyclic tables are references to the order they were reached in
+            if tables[v] then
+               out[#out + 1] = '\214\1' .. uint(tables[v], 4)
+               return
             end
-            if maxnum and #o > maxnum then o[maxnum + 1] = nil end
-            if opts.sortkeys and #o > maxn then alphanumsort(o, t, opts.sortkeys) end
-            local sparse = sparse and #o > maxn -- disable sparsness if only numeric keys (shorter output)
-            for n, key in ipairs(o) do
-               local value, ktype, plainindex = t[key], type(key), n <= maxn and not sparse
-               if opts.valignore and opts.valignore[value] -- skip ignored values; do nothing
-                   or opts.keyallow and not opts.keyallow[key]
-                   or opts.keyignore and opts.keyignore[key]
-                   or opts.valtypeignore and opts.valtypeignore[type(value)] -- skipping ignored value types
-                   or sparse and value == nil then                           -- skipping nils; do nothing
-               elseif ktype == 'table' or ktype == 'function' or badtype[ktype] then
-                  if not seen[key] and not globals[key] then
-                     sref[#sref + 1] = 'placeholder'
-                     local sname = safename(iname, gensym(key)) -- iname is table for local variables
-                     sref[#sref] = val2str(key, sname, indent, sname, iname, true)
-                  end
-                  sref[#sref + 1] = 'placeholder'
-                  local path = seen[t] .. '[' .. tostring(seen[key] or globals[key] or gensym(key)) .. ']'
-                  sref[#sref] = path ..
-                  space .. '=' .. space .. tostring(seen[value] or val2str(value, nil, indent, path))
-               else
-                  out[#out + 1] = val2str(value, key, indent, nil, seen[t], plainindex, level + 1)
-                  if maxlen then
-                     maxlen = maxlen - #out[#out]
-                     if maxlen < 0 then break end
-                  end
-               end
+
+            tables[v] = count
+            count = count + 1
+
+            local size = 0
+
+            for _ in next, v do
+               size = size + 1
+            end
+
+            out[#out + 1] = sized(size, 128, 16, nil, 222, 223)
+
+            for k, item in next, v do
+               add(k)
+               add(item)
             end
-            local prefix = string.rep(indent or '', level)
-            local head = indent and '{\n' .. prefix .. indent or '{'
-            local body = table.concat(out, ',' .. (indent and '\n' .. prefix .. indent or space))
-            local tail = indent and "\n" .. prefix .. '}' or '}'
-            return (custom and custom(tag, head, body, tail, level) or tag .. head .. body .. tail) .. comment(t, level)
-         elseif badtype[ttype] then
-            seen[t] = insref or spath
-            return tag .. globerr(t, level)
-         elseif ttype == 'function' then
-            seen[t] = insref or spath
-            if opts.nocode then return tag .. "function() --[[..skipped..]] end" .. comment(t, level) end
-            local ok, res = pcall(string.dump, t)
-            local func = ok and "((loadstring or load)(" .. safestr(res) .. ",'@serialized'))" .. comment(t, level)
-            return tag .. (func or globerr(t, level))
          else
-            return tag .. safestr(t)
-         end -- handle all other types
+            -- functions and what can't be sent are extension types with their text
+            local ok, text = pcall(tostring, v)
+
+            if not ok then
+               text = ty
+            end
+
+            out[#out + 1] = sized(#text, nil, 0, 199, 200, 201)
+            out[#out + 1] = (ty == 'function' and '\2' or '\3') .. text
+         end
       end
-      local sepr = indent and "\n" or ";" .. space
-      local body = val2str(t, name, indent) -- this call also populates sref
-      local tail = #sref > 1 and table.concat(sref, sepr) .. sepr or ''
-      local warn = opts.comment and #sref > 1 and space .. "--[[incomplete output with shared/self-references skipped]]" or
-          ''
-      return not name and body .. warn or "do local " .. body .. sepr .. tail .. "return " .. name .. sepr .. "end"
-   end
-
-   local function deserialize(data, opts)
-      local env = (opts and opts.safe == false) and G
-          or setmetatable({}, {
-             __index = function(t, k) return t end,
-             __call = function(t, ...) error("cannot call functions") end
-          })
-      local f, res = (loadstring or load)('return ' .. data, nil, nil, env)
-      if not f then f, res = (loadstring or load)(data, nil, nil, env) end
-      if not f then return f, res end
-      if setfenv then setfenv(f, env) end
-      return pcall(f)
-   end
-
-   local function merge(a, b)
-      if b then for k, v in pairs(b) do a[k] = v end end; return a;
-   end
-
-   serpent = {
-      _NAME = n,
-      _COPYRIGHT = c,
-      _DESCRIPTION = d,
-      _VERSION = v,
-      serialize = s,
-      load = deserialize,
-      dump = function(a, opts) return s(a, merge({ name = '_', compact = true, sparse = true }, opts)) end,
-      line = function(a, opts) return s(a, merge({ sortkeys = true, comment = true }, opts)) end,
-      block = function(a, opts) return s(a, merge({ indent = '  ', sortkeys = true, comment = true }, opts)) end
-   }
+
+      add(value)
+
+      return table.concat(out)
+   end
+end
+
+local base64
+do
+   local alphabet = 'ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/'
+   local digits = {}
+
+   for i = 1, #alphabet do
+      digits[i - 1] = alphabet:sub(i, i)
+   end
+
+   function base64(data)
+      return (data:gsub('..?.?', function(chunk)
+         local a, b, c = chunk:byte(1, 3)
+         local n = a * 65536 + (b or 0) * 256 + (c or 0)
+
+         return digits[floor(n / 262144)] .. digits[floor(n / 4096) % 64]
+            .. (b and digits[floor(n / 64) % 64] or '=') .. (c and digits[n % 64] or '=')
+      end))
+   end
 end
 
 local rpc = {}
 
+-- methods of files, `write` is replaced for stdout and stderr by `rpc.capture_output`
+local file_methods = io and getmetatable(io.stdout)
+file_methods = type(file_methods) == 'table' and file_methods.__index
+local file_write = type(file_methods) == 'table' and file_methods.write
+
+-- replaced by `manen agent` to answer over its connection
+function rpc.write(line)
+   if file_write then
+      file_write(io.stdout, line, '\n')
+   else
+      io.write(line, '\n')
+   end
+
+   io.flush()
+end
+
+-- one line per message: a record separator, the length of the rest, `:` and the
+-- MessagePack as base64, which the PTY passes through untouched
+function rpc.frame(value)
+   local payload = base64(encode(value))
+
+   return '\30' .. #payload .. ':' .. payload
+end
+
 function rpc.respond(command, data)
-   io.write(serpent.dump({
+   rpc.write(rpc.frame({
       ty = type(data),
       data = data,
       command = command
-   }, { metatostring = false }))
-   io.write('\n')
-   io.flush()
+   }))
 end
 
 function rpc.globals()
@@ -234,17 +206,74 @@ end
 
 local load_fn = _VERSION == 'Lua 5.1' and loadstring or load
 
-function rpc.exec(code)
-   code = load_fn('return ' .. code)()
+-- globals of a fresh process, left out of snapshots
+local builtin = {}
 
-   local fn = load_fn(code, 'repl')
+for name in pairs(_G) do
+   builtin[name] = true
+end
 
-   if not fn then
-      fn = assert(load_fn('return (' .. code .. ')', 'repl'))
+-- `n` keeps the count of values, since tables can't hold `nil` ones
+local function pack(...)
+   return { n = select('#', ...), ... }
+end
+
+-- enough for completion menus while keeping huge tables off the pipe
+local max_fields = 1000
+
+-- only the names and types of fields at a key path, following `__index` tables
+function rpc.fields(path)
+   path = load_fn('return ' .. path)()
+
+   local value = _G
+   for _, key in ipairs(path) do
+      if type(value) ~= 'table' then
+         rpc.respond('fields', { type = 'nil' })
+         return
+      end
+      value = rawget(value, key)
+   end
+
+   local ty = type(value)
+   if ty ~= 'table' then
+      local primitive = ty == 'number' or ty == 'boolean' or ty == 'string'
+      rpc.respond('fields', { type = ty, value = primitive and value or nil })
+      return
+   end
+
+   local getmt = debug and debug.getmetatable or getmetatable
+   local fields, count, seen = {}, 0, {}
+
+   while type(value) == 'table' and not seen[value] and count < max_fields do
+      seen[value] = true
+
+      for k, v in next, value do
+         if type(k) == 'string' and not fields[k] then
+            local vty = type(v)
+            fields[k] = { type = vty }
+            if vty == 'number' or vty == 'boolean' or vty == 'string' then
+               fields[k].value = v
+            end
+            count = count + 1
+
+            if count >= max_fields then
+               break
+            end
+         end
+      end
+
+      local mt = getmt(value)
+      value = type(mt) == 'table' and rawget(mt, '__index') or nil
    end
 
+   rpc.respond('fields', { type = 'table', fields = fields })
+end
+
+-- runs `fn` where `stop` can cancel it, answering with what it returns
+local function call(fn, ...)
    local function cleanup()
-      if debug and debug.sethook then
+      -- the hook of `manen agent` is left alone
+      if debug and debug.sethook and rpc.cancel_file then
          debug.sethook()
       end
 
@@ -275,17 +304,123 @@ function rpc.exec(code)
       debug.sethook(cancel, "", 500000)
    end
 
-   local success, res = pcall(fn)
+   local results = pack(pcall(fn, ...))
 
    cleanup()
 
-   if success then
-      rpc.respond('exec', res)
+   if results[1] then
+      rpc.respond('exec', pack(unpack(results, 2, results.n)))
    else
-      rpc.respond('error', res)
+      rpc.respond('error', results[2])
    end
 end
 
+function rpc.exec(code)
+   code = load_fn('return ' .. code)()
+
+   -- run as sent, input already has its trailing expression returned by the editor
+   local fn, err = load_fn(code, 'repl')
+
+   if not fn then
+      rpc.respond('error', err)
+      return
+   end
+
+   call(fn)
+end
+
+-- bytes of serialized globals a snapshot holds at most, those that don't fit are left
+-- out as the snapshot is sent after inputs
+local max_snapshot_size = 1024 * 1024
+
+-- tables nested in a snapshot, counting the snapshot itself, at most. `MAX_DEPTH` of
+-- src/serialize.rs, as `.restore` can't apply a deeper one
+local max_snapshot_depth = 180
+
+-- about the bytes `value` serializes to, `nil` when it doesn't come back the same from
+-- its serialized source, as with metatables, takes more than `limit` or is a table
+-- nested in `depth` others that goes deeper than a snapshot may
+local function plain_size(value, seen, limit, depth)
+   local kind = type(value)
+
+   if kind == 'string' then
+      return #value + 2
+   elseif kind == 'boolean' or kind == 'number' then
+      return 8
+   elseif kind ~= 'table' then
+      return nil
+   end
+
+   if seen[value] then
+      return 0
+   end
+
+   depth = depth + 1
+
+   if depth > max_snapshot_depth then
+      return nil
+   end
+
+   seen[value] = true
+
+   if getmetatable(value) ~= nil then
+      return nil
+   end
+
+   local size = 2
+
+   for key, inner in next, value do
+      local key_size = plain_size(key, seen, limit - size, depth)
+      local inner_size = key_size and plain_size(inner, seen, limit - size - key_size, depth)
+
+      if not inner_size then
+         return nil
+      end
+
+      size = size + key_size + inner_size
+
+      if size > limit then
+         return nil
+      end
+   end
+
+   return size
+end
+
+-- globals set since the process started that can be applied to the next one
+function rpc.snapshot()
+   local globals = {}
+   local size = 0
+
+   for name, value in next, _G do
+      local value_size = not builtin[name]
+         and plain_size(value, {}, max_snapshot_size - size, 1)
+
+      if value_size and size + value_size <= max_snapshot_size then
+         globals[name] = value
+         size = size + value_size
+      end
+   end
+
+   rpc.respond('snapshot', globals)
+end
+
+-- `{ path, args... }`, run like `lua path args...` would, with `arg` set the same way
+function rpc.run(command)
+   command = load_fn('return ' .. command)()
+
+   local fn, err = loadfile(command[1])
+
+   if not fn then
+      rpc.respond('error', err)
+      return
+   end
+
+   arg = { [0] = command[1], unpack(command, 2) }
+
+   call(fn, unpack(arg))
+end
+
 function rpc.prepare(file)
    -- LuaJIT can't stop due to JIT
    if not io or jit then
@@ -293,12 +428,84 @@ function rpc.prepare(file)
       return
    end
 
+   -- a file named by an SSH session doesn't exist on the remote host yet
+   local f = io.open(file, 'a')
+
+   if not f then
+      rpc.respond('cancel', false)
+      return
+   end
+
+   f:close()
+
    rpc.cancel_file = file
 
    rpc.respond('cancel', true)
 end
 
-for line in io.stdin:lines() do
+-- text for `io.write` and `file:write`, which only take strings and numbers
+local function written(...)
+   local parts = pack(...)
+
+   for i = 1, parts.n do
+      local ty = type(parts[i])
+
+      if ty ~= 'string' and ty ~= 'number' then
+         error(('bad argument #%d to \'write\' (string expected, got %s)'):format(i, ty), 3)
+      end
+
+      parts[i] = tostring(parts[i])
+   end
+
+   return table.concat(parts, '', 1, parts.n)
+end
+
+-- output of the code is sent as `stdout` and `stderr` messages, so it can't be taken for
+-- a response or leave a line unfinished in front of one
+function rpc.capture_output()
+   function print(...)
+      local parts = pack(...)
+
+      for i = 1, parts.n do
+         parts[i] = tostring(parts[i])
+      end
+
+      rpc.respond('stdout', table.concat(parts, '\t', 1, parts.n) .. '\n')
+   end
+
+   if not io then
+      return
+   end
+
+   local stdout = io.stdout
+
+   function io.write(...)
+      local output = io.output()
+
+      if output ~= stdout then
+         return output:write(...)
+      end
+
+      rpc.respond('stdout', written(...))
+      return stdout
+   end
+
+   if file_write then
+      function file_methods.write(file, ...)
+         if file == io.stdout then
+            rpc.respond('stdout', written(...))
+         elseif file == io.stderr then
+            rpc.respond('stderr', written(...))
+         else
+            return file_write(file, ...)
+         end
+
+         return file
+      end
+   end
+end
+
+function rpc.handle(line)
    local cmd, arg = line:match('(.-):(.*)')
    if cmd == nil then
       cmd = line
@@ -310,3 +517,18 @@ for line in io.stdin:lines() do
       rpc.respond('error', err)
    end
 end
+
+-- `manen agent` feeds lines from its connections itself
+if ... == 'module' then
+   return rpc
+end
+
+rpc.capture_output()
+
+for line in io.stdin:lines() do
+   rpc.handle(line)
+end
+
+if rpc.cancel_file and os and os.remove then
+   os.remove(rpc.cancel_file)
+end
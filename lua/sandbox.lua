@@ -0,0 +1,122 @@
+manen = manen or {}
+
+-- libraries and functions that reach outside the Lua state, `a.b` only removes the field
+local blocked = {
+   'io',
+   'debug',
+   'ffi',
+   'loadfile',
+   'dofile',
+   'require',
+   'package.loadlib',
+   'package.searchers',
+   'package.loaders',
+   'string.dump',
+   'os.execute',
+   'os.exit',
+   'os.getenv',
+   'os.remove',
+   'os.rename',
+   'os.tmpname',
+   'os.setlocale',
+}
+
+local raw_load, raw_loadstring = load, loadstring
+
+-- a function given to `load` is read whole, so the first byte can be checked
+local function source(chunk)
+   if type(chunk) ~= 'function' then
+      return chunk
+   end
+
+   local parts = {}
+
+   while true do
+      local part = chunk()
+
+      if part == nil or part == '' then
+         return table.concat(parts)
+      end
+
+      parts[#parts + 1] = part
+   end
+end
+
+-- crafted bytecode breaks out of the VM, so `load` and `loadstring` only take source
+local function load_source(chunk, chunkname, _, ...)
+   chunk = source(chunk)
+
+   if type(chunk) == 'string' and chunk:byte(1) == 27 then
+      return nil, 'attempt to load a binary chunk in the sandbox'
+   end
+
+   -- neither has a mode before 5.2, and `load` only takes functions there
+   if _VERSION == 'Lua 5.1' then
+      return raw_loadstring(chunk, chunkname)
+   end
+
+   return raw_load(chunk, chunkname, 't', ...)
+end
+
+-- `manen.sandbox({ 'io.write' })` removes what `blocked` lists but the allowed names,
+-- a library that is removed keeps the allowed functions of it. Allowing `load` keeps
+-- binary chunks loading
+function manen.sandbox(allow)
+   local allowed = {}
+
+   for _, name in ipairs(allow or {}) do
+      allowed[name] = true
+   end
+
+   local loaded = type(package) == 'table' and package.loaded
+   local preload = type(package) == 'table' and package.preload
+
+   for _, name in ipairs(blocked) do
+      local lib, field = name:match('^([^.]+)%.([^.]+)$')
+      -- libraries such as LuaJIT's `ffi` are only loaded, not globals
+      local library = _G[name]
+
+      if type(library) ~= 'table' and type(loaded) == 'table' then
+         library = loaded[name]
+      end
+
+      if allowed[name] then
+         -- kept as is
+      elseif lib then
+         if type(_G[lib]) == 'table' and not allowed[lib] then
+            _G[lib][field] = nil
+         end
+      elseif type(library) == 'table' then
+         local kept
+
+         for key, value in pairs(library) do
+            if allowed[name .. '.' .. tostring(key)] then
+               kept = kept or {}
+               kept[key] = value
+            end
+         end
+
+         _G[name] = kept
+
+         -- `package.loaded.io` would give the library back
+         if type(loaded) == 'table' then
+            loaded[name] = kept
+         end
+      else
+         _G[name] = nil
+      end
+
+      -- so would the loader of one not required yet, as `ffi` is on LuaJIT
+      if not lib and not allowed[name] and type(preload) == 'table' then
+         preload[name] = nil
+      end
+   end
+
+   if not allowed.load then
+      load = load_source
+
+      if raw_loadstring then
+         loadstring = load_source
+      end
+   end
+end
@@ -0,0 +1,54 @@
+-- Teal front-end, compiles input with the `tl` module found on package.path
+--
+-- the type environment is kept between calls so REPL declarations stay visible,
+-- syntax and type errors are raised as `filename:line:col: message` lines
+
+local load = loadstring or load
+local env
+
+-- tl isn't bundled, so say how to get it rather than fail at the first input
+local found, tl = pcall(require, 'tl')
+
+if not found then
+   error('the teal language needs the tl module on package.path, '
+      .. 'install it with `luarocks install tl`', 0)
+end
+
+local function generate(code, filename)
+   env = env or tl.init_env()
+
+   -- try as an expression first, like plain Lua input
+   local lua_code, result = tl.gen('return ' .. code, env)
+
+   if #(result.syntax_errors or {}) > 0 then
+      lua_code, result = tl.gen(code, env)
+   end
+
+   local errors = {}
+
+   for _, kind in ipairs({ 'syntax_errors', 'type_errors' }) do
+      for _, err in ipairs(result[kind] or {}) do
+         errors[#errors + 1] = ('%s:%d:%d: %s'):format(filename, err.y, err.x, err.msg)
+      end
+   end
+
+   if #errors > 0 then
+      error(table.concat(errors, '\n'), 0)
+   end
+
+   return lua_code
+end
+
+function __manen_teal_gen(code, filename)
+   return generate(code, filename or 'repl')
+end
+
+function __manen_teal(code, filename)
+   local chunk, err = load(generate(code, filename), '=' .. filename)
+
+   if not chunk then
+      error(err, 0)
+   end
+
+   return chunk()
+end
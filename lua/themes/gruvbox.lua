@@ -0,0 +1,43 @@
+-- https://github.com/morhetz/gruvbox, dark variant
+return {
+   syntax = {
+      keyword = '#fb4934',
+      ['keyword.operator'] = '#fe8019',
+      ['constant.builtin'] = '#d3869b',
+      number = '#d3869b',
+      operator = '#ebdbb2',
+      variable = '#ebdbb2',
+      ['variable.upvalue'] = 'italic #ebdbb2',
+      ['variable.global'] = '#83a598',
+      ['variable.builtin'] = '#fe8019',
+      ['variable.unknown'] = 'underline #fabd2f',
+      string = '#b8bb26',
+      ['string.escape'] = '#fe8019',
+      ['string.special'] = '#8ec07c',
+      comment = 'italic #928374',
+      attribute = '#8ec07c',
+      type = '#fabd2f',
+      ['variable.parameter'] = '#83a598',
+      ['function'] = '#8ec07c',
+      ['field.unknown'] = 'underline #fabd2f',
+      ['bracket.match'] = 'bold underline #fe8019',
+      error = 'underline #fb4934',
+      ['bracket.rainbow.1'] = '#fabd2f',
+      ['bracket.rainbow.2'] = '#d3869b',
+      ['bracket.rainbow.3'] = '#83a598',
+      ['bracket.rainbow.4'] = '#8ec07c',
+   },
+   values = {
+      ['nil'] = '#d3869b',
+      boolean = '#d3869b',
+      number = '#d3869b',
+      string = '#b8bb26',
+      table = '#83a598',
+      ['function'] = '#8ec07c',
+      thread = '#a89984',
+      userdata = '#fe8019',
+      address = '#fabd2f',
+      escape = '#fe8019',
+      note = '#928374',
+   },
+}
@@ -0,0 +1,258 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{IpAddr, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use mlua::prelude::*;
+
+use crate::{lua::RPC_CODE, share};
+
+pub const DEFAULT_PORT: u16 = 7378;
+
+/// Connections served at once, later ones are turned away until one closes
+const MAX_CONNECTIONS: usize = 16;
+
+/// Writes a line to the connection of the running request, if any
+fn send(current: &Mutex<Option<TcpStream>>, line: &[u8]) {
+    if let Some(stream) = current.lock().unwrap().as_mut() {
+        let _ = stream.write_all(line).and_then(|_| stream.write_all(b"\n"));
+    }
+}
+
+/// A Lua state served to REPLs attached with `manen.executor = 'remote'`
+struct Agent {
+    lua: Lua,
+    token: String,
+    /// Cancel flag of the connection whose request runs, so others can't stop it
+    cancel: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+    connections: AtomicUsize,
+    /// Connection answered by `rpc.write` while a request runs
+    current: Arc<Mutex<Option<TcpStream>>>,
+    // requests of every connection share the state, so they run one at a time
+    running: Mutex<()>,
+}
+
+impl Agent {
+    fn new(token: String) -> LuaResult<Self> {
+        let lua = Lua::new();
+        let cancel = Arc::new(Mutex::new(None::<Arc<AtomicBool>>));
+        let current = Arc::new(Mutex::new(None::<TcpStream>));
+
+        let inner_cancel = cancel.clone();
+        lua.set_hook(LuaHookTriggers::EVERY_LINE, move |_, _debug| {
+            let cancel = inner_cancel.lock().unwrap();
+
+            if cancel
+                .as_ref()
+                .is_some_and(|cancelled| cancelled.swap(false, Ordering::Relaxed))
+            {
+                return Err(LuaError::runtime("cancelled"));
+            }
+
+            Ok(LuaVmState::Continue)
+        });
+
+        let rpc: LuaTable = lua.load(RPC_CODE).set_name("=rpc").call("module")?;
+
+        let inner_current = current.clone();
+        rpc.raw_set(
+            "write",
+            lua.create_function(move |_, line: LuaString| {
+                send(&inner_current, &line.as_bytes());
+                Ok(())
+            })?,
+        )?;
+
+        // printed text is shown by the REPL, like that of a system Lua
+        rpc.get::<LuaFunction>("capture_output")?.call::<()>(())?;
+
+        lua.set_named_registry_value("rpc", rpc)?;
+
+        Ok(Self {
+            lua,
+            token,
+            cancel,
+            connections: AtomicUsize::new(0),
+            current,
+            running: Mutex::new(()),
+        })
+    }
+
+    fn run_request(
+        &self,
+        line: &str,
+        stream: &TcpStream,
+        cancelled: &Arc<AtomicBool>,
+        closed: &AtomicBool,
+    ) {
+        let _running = self.running.lock().unwrap();
+
+        // a cancel that came while nothing ran is not for this request, unless the
+        // connection closed while it waited
+        cancelled.store(closed.load(Ordering::Relaxed), Ordering::Relaxed);
+        *self.cancel.lock().unwrap() = Some(cancelled.clone());
+        *self.current.lock().unwrap() = stream.try_clone().ok();
+
+        // errors of the command itself are answered by `rpc.handle` of `lua/rpc.lua`
+        let handled = self
+            .lua
+            .named_registry_value::<LuaTable>("rpc")
+            .and_then(|rpc| rpc.get::<LuaFunction>("handle"))
+            .and_then(|handle| handle.call::<()>(line));
+
+        if let Err(e) = handled {
+            eprintln!("{e}");
+        }
+
+        *self.current.lock().unwrap() = None;
+        *self.cancel.lock().unwrap() = None;
+    }
+
+    /// Token handshake, then command lines run in order while `cancel` lines stop them
+    fn serve(self: Arc<Self>, stream: TcpStream) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+
+        if stream
+            .set_read_timeout(Some(share::HANDSHAKE_TIMEOUT))
+            .is_err()
+        {
+            return;
+        }
+
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+
+        let mut lines = BufReader::new(reader).lines();
+
+        let Some(Ok(token)) = lines.next() else {
+            return;
+        };
+
+        if !share::token_matches(token.trim(), &self.token) {
+            let _ = writer.write_all(b"invalid token\n");
+            return;
+        }
+
+        // a REPL may sit idle at its prompt for as long as it likes, but one that stops
+        // reading must not hold `current` while its answer is written
+        if stream.set_read_timeout(None).is_err()
+            || stream
+                .set_write_timeout(Some(share::WRITE_TIMEOUT))
+                .is_err()
+            || writer.write_all(b"connected\n").is_err()
+        {
+            return;
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel::<String>();
+
+        let agent = self.clone();
+        let request_cancelled = cancelled.clone();
+        let request_closed = closed.clone();
+        let requests = thread::spawn(move || {
+            for line in receiver {
+                // nobody is left to answer, so what was queued is dropped
+                if request_closed.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                agent.run_request(&line, &writer, &request_cancelled, &request_closed);
+            }
+        });
+
+        for line in lines.map_while(Result::ok) {
+            if line.trim() == "cancel" {
+                cancelled.store(true, Ordering::Relaxed);
+            } else if sender.send(line).is_err() {
+                break;
+            }
+        }
+
+        // the running request would otherwise keep every other connection waiting
+        closed.store(true, Ordering::Relaxed);
+        cancelled.store(true, Ordering::Relaxed);
+
+        drop(sender);
+        let _ = requests.join();
+    }
+}
+
+/// Serves connections of `listener` on a thread each, at most `MAX_CONNECTIONS` at once
+fn accept(agent: Arc<Agent>, listener: TcpListener) {
+    for mut stream in listener.incoming().flatten() {
+        if agent.connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+            agent.connections.fetch_sub(1, Ordering::Relaxed);
+            let _ = stream.write_all(b"too many connections\n");
+            continue;
+        }
+
+        let agent = agent.clone();
+
+        thread::spawn(move || {
+            agent.clone().serve(stream);
+            agent.connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Serves a Lua state on `bind` and `port` until interrupted, printing how to attach
+/// to it
+pub fn run(bind: IpAddr, port: u16) -> LuaResult<()> {
+    let listener = TcpListener::bind((bind, port)).map_err(LuaError::external)?;
+    let agent = Arc::new(Agent::new(share::random_token())?);
+
+    println!("manen.executor = 'remote'");
+    println!(
+        "manen.remote_agent = '{}@{}:{port}'",
+        agent.token,
+        share::advertised_host(bind)
+    );
+
+    accept(agent, listener);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{LuaExecutor, RemoteExecutor};
+
+    #[test]
+    fn remote_state() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let agent = Arc::new(Agent::new(String::from("secret")).unwrap());
+
+        thread::spawn(move || accept(agent, listener));
+
+        assert!(RemoteExecutor::new(&format!("wrong@127.0.0.1:{port}")).is_err());
+
+        let target = format!("secret@127.0.0.1:{port}");
+        let executor = RemoteExecutor::new(&target).unwrap();
+
+        executor.exec("answer = 41").unwrap();
+        assert_eq!(3, executor.exec("return 1, nil, 3").unwrap().len());
+        assert!(executor.exec("error('boom')").is_err());
+
+        // a later connection sees the same state
+        let executor = RemoteExecutor::new(&target).unwrap();
+
+        assert_eq!(
+            Some(42),
+            executor.eval("return answer + 1").unwrap().as_integer()
+        );
+        assert!(executor.resolve(&[String::from("string")]).is_some());
+    }
+}
@@ -0,0 +1,369 @@
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use mlua::prelude::*;
+
+use crate::inspect::{
+    InspectLimits, display_basic, display_key, display_limited, is_redacted, redacted, table_pairs,
+};
+
+// entries listed at once when a table is expanded, the rest wait behind a `…` row
+const CHUNK: usize = 200;
+
+const HELP: &str = "↑↓ move  → expand  ← collapse  PgUp/PgDn page  q quit";
+
+enum Row {
+    Entry {
+        depth: usize,
+        key: String,
+        value: LuaValue,
+        expanded: bool,
+        /// Under a key of `redact_keys`, drawn as `<redacted>` and never expanded
+        redacted: bool,
+    },
+    More {
+        depth: usize,
+        rest: Vec<(LuaValue, LuaValue)>,
+    },
+}
+
+impl Row {
+    fn depth(&self) -> usize {
+        match self {
+            Row::Entry { depth, .. } | Row::More { depth, .. } => *depth,
+        }
+    }
+}
+
+/// Interactive view of a value where tables are only listed once expanded
+pub struct Browser {
+    rows: Vec<Row>,
+    selected: usize,
+    top: usize,
+    limits: InspectLimits,
+}
+
+fn chunk_rows(
+    mut entries: Vec<(LuaValue, LuaValue)>,
+    depth: usize,
+    limits: InspectLimits,
+) -> Vec<Row> {
+    let rest = entries.split_off(CHUNK.min(entries.len()));
+
+    let mut rows = entries
+        .into_iter()
+        .map(|(key, value)| Row::Entry {
+            depth,
+            redacted: is_redacted(&key, limits),
+            key: display_key(&key, false),
+            value,
+            expanded: false,
+        })
+        .collect::<Vec<_>>();
+
+    if !rest.is_empty() {
+        rows.push(Row::More { depth, rest });
+    }
+
+    rows
+}
+
+impl Browser {
+    pub fn new(value: LuaValue, limits: InspectLimits) -> Self {
+        let mut browser = Self {
+            rows: vec![Row::Entry {
+                depth: 0,
+                key: String::new(),
+                value,
+                expanded: false,
+                redacted: false,
+            }],
+            selected: 0,
+            top: 0,
+            limits,
+        };
+
+        browser.expand();
+        browser
+    }
+
+    /// Lists the entries of the selected table, or the next ones behind a `…` row
+    fn expand(&mut self) {
+        let at = self.selected;
+
+        let rows = match &mut self.rows[at] {
+            Row::Entry {
+                depth,
+                value: LuaValue::Table(tbl),
+                expanded: expanded @ false,
+                redacted: false,
+                ..
+            } => {
+                *expanded = true;
+
+                chunk_rows(
+                    table_pairs(tbl, self.limits).collect(),
+                    *depth + 1,
+                    self.limits,
+                )
+            }
+            Row::More { depth, rest } => {
+                let rows = chunk_rows(std::mem::take(rest), *depth, self.limits);
+
+                self.rows.splice(at..=at, rows);
+                return;
+            }
+            _ => return,
+        };
+
+        self.rows.splice(at + 1..at + 1, rows);
+    }
+
+    /// Hides the entries of the selected table, or moves to the table holding it
+    fn collapse(&mut self) {
+        let at = self.selected;
+        let depth = self.rows[at].depth();
+
+        if let Row::Entry {
+            expanded: expanded @ true,
+            ..
+        } = &mut self.rows[at]
+        {
+            *expanded = false;
+
+            let end = self.rows[at + 1..]
+                .iter()
+                .position(|row| row.depth() <= depth)
+                .map_or(self.rows.len(), |i| at + 1 + i);
+
+            self.rows.drain(at + 1..end);
+            return;
+        }
+
+        if let Some(parent) = self.rows[..at].iter().rposition(|row| row.depth() < depth) {
+            self.selected = parent;
+        }
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        self.selected = self
+            .selected
+            .saturating_add_signed(offset)
+            .min(self.rows.len() - 1);
+    }
+
+    fn line(&self, row: &Row) -> String {
+        match row {
+            Row::Entry {
+                depth,
+                key,
+                value,
+                expanded,
+                redacted: is_redacted,
+            } => {
+                let marker = match (value, expanded) {
+                    _ if *is_redacted => "  ",
+                    (LuaValue::Table(_), true) => "▾ ",
+                    (LuaValue::Table(_), false) => "▸ ",
+                    _ => "  ",
+                };
+
+                let value = match value {
+                    _ if *is_redacted => redacted(false),
+                    LuaValue::Table(_) => display_basic(value, false),
+                    value => display_limited(value, false, self.limits),
+                };
+
+                format!("{}{marker}{key}{value}", "  ".repeat(*depth))
+            }
+            Row::More { depth, rest } => {
+                format!("{}▸ … ({} more)", "  ".repeat(*depth), rest.len())
+            }
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, page) = (width as usize, (height as usize).saturating_sub(1).max(1));
+
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + page {
+            self.top = self.selected + 1 - page;
+        }
+
+        queue!(out, cursor::MoveTo(0, 0), terminal::Clear(ClearType::All))?;
+
+        for (i, row) in self.rows.iter().enumerate().skip(self.top).take(page) {
+            let line = self.line(row).chars().take(width).collect::<String>();
+
+            if i == self.selected {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(line),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+
+            queue!(out, Print("\r\n"))?;
+        }
+
+        let status = format!("{}/{}  {HELP}", self.selected + 1, self.rows.len());
+
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1)),
+            SetAttribute(Attribute::Dim),
+            Print(status.chars().take(width).collect::<String>()),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        out.flush()
+    }
+
+    /// Takes over the terminal until `q`, Esc or Ctrl-C
+    pub fn run(mut self) -> io::Result<()> {
+        let mut out = io::stdout();
+
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+        let result = self.event_loop(&mut out);
+
+        execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+
+        result
+    }
+
+    fn event_loop(&mut self, out: &mut impl Write) -> io::Result<()> {
+        loop {
+            self.draw(out)?;
+
+            let Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) = event::read()?
+            else {
+                continue;
+            };
+
+            let page = terminal::size()?.1.saturating_sub(1).max(1) as isize;
+
+            match code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+                KeyCode::PageUp => self.move_by(-page),
+                KeyCode::PageDown => self.move_by(page),
+                KeyCode::Home => self.selected = 0,
+                KeyCode::End => self.selected = self.rows.len() - 1,
+                KeyCode::Right | KeyCode::Enter | KeyCode::Char('l') => self.expand(),
+                KeyCode::Left | KeyCode::Char('h') => self.collapse(),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_and_collapse() {
+        let lua = Lua::new();
+        let value = lua
+            .load("local t = { a = { b = 1 } } for i = 1, 250 do t[i] = i end return t")
+            .eval::<LuaValue>()
+            .unwrap();
+
+        let limits = InspectLimits {
+            sort_keys: true,
+            ..InspectLimits::default()
+        };
+
+        let mut browser = Browser::new(value, limits);
+
+        // the root, the first chunk and the row holding the rest
+        assert_eq!(2 + CHUNK, browser.rows.len());
+        assert!(matches!(browser.rows.last(), Some(Row::More { rest, .. }) if rest.len() == 51));
+
+        browser.selected = browser.rows.len() - 1;
+        browser.expand();
+
+        assert_eq!(252, browser.rows.len());
+        assert_eq!("  ▸ a = table", &browser.line(&browser.rows[251])[..15]);
+
+        browser.selected = 251;
+        browser.expand();
+
+        assert_eq!(253, browser.rows.len());
+
+        browser.selected = 252;
+        browser.collapse();
+
+        assert_eq!(251, browser.selected);
+
+        browser.collapse();
+
+        assert_eq!(252, browser.rows.len());
+    }
+
+    #[test]
+    fn redacted_values() {
+        let lua = Lua::new();
+        let value = lua
+            .load("return { token = 'hunter2', auth = { key = 1 }, name = 'ana' }")
+            .eval::<LuaValue>()
+            .unwrap();
+
+        // the same keys as the other tests, which may run at the same time
+        crate::inspect::set_redacted_keys(&[
+            String::from("auth"),
+            String::from("password"),
+            String::from("token"),
+        ]);
+
+        let limits = InspectLimits {
+            sort_keys: true,
+            redact: true,
+            ..InspectLimits::default()
+        };
+
+        let mut browser = Browser::new(value, limits);
+        let lines = browser
+            .rows
+            .iter()
+            .map(|row| browser.line(row))
+            .collect::<Vec<_>>();
+
+        assert!(lines.iter().all(|line| !line.contains("hunter2")));
+        assert!(lines.iter().any(|line| line.contains("ana")));
+        assert_eq!(
+            2,
+            lines
+                .iter()
+                .filter(|line| line.contains("<redacted>"))
+                .count()
+        );
+
+        // a redacted table stays closed
+        browser.selected = 1;
+        browser.expand();
+
+        assert_eq!(4, browser.rows.len());
+    }
+}
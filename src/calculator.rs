@@ -0,0 +1,171 @@
+// number forms accepted by the calculator preprocessor on top of plain Lua
+const UNITS: &[(&str, &str)] = &[
+    ("KB", "1e3"),
+    ("MB", "1e6"),
+    ("GB", "1e9"),
+    ("TB", "1e12"),
+    ("KiB", "1024"),
+    ("MiB", "1048576"),
+    ("GiB", "1073741824"),
+    ("TiB", "1099511627776"),
+];
+
+fn is_identifier(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn copy_until(chars: &[char], start: usize, terminator: &str) -> usize {
+    let terminator = terminator.chars().collect::<Vec<_>>();
+    let mut i = start;
+
+    while i < chars.len() {
+        if chars[i..].starts_with(&terminator) {
+            return i + terminator.len();
+        }
+
+        i += 1;
+    }
+
+    chars.len()
+}
+
+fn skip_string(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    chars.len()
+}
+
+fn rewrite_number(number: &str) -> String {
+    let digits = number.replace('_', "");
+
+    if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        if let Ok(value) = u64::from_str_radix(binary, 2) {
+            return value.to_string();
+        }
+    }
+
+    digits
+}
+
+/// Rewrites calculator shorthands into plain Lua, `None` when there is nothing to change
+///
+/// * `0b1010` binary literals and `1_000_000` digit separators
+/// * `4 KiB`, `2^10 MB` byte units after a number
+/// * `15%` postfix percentages, `10 % 3` and `10 % -3` stay modulo
+pub fn rewrite(line: &str) -> Option<String> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut output = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        let end = match c {
+            '"' | '\'' => skip_string(&chars, i),
+            '-' if chars.get(i + 1) == Some(&'-') => chars.len(),
+            '[' if matches!(chars.get(i + 1), Some('[' | '=')) => {
+                let level = chars[i + 1..].iter().take_while(|c| **c == '=').count();
+                copy_until(&chars, i, &format!("]{}]", "=".repeat(level)))
+            }
+            c if c.is_ascii_digit() && (i == 0 || !is_identifier(chars[i - 1])) => {
+                let mut end = i;
+
+                while end < chars.len()
+                    && (is_identifier(chars[end])
+                        || chars[end] == '.'
+                        || (matches!(chars[end], '+' | '-')
+                            && matches!(chars[end - 1], 'e' | 'E' | 'p' | 'P')
+                            && !chars[i..end].contains(&'x')))
+                {
+                    end += 1;
+                }
+
+                let number = rewrite_number(&chars[i..end].iter().collect::<String>());
+
+                let after = chars[end..]
+                    .iter()
+                    .take_while(|c| c.is_whitespace())
+                    .count();
+                let next = end + after;
+
+                let word = chars[next..]
+                    .iter()
+                    .take_while(|c| is_identifier(**c))
+                    .collect::<String>();
+
+                if let Some((_, factor)) = UNITS.iter().find(|(unit, _)| *unit == word) {
+                    // `2^10 KiB` scales the whole power, anything else just the number
+                    if output.trim_end().ends_with('^') {
+                        output.push_str(&format!("{number} * {factor}"));
+                    } else {
+                        output.push_str(&format!("({number} * {factor})"));
+                    }
+
+                    i = next + word.len();
+                    continue;
+                }
+
+                // a `%` with no operand to its right is a percentage, `-` would start one
+                if chars.get(next) == Some(&'%') {
+                    let rest = chars[next + 1..].iter().find(|c| !c.is_whitespace());
+
+                    if rest
+                        .is_none_or(|c| matches!(c, ')' | ']' | '}' | ',' | ';' | '+' | '*' | '/'))
+                    {
+                        output.push_str(&format!("({number} / 100)"));
+                        i = next + 1;
+                        continue;
+                    }
+                }
+
+                output.push_str(&number);
+
+                i = end;
+                continue;
+            }
+            _ => i + 1,
+        };
+
+        output.extend(&chars[i..end]);
+        i = end;
+    }
+
+    (output != line).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites() {
+        assert_eq!(Some(String::from("10 + 1")), rewrite("0b1010 + 1"));
+        assert_eq!(Some(String::from("1000000")), rewrite("1_000_000"));
+        assert_eq!(Some(String::from("2^10 * 1024")), rewrite("2^10 KiB"));
+        assert_eq!(Some(String::from("200 * (15 / 100)")), rewrite("200 * 15%"));
+        assert_eq!(
+            Some(String::from("(1 * 1048576) / (4 * 1024)")),
+            rewrite("1 MiB / 4 KiB")
+        );
+
+        assert_eq!(Some(String::from("(15 / 100) + 1")), rewrite("15% + 1"));
+
+        assert_eq!(None, rewrite("10 % 3"));
+        assert_eq!(None, rewrite("10 % -3"));
+        assert_eq!(None, rewrite("print('0b1010 KiB')"));
+        assert_eq!(None, rewrite("0x1F + 1e-3"));
+        assert_eq!(None, rewrite("x1_000"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use emmylua_parser::{LuaAstNode, LuaCallExpr, LuaExpr, LuaLiteralToken, LuaParser};
+use mlua::prelude::*;
+use nu_ansi_term::Color;
+
+use crate::parse;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Lua files under `paths` along with their modification time
+fn lua_files(paths: &[PathBuf]) -> BTreeMap<PathBuf, SystemTime> {
+    let mut files = BTreeMap::new();
+    let mut pending = paths.to_vec();
+
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lua") {
+            let modified = path.metadata().and_then(|m| m.modified());
+
+            files.insert(path, modified.unwrap_or(SystemTime::UNIX_EPOCH));
+        }
+    }
+
+    files
+}
+
+/// Modules required with a literal name, like `require("a.b")` or `require 'a'`
+fn requires(code: &str) -> Vec<String> {
+    let tree = LuaParser::parse(code, parse::config());
+
+    tree.get_chunk_node()
+        .descendants::<LuaCallExpr>()
+        .filter_map(|call| {
+            let LuaExpr::NameExpr(name) = call.get_prefix_expr()? else {
+                return None;
+            };
+
+            if name.get_name_text()? != "require" {
+                return None;
+            }
+
+            let LuaExpr::LiteralExpr(literal) = call.get_args_list()?.get_args().next()? else {
+                return None;
+            };
+
+            match literal.get_literal()? {
+                LuaLiteralToken::String(s) => Some(s.get_value()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Files a module name can load from, relative to each checked directory
+fn module_paths(roots: &[PathBuf], module: &str) -> Vec<PathBuf> {
+    let relative = module.replace('.', "/");
+
+    roots
+        .iter()
+        .filter(|root| root.is_dir())
+        .flat_map(|root| {
+            [
+                root.join(format!("{relative}.lua")),
+                root.join(&relative).join("init.lua"),
+            ]
+        })
+        .collect()
+}
+
+struct Checker {
+    lua: Lua,
+    roots: Vec<PathBuf>,
+    files: BTreeMap<PathBuf, SystemTime>,
+    diagnostics: BTreeMap<PathBuf, String>,
+    // files required by each file, used to find dependents of a change
+    dependencies: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Checker {
+    fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            lua: Lua::new(),
+            roots,
+            files: BTreeMap::new(),
+            diagnostics: BTreeMap::new(),
+            dependencies: HashMap::new(),
+        }
+    }
+
+    fn check_file(&mut self, path: &Path) {
+        self.diagnostics.remove(path);
+
+        let code = match fs::read_to_string(path) {
+            Ok(code) => code,
+            Err(e) => {
+                self.diagnostics.insert(path.to_path_buf(), e.to_string());
+                return;
+            }
+        };
+
+        let dependencies = requires(&code)
+            .iter()
+            .flat_map(|module| module_paths(&self.roots, module))
+            .filter(|dependency| dependency.exists())
+            .collect();
+
+        self.dependencies.insert(path.to_path_buf(), dependencies);
+
+        let res = self
+            .lua
+            .load(&code)
+            .set_name(format!("@{}", path.to_string_lossy()))
+            .into_function();
+
+        if let Err(LuaError::SyntaxError { message, .. }) = res {
+            self.diagnostics.insert(path.to_path_buf(), message);
+        }
+    }
+
+    /// `changed` along with every file that requires one of them, directly or not
+    fn dependents(&self, changed: HashSet<PathBuf>) -> HashSet<PathBuf> {
+        let mut affected = changed;
+
+        loop {
+            let before = affected.len();
+
+            for (file, dependencies) in &self.dependencies {
+                if dependencies.iter().any(|d| affected.contains(d)) {
+                    affected.insert(file.clone());
+                }
+            }
+
+            if affected.len() == before {
+                return affected;
+            }
+        }
+    }
+
+    /// Re-checks files that changed since the last call, returns if any did
+    fn refresh(&mut self) -> bool {
+        let files = lua_files(&self.roots);
+
+        let removed = self
+            .files
+            .keys()
+            .filter(|path| !files.contains_key(*path))
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        let mut changed = files
+            .iter()
+            .filter(|(path, modified)| self.files.get(*path) != Some(modified))
+            .map(|(path, _)| path.clone())
+            .collect::<HashSet<_>>();
+
+        if changed.is_empty() && removed.is_empty() {
+            return false;
+        }
+
+        for path in &removed {
+            self.diagnostics.remove(path);
+            self.dependencies.remove(path);
+        }
+
+        changed.extend(removed);
+        self.files = files;
+
+        let mut affected = self
+            .dependents(changed)
+            .into_iter()
+            .filter(|path| self.files.contains_key(path))
+            .collect::<Vec<_>>();
+
+        affected.sort();
+
+        for path in affected {
+            self.check_file(&path);
+        }
+
+        true
+    }
+
+    fn print_summary(&self, color: bool) {
+        for message in self.diagnostics.values() {
+            if color {
+                println!("{}", Color::Red.paint(message));
+            } else {
+                println!("{message}");
+            }
+        }
+
+        let summary = format!(
+            "{} files checked, {} with errors",
+            self.files.len(),
+            self.diagnostics.len()
+        );
+
+        if color {
+            println!("{}", Color::DarkGray.paint(summary));
+        } else {
+            println!("{summary}");
+        }
+    }
+}
+
+/// Reports syntax errors in the Lua files under `paths`, returns if all files passed
+///
+/// With `watch`, polls for changes forever and only re-checks modified files and the
+/// files that require them
+pub fn check(paths: Vec<PathBuf>, watch: bool, color: bool) -> bool {
+    let mut checker = Checker::new(paths);
+
+    checker.refresh();
+    checker.print_summary(color);
+
+    if !watch {
+        return checker.diagnostics.is_empty();
+    }
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        if checker.refresh() {
+            // clear the screen so the summary stays current
+            print!("\x1b[2J\x1b[H");
+            checker.print_summary(color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_modules() {
+        assert_eq!(
+            vec!["a.b", "c"],
+            requires("local b = require('a.b')\nlocal c = require 'c'\nrequire(name)")
+        );
+    }
+
+    #[test]
+    fn dependents_rechecked() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+
+        fs::write(root.join("lib.lua"), "return {}").unwrap();
+        fs::write(root.join("main.lua"), "local lib = require('lib')").unwrap();
+        fs::write(root.join("other.lua"), "return 1").unwrap();
+
+        let mut checker = Checker::new(vec![root.clone()]);
+        checker.refresh();
+
+        assert!(checker.diagnostics.is_empty());
+
+        let changed = HashSet::from([root.join("lib.lua")]);
+
+        assert_eq!(
+            HashSet::from([root.join("lib.lua"), root.join("main.lua")]),
+            checker.dependents(changed)
+        );
+
+        fs::write(root.join("other.lua"), "return (").unwrap();
+        checker.files.remove(&root.join("other.lua"));
+        checker.refresh();
+
+        assert!(checker.diagnostics.contains_key(&root.join("other.lua")));
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+
+use emmylua_parser::{LuaAstNode, LuaCallExpr, LuaExpr, LuaParser, LuaTokenKind};
+use mlua::prelude::*;
+
+use crate::{inspect::lua_string_literal, parse};
+
+// calls that leave a mark outside the session, checked before re-running a line
+const SIDE_EFFECTS: &[&str] = &[
+    "os.execute",
+    "os.exit",
+    "os.remove",
+    "os.rename",
+    "io.write",
+    "io.output",
+    "io.popen",
+    "http.post",
+];
+
+/// REPL commands and their usage, completed after a leading `.`
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("browse", "[expr]"),
+    ("expand-macro", "<code>"),
+    ("export", "<file>"),
+    ("gc", "[collect|count|stop|restart|step N]"),
+    ("get", "<path>"),
+    ("jit", "[on|off|flush|v|dump] [args]"),
+    ("language", "[name]"),
+    ("restart", ""),
+    ("restore", ""),
+    ("resume", "[values]"),
+    ("reveal", "<path>"),
+    ("snippet", "save|insert <name>"),
+    ("time", "[runs] <expr>"),
+];
+
+fn path_error(path: &str, message: &str) -> LuaError {
+    LuaError::RuntimeError(format!("invalid path '{path}': {message}"))
+}
+
+fn take_identifier(chars: &[char], start: usize) -> usize {
+    let mut end = start;
+
+    while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+        end += 1;
+    }
+
+    end
+}
+
+/// Converts a path like `a.b[3]["c d"]` into an equivalent Lua index expression
+///
+/// Paths starting with `.` or `[` are resolved against the last result (`_`)
+pub fn path_expression(path: &str) -> LuaResult<String> {
+    let path = path.trim();
+    let chars = path.chars().collect::<Vec<_>>();
+
+    let mut expression = String::new();
+    let mut i = 0;
+
+    if chars.is_empty() {
+        return Ok(String::from("_"));
+    }
+
+    if chars[0] == '.' || chars[0] == '[' {
+        expression.push('_');
+    } else {
+        if !chars[0].is_ascii_alphabetic() && chars[0] != '_' {
+            return Err(path_error(path, "expected identifier"));
+        }
+
+        i = take_identifier(&chars, 0);
+        expression.extend(&chars[..i]);
+    }
+
+    while i < chars.len() {
+        match chars[i] {
+            '.' => {
+                let end = take_identifier(&chars, i + 1);
+
+                if end == i + 1 || chars[i + 1].is_ascii_digit() {
+                    return Err(path_error(path, "expected field name after '.'"));
+                }
+
+                expression.extend(&chars[i..end]);
+                i = end;
+            }
+            '[' => {
+                let start = i + 1;
+                let mut end = start;
+
+                match chars.get(start) {
+                    Some(quote @ ('"' | '\'')) => {
+                        end += 1;
+
+                        while end < chars.len() && chars[end] != *quote {
+                            if chars[end] == '\\' {
+                                end += 1;
+                            }
+
+                            end += 1;
+                        }
+
+                        if end >= chars.len() {
+                            return Err(path_error(path, "unterminated string"));
+                        }
+
+                        end += 1;
+                    }
+                    Some(c) if c.is_ascii_digit() || *c == '-' => {
+                        end += 1;
+
+                        while end < chars.len()
+                            && (chars[end].is_ascii_digit() || chars[end] == '.')
+                        {
+                            end += 1;
+                        }
+                    }
+                    _ => return Err(path_error(path, "expected number or string index")),
+                }
+
+                if chars.get(end) != Some(&']') {
+                    return Err(path_error(path, "expected ']'"));
+                }
+
+                expression.push('[');
+                expression.extend(&chars[start..end]);
+                expression.push(']');
+                i = end + 1;
+            }
+            c => return Err(path_error(path, &format!("unexpected character '{c}'"))),
+        }
+    }
+
+    Ok(expression)
+}
+
+/// Splits arguments on whitespace, keeping strings and bracketed expressions intact
+pub fn split_arguments(args: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut chars = args.chars();
+
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            current.push(c);
+
+            if c == '\\' {
+                current.extend(chars.next());
+            } else if c == q {
+                quote = None;
+            }
+
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            c if c.is_whitespace() && depth == 0 => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+
+                continue;
+            }
+            _ => {}
+        }
+
+        current.push(c);
+    }
+
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+
+    arguments
+}
+
+/// If `args` after the name of an alias start with a binary operator, as in `pp = 1` or
+/// `pp == 1`, which use the name as a variable rather than call the alias
+fn continues_expression(args: &str) -> bool {
+    const OPERATORS: &[&str] = &[
+        "=", "<", ">", "+", "*", "/", "%", "^", "&", "|", ".", ":", ",", ")", "]", "}", ";",
+    ];
+
+    let args = args.trim_start();
+    let word = args
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default();
+
+    OPERATORS.iter().any(|op| args.starts_with(op))
+        // `pp -1` passes a negative number, `pp - 1` subtracts
+        || ["-", "~"].iter().any(|op| {
+            args.strip_prefix(op)
+                .is_some_and(|rest| rest.starts_with(char::is_whitespace) || rest.starts_with('='))
+        })
+        || matches!(word, "and" | "or")
+}
+
+/// Expands `name args...` using a user-defined alias, substituting `%1..%n` and `%*`,
+/// only when the name is followed by an argument rather than an operator
+pub fn expand_alias(line: &str, aliases: &HashMap<String, String>) -> Option<String> {
+    let line = line.trim_start();
+    let (name, args) = line.split_once(char::is_whitespace)?;
+    let template = aliases.get(name)?;
+
+    if args.trim().is_empty() || continues_expression(args) {
+        return None;
+    }
+
+    let arguments = split_arguments(args);
+    let mut expanded = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.peek() {
+            Some('*') => {
+                chars.next();
+                expanded.push_str(args.trim());
+            }
+            Some('%') => {
+                chars.next();
+                expanded.push('%');
+            }
+            Some(d) if d.is_ascii_digit() => {
+                let mut index = String::new();
+
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    index.push(*d);
+                    chars.next();
+                }
+
+                let index: usize = index.parse().unwrap_or_default();
+
+                if let Some(argument) = index.checked_sub(1).and_then(|i| arguments.get(i)) {
+                    expanded.push_str(argument);
+                } else {
+                    expanded.push_str("nil");
+                }
+            }
+            _ => expanded.push('%'),
+        }
+    }
+
+    Some(expanded)
+}
+
+// `jit.status()` gives whether the compiler is on followed by the enabled optimizations
+const JIT_STATUS: &str = "local status = { jit.status() }
+status[1] = status[1] and 'on' or 'off'
+return jit.version .. ' ' .. table.concat(status, ' ')";
+
+/// Lua code for `.jit` on LuaJIT, printing `jit.status()` after switching the compiler
+/// or turning the trace output of `jit.v` and `jit.dump` on and off
+pub fn jit_code(args: &str) -> LuaResult<String> {
+    let mut args = args.split_whitespace();
+    let action = args.next().unwrap_or("status");
+    let rest = args.collect::<Vec<_>>();
+
+    // files default to stderr and options to those of `jit.dump` itself
+    let optional = |arg: Option<&&str>| {
+        arg.map_or_else(
+            || String::from("nil"),
+            |arg| lua_string_literal(arg.as_bytes()),
+        )
+    };
+
+    let code = match (action, rest.as_slice()) {
+        ("status", []) => String::from(JIT_STATUS),
+        ("on" | "off" | "flush", []) => format!("jit.{action}()\n{JIT_STATUS}"),
+        ("v" | "dump", ["off"]) => {
+            format!("require('jit.{action}').off()\nreturn 'jit.{action} off'")
+        }
+        ("v", [] | [_]) => format!(
+            "require('jit.v').on({})\nreturn 'jit.v on'",
+            optional(rest.first())
+        ),
+        ("dump", [] | [_] | [_, _]) => format!(
+            "require('jit.dump').on({}, {})\nreturn 'jit.dump on'",
+            optional(rest.first()),
+            optional(rest.get(1))
+        ),
+        _ => {
+            return Err(LuaError::RuntimeError(String::from(
+                "usage: .jit [on|off|flush|v|dump] [args]",
+            )));
+        }
+    };
+
+    Ok(format!(
+        "if not jit then error('the session is not running LuaJIT', 0) end\n{code}"
+    ))
+}
+
+// sizes are in KiB as `collectgarbage('count')` gives them, `isrunning` is Lua 5.2 and later
+const GC_HELPERS: &str = "local function size(kb)
+   if kb >= 1024 * 1024 then
+      return string.format('%.1f GiB', kb / 1024 / 1024)
+   elseif kb >= 1024 then
+      return string.format('%.1f MiB', kb / 1024)
+   end
+   return string.format('%.1f KiB', kb)
+end
+local function heap()
+   return 'heap ' .. size(collectgarbage('count'))
+end
+local function running()
+   local ok, on = pcall(collectgarbage, 'isrunning')
+   if ok and type(on) == 'boolean' then
+      return on and ', collector running' or ', collector stopped'
+   end
+   return ''
+end
+";
+
+/// Lua code for `.gc`, running `collectgarbage` and describing the heap after it
+pub fn gc_code(args: &str) -> LuaResult<String> {
+    let args = args.split_whitespace().collect::<Vec<_>>();
+
+    let code = match args.as_slice() {
+        [] | ["count"] => String::from("return heap() .. running()"),
+        ["collect"] => String::from(
+            "local before = collectgarbage('count')
+collectgarbage('collect')
+local freed = math.max(before - collectgarbage('count'), 0)
+return 'freed ' .. size(freed) .. ', ' .. heap()",
+        ),
+        ["stop"] => String::from("collectgarbage('stop')\nreturn 'collector stopped, ' .. heap()"),
+        ["restart"] => {
+            String::from("collectgarbage('restart')\nreturn 'collector running, ' .. heap()")
+        }
+        ["step"] | ["step", _] => {
+            let size = match args.get(1) {
+                Some(size) => size
+                    .parse::<u32>()
+                    .map_err(|_| LuaError::RuntimeError(format!("invalid step size '{size}'")))?,
+                None => 0,
+            };
+
+            format!(
+                "local done = collectgarbage('step', {size})
+return (done and 'finished a cycle, ' or 'cycle not finished, ') .. heap()"
+            )
+        }
+        _ => {
+            return Err(LuaError::RuntimeError(String::from(
+                "usage: .gc [collect|count|stop|restart|step N]",
+            )));
+        }
+    };
+
+    Ok(format!("{GC_HELPERS}{code}"))
+}
+
+/// Side-effecting calls made by `code`, method calls to `:write` count for file handles
+pub fn side_effects(code: &str) -> Vec<String> {
+    let tree = LuaParser::parse(code, parse::config());
+
+    tree.get_chunk_node()
+        .descendants::<LuaCallExpr>()
+        .filter_map(|call| {
+            let LuaExpr::IndexExpr(index) = call.get_prefix_expr()? else {
+                return None;
+            };
+
+            let name = index.get_name_token()?.get_name_text().to_string();
+
+            if index.token_by_kind(LuaTokenKind::TkColon).is_some() {
+                return (name == "write").then(|| String::from(":write"));
+            }
+
+            let LuaExpr::NameExpr(prefix) = index.get_prefix_expr()? else {
+                return None;
+            };
+
+            let path = format!("{}.{name}", prefix.get_name_text()?);
+
+            SIDE_EFFECTS.contains(&path.as_str()).then_some(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_expressions() {
+        assert_eq!("a.b[3].c", path_expression("a.b[3].c").unwrap());
+        assert_eq!("_[1][\"key\"]", path_expression("[1][\"key\"]").unwrap());
+        assert_eq!("_.foo", path_expression(".foo").unwrap());
+        assert_eq!("_", path_expression("").unwrap());
+
+        assert!(path_expression("a.b()").is_err());
+        assert!(path_expression("a[b]").is_err());
+        assert!(path_expression("a.1").is_err());
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = HashMap::from([
+            (String::from("pp"), String::from("inspect(%1, true)")),
+            (String::from("add"), String::from("return %1 + %2")),
+        ]);
+
+        assert_eq!(
+            Some(String::from("inspect({ 1, 2 }, true)")),
+            expand_alias("pp { 1, 2 }", &aliases)
+        );
+        assert_eq!(
+            Some(String::from("return 1 + nil")),
+            expand_alias("add 1", &aliases)
+        );
+        assert_eq!(
+            Some(String::from("inspect(-1, true)")),
+            expand_alias("pp -1", &aliases)
+        );
+        assert_eq!(None, expand_alias("pp = 1", &aliases));
+        assert_eq!(None, expand_alias("pp == 1", &aliases));
+        assert_eq!(None, expand_alias("pp ~= nil", &aliases));
+        assert_eq!(None, expand_alias("pp - 1", &aliases));
+        assert_eq!(None, expand_alias("pp .. 'x'", &aliases));
+        assert_eq!(None, expand_alias("pp and 1", &aliases));
+        assert_eq!(None, expand_alias("pp", &aliases));
+        assert_eq!(None, expand_alias("print(1)", &aliases));
+    }
+
+    #[test]
+    fn jit_commands() {
+        assert!(jit_code("").unwrap().ends_with(JIT_STATUS));
+        assert!(jit_code("flush").unwrap().contains("jit.flush()\n"));
+        assert!(
+            jit_code("dump tbim trace.txt")
+                .unwrap()
+                .contains("require('jit.dump').on(\"tbim\", \"trace.txt\")")
+        );
+        assert!(
+            jit_code("v off")
+                .unwrap()
+                .contains("require('jit.v').off()")
+        );
+        assert!(jit_code("on now").is_err());
+
+        let error = Lua::new()
+            .load(jit_code("").unwrap())
+            .exec()
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("not running LuaJIT"));
+    }
+
+    #[test]
+    fn gc_commands() {
+        let lua = Lua::new();
+        let run = |args: &str| lua.load(gc_code(args).unwrap()).eval::<String>().unwrap();
+
+        assert!(run("").starts_with("heap "));
+        assert!(run("collect").starts_with("freed "));
+        assert!(run("stop").starts_with("collector stopped, heap "));
+        assert!(run("count").ends_with(", collector stopped"));
+        assert!(run("restart").starts_with("collector running, heap "));
+        assert!(run("step 10").contains(", heap "));
+        assert!(gc_code("step ten").is_err());
+        assert!(gc_code("sweep").is_err());
+    }
+
+    #[test]
+    fn side_effect_calls() {
+        assert_eq!(["os.remove"], side_effects("os.remove('a.txt')").as_slice());
+        assert_eq!([":write"], side_effects("f:write(1) f:read()").as_slice());
+        assert!(side_effects("print(os.time())").is_empty());
+    }
+}
@@ -1,14 +1,131 @@
-use std::sync::Arc;
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    fs, mem,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
+use clap::ValueEnum;
 use emmylua_parser::{
-    LuaAst, LuaAstNode, LuaAstToken, LuaBlock, LuaExpr, LuaIndexExpr, LuaNameExpr, LuaParser,
-    LuaSyntaxTree, LuaTokenKind,
+    LuaAst, LuaAstNode, LuaAstToken, LuaBlock, LuaCallExpr, LuaClosureExpr, LuaExpr, LuaIndexExpr,
+    LuaKind, LuaLiteralToken, LuaNameExpr, LuaParser, LuaStat, LuaSyntaxKind, LuaSyntaxToken,
+    LuaSyntaxTree, LuaTableExpr, LuaTableField, LuaTokenKind,
 };
 use mlua::prelude::*;
+use nu_ansi_term::{Color, Style};
 use reedline::{Completer, Span, Suggestion};
 use rowan::{TextRange, TextSize};
 
-use crate::{lua::LuaExecutor, parse};
+use crate::{
+    commands, inspect::display_basic, language::Language, lua::LuaExecutor, parse, snippets,
+};
+
+const EXPRESSION_KEYWORDS: &[&str] = &["false", "function", "nil", "not", "true"];
+
+// globals set up by the standard libraries of any Lua version, builtins only when the
+// session actually has them
+const STANDARD_GLOBALS: &[&str] = &[
+    "_G",
+    "_VERSION",
+    "assert",
+    "bit",
+    "bit32",
+    "collectgarbage",
+    "coroutine",
+    "debug",
+    "dofile",
+    "error",
+    "gcinfo",
+    "getfenv",
+    "getmetatable",
+    "io",
+    "ipairs",
+    "jit",
+    "load",
+    "loadfile",
+    "loadstring",
+    "math",
+    "module",
+    "newproxy",
+    "next",
+    "os",
+    "package",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "require",
+    "select",
+    "setfenv",
+    "setmetatable",
+    "string",
+    "table",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "utf8",
+    "warn",
+    "xpcall",
+];
+
+// functions whose first argument is a file path
+const PATH_FUNCTIONS: &[&str] = &[
+    "dofile",
+    "loadfile",
+    "io.open",
+    "io.lines",
+    "io.input",
+    "io.output",
+    "os.remove",
+    "os.rename",
+];
+
+// searched even when package.path leaves out the working directory
+const LOCAL_TEMPLATES: &[&str] = &["./?.lua", "./?/init.lua"];
+
+const STATEMENT_KEYWORDS: &[&str] = &[
+    "break", "do", "else", "elseif", "end", "for", "function", "goto", "if", "local", "repeat",
+    "return", "until", "while",
+];
+
+const METAMETHODS: &[&str] = &[
+    "__add",
+    "__band",
+    "__bnot",
+    "__bor",
+    "__bxor",
+    "__call",
+    "__close",
+    "__concat",
+    "__div",
+    "__eq",
+    "__gc",
+    "__idiv",
+    "__index",
+    "__le",
+    "__len",
+    "__lt",
+    "__metatable",
+    "__mod",
+    "__mode",
+    "__mul",
+    "__name",
+    "__newindex",
+    "__pairs",
+    "__pow",
+    "__shl",
+    "__shr",
+    "__sub",
+    "__tostring",
+    "__unm",
+];
 
 #[derive(Debug)]
 struct Variable {
@@ -22,115 +139,380 @@ struct Scope {
     variables: Vec<Variable>,
 }
 
+// weight kept by earlier uses each time a line is recorded
+const USAGE_DECAY: f64 = 0.99;
+
+// buffers whose trees are kept besides the current one
+const RECENT_TREES: usize = 8;
+
+/// Names from submitted lines: how often identifiers were used, with recent lines
+/// weighing more, and the fields lines evaluated this session assigned to each table path
+#[derive(Default)]
+pub struct SessionIndex {
+    scores: HashMap<String, f64>,
+    fields: HashMap<String, BTreeSet<String>>,
+}
+
+impl SessionIndex {
+    /// Counts the names of `line` after it was evaluated without an error, along with the
+    /// fields it assigned, which exist now
+    pub fn record(&mut self, line: &str) {
+        self.record_usage(line);
+
+        let tree = LuaParser::parse(line, parse::config());
+
+        for index in tree.get_chunk_node().descendants::<LuaIndexExpr>() {
+            if !is_assignment_target(&index) {
+                continue;
+            }
+
+            let (Some(path), Some(name)) =
+                (LuaCompleter::index_path(&index), index.get_name_token())
+            else {
+                continue;
+            };
+
+            self.fields
+                .entry(path.join("."))
+                .or_default()
+                .insert(name.get_name_text().to_string());
+        }
+    }
+
+    /// Counts the names of `line` only, for history whose assignments ran in earlier
+    /// sessions
+    pub fn record_usage(&mut self, line: &str) {
+        for score in self.scores.values_mut() {
+            *score *= USAGE_DECAY;
+        }
+
+        let names = line
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .filter(|name| !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()));
+
+        for name in names {
+            *self.scores.entry(name.to_string()).or_default() += 1.0;
+        }
+    }
+
+    /// Fields assigned to `path` by earlier lines, like `foo` after `t.foo = 1`
+    fn assigned_fields(&self, path: &[String]) -> Vec<String> {
+        self.fields
+            .get(&path.join("."))
+            .map(|fields| fields.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn score(&self, name: &str) -> f64 {
+        // dotted library fields count by their last name
+        let name = name.rsplit('.').next().unwrap_or(name);
+
+        self.scores.get(name).copied().unwrap_or_default()
+    }
+}
+
+/// Globals of the session shared by the completer and highlighter, kept between
+/// keystrokes since reading them is a round trip on system and remote executors.
+/// `Editor` refreshes them after each evaluation, while nobody is typing, as the
+/// sessions of system executors only work from the thread that started them
+#[derive(Clone)]
+pub struct GlobalsCache {
+    lua_executor: Arc<dyn LuaExecutor>,
+    globals: Arc<Mutex<Option<LuaTable>>>,
+}
+
+impl GlobalsCache {
+    pub fn new(lua_executor: Arc<dyn LuaExecutor>) -> Self {
+        Self {
+            lua_executor,
+            globals: Arc::default(),
+        }
+    }
+
+    /// Fetches the globals again, starting the executor if it wasn't
+    pub fn refresh(&self) {
+        // the last ones are better than none while a process restarts
+        if let Ok(globals) = self.lua_executor.globals() {
+            *self.globals.lock().unwrap() = Some(globals);
+        }
+    }
+
+    /// The globals last refreshed, never waiting on the executor
+    pub fn get(&self) -> Option<LuaTable> {
+        // the table of a state in this process is live, it never goes stale
+        if self.lua_executor.lua().is_some() {
+            return self.lua_executor.globals().ok();
+        }
+
+        self.globals.lock().unwrap().clone()
+    }
+}
+
 pub struct LuaCompleter {
     lua_executor: Arc<dyn LuaExecutor>,
     tree: LuaSyntaxTree,
 
     scopes: Vec<Scope>,
     text: String,
+    /// Trees of buffers completed before, most recent first, reused when editing goes
+    /// back to one as deleting what was just typed does
+    recent: VecDeque<(String, LuaSyntaxTree, Vec<Scope>)>,
+    #[cfg(test)]
+    parses: usize,
+
+    fuzzy: bool,
+    smart_case: bool,
+    session: Arc<Mutex<SessionIndex>>,
+
+    max_suggestions: Option<usize>,
+    min_query_length: usize,
+    dedupe_shadowed: bool,
+
+    budget: Option<Duration>,
+    deadline: Option<Instant>,
+    partial: Cell<bool>,
+    loading: Loading,
+    cache: GlobalsCache,
+    /// Globals read by the completion in progress, `Some(None)` when there were none
+    globals: RefCell<Option<Option<LuaTable>>>,
 }
 
+/// Line of the last completion that ran out of time, for the hinter to say more is
+/// coming rather than a suggestion the menu would let be picked
+pub type Loading = Arc<Mutex<Option<String>>>;
+
 impl LuaCompleter {
     pub fn new(lua_executor: Arc<dyn LuaExecutor>) -> Self {
         Self {
+            cache: GlobalsCache::new(lua_executor.clone()),
             lua_executor,
             tree: LuaParser::parse("", parse::config()),
             scopes: Vec::new(),
             text: String::new(),
+            recent: VecDeque::new(),
+            #[cfg(test)]
+            parses: 0,
+            fuzzy: false,
+            smart_case: false,
+            session: Arc::default(),
+            max_suggestions: None,
+            min_query_length: 0,
+            dedupe_shadowed: true,
+            budget: None,
+            deadline: None,
+            partial: Cell::new(false),
+            loading: Loading::default(),
+            globals: RefCell::new(None),
         }
     }
 
-    fn refresh_tree(&mut self, text: &str) {
-        self.tree = LuaParser::parse(text, parse::config());
-        self.text = text.to_string();
-        self.scopes = self.resolve_scopes();
+    /// Match candidates by subsequence instead of prefix, best matches first
+    pub fn with_fuzzy(mut self, fuzzy: bool) -> Self {
+        self.fuzzy = fuzzy;
+        self
     }
 
-    fn globals(&self) -> Vec<String> {
-        if let Ok(globals) = self.lua_executor.globals() {
-            globals
-                .pairs()
-                .flatten()
-                .map(|(k, _): (String, LuaValue)| k)
-                .collect()
-        } else {
-            Vec::new()
+    /// Lowercase queries ignore case, queries with an uppercase letter match it exactly
+    pub fn with_smart_case(mut self, smart_case: bool) -> Self {
+        self.smart_case = smart_case;
+        self
+    }
+
+    /// Rank equally good matches by how often they were submitted before
+    pub fn with_session(mut self, session: Arc<Mutex<SessionIndex>>) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Show at most `max` suggestions, `None` for no limit
+    pub fn with_max_suggestions(mut self, max: Option<usize>) -> Self {
+        self.max_suggestions = max;
+        self
+    }
+
+    /// Only complete names once this many characters are typed, fields after `.` or `:`
+    /// are always listed
+    pub fn with_min_query_length(mut self, length: usize) -> Self {
+        self.min_query_length = length;
+        self
+    }
+
+    /// List a global shadowed by a local once as the local, or else under both origins
+    pub fn with_dedupe_shadowed(mut self, dedupe: bool) -> Self {
+        self.dedupe_shadowed = dedupe;
+        self
+    }
+
+    /// Stop executor lookups after `budget`, returning what was gathered by then
+    pub fn with_budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Read globals from `cache`, shared with the highlighter
+    pub fn with_globals(mut self, cache: GlobalsCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Set to the line whenever a completion runs out of time, see `LuaHinter::with_loading`
+    pub fn with_loading(mut self, loading: Loading) -> Self {
+        self.loading = loading;
+        self
+    }
+
+    /// If the last completion ran out of time before it finished
+    pub fn is_partial(&self) -> bool {
+        self.partial.get()
+    }
+
+    /// Checked between executor lookups, marks the result as partial once true
+    fn exhausted(&self) -> bool {
+        let exhausted = self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline);
+
+        if exhausted {
+            self.partial.set(true);
         }
+
+        exhausted
     }
 
-    fn resolve_scopes(&self) -> Vec<Scope> {
-        let mut scopes = Vec::new();
+    /// Globals of the executor, read once per completion from the cache so it doesn't
+    /// wait on the executor. Lookups after the budget ran out are skipped
+    fn session_globals(&self) -> Option<LuaTable> {
+        let mut globals = self.globals.borrow_mut();
 
-        let chunk = self.tree.get_chunk_node();
+        if globals.is_none() && self.exhausted() {
+            return None;
+        }
 
-        for scope in chunk.descendants::<LuaBlock>() {
-            let mut variables = Vec::new();
-
-            match scope.get_parent() {
-                Some(LuaAst::LuaClosureExpr(closure)) => {
-                    if let Some(params) = closure.get_params_list() {
-                        for param in params.get_params() {
-                            if let Some(token) = param.get_name_token() {
-                                variables.push(Variable {
-                                    range: param.get_range(),
-                                    name: token.get_name_text().to_string(),
-                                });
-                            }
-                        }
-                    }
+        globals
+            .get_or_insert_with(|| {
+                // asking for completions starts the executor, unlike highlighting
+                if !self.lua_executor.is_started() {
+                    self.cache.refresh();
                 }
-                Some(LuaAst::LuaForRangeStat(range)) => {
-                    for token in range.get_var_name_list() {
-                        variables.push(Variable {
-                            range: token.get_range(),
-                            name: token.get_name_text().to_string(),
-                        })
-                    }
-                }
-                Some(LuaAst::LuaForStat(stat)) => {
-                    if let Some(token) = stat.get_var_name() {
-                        variables.push(Variable {
-                            range: token.get_range(),
-                            name: token.get_name_text().to_string(),
-                        });
-                    }
-                }
-                _ => {}
-            }
 
-            for node in scope.children::<LuaAst>() {
-                match node {
-                    LuaAst::LuaLocalFuncStat(stat) => {
-                        if let Some(name) = stat.get_local_name() {
-                            if let Some(token) = name.get_name_token() {
-                                variables.push(Variable {
-                                    range: token.get_range(),
-                                    name: token.get_name_text().to_string(),
-                                });
-                            }
-                        }
-                    }
-                    LuaAst::LuaLocalStat(stat) => {
-                        for name in stat.get_local_name_list() {
-                            if let Some(token) = name.get_name_token() {
-                                variables.push(Variable {
-                                    range: stat.get_range(),
-                                    name: token.get_name_text().to_string(),
-                                });
-                            }
-                        }
+                self.cache.get()
+            })
+            .clone()
+    }
+
+    fn filter_matches(
+        &self,
+        query: &str,
+        candidates: impl IntoIterator<Item = String>,
+    ) -> Vec<String> {
+        let has_upper = query.chars().any(char::is_uppercase);
+        let ignore_case = self.smart_case && !has_upper;
+        let exact_case = self.smart_case && has_upper;
+
+        let mut matches = candidates
+            .into_iter()
+            .filter_map(|s| {
+                let score = if self.fuzzy {
+                    // fuzzy scores ignore case, so an exact subsequence is checked apart
+                    let mut chars = s.chars();
+
+                    if exact_case && !query.chars().all(|q| chars.any(|c| c == q)) {
+                        return None;
                     }
-                    _ => {}
+
+                    fuzzy_score(query, &s)?
+                } else if s.starts_with(query)
+                    || (ignore_case && s.to_lowercase().starts_with(query))
+                {
+                    0
+                } else {
+                    return None;
+                };
+
+                Some((score, s))
+            })
+            .collect::<Vec<_>>();
+
+        let session = self.session.lock().unwrap();
+
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| session.score(b).total_cmp(&session.score(a)))
+                .then_with(|| a.cmp(b))
+        });
+
+        matches.into_iter().map(|(_, s)| s).collect()
+    }
+
+    /// `name.field` for every table global, so fuzzy queries can reach library functions
+    fn library_fields(&self) -> Vec<String> {
+        let Some(globals) = self.session_globals() else {
+            return Vec::new();
+        };
+
+        globals
+            .pairs::<String, LuaValue>()
+            .flatten()
+            .filter(|(name, _)| name != "_G")
+            .take_while(|_| !self.exhausted())
+            .filter_map(|(name, value)| match value {
+                LuaValue::Table(tbl) => Some(
+                    tbl.pairs::<String, LuaValue>()
+                        .flatten()
+                        .map(|(field, _)| format!("{name}.{field}"))
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn refresh_tree(&mut self, text: &str) {
+        // the menu asks again for the same buffer while cycling through suggestions
+        if self.text == text {
+            return;
+        }
+
+        let (tree, scopes) = match self.recent.iter().position(|(recent, ..)| recent == text) {
+            Some(index) => {
+                let (_, tree, scopes) = self.recent.remove(index).expect("recent tree");
+
+                (tree, scopes)
+            }
+            None => {
+                #[cfg(test)]
+                {
+                    self.parses += 1;
                 }
+
+                let tree = LuaParser::parse(text, parse::config());
+                let scopes = resolve_scopes(&tree);
+
+                (tree, scopes)
             }
+        };
 
-            scopes.push(Scope {
-                range: scope.get_range(),
-                variables,
-            });
-        }
+        let text = mem::replace(&mut self.text, text.to_string());
+        let tree = mem::replace(&mut self.tree, tree);
+        let scopes = mem::replace(&mut self.scopes, scopes);
 
-        scopes
+        self.recent.push_front((text, tree, scopes));
+        self.recent.truncate(RECENT_TREES);
+    }
+
+    fn globals(&self) -> Vec<String> {
+        if let Some(globals) = self.session_globals() {
+            globals
+                .pairs()
+                .flatten()
+                .take_while(|_| !self.exhausted())
+                .map(|(k, _): (String, LuaValue)| k)
+                .collect()
+        } else {
+            Vec::new()
+        }
     }
 
     fn locals(&self, position: u32) -> Vec<String> {
@@ -196,15 +578,78 @@ impl LuaCompleter {
     fn autocomplete_upvalue(&self, query: &str, position: u32) -> Vec<String> {
         let mut upvalues = self.locals(position);
         upvalues.extend(self.globals());
-        upvalues.sort();
 
-        upvalues
-            .into_iter()
-            .filter(|s| s.starts_with(query))
-            .collect()
+        self.filter_matches(query, upvalues)
+    }
+
+    fn keywords(&self, query: &str, identifier: &LuaNameExpr) -> Vec<String> {
+        let start = identifier.get_range().start();
+
+        // a name that begins its statement can be replaced by any statement keyword,
+        // anywhere else only keywords that start an expression make sense
+        let is_statement_start = identifier
+            .syntax()
+            .ancestors()
+            .find_map(LuaStat::cast)
+            .is_none_or(|stat| stat.get_range().start() == start);
+
+        let mut keywords = EXPRESSION_KEYWORDS.to_vec();
+
+        if is_statement_start {
+            keywords.extend(STATEMENT_KEYWORDS);
+            keywords.sort();
+            keywords.dedup();
+        }
+
+        self.filter_matches(query, keywords.into_iter().map(String::from))
+    }
+
+    /// Metamethod names when `identifier` is a key being typed in a metatable constructor
+    fn metamethods(&self, query: &str, identifier: &LuaNameExpr) -> Vec<String> {
+        let is_metatable = identifier
+            .syntax()
+            .parent()
+            .and_then(LuaTableField::cast)
+            .and_then(|field| field.syntax().parent())
+            .and_then(LuaTableExpr::cast)
+            .is_some_and(|table| is_metatable_constructor(&table));
+
+        if !is_metatable {
+            return Vec::new();
+        }
+
+        self.filter_matches(query, METAMETHODS.iter().map(|s| s.to_string()))
+    }
+
+    pub fn index_path(index: &LuaIndexExpr) -> Option<Vec<String>> {
+        let mut path = Vec::new();
+        let mut prefix = index.get_prefix_expr()?;
+
+        loop {
+            match prefix {
+                LuaExpr::IndexExpr(inner) => {
+                    path.push(inner.get_name_token()?.get_name_text().to_string());
+                    prefix = inner.get_prefix_expr()?;
+                }
+                LuaExpr::NameExpr(name) => {
+                    path.push(name.get_name_text()?);
+                    break;
+                }
+                _ => return None,
+            }
+        }
+
+        path.reverse();
+
+        Some(path)
     }
 
-    fn table_index(&self, position: u32) -> Option<(TextRange, Vec<String>)> {
+    /// Range, matching fields, path and resolved table of the index expression at
+    /// `position`, the table is kept so describing the fields doesn't resolve it again
+    fn table_index(
+        &self,
+        position: u32,
+    ) -> Option<(TextRange, Vec<String>, Vec<String>, Option<LuaTable>)> {
         let chunk = self.tree.get_chunk_node();
 
         for index in chunk.descendants::<LuaIndexExpr>() {
@@ -212,63 +657,62 @@ impl LuaCompleter {
                 .get_index_key()
                 .map(|k| k.get_range().map(|r| (r, k.get_path_part(), false)))
                 .unwrap_or_else(|| {
-                    index.token_by_kind(LuaTokenKind::TkDot).map(|t| {
-                        let range = t.get_range();
-                        (
-                            TextRange::new(range.start(), range.start() + TextSize::new(1)),
-                            String::new(),
-                            true,
-                        )
-                    })
+                    index
+                        .token_by_kind(LuaTokenKind::TkDot)
+                        .or_else(|| index.token_by_kind(LuaTokenKind::TkColon))
+                        .map(|t| {
+                            let range = t.get_range();
+                            (
+                                TextRange::new(range.start(), range.start() + TextSize::new(1)),
+                                String::new(),
+                                true,
+                            )
+                        })
                 })?;
 
             if position >= range.start().into() && position < range.end().into() {
-                let mut children: Vec<String> = Vec::new();
-
-                for parent_index in index.descendants::<LuaIndexExpr>() {
-                    if let Some(token) = parent_index.get_name_token() {
-                        children.push(token.get_name_text().to_string());
-                    }
-
-                    if let Some(LuaExpr::NameExpr(token)) = parent_index.get_prefix_expr() {
-                        children.push(token.get_name_text()?);
+                let (table, path) = match Self::index_path(&index) {
+                    Some(path) => (self.resolve_fields(&path), path),
+                    None if is_string_expr(&index.get_prefix_expr()?) => {
+                        (self.string_methods(), vec![String::from("string")])
                     }
-                }
-
-                if children.len() > 1 {
-                    children.reverse();
-                    children.pop();
-                }
+                    None => return None,
+                };
 
-                let fields = if let Ok(globals) = self.lua_executor.globals() {
-                    let mut var: LuaResult<LuaValue> = Ok(LuaValue::Table(globals));
+                // `obj:` only offers fields that can be called as methods
+                let is_method = index.token_by_kind(LuaTokenKind::TkColon).is_some();
+
+                let mut seen = HashSet::new();
+
+                // fields shadow those of the same name further up the `__index` chain
+                let mut fields = table
+                    .as_ref()
+                    .map(|tbl| {
+                        index_chain(&tbl)
+                            .into_iter()
+                            .flat_map(|tbl| tbl.pairs().flatten().collect::<Vec<_>>())
+                            .filter(|(k, _): &(String, LuaValue)| seen.insert(k.clone()))
+                            .filter(|(_, v)| !is_method || is_callable(v))
+                            .map(|(k, _)| k)
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
 
-                    for index in children.iter().rev() {
-                        if let Ok(LuaValue::Table(tbl)) = var {
-                            var = tbl.raw_get(index.as_str())
-                        }
-                    }
+                // assignments from earlier lines cover tables that can't be enumerated
+                let assigned = self.session.lock().unwrap().assigned_fields(&path);
+                fields.extend(assigned.into_iter().filter(|k| seen.insert(k.clone())));
 
-                    if let Ok(LuaValue::Table(tbl)) = var {
-                        tbl.pairs()
-                            .flatten()
-                            .map(|(k, _): (String, LuaValue)| k)
-                            .filter(|s| s.starts_with(&name))
-                            .collect::<Vec<_>>()
-                    } else {
-                        Vec::new()
-                    }
-                } else {
-                    Vec::new()
-                };
+                let fields = self.filter_matches(&name, fields);
 
                 if is_dot {
                     return Some((
                         TextRange::new(range.start() + TextSize::new(1), range.end()),
                         fields,
+                        path,
+                        table,
                     ));
                 } else {
-                    return Some((range, fields));
+                    return Some((range, fields, path, table));
                 }
             }
         }
@@ -276,7 +720,138 @@ impl LuaCompleter {
         None
     }
 
-    fn current_identifier(&self, position: u32) -> Option<(TextRange, String)> {
+    /// Table whose fields complete after `path`, strings complete with their methods
+    fn resolve_fields(&self, path: &[String]) -> Option<LuaTable> {
+        match self.lua_executor.resolve(path)? {
+            LuaValue::Table(tbl) => Some(tbl),
+            LuaValue::String(_) => self.string_methods(),
+            _ => None,
+        }
+    }
+
+    /// `__index` of the string metatable, falling back to the `string` library when the
+    /// executor's globals are a snapshot without callable functions
+    fn string_methods(&self) -> Option<LuaTable> {
+        let globals = self.session_globals()?;
+
+        globals
+            .raw_get::<LuaFunction>("getmetatable")
+            .and_then(|getmetatable| getmetatable.call::<LuaTable>(""))
+            .and_then(|metatable| metatable.raw_get::<LuaTable>("__index"))
+            .or_else(|_| globals.raw_get("string"))
+            .ok()
+    }
+
+    /// Parameter names registered by profiles, keyed by full path
+    fn signatures(&self) -> Option<LuaTable> {
+        self.session_globals()?.raw_get("_MANEN_SIGNATURES").ok()
+    }
+
+    /// Signature of `field` after `path` in the table `signatures` returned
+    fn signature(signatures: &LuaTable, path: &[String], field: &str) -> Option<String> {
+        let key = format!("{}.{field}", path.join("."));
+        let params: String = signatures.raw_get(key).ok()?;
+
+        Some(format!("{field}({params})"))
+    }
+
+    /// Range, text so far and callee when the cursor is inside a string passed as the
+    /// first argument of a call like `require("...` or `io.open "...`
+    fn string_argument(&self, position: u32) -> Option<(TextRange, String, String)> {
+        let chunk = self.tree.get_chunk_node();
+        let token = chunk
+            .syntax()
+            .token_at_offset(TextSize::new(position.saturating_sub(1)))
+            .right_biased()?;
+
+        if !matches!(token.kind(), LuaKind::Token(LuaTokenKind::TkString)) {
+            return None;
+        }
+
+        let previous = |token: &LuaSyntaxToken| {
+            let mut prev = token.prev_token()?;
+
+            while matches!(
+                prev.kind(),
+                LuaKind::Token(LuaTokenKind::TkWhitespace | LuaTokenKind::TkEndOfLine)
+            ) {
+                prev = prev.prev_token()?;
+            }
+
+            Some(prev)
+        };
+
+        let mut prev = previous(&token)?;
+
+        if matches!(prev.kind(), LuaKind::Token(LuaTokenKind::TkLeftParen)) {
+            prev = previous(&prev)?;
+        }
+
+        if !matches!(prev.kind(), LuaKind::Token(LuaTokenKind::TkName)) {
+            return None;
+        }
+
+        let mut callee = prev.text().to_string();
+
+        // walk back through `a.b.c` to get the full name of the function
+        while let Some(separator) = previous(&prev).filter(|t| {
+            matches!(
+                t.kind(),
+                LuaKind::Token(LuaTokenKind::TkDot | LuaTokenKind::TkColon)
+            )
+        }) {
+            prev = previous(&separator)
+                .filter(|t| matches!(t.kind(), LuaKind::Token(LuaTokenKind::TkName)))?;
+            callee = format!("{}{}{callee}", prev.text(), separator.text());
+        }
+
+        let range = token.text_range();
+        let text = token.text();
+        let start = range.start() + TextSize::new(1);
+
+        // a terminated string only completes up to its closing quote
+        let closed = text.len() > 1 && text.ends_with(&text[..1]);
+
+        if position < start.into() || (closed && position >= u32::from(range.end())) {
+            return None;
+        }
+
+        let range = TextRange::new(start, TextSize::new(position));
+        let query = self
+            .text
+            .get(usize::from(range.start())..usize::from(range.end()))?;
+
+        Some((range, query.to_string(), callee))
+    }
+
+    fn modules(&self, query: &str) -> Vec<String> {
+        let mut templates = Vec::new();
+
+        if let Some(package) = self
+            .session_globals()
+            .and_then(|g| g.get::<LuaTable>("package").ok())
+        {
+            for key in ["path", "cpath"] {
+                if let Ok(path) = package.get::<String>(key) {
+                    templates.extend(path.split(';').map(String::from));
+                }
+            }
+        }
+
+        templates.extend(LOCAL_TEMPLATES.iter().map(|s| s.to_string()));
+
+        let mut modules = templates
+            .iter()
+            .flat_map(|template| search_template(template, query))
+            .collect::<Vec<_>>();
+
+        modules.sort();
+        modules.dedup();
+
+        modules
+    }
+
+    fn current_identifier(&self, position: u32) -> Option<(TextRange, String, LuaNameExpr)> {
         let chunk = self.tree.get_chunk_node();
 
         for identifier in chunk.descendants::<LuaNameExpr>() {
@@ -284,7 +859,7 @@ impl LuaCompleter {
 
             if position >= range.start().into() && position < range.end().into() {
                 if let Some(name) = identifier.get_name_text() {
-                    return Some((range, name));
+                    return Some((range, name, identifier));
                 } else {
                     return None;
                 }
@@ -295,48 +870,897 @@ impl LuaCompleter {
     }
 }
 
-impl Completer for LuaCompleter {
-    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
-        let pos = pos as u32;
-        self.refresh_tree(line);
-
-        if let Some((range, current)) = self.current_identifier(pos.saturating_sub(1)) {
-            return self
-                .autocomplete_upvalue(&current, pos)
-                .into_iter()
-                .map(|s| Suggestion {
-                    value: s,
-                    span: Span::new(range.start().into(), range.end().into()),
-                    ..Default::default()
-                })
-                .collect();
-        }
+pub fn resolve_path(globals: LuaTable, path: &[String]) -> Option<LuaValue> {
+    let mut var = LuaValue::Table(globals);
 
-        if let Some((range, fields)) = self.table_index(pos.saturating_sub(1)) {
-            return fields
-                .into_iter()
-                .map(|s| Suggestion {
-                    value: s,
-                    span: Span::new(range.start().into(), range.end().into()),
-                    ..Default::default()
-                })
-                .collect();
+    for index in path.iter() {
+        if let LuaValue::Table(tbl) = var {
+            var = tbl.raw_get(index.as_str()).ok()?;
         }
-
-        Vec::new()
     }
+
+    Some(var)
 }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+/// Blocks of `tree` with the variables each declares, the chunk's own block first
+fn resolve_scopes(tree: &LuaSyntaxTree) -> Vec<Scope> {
+    let mut scopes = Vec::new();
 
-    use crate::lua::MluaExecutor;
+    let chunk = tree.get_chunk_node();
 
-    use super::*;
+    for scope in chunk.descendants::<LuaBlock>() {
+        let mut variables = Vec::new();
 
-    fn lua_executor() -> Arc<dyn LuaExecutor> {
-        Arc::new(MluaExecutor::new())
+        match scope.get_parent() {
+            Some(LuaAst::LuaClosureExpr(closure)) => {
+                if let Some(params) = closure.get_params_list() {
+                    for param in params.get_params() {
+                        if let Some(token) = param.get_name_token() {
+                            variables.push(Variable {
+                                range: param.get_range(),
+                                name: token.get_name_text().to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+            Some(LuaAst::LuaForRangeStat(range)) => {
+                for token in range.get_var_name_list() {
+                    variables.push(Variable {
+                        range: token.get_range(),
+                        name: token.get_name_text().to_string(),
+                    })
+                }
+            }
+            Some(LuaAst::LuaForStat(stat)) => {
+                if let Some(token) = stat.get_var_name() {
+                    variables.push(Variable {
+                        range: token.get_range(),
+                        name: token.get_name_text().to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        for node in scope.children::<LuaAst>() {
+            match node {
+                LuaAst::LuaLocalFuncStat(stat) => {
+                    if let Some(name) = stat.get_local_name() {
+                        if let Some(token) = name.get_name_token() {
+                            variables.push(Variable {
+                                range: token.get_range(),
+                                name: token.get_name_text().to_string(),
+                            });
+                        }
+                    }
+                }
+                LuaAst::LuaLocalStat(stat) => {
+                    for name in stat.get_local_name_list() {
+                        if let Some(token) = name.get_name_token() {
+                            variables.push(Variable {
+                                range: stat.get_range(),
+                                name: token.get_name_text().to_string(),
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        scopes.push(Scope {
+            range: scope.get_range(),
+            variables,
+        });
+    }
+
+    scopes
+}
+
+/// If `expr` is assigned to, as in `t.foo = 1` or `function t:foo() end`
+fn is_assignment_target(expr: &impl LuaAstNode) -> bool {
+    let Some(parent) = expr.syntax().parent() else {
+        return false;
+    };
+
+    match parent.kind() {
+        LuaKind::Syntax(LuaSyntaxKind::FuncStat) => true,
+        // targets come before the `=`, values after it
+        LuaKind::Syntax(LuaSyntaxKind::AssignStat) => parent
+            .children_with_tokens()
+            .find(|element| element.kind() == LuaKind::Token(LuaTokenKind::TkAssign))
+            .is_some_and(|assign| expr.syntax().text_range().end() <= assign.text_range().start()),
+        _ => false,
+    }
+}
+
+/// If `table` becomes a metatable, as in `setmetatable(t, { ... })` or `local mt = { ... }`
+fn is_metatable_constructor(table: &LuaTableExpr) -> bool {
+    let Some(parent) = table.syntax().parent() else {
+        return false;
+    };
+
+    match parent.kind() {
+        // the second argument of setmetatable
+        LuaKind::Syntax(LuaSyntaxKind::CallArgList) => {
+            let is_second = parent
+                .children()
+                .filter_map(LuaExpr::cast)
+                .position(|arg| arg.syntax() == table.syntax())
+                == Some(1);
+
+            is_second
+                && parent
+                    .parent()
+                    .and_then(LuaCallExpr::cast)
+                    .and_then(|call| call.get_prefix_expr())
+                    .is_some_and(|prefix| match prefix {
+                        LuaExpr::NameExpr(name) => {
+                            name.get_name_text().as_deref() == Some("setmetatable")
+                        }
+                        _ => false,
+                    })
+        }
+        // assigned to a name like `mt`, `class_mt` or `metatable`
+        LuaKind::Syntax(LuaSyntaxKind::LocalStat | LuaSyntaxKind::AssignStat) => parent
+            .descendants_with_tokens()
+            .filter_map(|element| element.into_token())
+            .take_while(|token| token.kind() != LuaKind::Token(LuaTokenKind::TkAssign))
+            .filter(|token| token.kind() == LuaKind::Token(LuaTokenKind::TkName))
+            .any(|token| {
+                let name = token.text().to_lowercase();
+
+                name == "mt" || name.ends_with("_mt") || name.contains("meta")
+            }),
+        _ => false,
+    }
+}
+
+/// `tbl` followed by every table reached through `__index` metatables, so objects
+/// include the methods of their classes
+fn index_chain(tbl: &LuaTable) -> Vec<LuaTable> {
+    let mut chain = vec![tbl.clone()];
+
+    while let Some(LuaValue::Table(index)) = chain
+        .last()
+        .and_then(|tbl| tbl.metatable())
+        .and_then(|metatable| metatable.raw_get("__index").ok())
+    {
+        // a class can set itself as `__index` of its own metatable
+        if chain.iter().any(|t| t.to_pointer() == index.to_pointer()) {
+            break;
+        }
+
+        chain.push(index);
+    }
+
+    chain
+}
+
+/// Ranges of `a.b` field names missing from the session's table `a`
+///
+/// Tables with a metatable may compute fields so they are never flagged, neither is a
+/// name under the cursor that is still the prefix of an existing field nor one being
+/// assigned, as `t.new = 1` adds it
+pub fn unknown_fields(globals: &LuaTable, tree: &LuaSyntaxTree, cursor: u32) -> Vec<TextRange> {
+    let chunk = tree.get_chunk_node();
+
+    let indexes = chunk
+        .descendants::<LuaIndexExpr>()
+        .filter(|index| index.token_by_kind(LuaTokenKind::TkDot).is_some())
+        .filter(|index| !is_assignment_target(index))
+        .collect::<Vec<_>>();
+
+    indexes
+        .into_iter()
+        .filter_map(|index| {
+            let token = index.get_name_token()?;
+            let name = token.get_name_text();
+            let path = LuaCompleter::index_path(&index)?;
+
+            let LuaValue::Table(tbl) = resolve_path(globals.clone(), &path)? else {
+                return None;
+            };
+
+            if tbl.metatable().is_some() || !tbl.raw_get::<LuaValue>(name).ok()?.is_nil() {
+                return None;
+            }
+
+            let range = token.get_range();
+
+            if u32::from(range.end()) == cursor
+                && tbl
+                    .pairs::<String, LuaValue>()
+                    .flatten()
+                    .any(|(k, _)| k.starts_with(name))
+            {
+                return None;
+            }
+
+            Some(range)
+        })
+        .collect()
+}
+
+/// What a name in the code refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Local,
+    /// Local of an enclosing function
+    Upvalue,
+    Global,
+    /// Global from the standard library of the session's Lua
+    Builtin,
+    /// Global the session does not have, also not assigned by the code
+    UnknownGlobal,
+}
+
+/// Names declared by the top-level statements of `tree`, with the offset each becomes
+/// visible from
+pub fn chunk_locals(tree: &LuaSyntaxTree) -> Vec<(u32, String)> {
+    resolve_scopes(tree)
+        .into_iter()
+        .next()
+        .map(|scope| {
+            scope
+                .variables
+                .into_iter()
+                .map(|var| (u32::from(var.range.end()), var.name))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Range and binding of each name read or assigned in `tree`, `outer` are locals
+/// declared before the code such as earlier statements of the same input
+///
+/// Like fields, a global being typed under the cursor is not unknown while it is the
+/// prefix of an existing one, nor is any global once the session's globals have a
+/// metatable
+pub fn bindings(
+    globals: &LuaTable,
+    tree: &LuaSyntaxTree,
+    outer: &[String],
+    cursor: u32,
+) -> Vec<(TextRange, Binding)> {
+    let chunk = tree.get_chunk_node();
+    let scopes = resolve_scopes(tree);
+
+    let closures = chunk
+        .descendants::<LuaClosureExpr>()
+        .map(|closure| closure.get_range())
+        .collect::<Vec<_>>();
+
+    chunk
+        .descendants::<LuaNameExpr>()
+        .filter_map(|expr| {
+            let token = expr.get_name_token()?;
+            let name = token.get_name_text();
+            let range = token.get_range();
+            let position = range.start();
+
+            // implicit, `self` of methods and `_ENV` of Lua 5.2+
+            if name == "self" || name == "_ENV" {
+                return None;
+            }
+
+            // nested blocks start later
+            let scope = scopes
+                .iter()
+                .filter(|scope| {
+                    scope.range.contains(position)
+                        && scope
+                            .variables
+                            .iter()
+                            .any(|var| var.name == name && var.range.end() <= position)
+                })
+                .max_by_key(|scope| scope.range.start());
+
+            let in_closure = |start: TextSize| {
+                closures
+                    .iter()
+                    .any(|closure| closure.start() >= start && closure.contains(position))
+            };
+
+            let binding = match scope {
+                Some(scope) if in_closure(scope.range.start()) => Binding::Upvalue,
+                Some(_) => Binding::Local,
+                None if outer.iter().any(|local| local == name) => {
+                    if in_closure(TextSize::from(0)) {
+                        Binding::Upvalue
+                    } else {
+                        Binding::Local
+                    }
+                }
+                None => {
+                    let is_set = globals.raw_get::<LuaValue>(name).is_ok_and(|v| !v.is_nil());
+
+                    let is_unknown = globals.metatable().is_none()
+                        && !is_set
+                        && !is_assignment_target(&expr)
+                        && (u32::from(range.end()) != cursor
+                            || !globals
+                                .pairs::<String, LuaValue>()
+                                .flatten()
+                                .any(|(k, _)| k.starts_with(name)));
+
+                    if is_unknown {
+                        Binding::UnknownGlobal
+                    } else if is_set && STANDARD_GLOBALS.contains(&name) {
+                        Binding::Builtin
+                    } else {
+                        Binding::Global
+                    }
+                }
+            };
+
+            Some((range, binding))
+        })
+        .collect()
+}
+
+/// Edits of one character that turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != *cb);
+
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Names `tree` assigns inside a function without a local of that name in scope, which
+/// makes them globals once the function runs, each with a similar local it could have
+/// meant. Globals the chunk itself assigns outside of functions are meant to be global
+pub fn function_globals(tree: &LuaSyntaxTree) -> Vec<(String, Option<String>)> {
+    let chunk = tree.get_chunk_node();
+    let scopes = resolve_scopes(tree);
+
+    let closures = chunk
+        .descendants::<LuaClosureExpr>()
+        .map(|closure| closure.get_range())
+        .collect::<Vec<_>>();
+
+    let visible = |position: TextSize| {
+        scopes
+            .iter()
+            .filter(|scope| scope.range.contains(position))
+            .flat_map(|scope| &scope.variables)
+            .filter(|var| var.range.end() <= position)
+            .collect::<Vec<_>>()
+    };
+
+    let targets = chunk
+        .descendants::<LuaNameExpr>()
+        .filter(|expr| is_assignment_target(expr))
+        .filter_map(|expr| {
+            let token = expr.get_name_token()?;
+            let position = token.get_range().start();
+
+            Some((token.get_name_text().to_string(), position))
+        })
+        .filter(|(name, position)| {
+            name != "_ENV" && !visible(*position).iter().any(|var| &var.name == name)
+        })
+        .collect::<Vec<_>>();
+
+    let is_in_function =
+        |position: &TextSize| closures.iter().any(|closure| closure.contains(*position));
+
+    let top_level = targets
+        .iter()
+        .filter(|(_, position)| !is_in_function(position))
+        .map(|(name, _)| name)
+        .collect::<HashSet<_>>();
+
+    let mut found: Vec<(String, Option<String>)> = Vec::new();
+
+    for (name, position) in &targets {
+        if !is_in_function(position)
+            || top_level.contains(name)
+            || found.iter().any(|(found, _)| found == name)
+        {
+            continue;
+        }
+
+        // a short name is a few edits from any other
+        let similar = visible(*position)
+            .iter()
+            .map(|var| (edit_distance(name, &var.name), &var.name))
+            .filter(|(distance, _)| *distance <= 2 && *distance < name.len())
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, similar)| similar.clone());
+
+        found.push((name.clone(), similar));
+    }
+
+    found
+}
+
+/// Subsequence match score of `query` in `candidate`, `None` if a character is missing
+///
+/// Runs of consecutive characters and characters starting a word score higher, skipped
+/// characters between matches cost a point each
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let chars = candidate.chars().collect::<Vec<_>>();
+    let mut score = 0;
+    let mut position = 0;
+
+    for (i, q) in query.chars().enumerate() {
+        let index = (position..chars.len())
+            .find(|&j| chars[j].to_ascii_lowercase() == q.to_ascii_lowercase())?;
+
+        score += 1;
+
+        if i > 0 {
+            if index == position {
+                score += 5;
+            } else {
+                score -= (index - position) as i64;
+            }
+        }
+
+        let word_start = index == 0
+            || matches!(chars[index - 1], '.' | ':' | '_')
+            || (chars[index].is_uppercase() && chars[index - 1].is_lowercase());
+
+        if word_start {
+            score += 3;
+        }
+
+        position = index + 1;
+    }
+
+    Some(score)
+}
+
+/// Type of a value for the completion menu, scalars also show what they hold
+fn describe(value: &LuaValue) -> String {
+    let type_name = match value {
+        LuaValue::Integer(_) | LuaValue::Number(_) => "number",
+        LuaValue::Boolean(_) | LuaValue::String(_) => value.type_name(),
+        value => return value.type_name().to_string(),
+    };
+
+    let mut display = display_basic(value, false);
+
+    if display.chars().count() > 32 {
+        display = display.chars().take(31).chain(['…']).collect();
+    }
+
+    format!("{type_name} = {display}")
+}
+
+fn is_callable(value: &LuaValue) -> bool {
+    match value {
+        LuaValue::Function(_) => true,
+        LuaValue::Table(tbl) => tbl
+            .metatable()
+            .is_some_and(|mt| mt.contains_key("__call").unwrap_or(false)),
+        _ => false,
+    }
+}
+
+fn is_string_expr(expr: &LuaExpr) -> bool {
+    match expr {
+        LuaExpr::LiteralExpr(literal) => {
+            matches!(literal.get_literal(), Some(LuaLiteralToken::String(_)))
+        }
+        LuaExpr::ParenExpr(paren) => paren.get_expr().is_some_and(|e| is_string_expr(&e)),
+        _ => false,
+    }
+}
+
+/// Paths relative to the working directory starting with `query`, directories end in `/`
+fn search_files(query: &str) -> Vec<String> {
+    let (dir, name) = match query.rsplit_once('/') {
+        Some((dir, name)) => (format!("{dir}/"), name),
+        None => (String::new(), query),
+    };
+
+    let search = if dir.is_empty() { "." } else { dir.as_str() };
+
+    let Ok(entries) = fs::read_dir(search) else {
+        return Vec::new();
+    };
+
+    let mut files = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_str()?.to_string();
+
+            // dotfiles only show up once asked for
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+
+            if entry.path().is_dir() {
+                Some(format!("{dir}{file_name}/"))
+            } else {
+                Some(format!("{dir}{file_name}"))
+            }
+        })
+        .collect::<Vec<_>>();
+
+    files.sort();
+
+    files
+}
+
+/// Modules matching `query` that one `package.path` template can load
+///
+/// Everything before the last `.` of the query is a directory, so nested modules such
+/// as `a.b.c` are found one level at a time
+fn search_template(template: &str, query: &str) -> Vec<String> {
+    let Some((prefix, suffix)) = template.split_once('?') else {
+        return Vec::new();
+    };
+
+    let (parent, name) = query.rsplit_once('.').unwrap_or(("", query));
+    let (base, file_prefix) = prefix.rsplit_once('/').unwrap_or((".", prefix));
+
+    let mut dir = Path::new(if base.is_empty() { "/" } else { base }).to_path_buf();
+
+    if !parent.is_empty() {
+        dir.push(parent.replace('.', "/"));
+    }
+
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+
+    let qualify = |module: &str| {
+        if parent.is_empty() {
+            module.to_string()
+        } else {
+            format!("{parent}.{module}")
+        }
+    };
+
+    let mut modules = Vec::new();
+
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        let module = if entry.path().is_dir() {
+            Some(file_name)
+        } else if suffix.contains('/') {
+            None
+        } else {
+            file_name
+                .strip_prefix(file_prefix)
+                .and_then(|s| s.strip_suffix(suffix))
+        };
+
+        if let Some(module) = module {
+            if !module.is_empty() && !module.contains('.') && module.starts_with(name) {
+                modules.push(qualify(module));
+            }
+        }
+    }
+
+    modules
+}
+
+/// Where a suggestion came from, shown as its description and color in the menu
+#[derive(Clone, Copy)]
+enum Origin {
+    Local,
+    Global,
+    Keyword,
+    Field,
+    Module,
+    File,
+    Command,
+    Metamethod,
+    Snippet,
+}
+
+impl Origin {
+    fn label(&self) -> &'static str {
+        match self {
+            Origin::Local => "local",
+            Origin::Global => "global",
+            Origin::Keyword => "keyword",
+            Origin::Field => "field",
+            Origin::Module => "module",
+            Origin::File => "file",
+            Origin::Command => "command",
+            Origin::Metamethod => "metamethod",
+            Origin::Snippet => "snippet",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Origin::Local => Color::LightCyan,
+            Origin::Global => Color::Blue,
+            Origin::Keyword => Color::Purple,
+            Origin::Field => Color::LightGray,
+            Origin::Module => Color::Green,
+            Origin::File => Color::Cyan,
+            Origin::Command => Color::Yellow,
+            Origin::Metamethod => Color::LightPurple,
+            Origin::Snippet => Color::LightGreen,
+        }
+    }
+
+    fn suggestion(
+        self,
+        value: String,
+        description: Option<String>,
+        range: TextRange,
+    ) -> Suggestion {
+        Suggestion {
+            value,
+            description: description.or_else(|| Some(self.label().to_string())),
+            style: Some(Style::new().fg(self.color())),
+            extra: Some(vec![self.label().to_string()]),
+            span: Span::new(range.start().into(), range.end().into()),
+            ..Default::default()
+        }
+    }
+}
+
+impl LuaCompleter {
+    /// Command names after the leading `.`, then whatever their argument takes
+    fn complete_command(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let name_end = line.find(char::is_whitespace).unwrap_or(line.len());
+
+        if pos == 0 {
+            return Vec::new();
+        }
+
+        if pos <= name_end {
+            let range = TextRange::new(TextSize::new(1), TextSize::new(pos as u32));
+            let query = &line[1..pos];
+
+            return commands::COMMANDS
+                .iter()
+                .filter(|(name, _)| name.starts_with(query))
+                .map(|(name, usage)| {
+                    let description = format!(".{name} {usage}");
+                    Origin::Command.suggestion(name.to_string(), Some(description), range)
+                })
+                .collect();
+        }
+
+        let mut start = line[name_end..]
+            .find(|c: char| !c.is_whitespace())
+            .map_or(line.len(), |i| name_end + i);
+
+        // `.time` takes an optional run count before the expression
+        if &line[1..name_end] == "time" {
+            let runs = line[start..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(line.len() - start);
+
+            if runs > 0 && line[start + runs..].starts_with(char::is_whitespace) {
+                start += runs + 1;
+            }
+        }
+
+        if pos < start {
+            return Vec::new();
+        }
+
+        let query = &line[start..pos];
+        let range = TextRange::new(TextSize::new(start as u32), TextSize::new(pos as u32));
+
+        match &line[1..name_end] {
+            "export" => search_files(query)
+                .into_iter()
+                .map(|s| Origin::File.suggestion(s, None, range))
+                .collect(),
+            "language" => Language::value_variants()
+                .iter()
+                .map(|language| language.name())
+                .filter(|name| name.starts_with(query))
+                .map(|name| Origin::Keyword.suggestion(name.to_string(), None, range))
+                .collect(),
+            "snippet" => match query.split_once(char::is_whitespace) {
+                None => ["insert", "save"]
+                    .into_iter()
+                    .filter(|action| action.starts_with(query))
+                    .map(|action| Origin::Keyword.suggestion(action.to_string(), None, range))
+                    .collect(),
+                Some((_, name)) => {
+                    let name = name.trim_start();
+                    let range = TextRange::new(
+                        TextSize::new((pos - name.len()) as u32),
+                        TextSize::new(pos as u32),
+                    );
+
+                    snippets::dir()
+                        .map(|dir| snippets::names(&dir))
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|snippet| snippet.starts_with(name))
+                        .map(|snippet| Origin::Snippet.suggestion(snippet, None, range))
+                        .collect()
+                }
+            },
+            "browse" | "get" | "reveal" | "time" | "expand-macro" => self
+                .complete(&line[start..], pos - start)
+                .into_iter()
+                .map(|mut suggestion| {
+                    suggestion.span.start += start;
+                    suggestion.span.end += start;
+                    suggestion
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Completer for LuaCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        self.deadline = self.budget.map(|budget| Instant::now() + budget);
+        self.partial.set(false);
+        *self.globals.get_mut() = None;
+
+        let mut suggestions = self.complete_within_budget(line, pos);
+
+        if let Some(max) = self.max_suggestions {
+            suggestions.truncate(max);
+        }
+
+        // the menu asks again on the next keystroke, which picks up the rest
+        *self.loading.lock().unwrap() = self.is_partial().then(|| line.to_string());
+
+        suggestions
+    }
+}
+
+impl LuaCompleter {
+    fn complete_within_budget(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        if line.starts_with('.') {
+            return self.complete_command(line, pos);
+        }
+
+        let pos = pos as u32;
+        self.refresh_tree(line);
+
+        if let Some((range, query, callee)) = self.string_argument(pos) {
+            if callee == "require" {
+                return self
+                    .modules(&query)
+                    .into_iter()
+                    .map(|s| Origin::Module.suggestion(s, None, range))
+                    .collect();
+            }
+
+            if PATH_FUNCTIONS.contains(&callee.as_str()) {
+                return search_files(&query)
+                    .into_iter()
+                    .map(|s| Origin::File.suggestion(s, None, range))
+                    .collect();
+            }
+        }
+
+        // grouped as locals, globals then keywords, or ranked by score when fuzzy
+        if let Some((range, current, identifier)) = self.current_identifier(pos.saturating_sub(1)) {
+            if current.chars().count() < self.min_query_length {
+                return Vec::new();
+            }
+
+            let locals = self.locals(pos);
+
+            let (mut local, mut global): (Vec<_>, Vec<_>) = self
+                .autocomplete_upvalue(&current, pos)
+                .into_iter()
+                .partition(|s| locals.contains(s));
+
+            local.dedup();
+            global.dedup();
+
+            if !self.dedupe_shadowed {
+                let globals = self.globals();
+                let shadowed = local.iter().filter(|s| globals.contains(s)).cloned();
+
+                global = self.filter_matches(&current, global.into_iter().chain(shadowed));
+            }
+
+            let mut names = [
+                (Origin::Metamethod, self.metamethods(&current, &identifier)),
+                (Origin::Local, local),
+                (Origin::Global, global),
+                (Origin::Keyword, self.keywords(&current, &identifier)),
+            ]
+            .into_iter()
+            .flat_map(|(origin, names)| names.into_iter().map(move |s| (origin, s)))
+            .collect::<Vec<_>>();
+
+            if self.fuzzy {
+                let fields = self.filter_matches(&current, self.library_fields());
+                names.extend(fields.into_iter().map(|s| (Origin::Field, s)));
+
+                names.sort_by_key(|(_, s)| Reverse(fuzzy_score(&current, s)));
+            }
+
+            let globals = self.session_globals();
+
+            return names
+                .into_iter()
+                .map(|(origin, s)| {
+                    let path = s.split('.').map(String::from).collect::<Vec<_>>();
+
+                    let description = match origin {
+                        Origin::Global | Origin::Field if !self.exhausted() => globals
+                            .clone()
+                            .and_then(|globals| resolve_path(globals, &path))
+                            .map(|value| describe(&value)),
+                        _ => None,
+                    };
+
+                    origin.suggestion(s, description, range)
+                })
+                .collect();
+        }
+
+        if let Some((range, fields, path, table)) = self.table_index(pos.saturating_sub(1)) {
+            let signatures = self.signatures();
+            let chain = table.as_ref().map(index_chain).unwrap_or_default();
+
+            return fields
+                .into_iter()
+                .map(|s| {
+                    if self.exhausted() {
+                        return Origin::Field.suggestion(s, None, range);
+                    }
+
+                    let description = signatures
+                        .as_ref()
+                        .and_then(|signatures| Self::signature(signatures, &path, &s))
+                        .or_else(|| {
+                            let value = chain
+                                .iter()
+                                .filter_map(|tbl| tbl.raw_get::<LuaValue>(s.as_str()).ok())
+                                .find(|value| !value.is_nil())?;
+
+                            Some(describe(&value))
+                        });
+
+                    Origin::Field.suggestion(s, description, range)
+                })
+                .collect();
+        }
+
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::lua::MluaExecutor;
+
+    use super::*;
+
+    fn lua_executor() -> Arc<dyn LuaExecutor> {
+        Arc::new(MluaExecutor::new())
     }
 
     fn line_to_position(line: usize, text: &str) -> u32 {
@@ -445,6 +1869,442 @@ mod tests {
         );
     }
 
+    #[test]
+    fn keywords() {
+        let mut completer = LuaCompleter::new(lua_executor());
+
+        let line = "local x = tr";
+        let names = completer
+            .complete(line, line.len())
+            .into_iter()
+            .map(|s| s.value)
+            .collect::<Vec<_>>();
+
+        assert!(names.contains(&String::from("true")));
+
+        let line = "local x = ret";
+        let names = completer
+            .complete(line, line.len())
+            .into_iter()
+            .map(|s| s.value)
+            .collect::<Vec<_>>();
+
+        assert!(!names.contains(&String::from("return")));
+    }
+
+    #[test]
+    fn grouped_by_origin() {
+        let lua = lua_executor();
+        lua.globals().unwrap().set("zebra", 1).unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        let line = "local zeta = 1; print(ze";
+        let origins = completer
+            .complete(line, line.len())
+            .into_iter()
+            .map(|s| (s.value, s.extra.unwrap().join("")))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            origins,
+            [("zeta", "local"), ("zebra", "global")].map(|(v, o)| (v.to_string(), o.to_string()))
+        );
+    }
+
+    #[test]
+    fn usage_ranking() {
+        let lua = lua_executor();
+
+        lua.exec("alpha = 1; alps = 2").unwrap();
+
+        let session = Arc::new(Mutex::new(SessionIndex::default()));
+        session.lock().unwrap().record("alps + 1");
+
+        let mut completer = LuaCompleter::new(lua).with_session(session.clone());
+
+        completer.refresh_tree("al");
+
+        assert_eq!(
+            vec!["alps", "alpha"],
+            completer.autocomplete_upvalue("al", 2)
+        );
+
+        session.lock().unwrap().record("alpha, alpha");
+
+        assert_eq!(
+            vec!["alpha", "alps"],
+            completer.autocomplete_upvalue("al", 2)
+        );
+    }
+
+    #[test]
+    fn dot_commands() {
+        let lua = lua_executor();
+
+        lua.exec("config = { depth = 1 }").unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        let names = completer
+            .complete(".ex", 3)
+            .into_iter()
+            .map(|s| s.value)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["expand-macro", "export"], names);
+
+        let line = ".get config.de";
+        let fields = completer.complete(line, line.len());
+
+        assert_eq!(fields[0].value, "depth");
+        assert_eq!(fields[0].span.start, 12);
+
+        let line = ".time 10 conf";
+        let names = completer
+            .complete(line, line.len())
+            .into_iter()
+            .map(|s| s.value)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["config"], names);
+    }
+
+    #[test]
+    fn smart_case() {
+        let lua = lua_executor();
+
+        lua.exec("toString = 1; tostringify = 2").unwrap();
+
+        let completer = LuaCompleter::new(lua.clone()).with_smart_case(true);
+
+        assert_eq!(
+            vec!["toString", "tostring", "tostringify"],
+            completer.autocomplete_upvalue("tostr", 0)
+        );
+        assert_eq!(vec!["toString"], completer.autocomplete_upvalue("toS", 0));
+
+        let completer = LuaCompleter::new(lua);
+
+        assert_eq!(
+            vec!["tostring", "tostringify"],
+            completer.autocomplete_upvalue("tostr", 0)
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let lua = lua_executor();
+        lua.globals().unwrap().set("zeta", 1).unwrap();
+
+        let line = "local zeta, zebra = 1, 2; print(ze";
+        let origins = |completer: &mut LuaCompleter| {
+            completer
+                .complete(line, line.len())
+                .into_iter()
+                .map(|s| format!("{} {}", s.value, s.extra.unwrap().join("")))
+                .collect::<Vec<_>>()
+        };
+
+        let mut completer = LuaCompleter::new(lua.clone());
+
+        assert_eq!(vec!["zebra local", "zeta local"], origins(&mut completer));
+
+        let mut completer = LuaCompleter::new(lua.clone()).with_dedupe_shadowed(false);
+
+        assert_eq!(
+            vec!["zebra local", "zeta local", "zeta global"],
+            origins(&mut completer)
+        );
+
+        let mut completer = LuaCompleter::new(lua.clone()).with_max_suggestions(Some(1));
+
+        assert_eq!(vec!["zebra local"], origins(&mut completer));
+
+        let mut completer = LuaCompleter::new(lua).with_min_query_length(3);
+
+        assert!(origins(&mut completer).is_empty());
+    }
+
+    #[test]
+    fn cancelled() {
+        let loading = Loading::default();
+        let mut completer = LuaCompleter::new(lua_executor())
+            .with_budget(Duration::ZERO)
+            .with_loading(loading.clone());
+
+        let line = "prin";
+        let suggestions = completer.complete(line, line.len());
+
+        assert!(completer.is_partial());
+        assert_eq!(Some(line), loading.lock().unwrap().as_deref());
+        // nothing that would insert text when picked stands in for the rest
+        assert!(suggestions.iter().all(|s| !s.value.is_empty()));
+        assert!(!suggestions.iter().any(|s| s.value == "print"));
+
+        let mut completer = LuaCompleter::new(lua_executor()).with_loading(loading.clone());
+        let names = completer.complete(line, line.len());
+
+        assert!(!completer.is_partial());
+        assert!(loading.lock().unwrap().is_none());
+        assert_eq!(
+            vec!["print"],
+            names.into_iter().map(|s| s.value).collect::<Vec<_>>()
+        );
+    }
+
+    /// Out-of-process executor as far as the cache can tell, counting fetches
+    struct Remote {
+        inner: MluaExecutor,
+        fetches: AtomicUsize,
+    }
+
+    impl LuaExecutor for Remote {
+        fn exec(&self, code: &str) -> LuaResult<LuaMultiValue> {
+            self.inner.exec(code)
+        }
+
+        fn globals(&self) -> LuaResult<LuaTable> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            self.inner.globals()
+        }
+
+        fn cancel(&self) {}
+    }
+
+    #[test]
+    fn cached_globals() {
+        let remote = Arc::new(Remote {
+            inner: MluaExecutor::new(),
+            fetches: AtomicUsize::new(0),
+        });
+        let cache = GlobalsCache::new(remote.clone());
+
+        assert!(cache.get().is_none());
+        assert_eq!(0, remote.fetches.load(Ordering::Relaxed));
+
+        cache.refresh();
+        remote.exec("answer = 42").unwrap();
+
+        let mut completer = LuaCompleter::new(remote.clone()).with_globals(cache.clone());
+        let names = |completer: &mut LuaCompleter| {
+            completer
+                .complete("answ", 4)
+                .into_iter()
+                .map(|s| s.value)
+                .collect::<Vec<_>>()
+        };
+
+        // typing reads what was fetched last rather than asking the executor each time
+        for _ in 0..3 {
+            assert_eq!(vec!["answer"], names(&mut completer));
+            assert!(cache.get().is_some());
+        }
+
+        assert_eq!(1, remote.fetches.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn metamethods() {
+        let mut completer = LuaCompleter::new(lua_executor());
+
+        let mut names = |line: &str| {
+            completer
+                .complete(line, line.len())
+                .into_iter()
+                .map(|s| s.value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["__index"], names("setmetatable({}, { __ind"));
+        assert_eq!(
+            vec!["__call", "__close", "__concat"],
+            names("local mt = { __c")
+        );
+        assert!(names("local t = { __ind").is_empty());
+        assert!(names("setmetatable({ __ind").is_empty());
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert!(fuzzy_score("tbins", "table.insert").is_some());
+        assert!(fuzzy_score("tbins", "table.remove").is_none());
+        assert!(fuzzy_score("ins", "insert") > fuzzy_score("ins", "ipairs_next_string"));
+
+        let mut completer = LuaCompleter::new(lua_executor()).with_fuzzy(true);
+
+        let line = "tbins";
+        let names = completer
+            .complete(line, line.len())
+            .into_iter()
+            .map(|s| s.value)
+            .collect::<Vec<_>>();
+
+        assert_eq!(names.first().map(String::as_str), Some("table.insert"));
+    }
+
+    #[test]
+    fn unknown_field_ranges() {
+        let lua = lua_executor();
+
+        let unknown = |text: &str, cursor: usize| {
+            let tree = LuaParser::parse(text, parse::config());
+
+            unknown_fields(&lua.globals().unwrap(), &tree, cursor as u32)
+                .into_iter()
+                .map(|range| &text[usize::from(range.start())..usize::from(range.end())])
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(unknown("print(string.lenght)", 0), ["lenght"]);
+        assert_eq!(unknown("print(string.len)", 0), Vec::<String>::new());
+        assert_eq!(unknown("string.rev", 10), Vec::<String>::new());
+        assert_eq!(unknown("string.rev", 0), ["rev"]);
+        assert_eq!(unknown("string.trim = nil", 0), Vec::<String>::new());
+        assert_eq!(
+            unknown("function string.trim() end", 0),
+            Vec::<String>::new()
+        );
+        assert_eq!(unknown("string.a, x = string.b, 1", 0), ["b"]);
+    }
+
+    #[test]
+    fn globals_in_functions() {
+        let function_globals =
+            |text: &str| function_globals(&LuaParser::parse(text, parse::config()));
+
+        assert_eq!(
+            function_globals(
+                "local function f(count) for i = 1, 3 do cuont = count + i end total = 1 end"
+            ),
+            [
+                (String::from("cuont"), Some(String::from("count"))),
+                (String::from("total"), None),
+            ]
+        );
+
+        assert!(function_globals("x = 1; function f() end; t = { g = function() end }").is_empty());
+        assert!(
+            function_globals("local x; function f() x = 1; local y; y = 2; t.z = 3 end").is_empty()
+        );
+        assert!(
+            function_globals("counter = 0; function inc() counter = counter + 1 end").is_empty()
+        );
+
+        assert_eq!(0, edit_distance("abc", "abc"));
+        assert_eq!(1, edit_distance("abc", "abd"));
+        assert_eq!(2, edit_distance("count", "cuont"));
+        assert_eq!(3, edit_distance("", "abc"));
+    }
+
+    #[test]
+    fn reused_trees() {
+        let mut completer = LuaCompleter::new(lua_executor());
+
+        completer.refresh_tree("local foo = 1; print(fo");
+        completer.refresh_tree("local foo = 1; print(fo");
+        assert_eq!(1, completer.parses);
+
+        completer.refresh_tree("local foo = 1; print(f");
+        completer.refresh_tree("local foo = 1; print(fo");
+        assert_eq!(2, completer.parses);
+        assert_eq!(["foo"], completer.locals(22).as_slice());
+
+        for i in 0..=RECENT_TREES {
+            completer.refresh_tree(&"x".repeat(i + 1));
+        }
+
+        // pushed out by the newer buffers
+        completer.refresh_tree("local foo = 1; print(f");
+        assert_eq!(2 + RECENT_TREES + 2, completer.parses);
+    }
+
+    #[test]
+    fn name_bindings() {
+        let lua = lua_executor();
+
+        let bindings = |text: &str, outer: &[&str], cursor: usize| {
+            let tree = LuaParser::parse(text, parse::config());
+            let outer = outer
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+
+            bindings(&lua.globals().unwrap(), &tree, &outer, cursor as u32)
+                .into_iter()
+                .map(|(range, binding)| {
+                    (
+                        text[usize::from(range.start())..usize::from(range.end())].to_string(),
+                        binding,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            bindings(
+                "local a = 1; local function f(b) return a, b, c, print end",
+                &[],
+                0
+            ),
+            [
+                (String::from("a"), Binding::Upvalue),
+                (String::from("b"), Binding::Local),
+                (String::from("c"), Binding::UnknownGlobal),
+                (String::from("print"), Binding::Builtin),
+            ]
+        );
+
+        assert_eq!(
+            bindings("x = a", &["a"], 0),
+            [
+                (String::from("x"), Binding::Global),
+                (String::from("a"), Binding::Local),
+            ]
+        );
+
+        lua.globals().unwrap().raw_remove("utf8").unwrap();
+
+        assert_eq!(
+            bindings("print(utf8)", &[], 0),
+            [
+                (String::from("print"), Binding::Builtin),
+                (String::from("utf8"), Binding::UnknownGlobal),
+            ]
+        );
+
+        assert_eq!(
+            bindings("pri", &[], 3),
+            [(String::from("pri"), Binding::Global)]
+        );
+    }
+
+    #[test]
+    fn value_descriptions() {
+        let lua = lua_executor();
+        lua.globals().unwrap().set("answer", 42).unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        let describe = |completer: &mut LuaCompleter, line: &str| {
+            completer
+                .complete(line, line.len())
+                .into_iter()
+                .map(|s| (s.value, s.description.unwrap_or_default()))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(
+            describe(&mut completer, "answ")
+                .contains(&(String::from("answer"), String::from("number = 42")))
+        );
+        assert!(
+            describe(&mut completer, "table.ins")
+                .contains(&(String::from("insert"), String::from("function")))
+        );
+    }
+
     #[test]
     fn table_index_query() {
         let lua = lua_executor();
@@ -459,6 +2319,187 @@ mod tests {
         );
     }
 
+    #[test]
+    fn string_methods() {
+        let lua = lua_executor();
+
+        lua.exec("s = 'hello'").unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        completer.refresh_tree("s:up");
+
+        assert_eq!(
+            &["upper"].map(|s| s.to_string()).as_slice(),
+            &completer.table_index(3).map(|t| t.1).unwrap()
+        );
+
+        completer.refresh_tree("(\"foo\"):su");
+
+        assert_eq!(
+            &["sub"].map(|s| s.to_string()).as_slice(),
+            &completer.table_index(9).map(|t| t.1).unwrap()
+        );
+    }
+
+    #[test]
+    fn method_index() {
+        let lua = lua_executor();
+
+        lua.exec("obj = { method = function() end, field = 1 }")
+            .unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        completer.refresh_tree("obj:");
+
+        let (range, fields, ..) = completer.table_index(3).unwrap();
+
+        assert_eq!(&["method"].map(|s| s.to_string()).as_slice(), &fields);
+        assert_eq!(u32::from(range.start()), 4);
+    }
+
+    #[test]
+    fn inherited_methods() {
+        let lua = lua_executor();
+
+        lua.exec(
+            "Base = {}
+            Base.__index = Base
+            function Base:greet() end
+            Derived = setmetatable({}, Base)
+            Derived.__index = Derived
+            function Derived:shout() end
+            obj = setmetatable({ name = 'a' }, Derived)",
+        )
+        .unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        completer.refresh_tree("obj:");
+
+        assert_eq!(
+            &["greet", "shout"].map(|s| s.to_string()).as_slice(),
+            &completer.table_index(3).map(|t| t.1).unwrap()
+        );
+    }
+
+    #[test]
+    fn assigned_fields() {
+        let lua = lua_executor();
+
+        // a proxy whose fields live behind `__index`/`__newindex` functions
+        lua.exec(
+            "local store = {}
+            lazy = setmetatable({}, {
+                __index = function(_, k) return store[k] end,
+                __newindex = function(_, k, v) store[k] = v end,
+            })",
+        )
+        .unwrap();
+
+        let session = Arc::new(Mutex::new(SessionIndex::default()));
+
+        for line in ["lazy.foo = 1", "function lazy.bar() end", "x = lazy.baz"] {
+            lua.exec(line).unwrap();
+            session.lock().unwrap().record(line);
+        }
+
+        // from the history of an earlier session, never run in this one
+        session.lock().unwrap().record_usage("lazy.old = 1");
+
+        let mut completer = LuaCompleter::new(lua).with_session(session);
+
+        completer.refresh_tree("lazy.");
+
+        assert_eq!(
+            &["bar", "foo"].map(|s| s.to_string()).as_slice(),
+            &completer.table_index(4).map(|t| t.1).unwrap()
+        );
+    }
+
+    #[test]
+    fn table_index_nested() {
+        let lua = lua_executor();
+
+        lua.exec("outer = { inner = { field = 1 }, other = 2 }")
+            .unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        completer.refresh_tree("print(outer.inner.");
+
+        assert_eq!(
+            &["field"].map(|s| s.to_string()).as_slice(),
+            &completer.table_index(17).map(|t| t.1).unwrap()
+        );
+    }
+
+    #[test]
+    fn require_modules() {
+        let dir = tempfile::tempdir().unwrap();
+
+        fs::write(dir.path().join("alpha.lua"), "").unwrap();
+        fs::create_dir_all(dir.path().join("pkg")).unwrap();
+        fs::write(dir.path().join("pkg/init.lua"), "").unwrap();
+        fs::write(dir.path().join("pkg/sub.lua"), "").unwrap();
+
+        let lua = lua_executor();
+        let root = dir.path().to_string_lossy();
+
+        lua.exec(&format!(
+            "package.path = '{root}/?.lua;{root}/?/init.lua'; package.cpath = ''"
+        ))
+        .unwrap();
+
+        let mut completer = LuaCompleter::new(lua);
+
+        let complete = |completer: &mut LuaCompleter, line: &str| {
+            completer
+                .complete(line, line.len())
+                .into_iter()
+                .map(|s| s.value)
+                .collect::<Vec<_>>()
+        };
+
+        let modules = complete(&mut completer, "require('");
+        assert!(modules.contains(&String::from("alpha")));
+        assert!(modules.contains(&String::from("pkg")));
+
+        assert_eq!(
+            &["pkg.sub"].map(|s| s.to_string()).as_slice(),
+            &complete(&mut completer, "local m = require \"pkg.s")
+        );
+    }
+
+    #[test]
+    fn file_paths() {
+        let dir = tempfile::tempdir().unwrap();
+
+        fs::write(dir.path().join("data.txt"), "").unwrap();
+        fs::create_dir_all(dir.path().join("nested")).unwrap();
+
+        let mut completer = LuaCompleter::new(lua_executor());
+
+        let root = dir.path().to_string_lossy();
+        let line = format!("io.open('{root}/");
+
+        let mut files = completer
+            .complete(&line, line.len())
+            .into_iter()
+            .map(|s| s.value)
+            .collect::<Vec<_>>();
+        files.sort();
+
+        assert_eq!(
+            files,
+            [format!("{root}/data.txt"), format!("{root}/nested/")]
+        );
+
+        let line = format!("print('{root}/");
+        assert!(completer.complete(&line, line.len()).is_empty());
+    }
+
     #[test]
     fn table_index_all() {
         let lua = lua_executor();
@@ -1,25 +1,186 @@
 use directories::ProjectDirs;
 use mlua::prelude::*;
-use std::{fs, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, fs, path::PathBuf, sync::Arc, time::Duration};
 
 use crate::{
-    inspect::TableFormat,
-    lua::{LuaExecutor, MluaExecutor, SystemLuaError, SystemLuaExecutor},
+    inspect::{
+        self, ByteEscape, Indent, InspectLimits, IntegerBase, NumberFormat, TableFormat,
+        ValueColors, lua_string_literal,
+    },
+    interpreters::{self, Interpreter},
+    lua::{
+        DeferredExecutor, LuaExecutor, MluaExecutor, ProcessOptions, RemoteExecutor, RestartPolicy,
+        SystemLuaError, SystemLuaExecutor,
+    },
+    palette::{self, ColorSupport},
+    parse::SyntaxColors,
 };
 
 #[derive(Clone, Copy)]
 pub enum Executor {
     System,
+    /// The first interpreter of `lua_preference` on the `PATH`, driven like a system Lua
+    Auto,
     Embedded,
+    /// A `manen agent` at `remote_agent`
+    Remote,
+    /// `lua` on `ssh_host`, driven like a system Lua
+    Ssh,
+    /// `lua` in a container of `docker_image`
+    Docker,
+}
+
+#[derive(Clone, Copy)]
+pub enum AsyncLoop {
+    Auto,
+    Copas,
+    Cqueues,
+    Luv,
+}
+
+impl AsyncLoop {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AsyncLoop::Auto => "auto",
+            AsyncLoop::Copas => "copas",
+            AsyncLoop::Cqueues => "cqueues",
+            AsyncLoop::Luv => "luv",
+        }
+    }
+}
+
+/// When evaluations that produce `nil` print it
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PrintNil {
+    /// Every evaluation, statements included
+    Always,
+    /// Only expressions and returned values that are `nil`
+    Expressions,
+    Never,
+}
+
+#[derive(Clone, Copy)]
+pub enum Profile {
+    OpenResty,
+    Love,
+    Neovim,
+}
+
+impl Profile {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Profile::OpenResty => include_str!("../lua/profiles/openresty.lua"),
+            Profile::Love => include_str!("../lua/profiles/love.lua"),
+            Profile::Neovim => include_str!("../lua/profiles/neovim.lua"),
+        }
+    }
 }
 
 #[derive(Clone, FromLua)]
 pub struct Config {
     pub executor: Executor,
     pub system_lua: Option<PathBuf>,
+    pub system_env: HashMap<String, String>,
+    pub system_cwd: Option<PathBuf>,
+    pub system_args: Vec<String>,
+    pub restart_policy: RestartPolicy,
+    pub lua_preference: Vec<String>,
+    /// Lua the embedded executor runs, an installed interpreter when it isn't the one
+    /// linked in
+    pub lua_version: Option<String>,
+    /// Run input without the libraries and functions `lua/sandbox.lua` blocks
+    pub sandbox: bool,
+    pub sandbox_allow: Vec<String>,
+    /// `token@host[:port]` as printed by `manen agent`
+    pub remote_agent: Option<String>,
+    pub ssh_host: Option<String>,
+    pub docker_image: Option<String>,
     pub table_format: TableFormat,
+    pub max_depth: Option<usize>,
+    pub max_items: Option<usize>,
+    pub max_string_length: Option<usize>,
+    pub redact_keys: Vec<String>,
+    pub sort_keys: bool,
+    pub indent: Indent,
+    pub compact_tables: bool,
+    pub hexdump_binary: bool,
+    pub byte_escape: ByteEscape,
+    pub table_summary: bool,
+    pub show_inheritance: bool,
     pub history_size: usize,
     pub color_output: bool,
+    /// `None` detects it from the environment
+    pub color_support: Option<ColorSupport>,
+    pub colors: ValueColors,
+    pub syntax_colors: SyntaxColors,
+    pub rainbow_brackets: bool,
+    pub numbers: NumberFormat,
+    pub fuzzy_completion: bool,
+    pub smart_case_completion: bool,
+    pub max_completions: Option<usize>,
+    pub min_completion_length: usize,
+    pub dedupe_shadowed_locals: bool,
+    pub encrypt_history: bool,
+    pub confirm_side_effects: bool,
+    pub calculator: bool,
+    pub report_globals: bool,
+    pub status_line: bool,
+    pub print_nil: PrintNil,
+    pub alias: HashMap<String, String>,
+    pub async_loop: Option<AsyncLoop>,
+    pub luv_interval: Option<Duration>,
+    pub profile: Option<Profile>,
+    pub seed: Option<u64>,
+}
+
+const ASYNC_CODE: &str = include_str!("../lua/async.lua");
+const SANDBOX_CODE: &str = include_str!("../lua/sandbox.lua");
+const COROUTINE_CODE: &str = include_str!("../lua/coroutine.lua");
+/// The `manen` module of `json`, `readfile`, `writefile`, `stringx` and `inspect`
+pub const LIBRARY_CODE: &str = include_str!("../lua/manen.lua");
+pub const FORMATTER_CODE: &str = include_str!("../lua/formatter.lua");
+
+/// Settings given as command line flags, over those of the config file
+#[derive(Default)]
+pub struct Overrides {
+    pub seed: Option<u64>,
+    pub sandbox: bool,
+    pub lua_version: Option<String>,
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut Config) {
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+
+        // the flag only turns it on, a sandbox from the config stays
+        config.sandbox |= self.sandbox;
+
+        if self.lua_version.is_some() {
+            config.lua_version = self.lua_version.clone();
+        }
+    }
+}
+
+/// Removes what reaches outside the Lua state but the `allow`ed names
+pub fn sandbox_code(allow: &[String]) -> String {
+    let names = allow
+        .iter()
+        .map(|name| lua_string_literal(name.as_bytes()))
+        .collect::<Vec<_>>();
+
+    format!("{SANDBOX_CODE}\nmanen.sandbox({{{}}})", names.join(", "))
+}
+
+/// Seeds `math.random` along with the random sources of loaded libraries and profiles
+pub fn seed_code(seed: u64) -> String {
+    format!(
+        "math.randomseed({seed})
+        local random = package and package.loaded.random
+        if type(random) == 'table' and random.seed then random.seed({seed}) end
+        if love and love.math then love.math.setRandomSeed({seed}) end"
+    )
 }
 
 impl Default for Config {
@@ -27,14 +188,65 @@ impl Default for Config {
         Self {
             executor: Executor::Embedded,
             system_lua: None,
+            system_env: HashMap::new(),
+            system_cwd: None,
+            system_args: Vec::new(),
+            restart_policy: RestartPolicy::default(),
+            lua_preference: interpreters::DEFAULT_PREFERENCE
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            sandbox: false,
+            sandbox_allow: Vec::new(),
+            lua_version: None,
+            remote_agent: None,
+            ssh_host: None,
+            docker_image: None,
             table_format: TableFormat::Inspect,
+            max_depth: None,
+            max_items: None,
+            max_string_length: Some(1024),
+            redact_keys: Vec::new(),
+            sort_keys: false,
+            indent: Indent::default(),
+            compact_tables: true,
+            hexdump_binary: false,
+            byte_escape: ByteEscape::default(),
+            table_summary: false,
+            show_inheritance: false,
             history_size: 256,
             color_output: true,
+            color_support: None,
+            colors: ValueColors::default(),
+            syntax_colors: SyntaxColors::default(),
+            rainbow_brackets: false,
+            numbers: NumberFormat::default(),
+            fuzzy_completion: false,
+            smart_case_completion: false,
+            max_completions: None,
+            min_completion_length: 0,
+            dedupe_shadowed_locals: true,
+            encrypt_history: false,
+            confirm_side_effects: false,
+            calculator: false,
+            report_globals: false,
+            status_line: true,
+            print_nil: PrintNil::Expressions,
+            alias: HashMap::new(),
+            async_loop: None,
+            luv_interval: None,
+            profile: None,
+            seed: None,
         }
     }
 }
 
 impl Config {
+    /// Sets the colors styles are mapped to, as configured or detected
+    pub fn apply_color_support(&self) {
+        palette::set_color_support(self.color_support.unwrap_or_else(ColorSupport::detect));
+    }
+
     pub fn load() -> LuaResult<Self> {
         let config = Self::default();
 
@@ -56,18 +268,219 @@ impl Config {
         Ok(config)
     }
 
+    pub fn limits(&self) -> InspectLimits {
+        InspectLimits {
+            max_depth: self.max_depth,
+            max_items: self.max_items,
+            max_string: self.max_string_length,
+            redact: !self.redact_keys.is_empty(),
+            sort_keys: self.sort_keys,
+            indent: self.indent,
+            max_width: if self.compact_tables {
+                inspect::terminal_width()
+            } else {
+                None
+            },
+            hexdump: self.hexdump_binary,
+            summary: self.table_summary,
+            inheritance: self.show_inheritance,
+        }
+    }
+
+    pub fn process_options(&self) -> ProcessOptions {
+        ProcessOptions {
+            env: self.system_env.clone(),
+            cwd: self.system_cwd.clone(),
+            args: self.system_args.clone(),
+            restart: self.restart_policy.clone(),
+        }
+    }
+
+    /// `lua_version` when it isn't the embedded VM
+    fn foreign_version(&self) -> Option<&str> {
+        self.lua_version
+            .as_deref()
+            .filter(|version| *version != interpreters::EMBEDDED_VERSION)
+    }
+
+    /// Names the auto executor looks for, with `lua_version` set only those of it, in the
+    /// order of `lua_preference` and then the rest of its names
+    fn auto_preference(&self) -> Vec<String> {
+        let Some(version) = &self.lua_version else {
+            return self.lua_preference.clone();
+        };
+
+        let names = interpreters::version_names(version);
+
+        let mut preference = self
+            .lua_preference
+            .iter()
+            .filter(|name| names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for name in names {
+            if !preference.contains(&name) {
+                preference.push(name);
+            }
+        }
+
+        preference
+    }
+
+    /// Interpreter the system executor starts, the first one installed for `auto` and
+    /// one of `lua_version` for the embedded executor when the build has another
+    pub fn system_program(&self) -> Option<PathBuf> {
+        let names = match (self.executor, self.foreign_version()) {
+            (Executor::System, _) => return self.system_lua.clone(),
+            (Executor::Auto, _) => self.auto_preference(),
+            (Executor::Embedded, Some(version)) => interpreters::version_names(version),
+            _ => return None,
+        };
+
+        interpreters::detect(&names)
+            .into_iter()
+            .next()
+            .map(|interpreter| interpreter.path)
+    }
+
+    /// Lua the ssh and docker executors start on the other side, where nothing can be
+    /// looked for: `system_lua` as given, else the usual name of `lua_version`
+    fn remote_program(&self) -> String {
+        if let Some(path) = &self.system_lua {
+            return path.to_string_lossy().to_string();
+        }
+
+        self.lua_version
+            .as_deref()
+            .and_then(|version| interpreters::version_names(version).into_iter().next())
+            .unwrap_or_else(|| String::from("lua"))
+    }
+
+    /// Fails when `lua_version` is neither embedded nor installed, or `system_lua` is
+    /// another version
+    pub fn check_lua_version(&self) -> LuaResult<()> {
+        match (self.executor, self.foreign_version()) {
+            (Executor::Embedded | Executor::Auto, Some(version))
+                if self.system_program().is_none() =>
+            {
+                Err(LuaError::RuntimeError(format!(
+                    "Lua {version} is not embedded, this build has {}, and none of {} is on the PATH",
+                    interpreters::EMBEDDED_VERSION,
+                    interpreters::version_names(version).join(", ")
+                )))
+            }
+            (Executor::System, _) => {
+                let (Some(version), Some(path)) = (&self.lua_version, &self.system_lua) else {
+                    return Ok(());
+                };
+
+                let interpreter = Interpreter {
+                    name: path.to_string_lossy().to_string(),
+                    path: path.clone(),
+                };
+
+                // one whose banner can't be read is given the benefit of the doubt
+                match interpreter.version() {
+                    Some(banner) if !interpreters::is_version(&banner, version) => {
+                        Err(LuaError::RuntimeError(format!(
+                            "system_lua is {banner}, not Lua {version}, unset lua_version or point system_lua at one"
+                        )))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn get_executor(&self) -> Result<Arc<dyn LuaExecutor>, SystemLuaError> {
+        self.check_lua_version()?;
+
         let executor = match self.executor {
-            Executor::Embedded => Arc::new(MluaExecutor::new()),
-            Executor::System => {
-                if let Some(path) = &self.system_lua {
-                    Arc::new(SystemLuaExecutor::new(&path.to_string_lossy())?)
+            Executor::Embedded => match self.system_program() {
+                Some(path) => {
+                    // the build links a single VM, so another version can't be embedded
+                    eprintln!(
+                        "Lua {} is not embedded, this build has {}, running {} instead",
+                        self.lua_version.as_deref().unwrap_or_default(),
+                        interpreters::EMBEDDED_VERSION,
+                        path.display()
+                    );
+
+                    Arc::new(SystemLuaExecutor::new(
+                        &path.to_string_lossy(),
+                        self.process_options(),
+                    )?)
+                }
+                None => Arc::new(MluaExecutor::new()) as Arc<dyn LuaExecutor>,
+            },
+            // without an interpreter installed, `auto` falls back to the embedded one
+            Executor::System | Executor::Auto => {
+                if let Some(path) = self.system_program() {
+                    Arc::new(SystemLuaExecutor::new(
+                        &path.to_string_lossy(),
+                        self.process_options(),
+                    )?)
                 } else {
                     Arc::new(MluaExecutor::new()) as Arc<dyn LuaExecutor>
                 }
             }
+            Executor::Remote => match &self.remote_agent {
+                Some(target) => Arc::new(RemoteExecutor::new(target)?),
+                None => {
+                    return Err(SystemLuaError::Lua(LuaError::RuntimeError(String::from(
+                        "the remote executor needs remote_agent to be set",
+                    ))));
+                }
+            },
+            Executor::Ssh => match &self.ssh_host {
+                Some(host) => Arc::new(SystemLuaExecutor::ssh(
+                    host,
+                    &self.remote_program(),
+                    self.process_options(),
+                )?),
+                None => {
+                    return Err(SystemLuaError::Lua(LuaError::RuntimeError(String::from(
+                        "the ssh executor needs ssh_host to be set",
+                    ))));
+                }
+            },
+            Executor::Docker => match &self.docker_image {
+                Some(image) => Arc::new(SystemLuaExecutor::docker(
+                    image,
+                    &self.remote_program(),
+                    self.process_options(),
+                )?),
+                None => {
+                    return Err(SystemLuaError::Lua(LuaError::RuntimeError(String::from(
+                        "the docker executor needs docker_image to be set",
+                    ))));
+                }
+            },
         };
 
+        let formatters = executor.exec(FORMATTER_CODE)?.into_iter().next();
+
+        if let (Some(lua), Some(LuaValue::Table(formatters))) = (executor.lua(), formatters) {
+            inspect::set_formatters(lua, formatters);
+        }
+
+        executor.exec(COROUTINE_CODE)?;
+        executor.exec(LIBRARY_CODE)?;
+
+        if self.async_loop.is_some() {
+            executor.exec(ASYNC_CODE)?;
+        }
+
+        if let Some(profile) = self.profile {
+            executor.exec(profile.code())?;
+        }
+
+        if let Some(seed) = self.seed {
+            executor.exec(&seed_code(seed))?;
+        }
+
         if let Some(proj_dirs) = ProjectDirs::from("gay.gayest", "", "Manen") {
             let config_dir = proj_dirs.config_dir();
             let rc_file = config_dir.join("rc.lua");
@@ -86,8 +499,28 @@ impl Config {
             }
         }
 
+        // last, the config and rc.lua are trusted
+        if self.sandbox {
+            executor.exec(&sandbox_code(&self.sandbox_allow))?;
+        }
+
         Ok(executor)
     }
+
+    /// `get_executor` run by the first call to the executor, followed by `prelude`
+    pub fn deferred_executor(&self, prelude: Option<&'static str>) -> Arc<dyn LuaExecutor> {
+        let config = self.clone();
+
+        Arc::new(DeferredExecutor::new(move || {
+            let executor = config.get_executor().map_err(LuaError::external)?;
+
+            if let Some(prelude) = prelude {
+                executor.exec(prelude)?;
+            }
+
+            Ok(executor)
+        }))
+    }
 }
 
 macro_rules! field {
@@ -103,7 +536,153 @@ macro_rules! field {
     };
 }
 
+/// Sets one `manen.colors` entry, `address = false` hides addresses
+fn set_color(colors: &mut ValueColors, key: &str, value: LuaValue) -> LuaResult<()> {
+    if key == "address" && value.as_boolean() == Some(false) {
+        colors.address = None;
+        return Ok(());
+    }
+
+    let name = field!(value, as_string_lossy, format!("colors.{key}"), "string");
+    let style = inspect::parse_style(&name)
+        .ok_or_else(|| LuaError::RuntimeError(format!("invalid color '{name}'")))?;
+
+    match key {
+        "nil" => colors.nil = style,
+        "boolean" => colors.boolean = style,
+        "number" => colors.number = style,
+        "string" => colors.string = style,
+        "table" => colors.table = style,
+        "function" => colors.function = style,
+        "thread" => colors.thread = style,
+        "userdata" => colors.userdata = style,
+        "address" => colors.address = Some(style),
+        "escape" => colors.escape = style,
+        "note" => colors.note = style,
+        key => return Err(LuaError::RuntimeError(format!("invalid color key '{key}'"))),
+    }
+
+    Ok(())
+}
+
+fn set_syntax_color(colors: &mut SyntaxColors, key: &str, value: LuaValue) -> LuaResult<()> {
+    let name = field!(
+        value,
+        as_string_lossy,
+        format!("syntax_colors.{key}"),
+        "string"
+    );
+    let style = inspect::parse_style(&name)
+        .ok_or_else(|| LuaError::RuntimeError(format!("invalid color '{name}'")))?;
+
+    if !colors.set(key, style) {
+        return Err(LuaError::RuntimeError(format!(
+            "invalid syntax color key '{key}'"
+        )));
+    }
+
+    Ok(())
+}
+
+// themes shipped with manen besides dark and light, files in the themes directory
+// of the config directory can be used the same way
+const THEMES: &[(&str, &str)] = &[("gruvbox", include_str!("../lua/themes/gruvbox.lua"))];
+
+/// Sets both color tables from `manen.theme`, themes other than dark and light are
+/// Lua files returning `syntax` and `values` colors applied over the dark theme
+fn set_theme(lua: &Lua, config: &mut Config, name: &str) -> LuaResult<()> {
+    match name {
+        "dark" => {
+            config.colors = ValueColors::default();
+            config.syntax_colors = SyntaxColors::default();
+
+            return Ok(());
+        }
+        "light" => {
+            config.colors = ValueColors::light();
+            config.syntax_colors = SyntaxColors::light();
+
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let code = match THEMES.iter().find(|(theme, _)| *theme == name) {
+        Some((_, code)) => code.to_string(),
+        None => ProjectDirs::from("gay.gayest", "", "Manen")
+            .and_then(|dirs| {
+                fs::read_to_string(dirs.config_dir().join("themes").join(format!("{name}.lua")))
+                    .ok()
+            })
+            .ok_or_else(|| LuaError::RuntimeError(format!("theme '{name}' not found")))?,
+    };
+
+    let theme = lua
+        .load(code)
+        .set_name(format!("={name} theme"))
+        .eval::<LuaTable>()?;
+
+    config.colors = ValueColors::default();
+    config.syntax_colors = SyntaxColors::default();
+
+    if let Some(syntax) = theme.get::<Option<LuaTable>>("syntax")? {
+        for (key, value) in syntax.pairs::<String, LuaValue>().flatten() {
+            set_syntax_color(&mut config.syntax_colors, &key, value)?;
+        }
+    }
+
+    if let Some(values) = theme.get::<Option<LuaTable>>("values")? {
+        for (key, value) in values.pairs::<String, LuaValue>().flatten() {
+            set_color(&mut config.colors, &key, value)?;
+        }
+    }
+
+    Ok(())
+}
+
 impl LuaUserData for Config {
+    fn add_fields<F: LuaUserDataFields<Self>>(fields: &mut F) {
+        // `manen.colors.number = 'cyan'` writes through to the config
+        fields.add_field_function_get("colors", |lua, this| {
+            let proxy = lua.create_table()?;
+            let metatable = lua.create_table()?;
+
+            metatable.raw_set(
+                "__newindex",
+                lua.create_function(move |_, (_, key, value): (LuaTable, String, LuaValue)| {
+                    set_color(&mut this.borrow_mut::<Config>()?.colors, &key, value)
+                })?,
+            )?;
+
+            proxy.set_metatable(Some(metatable));
+
+            Ok(proxy)
+        });
+
+        fields.add_field_function_get("syntax_colors", |lua, this| {
+            let proxy = lua.create_table()?;
+            let metatable = lua.create_table()?;
+
+            metatable.raw_set(
+                "__newindex",
+                lua.create_function(move |_, (_, key, value): (LuaTable, String, LuaValue)| {
+                    set_syntax_color(&mut this.borrow_mut::<Config>()?.syntax_colors, &key, value)
+                })?,
+            )?;
+
+            proxy.set_metatable(Some(metatable));
+
+            Ok(proxy)
+        });
+
+        fields.add_field_function_get("seed", |lua, this| {
+            lua.create_function(move |_, seed: u64| {
+                this.borrow_mut::<Config>()?.seed = Some(seed);
+                Ok(())
+            })
+        });
+    }
+
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_meta_method_mut(
             LuaMetaMethod::NewIndex,
@@ -114,7 +693,11 @@ impl LuaUserData for Config {
 
                         match executor.as_str() {
                             "system" => this.executor = Executor::System,
+                            "auto" => this.executor = Executor::Auto,
                             "embedded" => this.executor = Executor::Embedded,
+                            "remote" => this.executor = Executor::Remote,
+                            "ssh" => this.executor = Executor::Ssh,
+                            "docker" => this.executor = Executor::Docker,
                             _ => {
                                 return Err(LuaError::RuntimeError(String::from(
                                     "expected valid executor format",
@@ -139,6 +722,114 @@ impl LuaUserData for Config {
 
                         this.system_lua = Some(path);
                     }
+                    "system_env" => {
+                        let table = field!(value, as_table, "system_env", "table");
+
+                        this.system_env = table
+                            .pairs::<String, String>()
+                            .collect::<LuaResult<HashMap<_, _>>>()?;
+                    }
+                    "system_cwd" => {
+                        if value.is_nil() {
+                            this.system_cwd = None;
+                            return Ok(());
+                        }
+
+                        // not checked, it may be a path on the ssh host or in the container
+                        this.system_cwd = Some(PathBuf::from_lua(value, lua)?);
+                    }
+                    "system_args" => {
+                        let table = field!(value, as_table, "system_args", "table");
+
+                        this.system_args = table
+                            .sequence_values::<String>()
+                            .collect::<LuaResult<Vec<_>>>()?;
+                    }
+                    "sandbox" => {
+                        this.sandbox = field!(value, as_boolean, "sandbox", "bool");
+                    }
+                    "sandbox_allow" => {
+                        let table = field!(value, as_table, "sandbox_allow", "table");
+
+                        this.sandbox_allow = table
+                            .sequence_values::<String>()
+                            .collect::<LuaResult<Vec<_>>>()?;
+                    }
+                    "restart_on_crash" => {
+                        this.restart_policy.on_crash =
+                            field!(value, as_boolean, "restart_on_crash", "bool");
+                    }
+                    "max_restarts" => {
+                        if value.is_nil() {
+                            this.restart_policy.max_restarts = None;
+                            return Ok(());
+                        }
+
+                        this.restart_policy.max_restarts =
+                            Some(field!(value, as_usize, "max_restarts", "integer"));
+                    }
+                    "restart_backoff" => {
+                        let backoff = field!(value, as_u64, "restart_backoff", "integer");
+
+                        this.restart_policy.backoff = Duration::from_millis(backoff);
+                    }
+                    "lua_version" => {
+                        if value.is_nil() {
+                            this.lua_version = None;
+                            return Ok(());
+                        }
+
+                        let version = field!(value, as_string_lossy, "lua_version", "string");
+
+                        if !interpreters::VERSIONS.contains(&version.as_str()) {
+                            return Err(LuaError::RuntimeError(format!(
+                                "expected one of {} for lua_version",
+                                interpreters::VERSIONS.join(", ")
+                            )));
+                        }
+
+                        this.lua_version = Some(version);
+                    }
+                    "lua_preference" => {
+                        let table = field!(value, as_table, "lua_preference", "table");
+
+                        this.lua_preference = table
+                            .sequence_values::<String>()
+                            .collect::<LuaResult<Vec<_>>>()?;
+                    }
+                    "remote_agent" => {
+                        if value.is_nil() {
+                            this.remote_agent = None;
+                            return Ok(());
+                        }
+
+                        let target = field!(value, as_string_lossy, "remote_agent", "string");
+
+                        if !target.contains('@') {
+                            return Err(LuaError::RuntimeError(format!(
+                                "expected token@host[:port] for remote_agent, got '{target}'"
+                            )));
+                        }
+
+                        this.remote_agent = Some(target);
+                    }
+                    "ssh_host" => {
+                        if value.is_nil() {
+                            this.ssh_host = None;
+                            return Ok(());
+                        }
+
+                        this.ssh_host = Some(field!(value, as_string_lossy, "ssh_host", "string"));
+                    }
+                    "docker_image" => {
+                        if value.is_nil() {
+                            this.docker_image = None;
+                            return Ok(());
+                        }
+
+                        this.docker_image =
+                            Some(field!(value, as_string_lossy, "docker_image", "string"));
+                    }
                     "table_format" => {
                         let format = field!(value, as_string_lossy, "table_format", "string");
 
@@ -146,6 +837,7 @@ impl LuaUserData for Config {
                             "address" => this.table_format = TableFormat::Address,
                             "inspect" => this.table_format = TableFormat::Inspect,
                             "comfytable" => this.table_format = TableFormat::ComfyTable,
+                            "markdown" => this.table_format = TableFormat::Markdown,
                             _ => {
                                 return Err(LuaError::RuntimeError(String::from(
                                     "expected valid table format",
@@ -153,12 +845,260 @@ impl LuaUserData for Config {
                             }
                         }
                     }
+                    "max_depth" => {
+                        if value.is_nil() {
+                            this.max_depth = None;
+                            return Ok(());
+                        }
+
+                        this.max_depth = Some(field!(value, as_usize, "max_depth", "integer"));
+                    }
+                    "max_items" => {
+                        if value.is_nil() {
+                            this.max_items = None;
+                            return Ok(());
+                        }
+
+                        this.max_items = Some(field!(value, as_usize, "max_items", "integer"));
+                    }
+                    "max_string_length" => {
+                        if value.is_nil() {
+                            this.max_string_length = None;
+                            return Ok(());
+                        }
+
+                        this.max_string_length =
+                            Some(field!(value, as_usize, "max_string_length", "integer"));
+                    }
+                    "redact_keys" => {
+                        let table = field!(value, as_table, "redact_keys", "table");
+
+                        this.redact_keys = table
+                            .sequence_values::<String>()
+                            .collect::<LuaResult<Vec<_>>>()?;
+                    }
+                    "sort_keys" => {
+                        this.sort_keys = field!(value, as_boolean, "sort_keys", "bool");
+                    }
+                    "indent" => {
+                        this.indent = Indent::from_value(&value).ok_or_else(|| {
+                            LuaError::RuntimeError(String::from(
+                                "expected number of spaces or string of one repeated character for indent",
+                            ))
+                        })?;
+                    }
+                    "compact_tables" => {
+                        this.compact_tables =
+                            field!(value, as_boolean, "compact_tables", "bool");
+                    }
+                    "hexdump_binary" => {
+                        this.hexdump_binary =
+                            field!(value, as_boolean, "hexdump_binary", "bool");
+                    }
+                    "byte_escape" => {
+                        let escape = field!(value, as_string_lossy, "byte_escape", "string");
+
+                        this.byte_escape = match escape.as_str() {
+                            "hex" => ByteEscape::Hex,
+                            "decimal" => ByteEscape::Decimal,
+                            _ => {
+                                return Err(LuaError::RuntimeError(String::from(
+                                    "expected 'hex' or 'decimal' for byte_escape",
+                                )));
+                            }
+                        };
+                    }
+                    "float_precision" => {
+                        if value.is_nil() {
+                            this.numbers.precision = None;
+                            return Ok(());
+                        }
+
+                        this.numbers.precision =
+                            Some(field!(value, as_usize, "float_precision", "integer"));
+                    }
+                    "scientific_threshold" => {
+                        if value.is_nil() {
+                            this.numbers.scientific_threshold = None;
+                            return Ok(());
+                        }
+
+                        this.numbers.scientific_threshold =
+                            Some(field!(value, as_f64, "scientific_threshold", "number"));
+                    }
+                    "integer_base" => {
+                        let base = field!(value, as_string_lossy, "integer_base", "string");
+
+                        this.numbers.integer_base = match base.as_str() {
+                            "decimal" => IntegerBase::Decimal,
+                            "hex" => IntegerBase::Hex,
+                            "binary" => IntegerBase::Binary,
+                            _ => {
+                                return Err(LuaError::RuntimeError(String::from(
+                                    "expected 'decimal', 'hex' or 'binary' for integer_base",
+                                )));
+                            }
+                        };
+                    }
+                    "table_summary" => {
+                        this.table_summary = field!(value, as_boolean, "table_summary", "bool");
+                    }
+                    "show_inheritance" => {
+                        this.show_inheritance =
+                            field!(value, as_boolean, "show_inheritance", "bool");
+                    }
+                    "theme" => {
+                        let theme = field!(value, as_string_lossy, "theme", "string");
+
+                        set_theme(lua, this, &theme)?;
+                    }
                     "history_size" => {
                         this.history_size = field!(value, as_usize, "history_size", "integer");
                     }
                     "color_output" => {
                         this.color_output = field!(value, as_boolean, "color_output", "bool");
                     }
+                    "color_support" => {
+                        let name = field!(value, as_string_lossy, "color_support", "string");
+
+                        this.color_support = match name.as_str() {
+                            "auto" => None,
+                            name => Some(ColorSupport::from_name(name).ok_or_else(|| {
+                                LuaError::RuntimeError(String::from(
+                                    "expected 'auto', 'truecolor', '256', '16', '8' or 'none' for color_support",
+                                ))
+                            })?),
+                        };
+                    }
+                    "colors" => {
+                        let table = field!(value, as_table, "colors", "table");
+
+                        for (key, value) in table.pairs::<String, LuaValue>().flatten() {
+                            set_color(&mut this.colors, &key, value)?;
+                        }
+                    }
+                    "syntax_colors" => {
+                        let table = field!(value, as_table, "syntax_colors", "table");
+
+                        for (key, value) in table.pairs::<String, LuaValue>().flatten() {
+                            set_syntax_color(&mut this.syntax_colors, &key, value)?;
+                        }
+                    }
+                    "rainbow_brackets" => {
+                        this.rainbow_brackets =
+                            field!(value, as_boolean, "rainbow_brackets", "bool");
+                    }
+                    "fuzzy_completion" => {
+                        this.fuzzy_completion =
+                            field!(value, as_boolean, "fuzzy_completion", "bool");
+                    }
+                    "smart_case_completion" => {
+                        this.smart_case_completion =
+                            field!(value, as_boolean, "smart_case_completion", "bool");
+                    }
+                    "max_completions" => {
+                        if value.is_nil() {
+                            this.max_completions = None;
+                            return Ok(());
+                        }
+
+                        this.max_completions =
+                            Some(field!(value, as_usize, "max_completions", "integer"));
+                    }
+                    "min_completion_length" => {
+                        this.min_completion_length =
+                            field!(value, as_usize, "min_completion_length", "integer");
+                    }
+                    "dedupe_shadowed_locals" => {
+                        this.dedupe_shadowed_locals =
+                            field!(value, as_boolean, "dedupe_shadowed_locals", "bool");
+                    }
+                    "encrypt_history" => {
+                        this.encrypt_history = field!(value, as_boolean, "encrypt_history", "bool");
+                    }
+                    "confirm_side_effects" => {
+                        this.confirm_side_effects =
+                            field!(value, as_boolean, "confirm_side_effects", "bool");
+                    }
+                    "calculator" => {
+                        this.calculator = field!(value, as_boolean, "calculator", "bool");
+                    }
+                    "report_globals" => {
+                        this.report_globals = field!(value, as_boolean, "report_globals", "bool");
+                    }
+                    "status_line" => {
+                        this.status_line = field!(value, as_boolean, "status_line", "bool");
+                    }
+                    "print_nil" => {
+                        let print_nil = field!(value, as_string_lossy, "print_nil", "string");
+
+                        this.print_nil = match print_nil.as_str() {
+                            "always" => PrintNil::Always,
+                            "expressions" => PrintNil::Expressions,
+                            "never" => PrintNil::Never,
+                            _ => {
+                                return Err(LuaError::RuntimeError(String::from(
+                                    "expected valid nil printing mode",
+                                )));
+                            }
+                        };
+                    }
+                    "alias" => {
+                        let table = field!(value, as_table, "alias", "table");
+
+                        this.alias = table
+                            .pairs::<String, String>()
+                            .collect::<LuaResult<HashMap<_, _>>>()?;
+                    }
+                    "async_loop" => {
+                        if value.is_nil() {
+                            this.async_loop = None;
+                            return Ok(());
+                        }
+
+                        let async_loop = field!(value, as_string_lossy, "async_loop", "string");
+
+                        this.async_loop = Some(match async_loop.as_str() {
+                            "auto" => AsyncLoop::Auto,
+                            "copas" => AsyncLoop::Copas,
+                            "cqueues" => AsyncLoop::Cqueues,
+                            "luv" => AsyncLoop::Luv,
+                            _ => {
+                                return Err(LuaError::RuntimeError(String::from(
+                                    "expected valid event loop",
+                                )));
+                            }
+                        });
+                    }
+                    "luv_interval" => {
+                        if value.is_nil() {
+                            this.luv_interval = None;
+                            return Ok(());
+                        }
+
+                        let interval = field!(value, as_u64, "luv_interval", "integer");
+
+                        this.luv_interval = Some(Duration::from_millis(interval));
+                    }
+                    "profile" => {
+                        if value.is_nil() {
+                            this.profile = None;
+                            return Ok(());
+                        }
+
+                        let profile = field!(value, as_string_lossy, "profile", "string");
+
+                        match profile.as_str() {
+                            "openresty" => this.profile = Some(Profile::OpenResty),
+                            "love" => this.profile = Some(Profile::Love),
+                            "neovim" => this.profile = Some(Profile::Neovim),
+                            _ => {
+                                return Err(LuaError::RuntimeError(String::from(
+                                    "expected valid profile",
+                                )));
+                            }
+                        }
+                    }
                     key => return Err(LuaError::RuntimeError(format!("invalid key '{key}'"))),
                 }
                 Ok(())
@@ -166,3 +1106,221 @@ impl LuaUserData for Config {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandboxed(lua: Lua, allow: &[&str]) -> Lua {
+        let allow = allow
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+
+        lua.load(sandbox_code(&allow)).exec().unwrap();
+
+        lua
+    }
+
+    fn check(lua: &Lua, code: &str) -> bool {
+        lua.load(code).eval().unwrap()
+    }
+
+    #[test]
+    fn sandbox() {
+        let lua = sandboxed(Lua::new(), &[]);
+
+        assert!(check(&lua, "return io == nil and package.loaded.io == nil"));
+        assert!(check(&lua, "return os.execute == nil and os.time ~= nil"));
+        assert!(check(&lua, "return require == nil and string.dump == nil"));
+
+        let bytecode: LuaString = Lua::new()
+            .load("return string.dump(function() return os end)")
+            .eval()
+            .unwrap();
+
+        lua.globals().set("bytecode", bytecode).unwrap();
+
+        assert!(check(&lua, "return load(bytecode) == nil"));
+        assert!(check(
+            &lua,
+            "local parts = { bytecode:sub(1, 4), bytecode:sub(5) }
+            return load(function() return table.remove(parts, 1) end) == nil"
+        ));
+        assert!(check(&lua, "return load('return 1 + 1')() == 2"));
+
+        let lua = sandboxed(Lua::new(), &["io.write"]);
+
+        assert!(check(&lua, "return io.write ~= nil and io.open == nil"));
+    }
+
+    #[cfg(any(feature = "luajit", feature = "luajit52"))]
+    #[test]
+    fn sandbox_ffi() {
+        // the safe subset of libraries leaves `ffi` out to begin with
+        let lua = sandboxed(unsafe { Lua::unsafe_new() }, &[]);
+
+        assert!(check(
+            &lua,
+            "return ffi == nil and package.loaded.ffi == nil and package.preload.ffi == nil"
+        ));
+        assert!(check(
+            &lua,
+            "return loadstring(string.char(27) .. 'LJ') == nil"
+        ));
+    }
+
+    #[test]
+    fn json() {
+        let lua = Lua::new();
+
+        lua.load(LIBRARY_CODE).exec().unwrap();
+        lua.load("json = require('manen').json").exec().unwrap();
+
+        let error = |code: &str| lua.load(code).exec().unwrap_err().to_string();
+
+        assert!(check(
+            &lua,
+            r#"return json.encode({ b = { 1, 2, {} }, a = true, c = 1.5 })
+                == '{"a":true,"b":[1,2,[]],"c":1.5}'"#
+        ));
+        assert!(check(
+            &lua,
+            r#"return json.encode('a\n"\\\1') == '"a\\n\\"\\\\\\u0001"'"#
+        ));
+        assert!(check(
+            &lua,
+            r#"return json.decode('"\\"\\\\\\/\\b\\f\\n\\r\\t\\u00e9"') == '"\\/\b\f\n\r\té'"#
+        ));
+
+        // a surrogate pair is one character, encoded back as its UTF-8 bytes
+        assert!(check(
+            &lua,
+            r#"local s = json.decode('"\\ud83d\\ude00"')
+            return s == '\240\159\152\128' and json.encode(s) == '"' .. s .. '"'"#
+        ));
+
+        assert!(check(
+            &lua,
+            r#"local t = json.decode(' {"a": null, "b": [1, null, 3], "c": {"d": -2e3}} ')
+            return t.a == nil and t.b[2] == nil and t.b[3] == 3 and t.c.d == -2000"#
+        ));
+        assert!(check(
+            &lua,
+            r#"local text = '{"a":[1,2,{"b":"x"}],"c":false}'
+            return json.encode(json.decode(text)) == text"#
+        ));
+
+        assert!(error(r#"json.decode('{"a":}')"#).contains("invalid JSON at character 6"));
+        assert!(error("json.decode('[1, 2')").contains("invalid JSON"));
+        assert!(error(r#"json.decode('"\\x"')"#).contains("invalid escape"));
+        assert!(error("json.decode('1 2')").contains("trailing characters"));
+
+        // both would be written as the key "1"
+        assert!(error("json.encode({ [1] = 'a', ['1'] = 'b', x = 1 })").contains("both 1"));
+        assert!(check(
+            &lua,
+            r#"return json.encode({ [1] = 'a', [3] = 'c' }) == '{"1":"a","3":"c"}'"#
+        ));
+    }
+
+    #[test]
+    fn coroutines() {
+        let lua = unsafe { Lua::unsafe_new() };
+
+        lua.load(COROUTINE_CODE).exec().unwrap();
+
+        let call = |name: &str, arg: LuaValue| {
+            lua.globals()
+                .get::<LuaFunction>(name)
+                .unwrap()
+                .call::<Option<i64>>(arg)
+        };
+        let code = |code: &str| LuaValue::String(lua.create_string(code).unwrap());
+
+        // an input that yields is resumed by `.resume` with what it gets back
+        assert_eq!(
+            Some(1),
+            call("__manen_eval", code("return coroutine.yield(1) * 2")).unwrap()
+        );
+        assert_eq!(
+            Some(42),
+            call("__manen_resume", LuaValue::Integer(21)).unwrap()
+        );
+        assert!(call("__manen_resume", LuaValue::Nil).is_err());
+
+        let error = call(
+            "__manen_eval",
+            code("local function f() error('boom') end f()"),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("boom") && error.contains("stack traceback"));
+
+        // the sandbox replaces `load` after this was loaded, input still can't be bytecode
+        let bytecode: LuaString = lua
+            .load("return string.dump(function() return 1 end)")
+            .eval()
+            .unwrap();
+
+        assert!(call("__manen_eval", LuaValue::String(bytecode)).is_err());
+        assert_eq!(Some(2), call("__manen_eval", code("return 1 + 1")).unwrap());
+    }
+
+    #[test]
+    fn async_values() {
+        let lua = Lua::new();
+
+        lua.load(ASYNC_CODE).exec().unwrap();
+
+        let await_code = |code: &str| {
+            lua.globals()
+                .get::<LuaFunction>("__manen_await")
+                .unwrap()
+                .call::<Option<i64>>((crate::parse::auto_return(code), "auto"))
+                .unwrap()
+        };
+
+        // without a loop loaded the input runs as is, with what the REPL returns for it
+        assert_eq!(Some(1), await_code("local x = 1; x"));
+        assert_eq!(Some(2), await_code("1 + 1"));
+        assert_eq!(None, await_code("local y = 2"));
+    }
+
+    #[test]
+    fn lua_version_overrides() {
+        let mut config = Config {
+            executor: Executor::Auto,
+            lua_version: Some(String::from("5.3")),
+            seed: Some(1),
+            ..Config::default()
+        };
+
+        Overrides {
+            lua_version: Some(String::from("5.1")),
+            ..Overrides::default()
+        }
+        .apply(&mut config);
+
+        // only the flags that were given replace the config
+        assert_eq!(Some("5.1"), config.lua_version.as_deref());
+        assert_eq!(Some(1), config.seed);
+        assert!(!config.sandbox);
+
+        config.lua_preference = ["lua", "lua51", "luajit"].map(String::from).to_vec();
+        assert_eq!(["lua51", "lua5.1"], config.auto_preference().as_slice());
+
+        config.lua_version = None;
+        assert_eq!(config.lua_preference, config.auto_preference());
+
+        // nothing is detected over ssh or in a container, the names are used as is
+        assert_eq!("lua", config.remote_program());
+
+        config.lua_version = Some(String::from("5.3"));
+        assert_eq!("lua5.3", config.remote_program());
+
+        config.system_lua = Some(PathBuf::from("/opt/lua/bin/lua"));
+        assert_eq!("/opt/lua/bin/lua", config.remote_program());
+    }
+}
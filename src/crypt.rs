@@ -0,0 +1,177 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, Nonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, rand_core::RngCore},
+};
+use nix::sys::termios::{LocalFlags, SetArg, tcgetattr, tcsetattr};
+use thiserror::Error;
+
+// files start with this, then the salt, nonce and ciphertext
+const MAGIC: &[u8] = b"manen-enc1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Error)]
+pub enum CryptError {
+    #[error("not a file encrypted by manen")]
+    Format,
+    #[error("wrong passphrase or corrupted file")]
+    Decrypt,
+    #[error("failed to encrypt")]
+    Encrypt,
+    #[error("failed to derive key from passphrase")]
+    Key,
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Key derived from a passphrase, encrypting files that share its salt
+pub struct Vault {
+    cipher: ChaCha20Poly1305,
+    salt: [u8; SALT_LEN],
+}
+
+fn split_header(data: &[u8]) -> Result<([u8; SALT_LEN], &[u8]), CryptError> {
+    let rest = data.strip_prefix(MAGIC).ok_or(CryptError::Format)?;
+
+    if rest.len() < SALT_LEN + NONCE_LEN {
+        return Err(CryptError::Format);
+    }
+
+    let (salt, rest) = rest.split_at(SALT_LEN);
+
+    Ok((salt.try_into().map_err(|_| CryptError::Format)?, rest))
+}
+
+impl Vault {
+    /// Derives the key for `path`, reusing its salt when the file already exists
+    pub fn open(passphrase: &str, path: &Path) -> Result<Self, CryptError> {
+        let salt = match fs::read(path) {
+            Ok(data) => split_header(&data)?.0,
+            Err(_) => {
+                let mut salt = [0; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+
+                salt
+            }
+        };
+
+        Self::with_salt(passphrase, salt)
+    }
+
+    fn with_salt(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<Self, CryptError> {
+        let mut key = [0; 32];
+
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|_| CryptError::Key)?;
+
+        Ok(Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            salt,
+        })
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CryptError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| CryptError::Encrypt)?;
+
+        Ok([MAGIC, &self.salt, nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn unseal(&self, data: &[u8]) -> Result<Vec<u8>, CryptError> {
+        let (salt, rest) = split_header(data)?;
+
+        // another salt means another key, so the passphrase can't be checked here
+        if salt != self.salt {
+            return Err(CryptError::Decrypt);
+        }
+
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CryptError::Decrypt)
+    }
+
+    /// Seals `plaintext` into `path`
+    pub fn write(&self, path: &Path, plaintext: &[u8]) -> Result<(), CryptError> {
+        Ok(fs::write(path, self.seal(plaintext)?)?)
+    }
+}
+
+/// Decrypts any file written by a vault with the same passphrase
+pub fn decrypt(passphrase: &str, data: &[u8]) -> Result<Vec<u8>, CryptError> {
+    let (salt, _) = split_header(data)?;
+
+    Vault::with_salt(passphrase, salt)?.unseal(data)
+}
+
+/// `MANEN_PASSPHRASE`, or else asked for on the terminal without echoing it
+pub fn passphrase() -> io::Result<String> {
+    if let Ok(passphrase) = env::var("MANEN_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+
+    eprint!("passphrase: ");
+    io::stderr().flush()?;
+
+    let stdin = io::stdin();
+    let original = tcgetattr(&stdin).ok();
+
+    if let Some(original) = &original {
+        let mut hidden = original.clone();
+        hidden.local_flags.remove(LocalFlags::ECHO);
+
+        let _ = tcsetattr(&stdin, SetArg::TCSANOW, &hidden);
+    }
+
+    let mut passphrase = String::new();
+    let read = stdin.read_line(&mut passphrase);
+
+    if let Some(original) = &original {
+        let _ = tcsetattr(&stdin, SetArg::TCSANOW, original);
+    }
+
+    eprintln!();
+    read?;
+
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history");
+
+        let vault = Vault::open("hunter2", &path).unwrap();
+        vault.write(&path, b"token = 'abc'").unwrap();
+
+        let data = fs::read(&path).unwrap();
+
+        assert!(data.starts_with(MAGIC));
+        assert!(!data.windows(3).any(|w| w == b"abc"));
+
+        let reopened = Vault::open("hunter2", &path).unwrap();
+
+        assert_eq!(b"token = 'abc'".to_vec(), reopened.unseal(&data).unwrap());
+        assert_eq!(
+            b"token = 'abc'".to_vec(),
+            decrypt("hunter2", &data).unwrap()
+        );
+        assert!(decrypt("wrong", &data).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+
+use mlua::prelude::*;
+use nu_ansi_term::Color;
+
+use crate::inspect::{InspectLimits, display_path_key, inspect, is_redacted, key_order, redacted};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+struct Entry {
+    change: Change,
+    path: String,
+    old: Option<LuaValue>,
+    new: Option<LuaValue>,
+    /// Under a key of `redact_keys`, the values are not shown
+    redacted: bool,
+}
+
+fn path_name(path: &str) -> &str {
+    let path = path.strip_prefix('.').unwrap_or(path);
+
+    if path.is_empty() { "(root)" } else { path }
+}
+
+fn sorted_keys(tbl: &LuaTable) -> Vec<LuaValue> {
+    let mut keys = tbl
+        .pairs::<LuaValue, LuaValue>()
+        .flatten()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    keys.sort_by(key_order);
+    keys
+}
+
+struct Walk<'a> {
+    limits: InspectLimits,
+    visited: HashSet<(usize, usize)>,
+    entries: &'a mut Vec<Entry>,
+}
+
+// `redacted` once any key of the path is, so changes deeper in a secret table stay hidden
+fn compare(
+    a: &LuaValue,
+    b: &LuaValue,
+    path: String,
+    redacted: bool,
+    walk: &mut Walk,
+) -> LuaResult<()> {
+    let (LuaValue::Table(a_tbl), LuaValue::Table(b_tbl)) = (a, b) else {
+        if a != b {
+            walk.entries.push(Entry {
+                change: Change::Changed,
+                path,
+                old: Some(a.clone()),
+                new: Some(b.clone()),
+                redacted,
+            });
+        }
+
+        return Ok(());
+    };
+
+    // tables reached again through a cycle were compared the first time
+    if !walk
+        .visited
+        .insert((a_tbl.to_pointer() as usize, b_tbl.to_pointer() as usize))
+    {
+        return Ok(());
+    }
+
+    for key in sorted_keys(a_tbl) {
+        let old = a_tbl.raw_get::<LuaValue>(key.clone())?;
+        let new = b_tbl.raw_get::<LuaValue>(key.clone())?;
+        let path = format!("{path}{}", display_path_key(&key, false));
+        let redacted = redacted || is_redacted(&key, walk.limits);
+
+        if new.is_nil() {
+            walk.entries.push(Entry {
+                change: Change::Removed,
+                path,
+                old: Some(old),
+                new: None,
+                redacted,
+            });
+        } else {
+            compare(&old, &new, path, redacted, walk)?;
+        }
+    }
+
+    for key in sorted_keys(b_tbl) {
+        if a_tbl.raw_get::<LuaValue>(key.clone())?.is_nil() {
+            walk.entries.push(Entry {
+                change: Change::Added,
+                path: format!("{path}{}", display_path_key(&key, false)),
+                old: None,
+                new: Some(b_tbl.raw_get(key.clone())?),
+                redacted: redacted || is_redacted(&key, walk.limits),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn changes(a: &LuaValue, b: &LuaValue, limits: InspectLimits) -> LuaResult<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut walk = Walk {
+        limits,
+        visited: HashSet::new(),
+        entries: &mut entries,
+    };
+
+    compare(a, b, String::new(), false, &mut walk)?;
+
+    Ok(entries)
+}
+
+/// Added, removed and changed keys of `b` compared to `a`, one per line, empty when
+/// they are equal
+pub fn diff(
+    a: &LuaValue,
+    b: &LuaValue,
+    colorize: bool,
+    limits: InspectLimits,
+) -> LuaResult<String> {
+    let mut lines = Vec::new();
+
+    for entry in changes(a, b, limits)? {
+        let value = |value: &Option<LuaValue>| match value {
+            Some(_) if entry.redacted => Ok(redacted(colorize)),
+            Some(value) => inspect(value, colorize, limits),
+            None => Ok(String::new()),
+        };
+
+        let (sign, color, text) = match entry.change {
+            Change::Added => ("+", Color::Green, value(&entry.new)?),
+            Change::Removed => ("-", Color::Red, value(&entry.old)?),
+            Change::Changed => (
+                "~",
+                Color::Yellow,
+                format!("{} -> {}", value(&entry.old)?, value(&entry.new)?),
+            ),
+        };
+
+        let label = format!("{sign} {}", path_name(&entry.path));
+        let label = if colorize {
+            color.paint(label).to_string()
+        } else {
+            label
+        };
+
+        lines.push(format!("{label} = {text}"));
+    }
+
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_keys() {
+        let lua = Lua::new();
+
+        let a = lua
+            .load("return { name = 'a', n = 1, nested = { x = 1, y = 2 }, [5] = true }")
+            .eval::<LuaValue>()
+            .unwrap();
+        let b = lua
+            .load("return { name = 'b', n = 1, nested = { x = 1, z = 3 }, list = {} }")
+            .eval::<LuaValue>()
+            .unwrap();
+
+        let found = changes(&a, &b, InspectLimits::default())
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.change, entry.path))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (Change::Removed, String::from("[5]")),
+                (Change::Changed, String::from(".name")),
+                (Change::Removed, String::from(".nested.y")),
+                (Change::Added, String::from(".nested.z")),
+                (Change::Added, String::from(".list")),
+            ],
+            found
+        );
+
+        assert!(
+            changes(&a, &a, InspectLimits::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn redacted_values() {
+        let lua = Lua::new();
+
+        let a = lua
+            .load("return { password = 'old', auth = { key = 'k1' }, user = 'ana' }")
+            .eval::<LuaValue>()
+            .unwrap();
+        let b = lua
+            .load("return { password = 'new', auth = { key = 'k2' }, user = 'bo' }")
+            .eval::<LuaValue>()
+            .unwrap();
+
+        // the same keys as the other tests, which may run at the same time
+        crate::inspect::set_redacted_keys(&[
+            String::from("auth"),
+            String::from("password"),
+            String::from("token"),
+        ]);
+
+        let limits = InspectLimits {
+            redact: true,
+            ..InspectLimits::default()
+        };
+        let text = diff(&a, &b, false, limits).unwrap();
+
+        assert!(text.contains("~ password = <redacted> -> <redacted>"));
+        assert!(text.contains("~ auth.key = <redacted> -> <redacted>"));
+        assert!(text.contains("~ user = \"ana\" -> \"bo\""));
+        assert!(
+            !["old", "new", "k1", "k2"]
+                .iter()
+                .any(|secret| text.contains(secret))
+        );
+    }
+}
@@ -1,88 +1,601 @@
 use std::{
+    collections::HashSet,
+    fs,
+    io::{self, IsTerminal, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
     process,
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, Ordering},
+        mpsc,
     },
+    thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
+use clap::ValueEnum;
 use directories::ProjectDirs;
+use emmylua_parser::LuaParser;
 use mlua::prelude::*;
+use nu_ansi_term::Color;
 use reedline::{
-    DefaultPrompt, DefaultPromptSegment, EditCommand, Emacs, FileBackedHistory, IdeMenu, KeyCode,
-    KeyModifiers, MenuBuilder, Reedline, ReedlineEvent, ReedlineMenu, Signal,
-    default_emacs_keybindings,
+    DefaultPrompt, DefaultPromptSegment, EditCommand, Emacs, FileBackedHistory, Highlighter,
+    History, HistoryItem, IdeMenu, KeyCode, KeyModifiers, MenuBuilder, Reedline, ReedlineEvent,
+    ReedlineMenu, SearchDirection, SearchQuery, Signal, default_emacs_keybindings,
 };
 
+#[cfg(feature = "fennel")]
+use crate::fennel::{FennelHighlighter, FennelValidator};
 use crate::{
-    completion::LuaCompleter, config::Config, hinter::LuaHinter, inspect::display_basic,
-    lua::LuaExecutor, parse::LuaHighlighter, validator::LuaValidator,
+    browse::Browser,
+    calculator, commands,
+    completion::{self, GlobalsCache, Loading, LuaCompleter, SessionIndex},
+    config::{Config, Executor, Overrides, PrintNil},
+    crypt::{self, CryptError, Vault},
+    hinter::LuaHinter,
+    inspect::{self, InspectLimits, display_basic, lua_string_literal},
+    interpreters,
+    language::Language,
+    lua::LuaExecutor,
+    parse::{self, LiveHighlighter, LuaHighlighter},
+    report,
+    share::{self, Evaluate, Share},
+    snippets,
+    startup::StartupProfile,
+    tasks::{self, Task, Tasks, WakingEditMode},
+    transcript::Transcript,
+    validator::{self, LuaValidator},
 };
 
+// only tick luv if the session has loaded it
+const LUV_TICK: &str = "local uv = package and package.loaded.luv
+if uv then uv.run('nowait') end";
+
+// evaluations quicker than this never show the status line
+const STATUS_DELAY: Duration = Duration::from_secs(1);
+const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+// completions wait at most this long on the executor before showing partial results
+const COMPLETION_BUDGET: Duration = Duration::from_millis(30);
+
+// bytes of a printed value kept for `.export`, the rest only goes to stdout
+const TRANSCRIPT_OUTPUT_LIMIT: usize = 1 << 20;
+
+/// Elapsed time, heap size and collections shown on stderr while code runs, drawn by one
+/// thread kept for the whole session
+struct StatusLine {
+    state: Arc<Mutex<StatusState>>,
+    thread: Thread,
+}
+
+#[derive(Default)]
+struct StatusState {
+    /// Start of the evaluation in progress and the collections counted before it
+    run: Option<(Instant, u64)>,
+    shown: bool,
+}
+
+impl StatusLine {
+    fn spawn(lua_executor: Arc<dyn LuaExecutor>, color: bool) -> Self {
+        let state = Arc::new(Mutex::new(StatusState::default()));
+        let inner_state = state.clone();
+
+        let handle = thread::spawn(move || {
+            loop {
+                let mut state = inner_state.lock().unwrap();
+
+                let Some((start, initial_collections)) = state.run else {
+                    drop(state);
+                    thread::park();
+
+                    continue;
+                };
+
+                if start.elapsed() >= STATUS_DELAY {
+                    let mut status = format!("running for {:.1?}", start.elapsed());
+
+                    if let Some((used, collections)) = lua_executor.memory() {
+                        status.push_str(&format!(
+                            ", {:.1} MiB, {} gc",
+                            used as f64 / (1024.0 * 1024.0),
+                            collections.saturating_sub(initial_collections)
+                        ));
+                    }
+
+                    if color {
+                        status = Color::DarkGray.paint(status).to_string();
+                    }
+
+                    eprint!("\r\x1b[2K{status}");
+                    let _ = io::stderr().flush();
+
+                    state.shown = true;
+                }
+
+                drop(state);
+                thread::park_timeout(STATUS_INTERVAL);
+            }
+        });
+
+        Self {
+            state,
+            thread: handle.thread().clone(),
+        }
+    }
+
+    fn start(&self, collections: u64) {
+        self.state.lock().unwrap().run = Some((Instant::now(), collections));
+        self.thread.unpark();
+    }
+
+    /// Clears the line before returning, so output printed next isn't drawn over
+    fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.run = None;
+
+        if state.shown {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+
+            state.shown = false;
+        }
+    }
+}
+
+/// What the prompt shows, known without starting the executor to ask for `_VERSION`
+fn prompt_label(config: &Config) -> String {
+    match (config.executor, config.system_program()) {
+        (Executor::System | Executor::Auto | Executor::Embedded, Some(path)) => path
+            .file_name()
+            .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+            .to_string(),
+        (Executor::Remote, _) => config
+            .remote_agent
+            .as_ref()
+            .and_then(|target| target.split_once('@'))
+            .map_or_else(|| String::from("remote"), |(_, host)| host.to_string()),
+        (Executor::Ssh, _) => format!("lua@{}", config.ssh_host.as_deref().unwrap_or("ssh")),
+        (Executor::Docker, _) => config
+            .docker_image
+            .clone()
+            .unwrap_or_else(|| String::from("docker")),
+        // `_VERSION` of the embedded VM, which LuaJIT leaves at 5.1
+        _ => match interpreters::EMBEDDED_VERSION {
+            "luajit" => String::from("Lua 5.1"),
+            version => format!("Lua {version}"),
+        },
+    }
+}
+
+fn format_value(config: &Config, value: LuaValue, limits: InspectLimits) -> LuaResult<String> {
+    match value {
+        LuaValue::Table(tbl) => config
+            .table_format
+            .format(&tbl, config.color_output, limits),
+        value => inspect::inspect(&value, config.color_output, limits),
+    }
+}
+
+/// `format_value` written to `out` as it is produced
+fn write_value(
+    out: &mut impl Write,
+    config: &Config,
+    value: LuaValue,
+    limits: InspectLimits,
+) -> LuaResult<()> {
+    match value {
+        LuaValue::Table(tbl) => {
+            config
+                .table_format
+                .format_to(out, &tbl, config.color_output, limits)
+        }
+        value => inspect::inspect_to(out, &value, config.color_output, limits),
+    }
+}
+
+/// Stdout that also keeps the first `TRANSCRIPT_OUTPUT_LIMIT` bytes written to it
+struct TranscriptTee {
+    out: io::BufWriter<io::StdoutLock<'static>>,
+    kept: Vec<u8>,
+    written: usize,
+}
+
+impl TranscriptTee {
+    fn new() -> Self {
+        Self {
+            out: io::BufWriter::new(io::stdout().lock()),
+            kept: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// What was kept, noting how much was left out
+    fn kept(&self) -> String {
+        let kept = String::from_utf8_lossy(&self.kept);
+
+        if self.written > self.kept.len() {
+            format!("{kept}… ({} bytes)", self.written)
+        } else {
+            kept.to_string()
+        }
+    }
+}
+
+impl Write for TranscriptTee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        let room = TRANSCRIPT_OUTPUT_LIMIT.saturating_sub(self.kept.len());
+
+        self.kept.extend_from_slice(&buf[..written.min(room)]);
+        self.written += written;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Names functions in `code` would make global that aren't globals before or after
+/// running it, so a counter the same input sets up isn't warned about
+fn function_global_warnings(
+    code: &str,
+    before: &HashSet<String>,
+    after: &HashSet<String>,
+) -> Vec<(String, Option<String>)> {
+    let tree = LuaParser::parse(code, parse::config());
+
+    completion::function_globals(&tree)
+        .into_iter()
+        .filter(|(name, _)| !before.contains(name) && !after.contains(name))
+        .collect()
+}
+
+/// Runs and code of `.time [runs] <expr>`, in Lua a leading number is only the count
+/// when the rest runs on its own, so `.time 1 + 1` times `1 + 1` once
+fn time_args(args: &str, lua: bool) -> (u32, &str) {
+    let Some((count, code)) = args.split_once(char::is_whitespace) else {
+        return (1, args);
+    };
+
+    match count.parse::<u32>() {
+        Ok(runs) if !lua || validator::is_valid(code.trim()) => (runs.max(1), code.trim()),
+        _ => (1, args),
+    }
+}
+
+/// Where history is kept in plain text, the default
+fn history_path() -> Option<PathBuf> {
+    ProjectDirs::from("gay.gayest", "", "Manen").map(|dirs| dirs.data_dir().join("history"))
+}
+
+/// Where history is kept when `encrypt_history` is set
+fn encrypted_history_path() -> Option<PathBuf> {
+    ProjectDirs::from("gay.gayest", "", "Manen").map(|dirs| dirs.data_dir().join("history.enc"))
+}
+
+/// Entries of the encrypted history, separated by NUL since they can span lines
+fn read_encrypted_history(vault: &Vault) -> Result<Vec<String>, CryptError> {
+    let Some(path) = encrypted_history_path().filter(|path| path.exists()) else {
+        return Ok(Vec::new());
+    };
+
+    let history = vault.unseal(&fs::read(path)?)?;
+
+    Ok(String::from_utf8_lossy(&history)
+        .split('\0')
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn line_editor(
+    config: &Config,
+    lua_executor: &Arc<dyn LuaExecutor>,
+    globals: &GlobalsCache,
+    session: &Arc<Mutex<SessionIndex>>,
+    language: Language,
+    vault: Option<&Vault>,
+    tasks: &Tasks,
+) -> Reedline {
+    let mut keybindings = default_emacs_keybindings();
+    keybindings.add_binding(
+        KeyModifiers::NONE,
+        KeyCode::Tab,
+        ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(String::from("completion_menu")),
+            ReedlineEvent::MenuNext,
+        ]),
+    );
+    keybindings.add_binding(
+        KeyModifiers::ALT,
+        KeyCode::Enter,
+        ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
+    );
+    keybindings.add_binding(
+        KeyModifiers::ALT,
+        KeyCode::Char('n'),
+        ReedlineEvent::ExecuteHostCommand(String::from(snippets::NEXT_PLACEHOLDER)),
+    );
+
+    let ide_menu = IdeMenu::default().with_name("completion_menu");
+    let loading = Loading::default();
+
+    let mut editor = Reedline::create()
+        .with_edit_mode(Box::new(WakingEditMode::new(
+            Box::new(Emacs::new(keybindings)),
+            tasks.clone(),
+        )))
+        .with_menu(ReedlineMenu::EngineCompleter(Box::new(ide_menu)))
+        .with_ansi_colors(config.color_output);
+
+    editor = match language {
+        Language::Lua => editor
+            .with_validator(Box::new(LuaValidator::new()))
+            .with_completer(Box::new(
+                LuaCompleter::new(lua_executor.clone())
+                    .with_fuzzy(config.fuzzy_completion)
+                    .with_smart_case(config.smart_case_completion)
+                    .with_session(session.clone())
+                    .with_max_suggestions(config.max_completions)
+                    .with_min_query_length(config.min_completion_length)
+                    .with_dedupe_shadowed(config.dedupe_shadowed_locals)
+                    .with_budget(COMPLETION_BUDGET)
+                    .with_globals(globals.clone())
+                    .with_loading(loading.clone()),
+            ))
+            .with_highlighter(Box::new(LiveHighlighter::new(globals.clone())))
+            .with_hinter(Box::new(
+                LuaHinter::new(lua_executor.clone()).with_loading(loading),
+            )),
+        // completion and hints work on the Lua syntax tree
+        #[cfg(feature = "fennel")]
+        Language::Fennel => editor
+            .with_validator(Box::new(FennelValidator))
+            .with_highlighter(Box::new(FennelHighlighter)),
+        // close enough to Lua for highlighting, the hinter would flag type annotations
+        #[cfg(feature = "teal")]
+        Language::Teal => editor
+            .with_validator(Box::new(LuaValidator::new()))
+            .with_completer(Box::new(
+                LuaCompleter::new(lua_executor.clone())
+                    .with_fuzzy(config.fuzzy_completion)
+                    .with_smart_case(config.smart_case_completion)
+                    .with_session(session.clone())
+                    .with_max_suggestions(config.max_completions)
+                    .with_min_query_length(config.min_completion_length)
+                    .with_dedupe_shadowed(config.dedupe_shadowed_locals)
+                    .with_budget(COMPLETION_BUDGET)
+                    .with_globals(globals.clone()),
+            ))
+            .with_highlighter(Box::new(LuaHighlighter::default())),
+    };
+
+    // kept in memory and sealed into the file after each line by `Editor`
+    if let Some(vault) = vault {
+        if let Ok(mut history) = FileBackedHistory::new(config.history_size) {
+            // plain history from before `encrypt_history` was set is older, `Editor`
+            // seals it and removes the file
+            let plain = history_path()
+                .filter(|path| path.exists())
+                .and_then(|path| FileBackedHistory::with_file(config.history_size, path).ok())
+                .and_then(|plain| {
+                    plain
+                        .search(SearchQuery::everything(SearchDirection::Forward, None))
+                        .ok()
+                })
+                .unwrap_or_default()
+                .into_iter()
+                .map(|item| item.command_line);
+
+            for line in plain.chain(read_encrypted_history(vault).unwrap_or_default()) {
+                let _ = history.save(HistoryItem::from_command_line(line));
+            }
+
+            editor = editor.with_history(Box::new(history));
+        }
+    } else if let Some(path) = history_path() {
+        let history = FileBackedHistory::with_file(config.history_size, path);
+
+        if let Ok(history) = history {
+            editor = editor.with_history(Box::new(history))
+        }
+    }
+
+    editor
+}
+
 pub struct Editor {
     prompt: DefaultPrompt,
     editor: Reedline,
     lua_executor: Arc<dyn LuaExecutor>,
+    /// Read by the completer and highlighter, refreshed after each evaluation
+    globals: GlobalsCache,
     config: Config,
+    transcript: Transcript,
+    language: Language,
+    // every line submitted so far, including earlier sessions through the history
+    submitted: HashSet<String>,
+    session: Arc<Mutex<SessionIndex>>,
+    // the last line of code evaluated, what `.snippet save <name>` stores
+    last_input: Option<String>,
+    share: Option<Arc<Share>>,
+    // who the output is broadcast as, the guest while one's input runs
+    author: String,
+    tasks: Tasks,
+    vault: Option<Vault>,
+    status: Option<StatusLine>,
+    /// When the snapshot last offered by `offer_restore` was taken
+    restore_offered: Option<Instant>,
 }
 
 impl Editor {
-    pub fn new() -> LuaResult<Self> {
-        let config = Config::load()?;
-        let lua_executor = config.get_executor().map_err(LuaError::external)?;
+    /// Prints how long each step took when `startup_profile` is set
+    pub fn new(
+        language: Language,
+        overrides: &Overrides,
+        startup_profile: bool,
+    ) -> LuaResult<Self> {
+        let mut profile = StartupProfile::new(startup_profile);
+        let mut config = Config::load()?;
+
+        overrides.apply(&mut config);
+
+        config.apply_color_support();
+        inspect::set_value_colors(config.colors);
+        parse::set_syntax_colors(config.syntax_colors);
+        parse::set_rainbow_brackets(config.rainbow_brackets);
+        inspect::set_redacted_keys(&config.redact_keys);
+        inspect::set_number_format(config.numbers);
+        inspect::set_byte_escape(config.byte_escape);
+        profile.step("config");
 
-        let version: String = lua_executor.globals()?.get("_VERSION")?;
+        // started by the first evaluation, completion or highlight instead of here
+        let lua_executor = config.deferred_executor(language.prelude());
+
+        // a compiler that isn't installed fails here rather than at the first input
+        if language.prelude().is_some() {
+            lua_executor.globals()?;
+        }
 
         let prompt = DefaultPrompt::new(
-            DefaultPromptSegment::Basic(version),
+            DefaultPromptSegment::Basic(prompt_label(&config)),
             DefaultPromptSegment::Empty,
         );
 
-        let mut keybindings = default_emacs_keybindings();
-        keybindings.add_binding(
-            KeyModifiers::NONE,
-            KeyCode::Tab,
-            ReedlineEvent::UntilFound(vec![
-                ReedlineEvent::Menu(String::from("completion_menu")),
-                ReedlineEvent::MenuNext,
-            ]),
-        );
-        keybindings.add_binding(
-            KeyModifiers::ALT,
-            KeyCode::Enter,
-            ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
+        profile.step("prompt");
+
+        let vault = match encrypted_history_path() {
+            Some(path) if config.encrypt_history => {
+                let passphrase = crypt::passphrase().map_err(LuaError::external)?;
+                let vault = Vault::open(&passphrase, &path).map_err(LuaError::external)?;
+
+                // a wrong passphrase fails here rather than losing the history later
+                read_encrypted_history(&vault).map_err(LuaError::external)?;
+
+                Some(vault)
+            }
+            _ => None,
+        };
+
+        if vault.is_some() {
+            profile.step("history decryption");
+        }
+
+        let session = Arc::new(Mutex::new(SessionIndex::default()));
+        let tasks = Tasks::default();
+        let globals = GlobalsCache::new(lua_executor.clone());
+        let editor = line_editor(
+            &config,
+            &lua_executor,
+            &globals,
+            &session,
+            language,
+            vault.as_ref(),
+            &tasks,
         );
+        profile.step("line editor");
 
-        let ide_menu = IdeMenu::default().with_name("completion_menu");
+        let history = editor
+            .history()
+            .search(SearchQuery::everything(SearchDirection::Forward, None))
+            .map(|items| {
+                items
+                    .into_iter()
+                    .map(|item| item.command_line)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
 
-        let mut editor = Reedline::create()
-            .with_validator(Box::new(LuaValidator::new()))
-            .with_completer(Box::new(LuaCompleter::new(
-                lua_executor.clone() as Arc<dyn LuaExecutor>
-            )))
-            .with_highlighter(Box::new(LuaHighlighter))
-            .with_hinter(Box::new(LuaHinter))
-            .with_edit_mode(Box::new(Emacs::new(keybindings)))
-            .with_menu(ReedlineMenu::EngineCompleter(Box::new(ide_menu)))
-            .with_ansi_colors(config.color_output);
+        let submitted = history.iter().cloned().collect();
+        profile.step("history");
 
-        if let Some(proj_dirs) = ProjectDirs::from("gay.gayest", "", "Manen") {
-            let history = FileBackedHistory::with_file(
-                config.history_size,
-                proj_dirs.data_dir().join("history"),
-            );
+        // oldest first so recent lines end up weighing more, indexed while the prompt is up
+        let index = session.clone();
 
-            if let Ok(history) = history {
-                editor = editor.with_history(Box::new(history))
+        thread::spawn(move || {
+            let mut session = index.lock().unwrap();
+
+            for line in &history {
+                session.record_usage(line);
             }
+        });
+
+        if startup_profile {
+            // normally paid on first use, started here to show up in the breakdown
+            let started = lua_executor.globals();
+            profile.step("executor (deferred)");
+            started?;
+
+            profile.print(config.color_output);
         }
 
-        Ok(Self {
+        let status = (config.status_line && io::stderr().is_terminal())
+            .then(|| StatusLine::spawn(lua_executor.clone(), config.color_output));
+
+        let editor = Self {
             prompt,
             editor,
             lua_executor,
+            globals,
             config,
-        })
+            transcript: Transcript::new(config.seed),
+            language,
+            submitted,
+            session,
+            last_input: None,
+            share: None,
+            author: String::from("host"),
+            tasks,
+            vault,
+            status,
+            restore_offered: None,
+        };
+
+        // the plain history was read into the vault by `line_editor`, kept if sealing fails
+        if let Some(path) = history_path().filter(|path| editor.vault.is_some() && path.exists()) {
+            match editor.save_encrypted_history() {
+                Ok(()) => fs::remove_file(path).map_err(LuaError::external)?,
+                Err(e) => eprintln!("failed to encrypt the plain history: {e}"),
+            }
+        }
+
+        Ok(editor)
+    }
+
+    /// Opens the session to `manen join` on `bind` and `port`, guests only watch when
+    /// `read_only`
+    pub fn share(mut self, bind: IpAddr, port: u16, read_only: bool) -> LuaResult<Self> {
+        let tasks = self.tasks.clone();
+
+        // evaluated by the main thread like the host's input, see `run_tasks`
+        let evaluate: Arc<Evaluate> = Arc::new(move |name: &str, input: &str| {
+            let (reply, answer) = mpsc::channel();
+
+            tasks.push(Task::Guest {
+                name: name.to_string(),
+                input: input.to_string(),
+                reply,
+            });
+
+            answer
+                .recv()
+                .unwrap_or_else(|_| Err(String::from("the session ended")))
+        });
+
+        let share = Share::start(bind, port, read_only, self.config.color_output, evaluate)
+            .map_err(LuaError::external)?;
+
+        println!(
+            "sharing session, join with `{}`",
+            share.join_command(bind, port)
+        );
+
+        self.share = Some(share);
+
+        Ok(self)
     }
 
     fn register_ctrl_c(&self, is_running_lua: Arc<AtomicBool>) {
@@ -98,22 +611,93 @@ impl Editor {
         .unwrap();
     }
 
+    /// Queues a tick every `interval`, run by the main thread between lines
+    fn spawn_luv_ticker(&self, interval: Duration) {
+        let tasks = self.tasks.clone();
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                tasks.push_luv_tick();
+            }
+        });
+    }
+
     pub fn run(mut self) {
         let is_running_lua = Arc::new(AtomicBool::new(false));
 
         self.register_ctrl_c(is_running_lua.clone());
 
+        if let Some(interval) = self.config.luv_interval {
+            // started before the prompt rather than by a tick while typing
+            let _ = self.lua_executor.globals();
+
+            self.spawn_luv_ticker(interval);
+        }
+
+        // crossterm only turns the SIGWINCH of `Tasks::push` into an event once it listens
+        let _ = crossterm::event::poll(Duration::ZERO);
+
         loop {
+            self.run_tasks(&is_running_lua);
+
             let signal = self.editor.read_line(&self.prompt);
 
             match signal {
+                // tasks run at the top of the loop, the buffer is kept like below
+                Ok(Signal::Success(line)) if line == tasks::WAKE => {}
+                // the buffer is kept, so the next read continues editing it
+                Ok(Signal::Success(line)) if line == snippets::NEXT_PLACEHOLDER => {
+                    self.jump_to_placeholder();
+                }
                 Ok(Signal::Success(line)) => {
+                    if !self.confirm_rerun(&line) {
+                        continue;
+                    }
+
                     is_running_lua.store(true, Ordering::Relaxed);
 
-                    if let Err(e) = self.eval(&line) {
-                        eprintln!("{e}")
+                    self.transcript.push_input(&line);
+
+                    if let Some(share) = &self.share {
+                        share.broadcast_input("host", &line);
+                    }
+                    let result = self.eval(&line);
+
+                    // only what ran adds the fields it assigns
+                    if result.is_ok() {
+                        self.session.lock().unwrap().record(&line);
                     }
 
+                    if let Err(e) = result {
+                        eprintln!("{e}");
+                        self.transcript.push_output(&e.to_string());
+
+                        // the last failure is kept in plain text, unlike the history
+                        if self.vault.is_none() {
+                            report::record_failure(&line, &e.to_string());
+                        }
+
+                        if let Some(share) = &self.share {
+                            share.broadcast_error("host", &e.to_string());
+                        }
+                    }
+
+                    if !line.starts_with('.') {
+                        self.last_input = Some(line);
+                    }
+
+                    if let Err(e) = self.save_encrypted_history() {
+                        eprintln!("failed to save history: {e}");
+                    }
+
+                    if self.config.luv_interval.is_some() {
+                        let _ = self.lua_executor.exec(LUV_TICK);
+                    }
+
+                    self.offer_restore();
+                    self.globals.refresh();
+
                     is_running_lua.store(false, Ordering::Relaxed);
                 }
                 Ok(Signal::CtrlC) | Ok(Signal::CtrlD) => break,
@@ -122,17 +706,644 @@ impl Editor {
         }
     }
 
-    fn eval(&self, line: &str) -> LuaResult<()> {
-        let value: LuaValue = self.lua_executor.exec(line)?;
-        let config = &self.config;
+    /// Runs the tasks of other threads, between reads so their output doesn't cut into
+    /// the prompt
+    fn run_tasks(&mut self, is_running_lua: &AtomicBool) {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Guest { name, input, reply } => {
+                    is_running_lua.store(true, Ordering::Relaxed);
+                    let result = self.eval_guest(&name, &input);
+                    is_running_lua.store(false, Ordering::Relaxed);
+                    self.globals.refresh();
+
+                    let _ = reply.send(result);
+                }
+                Task::LuvTick => {
+                    is_running_lua.store(true, Ordering::Relaxed);
+                    let _ = self.lua_executor.exec(LUV_TICK);
+                    is_running_lua.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Evaluates a guest's input like the host's, its output is broadcast as theirs
+    fn eval_guest(&mut self, name: &str, input: &str) -> Result<(), String> {
+        share::print_input(name, input, self.config.color_output);
+
+        if !self.confirm_rerun(input) {
+            return Err(String::from("the host declined to run this again"));
+        }
+
+        self.transcript.push_input(input);
+
+        let host = std::mem::replace(&mut self.author, name.to_string());
+        let result = self.eval(input);
+        self.author = host;
+
+        if result.is_ok() {
+            self.session.lock().unwrap().record(input);
+        }
+
+        result.map_err(|e| {
+            eprintln!("{e}");
+            self.transcript.push_output(&e.to_string());
+
+            e.to_string()
+        })
+    }
+
+    fn save_encrypted_history(&self) -> Result<(), CryptError> {
+        let (Some(vault), Some(path)) = (&self.vault, encrypted_history_path()) else {
+            return Ok(());
+        };
+
+        let lines = self
+            .editor
+            .history()
+            .search(SearchQuery::everything(SearchDirection::Forward, None))
+            .map(|items| {
+                items
+                    .into_iter()
+                    .map(|item| item.command_line)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+
+        vault.write(&path, lines.join("\0").as_bytes())
+    }
+
+    /// Asks to apply the globals saved before the system Lua was restarted, once for each
+    /// restart as `.restore` still applies them after a no
+    fn offer_restore(&mut self) {
+        let Some((count, taken)) = self.lua_executor.saved_globals() else {
+            return;
+        };
+
+        if count == 0 || self.restore_offered == Some(taken) {
+            return;
+        }
+
+        self.restore_offered = Some(taken);
+
+        print!(
+            "the Lua process was restarted, restore {count} globals saved {}s before? [y/N] ",
+            taken.elapsed().as_secs()
+        );
+        let _ = io::stdout().flush();
+
+        let mut answer = String::new();
+
+        if io::stdin().read_line(&mut answer).is_err()
+            || !matches!(answer.trim(), "y" | "Y" | "yes")
+        {
+            return;
+        }
+
+        if let Err(e) = self.command("restore") {
+            eprintln!("{e}");
+        }
+    }
+
+    /// Asks before running a side-effecting line again, see `confirm_side_effects`
+    fn confirm_rerun(&mut self, line: &str) -> bool {
+        let is_rerun = !self.submitted.insert(line.to_string());
+
+        if !self.config.confirm_side_effects || !is_rerun {
+            return true;
+        }
+
+        let calls = commands::side_effects(line);
+
+        if calls.is_empty() {
+            return true;
+        }
+
+        print!(
+            "this was run before and calls {}, run again? [y/N] ",
+            calls.join(", ")
+        );
+        let _ = io::stdout().flush();
+
+        let mut answer = String::new();
+
+        if io::stdin().read_line(&mut answer).is_err() {
+            return false;
+        }
+
+        matches!(answer.trim(), "y" | "Y" | "yes")
+    }
+
+    fn eval(&mut self, line: &str) -> LuaResult<()> {
+        if let Some(command) = line.strip_prefix('.') {
+            return self.command(command.trim());
+        }
+
+        let mut code =
+            commands::expand_alias(line, &self.config.alias).unwrap_or_else(|| line.to_string());
+
+        if self.config.calculator {
+            if let Some(rewritten) = calculator::rewrite(&code) {
+                let note = format!("-- {rewritten}");
+
+                if self.config.color_output {
+                    self.output(&Color::DarkGray.dimmed().paint(note).to_string());
+                } else {
+                    self.output(&note);
+                }
+
+                code = rewritten;
+            }
+        }
+
+        let before = self.config.report_globals.then(|| self.global_names());
+        let result = self.exec(&code);
+
+        // globals defined before an error leak all the same
+        if let Some(before) = before {
+            self.report_globals(&code, &before);
+        }
+
+        let values = result?;
+
+        if values.iter().all(LuaValue::is_nil) && !self.prints_nil(&code) {
+            return Ok(());
+        }
+
+        self.print_values(values)
+    }
+
+    fn global_names(&self) -> HashSet<String> {
+        self.lua_executor
+            .globals()
+            .map(|globals| {
+                globals
+                    .pairs::<String, LuaValue>()
+                    .flatten()
+                    .map(|(name, _)| name)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Prints the globals `code` defined and the names its functions would make global,
+    /// `_` is left out as every result sets it
+    fn report_globals(&mut self, code: &str, before: &HashSet<String>) {
+        let after = self.global_names();
+
+        let mut defined = after
+            .iter()
+            .filter(|name| *name != "_" && !before.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        defined.sort();
+
+        if !defined.is_empty() {
+            let note = format!("defined globals: {}", defined.join(", "));
+
+            if self.config.color_output {
+                self.output(&Color::DarkGray.dimmed().paint(note).to_string());
+            } else {
+                self.output(&note);
+            }
+        }
+
+        if self.language != Language::Lua {
+            return;
+        }
+
+        for (name, similar) in function_global_warnings(code, before, &after) {
+            let warning = match similar {
+                Some(similar) => format!(
+                    "warning: `{name}` becomes global when the function runs, did you mean `{similar}`?"
+                ),
+                None => format!(
+                    "warning: `{name}` becomes global when the function runs, missing `local`?"
+                ),
+            };
+
+            if self.config.color_output {
+                self.output(&Color::Yellow.paint(warning).to_string());
+            } else {
+                self.output(&warning);
+            }
+        }
+    }
+
+    fn prints_nil(&self, code: &str) -> bool {
+        match self.config.print_nil {
+            PrintNil::Always => true,
+            PrintNil::Never => false,
+            // other languages have no statements to tell apart
+            PrintNil::Expressions => {
+                self.language != Language::Lua || validator::is_expression(code)
+            }
+        }
+    }
+
+    fn exec(&self, code: &str) -> LuaResult<LuaMultiValue> {
+        let code = &self.language.wrap(code, "repl");
+
+        if let Some(status) = &self.status {
+            let collections = self
+                .lua_executor
+                .memory()
+                .map(|(_, c)| c)
+                .unwrap_or_default();
+            status.start(collections);
+        }
+
+        // both paths print the values of a trailing expression, like `x` of `local x = 1; x`
+        let code = parse::auto_return(code);
+
+        let result = if let Some(async_loop) = self.config.async_loop {
+            self.lua_executor.exec(&format!(
+                "return __manen_last(__manen_await({}, '{}'))",
+                lua_string_literal(code.as_bytes()),
+                async_loop.name()
+            ))
+        } else {
+            self.lua_executor.exec(&format!(
+                "return __manen_last(__manen_eval({}))",
+                lua_string_literal(code.as_bytes())
+            ))
+        };
+
+        if let Some(status) = &self.status {
+            status.stop();
+        }
+
+        result
+    }
+
+    fn command(&mut self, command: &str) -> LuaResult<()> {
+        let (name, args) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+
+        match name {
+            "get" => {
+                let expression = commands::path_expression(args)?;
+                let value = self.lua_executor.eval(&format!("return {expression}"))?;
+
+                self.print_value(value)
+            }
+            "reveal" => {
+                let expression = commands::path_expression(args)?;
+                let value = self.lua_executor.eval(&format!("return {expression}"))?;
+
+                let limits = InspectLimits {
+                    redact: false,
+                    ..self.config.limits()
+                };
+
+                // printed only here, so the value stays out of transcripts and shared sessions
+                println!("{}", format_value(&self.config, value, limits)?);
+
+                Ok(())
+            }
+            "browse" => {
+                if !io::stdout().is_terminal() {
+                    return Err(LuaError::RuntimeError(String::from(
+                        ".browse needs a terminal",
+                    )));
+                }
+
+                let code = if args.trim().is_empty() { "_" } else { args };
+                let value = self.exec(code)?.into_iter().next().unwrap_or(LuaValue::Nil);
+
+                inspect::with_thread_debug(self.lua_executor.lua(), || {
+                    Browser::new(value, self.config.limits()).run()
+                })
+                .map_err(LuaError::external)
+            }
+            "time" => self.time(args),
+            "jit" => {
+                let value = self.lua_executor.eval(&commands::jit_code(args)?)?;
+
+                if let LuaValue::String(s) = value {
+                    self.output(&s.to_string_lossy());
+                }
+
+                Ok(())
+            }
+            "gc" => {
+                let value = self.lua_executor.eval(&commands::gc_code(args)?)?;
+
+                if let LuaValue::String(s) = value {
+                    self.output(&s.to_string_lossy());
+                }
+
+                Ok(())
+            }
+            "export" => {
+                let path = args.trim();
+
+                if path.is_empty() {
+                    return Err(LuaError::RuntimeError(String::from(
+                        "usage: .export <file.md|file.html>",
+                    )));
+                }
+
+                let path = Path::new(path);
+
+                match &self.vault {
+                    Some(vault) => vault
+                        .write(path, self.transcript.render(path).as_bytes())
+                        .map_err(LuaError::external),
+                    None => self.transcript.export(path).map_err(LuaError::external),
+                }
+            }
+            "resume" => {
+                let values = self.lua_executor.exec(&format!(
+                    "return __manen_last(__manen_resume({}))",
+                    args.trim()
+                ))?;
+
+                self.print_values(values)
+            }
+            "restart" => {
+                self.lua_executor.restart()?;
+                self.output("started the system Lua again");
+
+                Ok(())
+            }
+            "restore" => match self.lua_executor.restore()? {
+                Some((count, age)) => {
+                    self.output(&format!(
+                        "restored {count} globals saved {}s ago",
+                        age.as_secs()
+                    ));
+
+                    Ok(())
+                }
+                None => Err(LuaError::RuntimeError(String::from(
+                    "no globals were saved before a restart",
+                ))),
+            },
+            "expand-macro" => self.expand_macro(args),
+            "language" => self.set_language(args.trim()),
+            "snippet" => self.snippet(args.trim()),
+            name => Err(LuaError::RuntimeError(format!("unknown command '.{name}'"))),
+        }
+    }
+
+    fn set_language(&mut self, name: &str) -> LuaResult<()> {
+        if name.is_empty() {
+            let name = self.language.name();
+            self.output(name);
+
+            return Ok(());
+        }
+
+        let language = <Language as ValueEnum>::from_str(name, true)
+            .map_err(|_| LuaError::RuntimeError(format!("unknown language '{name}'")))?;
+
+        if let Some(prelude) = language.prelude() {
+            self.lua_executor.exec(prelude)?;
+        }
+
+        self.editor = line_editor(
+            &self.config,
+            &self.lua_executor,
+            &self.globals,
+            &self.session,
+            language,
+            self.vault.as_ref(),
+            &self.tasks,
+        );
+        self.language = language;
+
+        Ok(())
+    }
+
+    fn snippet(&mut self, args: &str) -> LuaResult<()> {
+        let usage = || LuaError::RuntimeError(String::from("usage: .snippet save|insert <name>"));
+
+        let dir = snippets::dir().ok_or_else(|| {
+            LuaError::RuntimeError(String::from("no data directory to store snippets in"))
+        })?;
+
+        let (action, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let (name, code) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .unwrap_or((rest.trim_start(), ""));
+
+        match action {
+            "" => {
+                let names = snippets::names(&dir);
 
-        let stringify = match value {
-            LuaValue::Table(tbl) => config.table_format.format(&tbl, config.color_output)?,
-            value => display_basic(&value, config.color_output),
+                if !names.is_empty() {
+                    self.output(&names.join("\n"));
+                }
+
+                Ok(())
+            }
+            "save" if !name.is_empty() => {
+                // without code, the last evaluated input becomes the snippet
+                let code = match (code.trim(), &self.last_input) {
+                    ("", Some(last)) => last.clone(),
+                    ("", None) => {
+                        return Err(LuaError::RuntimeError(String::from(
+                            "nothing evaluated yet, use .snippet save <name> <code>",
+                        )));
+                    }
+                    (code, _) => code.to_string(),
+                };
+
+                snippets::save(&dir, name, &code).map_err(LuaError::external)
+            }
+            "insert" if !name.is_empty() => {
+                let code = snippets::load(&dir, name).map_err(LuaError::external)?;
+
+                self.editor
+                    .run_edit_commands(&[EditCommand::Clear, EditCommand::InsertString(code)]);
+                self.jump_to_placeholder();
+
+                Ok(())
+            }
+            _ => Err(usage()),
+        }
+    }
+
+    /// Moves the cursor to the lowest numbered `$n` left in the buffer and removes it
+    fn jump_to_placeholder(&mut self) {
+        let buffer = self.editor.current_buffer_contents().to_string();
+
+        if let Some(range) = snippets::next_placeholder(&buffer) {
+            let mut edits = vec![EditCommand::MoveToPosition {
+                position: range.start,
+                select: false,
+            }];
+            edits.extend(range.map(|_| EditCommand::Delete));
+
+            self.editor.run_edit_commands(&edits);
+        }
+    }
+
+    fn expand_macro(&mut self, code: &str) -> LuaResult<()> {
+        if code.is_empty() {
+            return Err(LuaError::RuntimeError(String::from(
+                "usage: .expand-macro <code>",
+            )));
+        }
+
+        let lua = match self.lua_executor.eval(&self.language.compile(code))? {
+            LuaValue::String(s) => s.to_string_lossy(),
+            value => display_basic(&value, false),
         };
 
-        println!("{stringify}");
+        if self.config.color_output {
+            self.output(&parse::highlight(&lua).render_simple());
+        } else {
+            self.output(&lua);
+        }
 
         Ok(())
     }
+
+    fn time(&mut self, args: &str) -> LuaResult<()> {
+        let (runs, code) = time_args(args, self.language == Language::Lua);
+
+        if code.is_empty() {
+            return Err(LuaError::RuntimeError(String::from(
+                "usage: .time [runs] <expr>",
+            )));
+        }
+
+        let mut timings = Vec::with_capacity(runs as usize);
+        let mut values = LuaMultiValue::new();
+
+        for _ in 0..runs {
+            let start = Instant::now();
+            values = self.exec(code)?;
+            timings.push(start.elapsed());
+        }
+
+        let min = timings.iter().min().copied().unwrap_or_default();
+        let max = timings.iter().max().copied().unwrap_or_default();
+        let mean = timings.iter().sum::<Duration>() / runs;
+
+        self.print_values(values)?;
+
+        let summary = format!("min {min:?}, mean {mean:?}, max {max:?} ({runs} runs)");
+
+        if self.config.color_output {
+            self.output(&Color::DarkGray.paint(summary).to_string());
+        } else {
+            self.output(&summary);
+        }
+
+        Ok(())
+    }
+
+    /// Each value on its own line like `manen run`, a statement's lack of values as `nil`
+    fn print_values(&mut self, values: LuaMultiValue) -> LuaResult<()> {
+        if values.is_empty() {
+            return self.print_value(LuaValue::Nil);
+        }
+
+        for value in values {
+            if value.is_nil() && self.config.print_nil == PrintNil::Never {
+                continue;
+            }
+
+            self.print_value(value)?;
+        }
+
+        Ok(())
+    }
+
+    fn print_value(&mut self, value: LuaValue) -> LuaResult<()> {
+        let lua = self.lua_executor.lua();
+
+        // guests of a shared session are sent the whole output at once
+        if self.share.is_some() {
+            let stringify = inspect::with_thread_debug(lua, || {
+                format_value(&self.config, value, self.config.limits())
+            })?;
+            self.output(&stringify);
+
+            return Ok(());
+        }
+
+        let mut tee = TranscriptTee::new();
+
+        inspect::with_thread_debug(lua, || {
+            write_value(&mut tee, &self.config, value, self.config.limits())
+        })?;
+        writeln!(tee)
+            .and_then(|_| tee.flush())
+            .map_err(LuaError::external)?;
+
+        let mut kept = tee.kept();
+        kept.truncate(kept.trim_end_matches('\n').len());
+        self.transcript.push_output(&kept);
+
+        Ok(())
+    }
+
+    fn output(&mut self, text: &str) {
+        println!("{text}");
+        self.transcript.push_output(text);
+
+        if let Some(share) = &self.share {
+            share.broadcast_output(&self.author, text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_warnings() {
+        let lua = Lua::new();
+
+        let warnings = |code: &str| {
+            let names = || {
+                lua.globals()
+                    .pairs::<String, LuaValue>()
+                    .flatten()
+                    .map(|(name, _)| name)
+                    .collect::<HashSet<_>>()
+            };
+
+            let before = names();
+            let _ = lua.load(code).exec();
+
+            function_global_warnings(code, &before, &names())
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(warnings("counter = 0; function inc() counter = counter + 1 end").is_empty());
+        // already global from the input before
+        assert!(warnings("function inc() counter = counter + 1 end").is_empty());
+        assert_eq!(
+            ["total"],
+            warnings("function add(n) total = n end").as_slice()
+        );
+        // set by running the function, no longer a mistake to warn about
+        assert!(warnings("function reset() level = 1 end; reset()").is_empty());
+    }
+
+    #[test]
+    fn time_counts() {
+        assert_eq!((100, "f()"), time_args("100 f()", true));
+        assert_eq!((5, "-x"), time_args("5 -x", true));
+        assert_eq!((1, "1 + 1"), time_args("1 + 1", true));
+        assert_eq!((1, "1 .. 'a'"), time_args("1 .. 'a'", true));
+        assert_eq!((1, "f()"), time_args("f()", true));
+        assert_eq!((3, "(f)"), time_args("3 (f)", false));
+    }
 }
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use nu_ansi_term::Style;
+use reedline::{StyledText, ValidationResult, Validator};
+
+use crate::parse::{Capture, Rainbow, matching_brackets};
+
+lazy_static! {
+    static ref SPECIAL_FORMS: HashSet<&'static str> = HashSet::from_iter([
+        "fn",
+        "lambda",
+        "λ",
+        "let",
+        "local",
+        "var",
+        "set",
+        "global",
+        "tset",
+        "if",
+        "when",
+        "unless",
+        "each",
+        "for",
+        "while",
+        "do",
+        "match",
+        "case",
+        "match-try",
+        "case-try",
+        "values",
+        "macro",
+        "macros",
+        "import-macros",
+        "require-macros",
+        "doto",
+        "->",
+        "->>",
+        "-?>",
+        "-?>>",
+        "?.",
+        "collect",
+        "icollect",
+        "accumulate",
+        "faccumulate",
+        "fcollect",
+        "partial",
+        "pick-values",
+        "pick-args",
+        "with-open",
+        "include",
+        "eval-compiler",
+        "quote",
+        "hashfn",
+        "length",
+        "not",
+        "and",
+        "or",
+        "where",
+        "comment",
+    ]);
+}
+
+#[derive(Clone, Copy)]
+enum Token<'a> {
+    Whitespace(&'a str),
+    Comment(&'a str),
+    String(&'a str),
+    Number(&'a str),
+    Open(char),
+    Close(char),
+    Symbol(&'a str),
+}
+
+impl Token<'_> {
+    fn byte_len(&self) -> usize {
+        match self {
+            Token::Whitespace(s)
+            | Token::Comment(s)
+            | Token::String(s)
+            | Token::Number(s)
+            | Token::Symbol(s) => s.len(),
+            Token::Open(c) | Token::Close(c) => c.len_utf8(),
+        }
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+/// Length of the string `rest` starts with up to its closing quote, `None` when the text
+/// ends before one, an escaped quote doesn't close it
+fn string_len(rest: &str) -> Option<usize> {
+    let mut escaped = false;
+
+    for (i, c) in rest.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            return Some(i + 1);
+        }
+    }
+
+    None
+}
+
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            c if c.is_whitespace() => rest
+                .find(|c: char| !c.is_whitespace())
+                .unwrap_or(rest.len()),
+            ';' => rest.find('\n').unwrap_or(rest.len()),
+            '"' => string_len(rest).unwrap_or(rest.len()),
+            '(' | '[' | '{' | ')' | ']' | '}' => 1,
+            _ => rest
+                .find(is_delimiter)
+                .unwrap_or(rest.len())
+                .max(c.len_utf8()),
+        };
+
+        let (text, remaining) = rest.split_at(len);
+
+        tokens.push(match c {
+            c if c.is_whitespace() => Token::Whitespace(text),
+            ';' => Token::Comment(text),
+            '"' => Token::String(text),
+            '(' | '[' | '{' => Token::Open(c),
+            ')' | ']' | '}' => Token::Close(c),
+            _ if text
+                .trim_start_matches('-')
+                .starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                Token::Number(text)
+            }
+            _ => Token::Symbol(text),
+        });
+
+        rest = remaining;
+    }
+
+    tokens
+}
+
+pub struct FennelValidator;
+
+impl Validator for FennelValidator {
+    fn validate(&self, line: &str) -> ValidationResult {
+        if line.starts_with(".") {
+            return ValidationResult::Complete;
+        }
+
+        let mut depth = 0isize;
+
+        for token in tokenize(line) {
+            match token {
+                Token::Open(_) => depth += 1,
+                Token::Close(_) => depth -= 1,
+                Token::String(s) if string_len(s).is_none() => {
+                    return ValidationResult::Incomplete;
+                }
+                _ => {}
+            }
+        }
+
+        if depth > 0 {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Complete
+        }
+    }
+}
+
+pub struct FennelHighlighter;
+
+impl reedline::Highlighter for FennelHighlighter {
+    fn highlight(&self, line: &str, cursor: usize) -> StyledText {
+        let tokens = tokenize(line);
+        let mut offsets = Vec::with_capacity(tokens.len());
+        let mut offset = 0;
+
+        for token in &tokens {
+            offsets.push(offset);
+            offset += token.byte_len();
+        }
+
+        let brackets = tokens
+            .iter()
+            .zip(&offsets)
+            .filter_map(|(token, offset)| match token {
+                Token::Open(c) | Token::Close(c) => Some((*offset, *c)),
+                _ => None,
+            });
+
+        let matched = matching_brackets(brackets, cursor);
+
+        let mut text = StyledText::new();
+        let mut after_open = false;
+        let mut rainbow = Rainbow::default();
+
+        for (token, offset) in tokens.into_iter().zip(offsets) {
+            let (style, s) = match token {
+                Token::Whitespace(s) => (Style::new(), s.to_string()),
+                Token::Comment(s) => (Capture::Comment.style(), s.to_string()),
+                Token::String(s) => (Capture::String.style(), s.to_string()),
+                Token::Number(s) => (Capture::Number.style(), s.to_string()),
+                Token::Open(c) | Token::Close(c) => {
+                    let c = c.to_string();
+                    let style = rainbow.style(&c, Capture::Operator.style());
+
+                    if matched.is_some_and(|(open, close)| offset == open || offset == close) {
+                        (Capture::MatchingBracket.style(), c)
+                    } else {
+                        (style, c)
+                    }
+                }
+                Token::Symbol(s) => {
+                    let capture = match s {
+                        "true" | "false" | "nil" => Capture::Constant,
+                        s if s.starts_with(':') && s.len() > 1 => Capture::String,
+                        s if SPECIAL_FORMS.contains(s) => Capture::Keyword,
+                        _ if after_open => Capture::Function,
+                        _ => Capture::Variable,
+                    };
+
+                    (capture.style(), s.to_string())
+                }
+            };
+
+            after_open = matches!(token, Token::Open('('))
+                || (after_open && matches!(token, Token::Whitespace(_)));
+
+            text.push((style, s));
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_complete(line: &str) -> bool {
+        matches!(FennelValidator.validate(line), ValidationResult::Complete)
+    }
+
+    #[test]
+    fn strings() {
+        assert!(is_complete(r#"(print "a")"#));
+        assert!(is_complete(r#"(print "say \"hi\"")"#));
+        assert!(!is_complete(r#"(print "a"#));
+        assert!(!is_complete(r#"(print "a\")"#));
+        assert!(!is_complete(r#"""#));
+        assert!(!is_complete(r#"(print "a\"#));
+        assert!(is_complete(r#""a\\""#));
+    }
+}
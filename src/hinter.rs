@@ -1,8 +1,11 @@
+use std::sync::Arc;
+
+use emmylua_parser::LuaParser;
 use mlua::prelude::*;
 use nu_ansi_term::{Color, Style};
 use reedline::{Hinter, History};
 
-use crate::inspect::display_basic;
+use crate::{completion::Loading, inspect::display_basic, lua::LuaExecutor, parse, signature};
 
 fn burner_lua() -> Lua {
     #[cfg(any(feature = "lua54", feature = "lua53"))]
@@ -28,17 +31,66 @@ fn burner_lua() -> Lua {
     lua
 }
 
-pub struct LuaHinter;
+pub struct LuaHinter {
+    lua_executor: Arc<dyn LuaExecutor>,
+    loading: Loading,
+}
+
+impl LuaHinter {
+    pub fn new(lua_executor: Arc<dyn LuaExecutor>) -> Self {
+        Self {
+            lua_executor,
+            loading: Loading::default(),
+        }
+    }
+
+    /// Say completions are still loading while the line is the one they ran out of time on
+    pub fn with_loading(mut self, loading: Loading) -> Self {
+        self.loading = loading;
+        self
+    }
+
+    /// Parameters of the call the cursor is in, shown instead of the result
+    fn signature(&self, line: &str, pos: usize, use_ansi_coloring: bool) -> Option<String> {
+        // see `LuaExecutor::is_started`
+        if !self.lua_executor.is_started() {
+            return None;
+        }
+
+        let tree = LuaParser::parse(line, parse::config());
+        let call = signature::call_at(&tree, pos as u32)?;
+        let params = signature::parameters(self.lua_executor.as_ref(), &call)?;
+
+        Some(format!(
+            "  {}",
+            signature::format(&call, &params, use_ansi_coloring)
+        ))
+    }
+}
 
 impl Hinter for LuaHinter {
     fn handle(
         &mut self,
         line: &str,
-        _pos: usize,
+        pos: usize,
         _history: &dyn History,
         use_ansi_coloring: bool,
         _cwd: &str,
     ) -> String {
+        if self.loading.lock().unwrap().as_deref() == Some(line) {
+            let hint = String::from("  still loading…");
+
+            return if use_ansi_coloring {
+                Color::DarkGray.paint(hint).to_string()
+            } else {
+                hint
+            };
+        }
+
+        if let Some(hint) = self.signature(line, pos, use_ansi_coloring) {
+            return hint;
+        }
+
         let lua = burner_lua();
 
         let value: LuaValue = match lua.load(line).set_name("=").eval() {
@@ -73,3 +125,32 @@ impl Hinter for LuaHinter {
         String::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use reedline::FileBackedHistory;
+
+    use super::*;
+    use crate::lua::MluaExecutor;
+
+    #[test]
+    fn hints() {
+        let history = FileBackedHistory::new(10).unwrap();
+        let loading = Loading::default();
+        let mut hinter =
+            LuaHinter::new(Arc::new(MluaExecutor::new())).with_loading(loading.clone());
+
+        let mut hint = |line: &str| hinter.handle(line, line.len(), &history, false, "");
+
+        assert_eq!(" (true)", hint("1 < 2"));
+        assert_eq!(" (\"ab\")", hint("'a' .. 'b'"));
+        assert!(hint("1 +").contains("(unexpected symbol near <eof>)"));
+
+        // nothing to show, or nothing the burner state may run
+        assert_eq!("", hint("nil"));
+        assert_eq!("", hint("while true do end"));
+
+        *loading.lock().unwrap() = Some(String::from("pri"));
+        assert_eq!("  still loading…", hint("pri"));
+    }
+}
@@ -1,17 +1,22 @@
 use std::{
+    cell::RefCell,
+    cmp::Ordering,
     collections::{HashMap, HashSet},
     fmt::{self, Write},
-    sync::Arc,
+    io::{self, IsTerminal},
+    sync::{Arc, RwLock},
 };
 
 use aho_corasick::AhoCorasick;
-use comfy_table::{Table, presets::UTF8_FULL_CONDENSED};
+use comfy_table::{Cell, CellAlignment, ContentArrangement, Table, presets::UTF8_FULL_CONDENSED};
 use lazy_static::lazy_static;
 use mlua::prelude::*;
-use nu_ansi_term::{AnsiString, AnsiStrings, Color};
+use nu_ansi_term::{AnsiString, AnsiStrings, Color, Style};
+
+use crate::palette;
 
 lazy_static! {
-    static ref AC_REPLACEMENTS: (AhoCorasick, Vec<String>) = {
+    static ref AC_REPLACEMENTS: (AhoCorasick, Vec<String>, Vec<String>) = {
         let mut escapes = vec![
             String::from("\x07"),
             String::from("\x08"),
@@ -36,19 +41,24 @@ lazy_static! {
             String::from("\\\\"),
         ];
 
+        let mut padded = replacements.clone();
+
         for i in 0..=31 {
             escapes.push(String::from_utf8_lossy(&[i]).to_string());
             replacements.push(format!("\\{i}"));
+            padded.push(format!("\\{i:03}"));
         }
 
-        (AhoCorasick::new(escapes).unwrap(), replacements)
+        (AhoCorasick::new(escapes).unwrap(), replacements, padded)
     };
     static ref ESCAPER: &'static AhoCorasick = &AC_REPLACEMENTS.0;
-    static ref REPLACEMENT_COLOR: Vec<String> = AC_REPLACEMENTS
-        .1
-        .iter()
-        .map(|s| format!("{}{}", Color::Cyan.paint(s), Color::Green.prefix()))
-        .collect();
+    static ref VALUE_COLORS: RwLock<ValueColors> = RwLock::new(ValueColors::default());
+    static ref REDACTED_KEYS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+    static ref NUMBER_FORMAT: RwLock<NumberFormat> = RwLock::new(NumberFormat::default());
+    static ref BYTE_ESCAPE: RwLock<ByteEscape> = RwLock::new(ByteEscape::default());
+    /// Colored `ESCAPER` replacements, made on first use and again after the colors change
+    static ref COLORED_REPLACEMENTS: RwLock<HashMap<ByteEscape, Arc<Vec<String>>>> =
+        RwLock::new(HashMap::new());
     static ref KEYWORDS: HashSet<&'static str> = HashSet::from_iter([
         "and", "break", "do", "else", "elseif", "end", "else", "false", "for", "function", "goto",
         "if", "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until",
@@ -56,14 +66,82 @@ lazy_static! {
     ]);
 }
 
-fn escape_control(s: &str) -> String {
+/// How bytes that aren't valid UTF-8 are escaped in printed strings
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ByteEscape {
+    /// `\xFF`, which only Lua 5.2 and newer read back
+    #[default]
+    Hex,
+    /// `\255`, with control characters padded to `\007` so the output is a string
+    /// literal any Lua version reads back as the same bytes
+    Decimal,
+}
+
+impl ByteEscape {
+    fn replacements(self) -> &'static [String] {
+        match self {
+            ByteEscape::Hex => &AC_REPLACEMENTS.1,
+            ByteEscape::Decimal => &AC_REPLACEMENTS.2,
+        }
+    }
+
+    /// Prefix replacing the `\u{FFFD}` marker `remove_invalid` puts before each byte
+    fn prefix(self) -> &'static str {
+        match self {
+            ByteEscape::Hex => "\\x",
+            ByteEscape::Decimal => "\\",
+        }
+    }
+
+    /// Digits `remove_invalid` writes after the marker
+    fn digits(self) -> usize {
+        match self {
+            ByteEscape::Hex => 2,
+            ByteEscape::Decimal => 3,
+        }
+    }
+}
+
+pub fn set_byte_escape(escape: ByteEscape) {
+    *BYTE_ESCAPE.write().unwrap() = escape;
+}
+
+fn byte_escape() -> ByteEscape {
+    *BYTE_ESCAPE.read().unwrap()
+}
+
+fn escape_control(s: &str, escape: ByteEscape) -> String {
     ESCAPER
-        .replace_all(s, &AC_REPLACEMENTS.1)
-        .replace("\u{FFFD}", "\\x")
+        .replace_all(s, escape.replacements())
+        .replace("\u{FFFD}", escape.prefix())
+}
+
+fn colored_replacements(escape: ByteEscape) -> Arc<Vec<String>> {
+    if let Some(replacements) = COLORED_REPLACEMENTS.read().unwrap().get(&escape) {
+        return replacements.clone();
+    }
+
+    let colors = value_colors();
+    let replacements = Arc::new(
+        escape
+            .replacements()
+            .iter()
+            .map(|s| format!("{}{}", colors.escape.paint(s), colors.string.prefix()))
+            .collect::<Vec<_>>(),
+    );
+
+    COLORED_REPLACEMENTS
+        .write()
+        .unwrap()
+        .insert(escape, replacements.clone());
+
+    replacements
 }
 
-fn escape_control_color(s: &str) -> String {
-    let s = ESCAPER.replace_all(s, &REPLACEMENT_COLOR);
+fn escape_control_color(s: &str, escape: ByteEscape) -> String {
+    let colors = value_colors();
+    let replacements = colored_replacements(escape);
+    let s = ESCAPER.replace_all(s, replacements.as_slice());
     let mut chars = s.chars();
     let mut new = String::new();
 
@@ -73,68 +151,73 @@ fn escape_control_color(s: &str) -> String {
             continue;
         }
 
-        let (hex1, hex2) = (chars.next(), chars.next());
-        let escape = format!(
-            "\\x{}{}",
-            hex1.unwrap_or_default(),
-            hex2.unwrap_or_default()
-        );
+        let digits = chars.by_ref().take(escape.digits()).collect::<String>();
 
         new.push_str(&format!(
             "{}{}",
-            Color::Cyan.paint(escape),
-            Color::Green.prefix()
+            colors.escape.paint(format!("{}{digits}", escape.prefix())),
+            colors.string.prefix()
         ));
     }
 
     new
 }
 
-fn remove_invalid(mut bytes: &[u8]) -> String {
+/// Valid UTF-8 of `bytes`, with every other byte written as `\u{FFFD}` followed by its
+/// digits in `escape`'s base
+fn remove_invalid(bytes: &[u8], escape: ByteEscape) -> String {
     let mut buffer = String::new();
 
-    loop {
-        match str::from_utf8(bytes) {
-            Ok(s) => {
-                buffer.push_str(s);
-                return buffer;
-            }
-            Err(e) => {
-                let (valid, invalid) = bytes.split_at(e.valid_up_to());
-
-                if !valid.is_empty() {
-                    //  SAFETY: We already know the bytes until this point are valid
-                    buffer.push_str(unsafe { str::from_utf8_unchecked(valid) })
-                }
-
-                let error_len = if let Some(len) = e.error_len() {
-                    len
-                } else {
-                    return buffer;
-                };
-
-                for bad_byte in &invalid[..error_len] {
-                    // this *might* cause some false positives
-                    buffer.push_str(&format!("\u{FFFD}{bad_byte:X?}"));
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            // a real U+FFFD is escaped too, rather than read as a marker
+            if c == '\u{FFFD}' {
+                for byte in c.to_string().bytes() {
+                    push_escaped(&mut buffer, byte, escape);
                 }
-
-                bytes = &invalid[error_len..];
+            } else {
+                buffer.push(c);
             }
         }
+
+        for &byte in chunk.invalid() {
+            push_escaped(&mut buffer, byte, escape);
+        }
     }
+
+    buffer
+}
+
+fn push_escaped(buffer: &mut String, byte: u8, escape: ByteEscape) {
+    let _ = match escape {
+        ByteEscape::Hex => write!(buffer, "\u{FFFD}{byte:02X}"),
+        ByteEscape::Decimal => write!(buffer, "\u{FFFD}{byte:03}"),
+    };
 }
 
 pub fn cleanup_string(lua_str: &LuaString) -> String {
-    escape_control(&remove_invalid(&lua_str.as_bytes()))
+    let escape = byte_escape();
+
+    escape_control(&remove_invalid(&lua_str.as_bytes(), escape), escape)
 }
 
+/// `bytes` as a quoted string, escaped with the style set by `set_byte_escape`
 pub fn format_string_bytes(bytes: &[u8], colorize: bool) -> String {
-    let mut s = remove_invalid(bytes);
+    format_string_escaped(bytes, colorize, byte_escape())
+}
+
+/// `bytes` as a string literal for generated Lua code, readable by any Lua version
+pub fn lua_string_literal(bytes: &[u8]) -> String {
+    format_string_escaped(bytes, false, ByteEscape::Decimal)
+}
+
+fn format_string_escaped(bytes: &[u8], colorize: bool, escape: ByteEscape) -> String {
+    let mut s = remove_invalid(bytes, escape);
 
     if colorize {
-        s = escape_control_color(&s);
+        s = escape_control_color(&s, escape);
     } else {
-        s = escape_control(&s);
+        s = escape_control(&s, escape);
     }
 
     let pair = (s.contains("'"), s.contains('"'));
@@ -146,288 +229,2053 @@ pub fn format_string_bytes(bytes: &[u8], colorize: bool) -> String {
     }
 }
 
+// share of bytes that have to be escaped before a string counts as binary
+const BINARY_RATIO: f64 = 0.3;
+
+/// If most of `bytes` would print as escapes rather than text
+pub fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+
+    let mut escaped = 0;
+
+    for chunk in bytes.utf8_chunks() {
+        escaped += chunk
+            .valid()
+            .chars()
+            .filter(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+            .count();
+        escaped += chunk.invalid().len();
+    }
+
+    escaped as f64 / bytes.len() as f64 > BINARY_RATIO
+}
+
+/// Offsets, bytes in hex and their printable ASCII, 16 bytes per line like `hexdump -C`
+pub fn hexdump(bytes: &[u8], colorize: bool) -> String {
+    let colors = value_colors();
+    let paint = |style: Style, text: String| {
+        if colorize {
+            style.paint(text).to_string()
+        } else {
+            text
+        }
+    };
+
+    let mut lines = Vec::new();
+
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+
+        for (j, byte) in line.iter().enumerate() {
+            if j == 8 {
+                hex.push(' ');
+            }
+
+            let _ = write!(hex, "{byte:02x} ");
+        }
+
+        let ascii = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+
+        lines.push(format!(
+            "{}  {hex:<49} |{}|",
+            paint(colors.note, format!("{:08x}", i * 16)),
+            paint(colors.string, ascii)
+        ));
+    }
+
+    lines.join("\n")
+}
+
 fn format_string_lua_string(lua_str: &LuaString, colorize: bool) -> String {
     format_string_bytes(&lua_str.as_bytes(), colorize)
 }
 
-fn addr_color(value: &LuaValue) -> Option<(String, Color)> {
-    match value {
-        LuaValue::LightUserData(l) => Some((format!("{:?}", l.0), Color::Cyan)),
-        LuaValue::Table(t) => Some((format!("{:?}", t.to_pointer()), Color::LightBlue)),
-        LuaValue::Function(f) => Some((format!("{:?}", f.to_pointer()), Color::Purple)),
-        LuaValue::Thread(t) => Some((format!("{:?}", t.to_pointer()), Color::LightGray)),
-        LuaValue::UserData(u) => Some((format!("{:?}", u.to_pointer()), Color::Cyan)),
-        _ => None,
+/// Styles of printed values, set from `manen.colors`
+#[derive(Clone, Copy)]
+pub struct ValueColors {
+    pub nil: Style,
+    pub boolean: Style,
+    pub number: Style,
+    pub string: Style,
+    pub table: Style,
+    pub function: Style,
+    pub thread: Style,
+    pub userdata: Style,
+    /// `None` leaves addresses out, printing only the type name
+    pub address: Option<Style>,
+    /// Escape sequences inside strings
+    pub escape: Style,
+    /// Text about a value rather than part of it, like `<redacted>` or `… (10240 bytes)`
+    pub note: Style,
+}
+
+impl Default for ValueColors {
+    fn default() -> Self {
+        Self {
+            nil: Color::LightRed.normal(),
+            boolean: Color::LightYellow.normal(),
+            number: Color::LightYellow.normal(),
+            string: Color::Green.normal(),
+            table: Color::LightBlue.normal(),
+            function: Color::Purple.normal(),
+            thread: Color::LightGray.normal(),
+            userdata: Color::Cyan.normal(),
+            address: Some(Color::LightYellow.normal()),
+            escape: Color::Cyan.normal(),
+            note: Color::DarkGray.normal(),
+        }
     }
 }
 
-fn handle_strings<'a>(colorize: bool, strings: AnsiStrings<'a>) -> String {
-    if colorize {
-        strings.to_string()
-    } else {
-        nu_ansi_term::unstyle(&strings)
+impl ValueColors {
+    /// Darker colors that stay readable on a light background
+    pub fn light() -> Self {
+        Self {
+            nil: Color::Red.normal(),
+            boolean: Color::Purple.normal(),
+            number: Color::Purple.normal(),
+            string: Color::Green.normal(),
+            table: Color::Blue.normal(),
+            function: Color::Purple.normal(),
+            thread: Color::DarkGray.normal(),
+            userdata: Color::Cyan.normal(),
+            address: Some(Color::Yellow.normal()),
+            escape: Color::Blue.normal(),
+            note: Color::DarkGray.normal(),
+        }
     }
 }
 
-pub fn display_basic(value: &LuaValue, colorize: bool) -> String {
-    match addr_color(value) {
-        Some((addr, color)) => {
-            let strings: &[AnsiString<'static>] = &[
-                color.paint(value.type_name()),
-                Color::Default.paint("@"),
-                Color::LightYellow.paint(addr),
-            ];
+/// Base integers are printed in, set from `manen.integer_base`
+#[derive(Clone, Copy, Default)]
+pub enum IntegerBase {
+    #[default]
+    Decimal,
+    Hex,
+    Binary,
+}
 
-            handle_strings(colorize, AnsiStrings(strings))
+/// How numbers are printed, set from `manen.float_precision`, `manen.scientific_threshold`
+/// and `manen.integer_base`
+#[derive(Clone, Copy, Default)]
+pub struct NumberFormat {
+    /// Digits after the decimal point of floats
+    pub precision: Option<usize>,
+    /// Magnitude from which floats, and below its inverse, are printed like `1.5e20`
+    pub scientific_threshold: Option<f64>,
+    pub integer_base: IntegerBase,
+}
+
+impl NumberFormat {
+    pub fn integer(&self, i: i64) -> String {
+        let sign = if i < 0 { "-" } else { "" };
+
+        match self.integer_base {
+            IntegerBase::Decimal => i.to_string(),
+            IntegerBase::Hex => format!("{sign}0x{:x}", i.unsigned_abs()),
+            IntegerBase::Binary => format!("{sign}0b{:b}", i.unsigned_abs()),
         }
-        None => {
-            let strings = &[match value {
-                LuaValue::Nil => Color::LightRed.paint("nil"),
-                LuaValue::Boolean(b) => Color::LightYellow.paint(b.to_string()),
-                LuaValue::Integer(i) => Color::LightYellow.paint(i.to_string()),
-                LuaValue::Number(n) => Color::LightYellow.paint(n.to_string()),
-                LuaValue::String(s) => Color::Green.paint(format_string_lua_string(s, colorize)),
-                val => Color::LightGray.paint(val.to_string().unwrap_or_default()),
-            }];
+    }
 
-            handle_strings(colorize, AnsiStrings(strings))
+    pub fn float(&self, n: f64) -> String {
+        // integral floats are integers in Lua 5.1 and LuaJIT
+        if n.fract() == 0.0 && n.abs() < 2f64.powi(53) {
+            if let IntegerBase::Hex | IntegerBase::Binary = self.integer_base {
+                return self.integer(n as i64);
+            }
+        }
+
+        let scientific = self.scientific_threshold.is_some_and(|threshold| {
+            n.is_finite() && n != 0.0 && (n.abs() >= threshold || n.abs() < 1.0 / threshold)
+        });
+
+        match (scientific, self.precision) {
+            (true, Some(precision)) => format!("{n:.precision$e}"),
+            (true, None) => format!("{n:e}"),
+            (false, Some(precision)) if n.is_finite() => format!("{n:.precision$}"),
+            (false, _) => n.to_string(),
         }
     }
 }
 
-fn is_short_printable_inner(tbl: &LuaTable, seen: &mut HashSet<usize>) -> bool {
-    let addr = tbl.to_pointer() as usize;
+/// `#rrggbb` as a true color
+fn parse_hex(word: &str) -> Option<Color> {
+    let hex = word.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
 
-    if seen.contains(&addr) {
-        return false;
-    }
+    Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+}
 
-    seen.insert(addr);
+/// Parses a style such as `cyan`, `dim`, `bold light_red` or `italic #928374`
+pub fn parse_style(text: &str) -> Option<Style> {
+    let mut style = Style::new();
 
-    for (key, value) in tbl.pairs::<LuaValue, LuaValue>().flatten() {
-        if !key.is_integer() {
-            return false;
+    for word in text.split_whitespace() {
+        if let Some(color) = parse_hex(word) {
+            style = style.fg(color);
+            continue;
         }
 
-        if let LuaValue::Table(inner) = value {
-            let printable = is_short_printable_inner(&inner, seen);
+        style = match word.to_lowercase().replace('-', "_").as_str() {
+            "dim" => style.dimmed(),
+            "bold" => style.bold(),
+            "italic" => style.italic(),
+            "underline" => style.underline(),
+            "default" => style.fg(Color::Default),
+            "black" => style.fg(Color::Black),
+            "red" => style.fg(Color::Red),
+            "green" => style.fg(Color::Green),
+            "yellow" => style.fg(Color::Yellow),
+            "blue" => style.fg(Color::Blue),
+            "purple" | "magenta" => style.fg(Color::Purple),
+            "cyan" => style.fg(Color::Cyan),
+            "white" => style.fg(Color::White),
+            "gray" | "light_gray" => style.fg(Color::LightGray),
+            "dark_gray" => style.fg(Color::DarkGray),
+            "light_red" => style.fg(Color::LightRed),
+            "light_green" => style.fg(Color::LightGreen),
+            "light_yellow" => style.fg(Color::LightYellow),
+            "light_blue" => style.fg(Color::LightBlue),
+            "light_purple" | "light_magenta" => style.fg(Color::LightPurple),
+            "light_cyan" => style.fg(Color::LightCyan),
+            _ => return None,
+        };
+    }
 
-            if !printable {
-                return false;
-            }
-        }
+    Some(style)
+}
+
+pub fn set_value_colors(colors: ValueColors) {
+    *VALUE_COLORS.write().unwrap() = colors;
+    COLORED_REPLACEMENTS.write().unwrap().clear();
+}
+
+fn value_colors() -> ValueColors {
+    let colors = *VALUE_COLORS.read().unwrap();
+
+    ValueColors {
+        nil: palette::downgrade(colors.nil),
+        boolean: palette::downgrade(colors.boolean),
+        number: palette::downgrade(colors.number),
+        string: palette::downgrade(colors.string),
+        table: palette::downgrade(colors.table),
+        function: palette::downgrade(colors.function),
+        thread: palette::downgrade(colors.thread),
+        userdata: palette::downgrade(colors.userdata),
+        address: colors.address.map(palette::downgrade),
+        escape: palette::downgrade(colors.escape),
+        note: palette::downgrade(colors.note),
     }
+}
 
-    true
+pub fn set_number_format(format: NumberFormat) {
+    *NUMBER_FORMAT.write().unwrap() = format;
 }
 
-pub fn is_short_printable(tbl: &LuaTable) -> bool {
-    let mut seen = HashSet::new();
+fn number_format() -> NumberFormat {
+    *NUMBER_FORMAT.read().unwrap()
+}
 
-    is_short_printable_inner(tbl, &mut seen)
+/// `debug.getinfo` and `debug.getlocal` of a state, kept in its app data since the
+/// sandbox may remove `debug`
+#[derive(Clone)]
+struct ThreadDebug {
+    getinfo: LuaFunction,
+    getlocal: Option<LuaFunction>,
 }
 
-pub fn print_array(tbl: &LuaTable, colorize: bool) -> String {
-    let mut buff = Vec::new();
+/// Functions registered with `manen.formatter` in a state, keyed by metatable
+struct Formatters(LuaTable);
 
-    if tbl.is_empty() {
-        return String::from("{}");
-    }
+thread_local! {
+    /// Set by `with_thread_debug` while values of the state it belongs to are printed
+    static THREAD_DEBUG: RefCell<Option<ThreadDebug>> = const { RefCell::new(None) };
+    /// Formatters of the state whose values `with_thread_debug` prints
+    static THREAD_FORMATTERS: RefCell<Option<LuaTable>> = const { RefCell::new(None) };
+}
 
-    for (_, value) in tbl.pairs::<LuaValue, LuaValue>().flatten() {
-        if let LuaValue::Table(inner) = value {
-            buff.push(print_array(&inner, colorize));
-        } else {
-            buff.push(display_basic(&value, colorize));
-        }
+/// Keeps the `debug` functions of `lua` to find where its suspended coroutines stopped
+/// and name the parameters of its functions
+pub fn set_thread_debug(lua: &Lua) {
+    let Ok(debug) = lua.globals().raw_get::<LuaTable>("debug") else {
+        return;
+    };
+
+    if let Ok(getinfo) = debug.raw_get::<LuaFunction>("getinfo") {
+        lua.set_app_data(ThreadDebug {
+            getinfo,
+            getlocal: debug.raw_get::<LuaFunction>("getlocal").ok(),
+        });
     }
+}
 
-    format!("{{ {} }}", buff.join(", "))
+/// Keeps the registry `lua/formatter.lua` returns, to print the tables of `lua` with
+pub fn set_formatters(lua: &Lua, formatters: LuaTable) {
+    lua.set_app_data(Formatters(formatters));
 }
 
-fn is_valid_identifier(s: &str) -> bool {
-    if KEYWORDS.contains(s) {
-        return false;
-    }
+/// Runs `f` printing suspended coroutines with where they stopped and tables with their
+/// formatters, only values of `lua` may be printed as a function of one state can't be
+/// called with another's threads
+pub fn with_thread_debug<R>(lua: Option<&Lua>, f: impl FnOnce() -> R) -> R {
+    let debug = lua
+        .and_then(|lua| lua.app_data_ref::<ThreadDebug>())
+        .map(|debug| ThreadDebug::clone(&debug));
+    let formatters = lua
+        .and_then(|lua| lua.app_data_ref::<Formatters>())
+        .map(|formatters| formatters.0.clone());
+
+    let previous = THREAD_DEBUG.with_borrow_mut(|current| std::mem::replace(current, debug));
+    let previous_formatters =
+        THREAD_FORMATTERS.with_borrow_mut(|current| std::mem::replace(current, formatters));
+    let result = f();
+    THREAD_DEBUG.with_borrow_mut(|current| *current = previous);
+    THREAD_FORMATTERS.with_borrow_mut(|current| *current = previous_formatters);
+
+    result
+}
 
-    let mut chars = s.chars();
-    let first = if let Some(c) = chars.next() {
-        c
-    } else {
+/// Values under keys containing any of `patterns`, ignoring case, print as `<redacted>`
+/// when `InspectLimits::redact` is set
+pub fn set_redacted_keys(patterns: &[String]) {
+    *REDACTED_KEYS.write().unwrap() = patterns.iter().map(|p| p.to_lowercase()).collect();
+}
+
+pub(crate) fn is_redacted(key: &LuaValue, limits: InspectLimits) -> bool {
+    let LuaValue::String(key) = key else {
         return false;
     };
 
-    //  [A-Z_a-z]
-    if !first.is_ascii_alphabetic() && first != '_' {
+    if !limits.redact {
         return false;
     }
 
-    let s = chars.as_str();
+    let key = key.to_string_lossy().to_lowercase();
 
-    if s.is_empty() {
-        return true;
-    }
+    REDACTED_KEYS
+        .read()
+        .unwrap()
+        .iter()
+        .any(|pattern| key.contains(pattern.as_str()))
+}
 
-    // [A-Z_a-z0-9]
-    if s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
-        .is_some()
-    {
-        return false;
+pub(crate) fn redacted(colorize: bool) -> String {
+    if colorize {
+        value_colors().note.paint("<redacted>").to_string()
+    } else {
+        String::from("<redacted>")
     }
+}
 
-    true
+fn addr_color(value: &LuaValue, colors: &ValueColors) -> Option<(String, Style)> {
+    match value {
+        LuaValue::LightUserData(l) => Some((format!("{:?}", l.0), colors.userdata)),
+        LuaValue::Table(t) => Some((format!("{:?}", t.to_pointer()), colors.table)),
+        LuaValue::Function(f) => Some((format!("{:?}", f.to_pointer()), colors.function)),
+        LuaValue::Thread(t) => Some((format!("{:?}", t.to_pointer()), colors.thread)),
+        LuaValue::UserData(u) => Some((format!("{:?}", u.to_pointer()), colors.userdata)),
+        _ => None,
+    }
 }
 
-fn display_table_inner(
-    tbl: &LuaTable,
-    colorize: bool,
-    seen: &mut HashMap<usize, usize>,
-    indent: usize,
-) -> Result<String, fmt::Error> {
-    let ptr = tbl.to_pointer() as usize;
-    if let Some(id) = seen.get(&ptr) {
-        return Ok(format!("<{id}>"));
+fn handle_strings<'a>(colorize: bool, strings: AnsiStrings<'a>) -> String {
+    if colorize {
+        strings.to_string()
+    } else {
+        nu_ansi_term::unstyle(&strings)
     }
+}
 
-    let id = seen.len();
-    seen.insert(ptr, id);
+// stops a `__pairs` that never returns nil
+const MAX_USERDATA_FIELDS: usize = 10_000;
 
-    let printable = is_short_printable(tbl);
+fn userdata_metafield<V: FromLua>(ud: &LuaAnyUserData, name: &str) -> Option<V> {
+    ud.metatable().ok()?.get::<Option<V>>(name).ok()?
+}
 
-    if printable {
-        return Ok(print_array(tbl, colorize));
-    }
+/// `__tostring` of userdata that defines it
+fn userdata_tostring(ud: &LuaAnyUserData) -> Option<String> {
+    userdata_metafield::<LuaFunction>(ud, "__tostring")?
+        .call::<String>(ud.clone())
+        .ok()
+}
 
-    let mut buffer = String::new();
+/// Fields of userdata from `__pairs`, a table user value or an `__index` table,
+/// the first of them it has
+fn userdata_fields(ud: &LuaAnyUserData) -> Option<Vec<(LuaValue, LuaValue)>> {
+    // a `__tostring` already says how the value wants to be shown
+    if userdata_metafield::<LuaFunction>(ud, "__tostring").is_some() {
+        return None;
+    }
 
-    // TODO; only output id if necessary
-    writeln!(&mut buffer, "<{id}>{{")?;
+    if let Some(pairs) = userdata_metafield::<LuaFunction>(ud, "__pairs") {
+        let (next, state, mut control) = pairs
+            .call::<(LuaFunction, LuaValue, LuaValue)>(ud.clone())
+            .ok()?;
 
-    for (key, value) in tbl.pairs::<LuaValue, LuaValue>().flatten() {
-        buffer.push_str(&("   ".repeat(indent + 1)));
+        let mut fields = Vec::new();
 
-        if let LuaValue::String(ref s) = key {
-            let clean = cleanup_string(s);
+        while fields.len() < MAX_USERDATA_FIELDS {
+            let (key, value) = next
+                .call::<(LuaValue, LuaValue)>((state.clone(), control))
+                .ok()?;
 
-            if is_valid_identifier(&clean) {
-                write!(&mut buffer, "{clean} = ")?
-            } else {
-                write!(&mut buffer, "[{}] = ", display_basic(&key, colorize))?
+            if key.is_nil() {
+                break;
             }
-        } else {
-            write!(&mut buffer, "[{}] = ", display_basic(&key, colorize))?;
-        }
 
-        if let LuaValue::Table(t) = value {
-            writeln!(
-                &mut buffer,
-                "{},",
-                display_table_inner(&t, colorize, seen, indent + 1)?
-            )?;
-        } else {
-            writeln!(&mut buffer, "{},", display_basic(&value, colorize))?;
+            control = key.clone();
+            fields.push((key, value));
         }
+
+        return Some(fields);
     }
 
-    write!(&mut buffer, "{}}}", "   ".repeat(indent))?;
+    let table = ud
+        .user_value::<Option<LuaTable>>()
+        .ok()
+        .flatten()
+        .or_else(|| userdata_metafield::<LuaTable>(ud, "__index"))?;
 
-    Ok(buffer)
+    Some(table.pairs::<LuaValue, LuaValue>().flatten().collect())
 }
 
-pub fn display_table(tbl: &LuaTable, colorize: bool) -> LuaResult<String> {
-    let mut seen = HashMap::new();
+/// Names of the parameters of a Lua function, through the `debug` library of the state
+/// `with_thread_debug` prints, which needs Lua 5.2 or newer
+fn function_params(f: &LuaFunction) -> Option<Vec<String>> {
+    // the function's environment may hold a `debug` of the user's own making
+    let ThreadDebug { getinfo, getlocal } = THREAD_DEBUG.with_borrow(Option::clone)?;
+
+    let info = getinfo.call::<LuaTable>((f.clone(), "u")).ok()?;
+    let count = info.raw_get::<usize>("nparams").ok()?;
+
+    let mut params = (1..=count)
+        .map(|i| {
+            getlocal
+                .as_ref()
+                .and_then(|getlocal| getlocal.call::<Option<String>>((f.clone(), i)).ok())
+                .flatten()
+                .unwrap_or_else(|| format!("arg{i}"))
+        })
+        .collect::<Vec<_>>();
+
+    if info.raw_get::<bool>("isvararg").unwrap_or_default() {
+        params.push(String::from("..."));
+    }
 
-    display_table_inner(tbl, colorize, &mut seen, 0)
-        .map_err(|e| LuaError::ExternalError(Arc::new(e)))
+    Some(params)
 }
 
-pub fn inspect(value: &LuaValue, colorize: bool) -> LuaResult<String> {
-    match value {
-        LuaValue::Table(tbl) => display_table(tbl, colorize),
-        value => Ok(display_basic(value, colorize)),
-    }
-}
+/// Where a function comes from, such as `(a, b, ...) [stdin:3]` or `[C]`
+fn function_details(f: &LuaFunction) -> String {
+    let info = f.info();
 
-#[derive(Clone, Copy)]
-pub enum TableFormat {
-    ComfyTable,
-    Inspect,
-    Address,
-}
+    if info.what == "C" {
+        return String::from("[C]");
+    }
 
-fn comfy_table_inner(
-    tbl: &LuaTable,
-    recursive: bool,
-    visited: &mut HashMap<usize, usize>,
-) -> LuaResult<String> {
-    let addr = tbl.to_pointer() as usize;
+    let location = match (info.short_src, info.line_defined) {
+        (Some(src), Some(line)) if line > 0 => format!("[{src}:{line}]"),
+        (Some(src), _) => format!("[{src}]"),
+        (None, _) => format!("[{}]", info.what),
+    };
 
-    if let Some(id) = visited.get(&addr) {
-        return Ok(format!("<table {id}>"));
+    match function_params(f) {
+        Some(params) => format!("({}) {location}", params.join(", ")),
+        None => location,
     }
+}
 
-    let id = visited.len();
-    visited.insert(addr, id);
+// stack levels of a suspended coroutine searched for the Lua code that yielded
+const MAX_YIELD_LEVELS: usize = 8;
 
-    let printable = is_short_printable(tbl);
+/// `short_src:currentline` of the innermost Lua function on the stack of `thread`
+fn thread_line(thread: &LuaThread) -> Option<String> {
+    let getinfo = THREAD_DEBUG.with_borrow(Option::clone)?.getinfo;
 
-    if printable {
-        return Ok(print_array(tbl, false));
-    }
+    (0..MAX_YIELD_LEVELS).find_map(|level| {
+        let info = getinfo
+            .call::<Option<LuaTable>>((thread.clone(), level, "Sl"))
+            .ok()??;
+        let line = info.raw_get::<i64>("currentline").ok()?;
 
-    let mut table = Table::new();
-    table.load_preset(UTF8_FULL_CONDENSED);
-    table.set_header(vec![format!("<table {id}>")]);
+        if line <= 0 {
+            return None;
+        }
 
-    for (key, value) in tbl.pairs::<LuaValue, LuaValue>().flatten() {
-        let (key_str, value_str) = if let LuaValue::Table(sub) = value {
-            if recursive {
-                (
-                    display_basic(&key, false),
-                    comfy_table_inner(&sub, recursive, visited)?,
-                )
-            } else {
-                (
-                    display_basic(&key, false),
-                    display_basic(&LuaValue::Table(sub), false),
-                )
-            }
-        } else {
-            (display_basic(&key, false), display_basic(&value, false))
-        };
+        let src = info.raw_get::<String>("short_src").ok()?;
 
-        table.add_row(vec![key_str, value_str]);
-    }
+        Some(format!("{src}:{line}"))
+    })
+}
 
-    if table.is_empty() {
-        Ok(String::from("{}"))
-    } else {
-        Ok(table.to_string())
+/// State of a coroutine, such as `(suspended at stdin:4)` or `(dead)`
+fn thread_details(thread: &LuaThread) -> String {
+    match thread.status() {
+        LuaThreadStatus::Resumable => match thread_line(thread) {
+            Some(line) => format!("(suspended at {line})"),
+            None => String::from("(suspended)"),
+        },
+        LuaThreadStatus::Running => String::from("(running)"),
+        LuaThreadStatus::Finished | LuaThreadStatus::Error => String::from("(dead)"),
     }
 }
 
-pub fn comfy_table(tbl: &LuaTable, recursive: bool) -> LuaResult<String> {
-    let mut visited = HashMap::new();
-    comfy_table_inner(tbl, recursive, &mut visited)
+/// `userdata`, or the `__name` set by `luaL_newmetatable` and mlua
+fn type_label(value: &LuaValue) -> String {
+    match value {
+        LuaValue::UserData(ud) => {
+            userdata_metafield(ud, "__name").unwrap_or_else(|| String::from("userdata"))
+        }
+        value => value.type_name().to_string(),
+    }
 }
 
-impl TableFormat {
-    pub fn format(&self, tbl: &LuaTable, colorize: bool) -> LuaResult<String> {
-        match self {
-            TableFormat::Address => {
-                if colorize {
-                    Ok(format!(
-                        "{}{}{}",
-                        Color::LightBlue.paint("table"),
-                        Color::Default.paint("@"),
-                        Color::LightYellow.paint(format!("{:?}", tbl.to_pointer()))
-                    ))
-                } else {
-                    Ok(format!("table@{:?}", tbl.to_pointer()))
+pub fn display_basic(value: &LuaValue, colorize: bool) -> String {
+    let colors = value_colors();
+
+    if let LuaValue::UserData(ud) = value {
+        if let Some(text) = userdata_tostring(ud) {
+            return handle_strings(colorize, AnsiStrings(&[colors.userdata.paint(text)]));
+        }
+    }
+
+    match addr_color(value, &colors) {
+        Some((addr, style)) => {
+            let mut strings: Vec<AnsiString<'static>> = match colors.address {
+                Some(address) => vec![
+                    style.paint(type_label(value)),
+                    Color::Default.paint("@"),
+                    address.paint(addr),
+                ],
+                None => vec![style.paint(type_label(value))],
+            };
+
+            match value {
+                LuaValue::Function(f) => {
+                    strings.push(colors.note.paint(format!(" {}", function_details(f))));
+                }
+                LuaValue::Thread(thread) => {
+                    strings.push(colors.note.paint(format!(" {}", thread_details(thread))));
                 }
+                _ => {}
             }
-            TableFormat::Inspect => display_table(tbl, colorize).map_err(LuaError::external),
-            TableFormat::ComfyTable => comfy_table(tbl, true),
+
+            handle_strings(colorize, AnsiStrings(&strings))
         }
+        None => {
+            let strings = &[match value {
+                LuaValue::Nil => colors.nil.paint("nil"),
+                LuaValue::Boolean(b) => colors.boolean.paint(b.to_string()),
+                LuaValue::Integer(i) => colors.number.paint(number_format().integer(*i)),
+                LuaValue::Number(n) => colors.number.paint(number_format().float(*n)),
+                LuaValue::String(s) => colors.string.paint(format_string_lua_string(s, colorize)),
+                val => Color::LightGray.paint(val.to_string().unwrap_or_default()),
+            }];
+
+            handle_strings(colorize, AnsiStrings(strings))
+        }
+    }
+}
+
+fn is_short_printable_inner(tbl: &LuaTable, seen: &mut HashSet<usize>) -> bool {
+    let addr = tbl.to_pointer() as usize;
+
+    if seen.contains(&addr) {
+        return false;
+    }
+
+    seen.insert(addr);
+
+    for (key, value) in tbl.pairs::<LuaValue, LuaValue>().flatten() {
+        if !key.is_integer() {
+            return false;
+        }
+
+        if let LuaValue::Table(inner) = value {
+            let printable = is_short_printable_inner(&inner, seen);
+
+            if !printable {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+pub fn is_short_printable(tbl: &LuaTable) -> bool {
+    let mut seen = HashSet::new();
+
+    is_short_printable_inner(tbl, &mut seen)
+}
+
+/// How much of a table gets printed, `None` means no limit
+#[derive(Clone, Copy, Default)]
+pub struct InspectLimits {
+    /// Nesting levels shown before tables collapse into `{...}`
+    pub max_depth: Option<usize>,
+    /// Entries shown per table before the rest are summarized
+    pub max_items: Option<usize>,
+    /// Bytes of a string shown before it is cut short with its full length
+    pub max_string: Option<usize>,
+    /// Hide values under keys set with `set_redacted_keys`
+    pub redact: bool,
+    /// Print keys in a stable order instead of the order `next` returns them
+    pub sort_keys: bool,
+    pub indent: Indent,
+    /// Columns a table may take up on one line before it is spread over several
+    pub max_width: Option<usize>,
+    /// Print strings that are mostly escapes as a hex dump
+    pub hexdump: bool,
+    /// Count array items and hash keys in the header of each table
+    pub summary: bool,
+    /// Name the classes a table inherits from through `__index`
+    pub inheritance: bool,
+}
+
+/// Width of the terminal stdout writes to
+pub fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+
+    crossterm::terminal::size()
+        .ok()
+        .map(|(columns, _)| columns as usize)
+}
+
+/// What each nesting level of a printed table is indented with
+#[derive(Clone, Copy)]
+pub struct Indent {
+    pub width: usize,
+    pub fill: char,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Self {
+            width: 3,
+            fill: ' ',
+        }
+    }
+}
+
+impl Indent {
+    /// A number of spaces, or a string of one repeated character such as `'\t'`
+    pub fn from_value(value: &LuaValue) -> Option<Self> {
+        if let Some(width) = value.as_usize() {
+            return Some(Self { width, fill: ' ' });
+        }
+
+        let unit = value.as_string_lossy()?;
+        let fill = unit.chars().next()?;
+
+        unit.chars().all(|c| c == fill).then(|| Self {
+            width: unit.chars().count(),
+            fill,
+        })
+    }
+
+    fn at(&self, level: usize) -> String {
+        self.fill.to_string().repeat(self.width * level)
+    }
+}
+
+impl InspectLimits {
+    fn is_too_deep(&self, depth: usize) -> bool {
+        self.max_depth.is_some_and(|max| depth >= max)
+    }
+
+    fn is_too_long(&self, count: usize) -> bool {
+        self.max_items.is_some_and(|max| count >= max)
+    }
+}
+
+// integers in order, then strings byte by byte, then everything else as found
+pub fn key_order(a: &LuaValue, b: &LuaValue) -> Ordering {
+    fn rank(key: &LuaValue) -> u8 {
+        match key {
+            LuaValue::Integer(_) => 0,
+            LuaValue::Number(n) if n.fract() == 0.0 => 0,
+            LuaValue::String(_) => 1,
+            _ => 2,
+        }
+    }
+
+    match (a, b) {
+        (LuaValue::String(a), LuaValue::String(b)) => a.as_bytes()[..].cmp(&b.as_bytes()[..]),
+        (a, b) if rank(a) == 0 && rank(b) == 0 => {
+            let (a, b) = (
+                a.as_f64().unwrap_or_default(),
+                b.as_f64().unwrap_or_default(),
+            );
+
+            a.total_cmp(&b)
+        }
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Pairs of `tbl`, sorted with `key_order` when `limits.sort_keys` is set
+pub fn table_pairs(
+    tbl: &LuaTable,
+    limits: InspectLimits,
+) -> Box<dyn Iterator<Item = (LuaValue, LuaValue)> + '_> {
+    let pairs = tbl.pairs::<LuaValue, LuaValue>().flatten();
+
+    if !limits.sort_keys {
+        return Box::new(pairs);
+    }
+
+    let mut pairs = pairs.collect::<Vec<_>>();
+    pairs.sort_by(|(a, _), (b, _)| key_order(a, b));
+
+    Box::new(pairs.into_iter())
+}
+
+/// `display_basic` with strings longer than `limits.max_string` cut short
+pub fn display_limited(value: &LuaValue, colorize: bool, limits: InspectLimits) -> String {
+    let LuaValue::String(s) = value else {
+        return display_basic(value, colorize);
+    };
+
+    let bytes = s.as_bytes();
+
+    match limits.max_string {
+        Some(max) if bytes.len() > max => {
+            let strings = &[
+                value_colors()
+                    .string
+                    .paint(format_string_bytes(&bytes[..max], colorize)),
+                value_colors()
+                    .note
+                    .paint(format!("… ({} bytes)", bytes.len())),
+            ];
+
+            handle_strings(colorize, AnsiStrings(strings))
+        }
+        _ => display_basic(value, colorize),
+    }
+}
+
+fn print_array_inner(
+    tbl: &LuaTable,
+    colorize: bool,
+    limits: InspectLimits,
+    depth: usize,
+) -> String {
+    let mut buff = Vec::new();
+
+    if tbl.is_empty() {
+        return String::from("{}");
+    }
+
+    if limits.is_too_deep(depth) {
+        return String::from("{...}");
+    }
+
+    let mut pairs = table_pairs(tbl, limits);
+
+    while let Some((_, value)) = pairs.next() {
+        if limits.is_too_long(buff.len()) {
+            buff.push(format!("... ({} more)", 1 + pairs.count()));
+            break;
+        }
+
+        if let LuaValue::Table(inner) = value {
+            buff.push(print_array_inner(&inner, colorize, limits, depth + 1));
+        } else {
+            buff.push(display_limited(&value, colorize, limits));
+        }
+    }
+
+    format!("{{ {} }}", buff.join(", "))
+}
+
+pub fn print_array(tbl: &LuaTable, colorize: bool, limits: InspectLimits) -> String {
+    print_array_inner(tbl, colorize, limits, 0)
+}
+
+pub fn is_valid_identifier(s: &str) -> bool {
+    if KEYWORDS.contains(s) {
+        return false;
+    }
+
+    let mut chars = s.chars();
+    let first = if let Some(c) = chars.next() {
+        c
+    } else {
+        return false;
+    };
+
+    //  [A-Z_a-z]
+    if !first.is_ascii_alphabetic() && first != '_' {
+        return false;
+    }
+
+    let s = chars.as_str();
+
+    if s.is_empty() {
+        return true;
+    }
+
+    // [A-Z_a-z0-9]
+    if s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .is_some()
+    {
+        return false;
+    }
+
+    true
+}
+
+/// What the function registered with `manen.formatter` for the metatable of `tbl`
+/// returns, or the error it raised, `None` if there is none
+fn custom_format(tbl: &LuaTable, colorize: bool) -> Option<String> {
+    let metatable = tbl.metatable()?;
+    let format = THREAD_FORMATTERS
+        .with_borrow(Option::clone)?
+        .raw_get::<Option<LuaFunction>>(metatable)
+        .ok()??;
+
+    match format.call::<LuaValue>(tbl.clone()) {
+        Ok(LuaValue::String(s)) => Some(s.to_string_lossy()),
+        // another table would be formatted the same way again
+        Ok(LuaValue::Table(_)) => None,
+        Ok(value) => Some(display_basic(&value, colorize)),
+        Err(e) => {
+            // the traceback would spread the value over many lines
+            let message = e.to_string();
+            let message = format!(
+                "<formatter error: {}>",
+                message.lines().next().unwrap_or_default()
+            );
+
+            if colorize {
+                Some(value_colors().note.paint(message).to_string())
+            } else {
+                Some(message)
+            }
+        }
+    }
+}
+
+// classes followed through `__index` before the chain is cut short
+const MAX_INHERITANCE: usize = 16;
+
+/// Name given with `manen.class`, or a `__name` field, of a table used as a class
+fn class_name(class: &LuaTable) -> String {
+    ["__manen_class", "__name"]
+        .into_iter()
+        .find_map(|field| class.raw_get::<Option<String>>(field).ok()?)
+        .unwrap_or_else(|| display_basic(&LuaValue::Table(class.clone()), false))
+}
+
+/// `: extends Dog < Animal` for a table whose metatable's `__index` is a table, and
+/// the `__index` tables of their metatables in turn
+fn inheritance(tbl: &LuaTable, colorize: bool) -> Option<String> {
+    let mut classes = Vec::new();
+    let mut seen = HashSet::new();
+    let mut current = tbl.clone();
+
+    while classes.len() < MAX_INHERITANCE {
+        let Some(class) = current
+            .metatable()
+            .and_then(|mt| mt.raw_get::<Option<LuaTable>>("__index").ok().flatten())
+        else {
+            break;
+        };
+
+        if !seen.insert(class.to_pointer() as usize) {
+            break;
+        }
+
+        classes.push(class_name(&class));
+        current = class;
+    }
+
+    if classes.is_empty() {
+        return None;
+    }
+
+    let marker = format!(": extends {}", classes.join(" < "));
+
+    if colorize {
+        Some(value_colors().note.paint(marker).to_string())
+    } else {
+        Some(marker)
+    }
+}
+
+/// `(3 array items, 2 hash keys)`, array items being the keys from 1 to `#tbl`
+fn shape(tbl: &LuaTable, colorize: bool) -> String {
+    let len = tbl.raw_len() as f64;
+    let (mut array, mut hash) = (0, 0);
+
+    for (key, _) in tbl.pairs::<LuaValue, LuaValue>().flatten() {
+        let index = match key {
+            LuaValue::Integer(i) => Some(i as f64),
+            LuaValue::Number(n) if n.fract() == 0.0 => Some(n),
+            _ => None,
+        };
+
+        if index.is_some_and(|i| i >= 1.0 && i <= len) {
+            array += 1;
+        } else {
+            hash += 1;
+        }
+    }
+
+    let count = |n: usize, what: &str| {
+        if n == 1 {
+            format!("1 {what}")
+        } else {
+            format!("{n} {what}s")
+        }
+    };
+
+    let text = format!(
+        "({}, {})",
+        count(array, "array item"),
+        count(hash, "hash key")
+    );
+
+    if colorize {
+        value_colors().note.paint(text).to_string()
+    } else {
+        text
+    }
+}
+
+/// Columns `text` takes up on the terminal, leaving out its color codes
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // skips to the end of `\x1b[...m`
+            '\x1b' => {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            '\t' => width += 8 - width % 8,
+            _ => width += 1,
+        }
+    }
+
+    width
+}
+
+/// `.name` for keys that are identifiers, `[key]` for the rest
+pub fn display_path_key(key: &LuaValue, colorize: bool) -> String {
+    if let LuaValue::String(s) = key {
+        let clean = cleanup_string(s);
+
+        if is_valid_identifier(&clean) {
+            return format!(".{clean}");
+        }
+    }
+
+    format!("[{}]", display_basic(key, colorize))
+}
+
+/// `name = ` for keys that are identifiers, `[key] = ` for the rest
+pub fn display_key(key: &LuaValue, colorize: bool) -> String {
+    if let LuaValue::String(s) = key {
+        let clean = cleanup_string(s);
+
+        if is_valid_identifier(&clean) {
+            return format!("{clean} = ");
+        }
+    }
+
+    format!("[{}] = ", display_basic(key, colorize))
+}
+
+/// `tbl` on one line if it fits in `budget` columns, the tables it reaches are
+/// numbered in `added` so `seen` is left alone when it doesn't fit
+fn compact_table(
+    tbl: &LuaTable,
+    colorize: bool,
+    limits: InspectLimits,
+    seen: &HashMap<usize, usize>,
+    added: &mut HashMap<usize, usize>,
+    indent: usize,
+    budget: usize,
+) -> Option<String> {
+    let ptr = tbl.to_pointer() as usize;
+
+    if let Some(id) = seen.get(&ptr).or_else(|| added.get(&ptr)) {
+        return Some(format!("<{id}>"));
+    }
+
+    if let Some(text) = custom_format(tbl, colorize) {
+        return (visible_width(&text) <= budget && !text.contains('\n')).then_some(text);
+    }
+
+    let id = seen.len() + added.len();
+    added.insert(ptr, id);
+
+    let class = limits
+        .inheritance
+        .then(|| inheritance(tbl, colorize))
+        .flatten()
+        .map_or_else(String::new, |class| format!("{class} "));
+
+    let line = if class.is_empty() && is_short_printable(tbl) {
+        print_array_inner(tbl, colorize, limits, indent)
+    } else if limits.is_too_deep(indent) {
+        format!("<{id}>{class}{{...}}")
+    } else {
+        let mut prefix = format!("<{id}>{class}");
+
+        // as `display_table_inner` puts it before the braces
+        if limits.summary {
+            if !prefix.ends_with(' ') {
+                prefix.push(' ');
+            }
+
+            prefix.push_str(&format!("{} ", shape(tbl, colorize)));
+        }
+
+        let mut pairs = table_pairs(tbl, limits);
+        let mut entries = Vec::new();
+        let mut width = visible_width(&format!("{prefix}{{  }}"));
+
+        while let Some((key, value)) = pairs.next() {
+            if limits.is_too_long(entries.len()) {
+                entries.push(format!("... ({} more)", 1 + pairs.count()));
+                break;
+            }
+
+            let key_text = display_key(&key, colorize);
+            let column = width + visible_width(&key_text);
+
+            let value_text = if is_redacted(&key, limits) {
+                redacted(colorize)
+            } else {
+                match value {
+                    LuaValue::Table(t) => compact_table(
+                        &t,
+                        colorize,
+                        limits,
+                        seen,
+                        added,
+                        indent + 1,
+                        budget.checked_sub(column)?,
+                    )?,
+                    // its fields are listed one per line
+                    LuaValue::UserData(ud)
+                        if userdata_fields(&ud).is_some_and(|fields| !fields.is_empty()) =>
+                    {
+                        return None;
+                    }
+                    value => display_limited(&value, colorize, limits),
+                }
+            };
+
+            let entry = format!("{key_text}{value_text}");
+            width += visible_width(&entry) + 2;
+
+            if width > budget || entry.contains('\n') {
+                return None;
+            }
+
+            entries.push(entry);
+        }
+
+        format!("{prefix}{{ {} }}", entries.join(", "))
+    };
+
+    (visible_width(&line) <= budget && !line.contains('\n')).then_some(line)
+}
+
+fn display_table_inner(
+    out: &mut dyn fmt::Write,
+    tbl: &LuaTable,
+    colorize: bool,
+    limits: InspectLimits,
+    seen: &mut HashMap<usize, usize>,
+    indent: usize,
+    column: usize,
+) -> fmt::Result {
+    let ptr = tbl.to_pointer() as usize;
+    if let Some(id) = seen.get(&ptr) {
+        return write!(out, "<{id}>");
+    }
+
+    if let Some(text) = custom_format(tbl, colorize) {
+        return out.write_str(&text);
+    }
+
+    if let Some(max_width) = limits.max_width {
+        let mut added = HashMap::new();
+        let budget = max_width.saturating_sub(column);
+
+        if let Some(line) = compact_table(tbl, colorize, limits, seen, &mut added, indent, budget) {
+            seen.extend(added);
+
+            return out.write_str(&line);
+        }
+    }
+
+    let id = seen.len();
+    seen.insert(ptr, id);
+
+    let class = limits
+        .inheritance
+        .then(|| inheritance(tbl, colorize))
+        .flatten();
+
+    let printable = class.is_none() && is_short_printable(tbl);
+
+    if printable {
+        return out.write_str(&print_array_inner(tbl, colorize, limits, indent));
+    }
+
+    let mut prefix = format!("<{id}>");
+
+    if let Some(class) = class {
+        prefix.push_str(&class);
+        prefix.push(' ');
+    }
+
+    if limits.is_too_deep(indent) {
+        return write!(out, "{prefix}{{...}}");
+    }
+
+    if limits.summary {
+        if !prefix.ends_with(' ') {
+            prefix.push(' ');
+        }
+
+        prefix.push_str(&format!("{} ", shape(tbl, colorize)));
+    }
+
+    // TODO; only output id if necessary
+    display_entries(
+        out,
+        prefix,
+        table_pairs(tbl, limits),
+        colorize,
+        limits,
+        seen,
+        indent,
+    )
+}
+
+/// Entries between braces, one per line, after `prefix`
+fn display_entries(
+    out: &mut dyn fmt::Write,
+    prefix: String,
+    mut pairs: impl Iterator<Item = (LuaValue, LuaValue)>,
+    colorize: bool,
+    limits: InspectLimits,
+    seen: &mut HashMap<usize, usize>,
+    indent: usize,
+) -> fmt::Result {
+    writeln!(out, "{prefix}{{")?;
+
+    let mut count = 0;
+
+    while let Some((key, value)) = pairs.next() {
+        out.write_str(&limits.indent.at(indent + 1))?;
+
+        if limits.is_too_long(count) {
+            writeln!(out, "... ({} more)", 1 + pairs.count())?;
+            break;
+        }
+
+        count += 1;
+
+        let key_text = display_key(&key, colorize);
+        let column = visible_width(&limits.indent.at(indent + 1)) + visible_width(&key_text);
+
+        out.write_str(&key_text)?;
+
+        if is_redacted(&key, limits) {
+            out.write_str(&redacted(colorize))?;
+        } else if let LuaValue::Table(t) = value {
+            display_table_inner(out, &t, colorize, limits, seen, indent + 1, column)?;
+        } else if let LuaValue::UserData(ud) = value {
+            display_userdata_inner(out, &ud, colorize, limits, seen, indent + 1)?;
+        } else {
+            out.write_str(&display_limited(&value, colorize, limits))?;
+        }
+
+        out.write_str(",\n")?;
+    }
+
+    write!(out, "{}}}", limits.indent.at(indent))
+}
+
+fn display_userdata_inner(
+    out: &mut dyn fmt::Write,
+    ud: &LuaAnyUserData,
+    colorize: bool,
+    limits: InspectLimits,
+    seen: &mut HashMap<usize, usize>,
+    indent: usize,
+) -> fmt::Result {
+    let basic = display_basic(&LuaValue::UserData(ud.clone()), colorize);
+    let ptr = ud.to_pointer() as usize;
+
+    if seen.contains_key(&ptr) || limits.is_too_deep(indent) {
+        return out.write_str(&basic);
+    }
+
+    let mut fields = match userdata_fields(ud) {
+        Some(fields) if !fields.is_empty() => fields,
+        _ => return out.write_str(&basic),
+    };
+
+    seen.insert(ptr, seen.len());
+
+    if limits.sort_keys {
+        fields.sort_by(|(a, _), (b, _)| key_order(a, b));
+    }
+
+    display_entries(
+        out,
+        format!("{basic} "),
+        fields.into_iter(),
+        colorize,
+        limits,
+        seen,
+        indent,
+    )
+}
+
+pub fn display_table(tbl: &LuaTable, colorize: bool, limits: InspectLimits) -> LuaResult<String> {
+    let mut buffer = String::new();
+
+    display_table_inner(
+        &mut buffer,
+        tbl,
+        colorize,
+        limits,
+        &mut HashMap::new(),
+        0,
+        0,
+    )
+    .map_err(|e| LuaError::ExternalError(Arc::new(e)))?;
+
+    Ok(buffer)
+}
+
+pub fn inspect(value: &LuaValue, colorize: bool, limits: InspectLimits) -> LuaResult<String> {
+    match value {
+        LuaValue::Table(tbl) => display_table(tbl, colorize, limits),
+        // only on its own, a dump spans lines that wouldn't line up inside a table
+        LuaValue::String(s) if limits.hexdump && is_binary(&s.as_bytes()) => {
+            let bytes = s.as_bytes();
+
+            Ok(match limits.max_string {
+                Some(max) if bytes.len() > max => {
+                    let strings = &[value_colors()
+                        .note
+                        .paint(format!("… ({} bytes)", bytes.len()))];
+
+                    format!(
+                        "{}\n{}",
+                        hexdump(&bytes[..max], colorize),
+                        handle_strings(colorize, AnsiStrings(strings))
+                    )
+                }
+                _ => hexdump(&bytes, colorize),
+            })
+        }
+        LuaValue::UserData(ud) => {
+            let mut buffer = String::new();
+
+            display_userdata_inner(&mut buffer, ud, colorize, limits, &mut HashMap::new(), 0)
+                .map_err(|e| LuaError::ExternalError(Arc::new(e)))?;
+
+            Ok(buffer)
+        }
+        value => Ok(display_limited(value, colorize, limits)),
+    }
+}
+
+/// Hands what is written through `fmt::Write` to an `io::Write`, keeping the error
+/// `fmt::Error` can't carry
+struct IoWriter<'a, W: io::Write> {
+    inner: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// `inspect` written to `out` as it is produced, so large tables are never held in
+/// memory as a whole
+pub fn inspect_to(
+    out: &mut impl io::Write,
+    value: &LuaValue,
+    colorize: bool,
+    limits: InspectLimits,
+) -> LuaResult<()> {
+    let mut writer = IoWriter {
+        inner: out,
+        error: None,
+    };
+
+    let result = match value {
+        LuaValue::Table(tbl) => display_table_inner(
+            &mut writer,
+            tbl,
+            colorize,
+            limits,
+            &mut HashMap::new(),
+            0,
+            0,
+        ),
+        LuaValue::UserData(ud) => {
+            display_userdata_inner(&mut writer, ud, colorize, limits, &mut HashMap::new(), 0)
+        }
+        value => {
+            let text = inspect(value, colorize, limits)?;
+
+            fmt::Write::write_str(&mut writer, &text)
+        }
+    };
+
+    result.map_err(|e| match writer.error.take() {
+        Some(e) => LuaError::external(e),
+        None => LuaError::ExternalError(Arc::new(e)),
+    })
+}
+
+#[derive(Clone, Copy)]
+pub enum TableFormat {
+    ComfyTable,
+    Inspect,
+    Address,
+    Markdown,
+}
+
+/// How `comfy_table` lays out cells and rows
+pub struct ComfyOptions {
+    /// Draw nested tables as tables rather than `table@0x...`
+    pub recursive: bool,
+    /// Right-align cells holding numbers
+    pub align_numbers: bool,
+    /// Order arrays of records by the values under this key instead of their index
+    pub sort_by: Option<String>,
+}
+
+impl Default for ComfyOptions {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            align_numbers: false,
+            sort_by: None,
+        }
+    }
+}
+
+/// A value ordered by number, or with `key_order`, so records can be sorted by one of their fields
+struct SortValue(LuaValue);
+
+impl PartialEq for SortValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortValue {}
+
+impl PartialOrd for SortValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SortValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.0.as_f64(), other.0.as_f64()) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => key_order(&self.0, &other.0),
+        }
+    }
+}
+
+/// A cell holding a value that isn't a table
+fn value_cell(value: &LuaValue, options: &ComfyOptions, limits: InspectLimits) -> Cell {
+    let cell = Cell::new(display_limited(value, false, limits));
+
+    match value {
+        LuaValue::Integer(_) | LuaValue::Number(_) if options.align_numbers => {
+            cell.set_alignment(CellAlignment::Right)
+        }
+        _ => cell,
+    }
+}
+
+// narrowest cell a nested table is drawn in, narrower ones print as `table@0x...`
+const MIN_NESTED_WIDTH: usize = 12;
+
+// border and padding columns comfy_table adds around each cell, plus the last border
+const CELL_FRAME: usize = 3;
+
+/// Applies `width` to `table`, letting comfy_table wrap cells that don't fit
+fn constrain(table: &mut Table, width: Option<usize>) {
+    if let Some(width) = width {
+        table.set_content_arrangement(ContentArrangement::Dynamic);
+        table.set_width(width.min(u16::MAX as usize) as u16);
+    }
+}
+
+/// Renders a table found in a cell `width` columns wide
+fn comfy_cell(
+    tbl: LuaTable,
+    options: &ComfyOptions,
+    limits: InspectLimits,
+    visited: &mut HashMap<usize, usize>,
+    depth: usize,
+    width: Option<usize>,
+) -> LuaResult<String> {
+    match width {
+        _ if !options.recursive => Ok(display_basic(&LuaValue::Table(tbl), false)),
+        Some(width) if width < MIN_NESTED_WIDTH => Ok(display_basic(&LuaValue::Table(tbl), false)),
+        width => comfy_table_inner(&tbl, options, limits, visited, depth, width),
+    }
+}
+
+fn comfy_table_inner(
+    tbl: &LuaTable,
+    options: &ComfyOptions,
+    limits: InspectLimits,
+    visited: &mut HashMap<usize, usize>,
+    depth: usize,
+    width: Option<usize>,
+) -> LuaResult<String> {
+    let addr = tbl.to_pointer() as usize;
+
+    if let Some(id) = visited.get(&addr) {
+        return Ok(format!("<table {id}>"));
+    }
+
+    if let Some(text) = custom_format(tbl, false) {
+        return Ok(text);
+    }
+
+    let id = visited.len();
+    visited.insert(addr, id);
+
+    let printable = is_short_printable(tbl);
+
+    if printable {
+        return Ok(print_array_inner(tbl, false, limits, depth));
+    }
+
+    if limits.is_too_deep(depth) {
+        return Ok(format!("<table {id}> {{...}}"));
+    }
+
+    let header = if limits.summary {
+        format!("<table {id}> {}", shape(tbl, false))
+    } else {
+        format!("<table {id}>")
+    };
+
+    if let Some(columns) = record_columns(tbl) {
+        return comfy_records(
+            tbl, header, &columns, options, limits, visited, depth, width,
+        );
+    }
+
+    let mut entries = Vec::new();
+    let mut more = None;
+    let mut pairs = table_pairs(tbl, limits);
+
+    while let Some((key, value)) = pairs.next() {
+        if limits.is_too_long(entries.len()) {
+            more = Some(1 + pairs.count());
+            break;
+        }
+
+        entries.push((display_basic(&key, false), key, value));
+    }
+
+    if entries.is_empty() {
+        return Ok(String::from("{}"));
+    }
+
+    // the key column is as wide as its widest key or the header above it
+    let key_width = entries
+        .iter()
+        .map(|(key_str, ..)| visible_width(key_str))
+        .chain([visible_width(&header)])
+        .max()
+        .unwrap_or_default();
+    let value_width = width.map(|width| width.saturating_sub(key_width + 2 * CELL_FRAME + 1));
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header(vec![header]);
+    constrain(&mut table, width);
+
+    for (key_str, key, value) in entries {
+        let value_cell = if is_redacted(&key, limits) {
+            Cell::new(redacted(false))
+        } else if let LuaValue::Table(sub) = value {
+            Cell::new(comfy_cell(
+                sub,
+                options,
+                limits,
+                visited,
+                depth + 1,
+                value_width,
+            )?)
+        } else {
+            value_cell(&value, options, limits)
+        };
+
+        table.add_row(vec![Cell::new(key_str), value_cell]);
+    }
+
+    if let Some(more) = more {
+        table.add_row(vec![format!("... ({more} more)")]);
+    }
+
+    Ok(table.to_string())
+}
+
+/// Keys shared by every element when `tbl` is an array of records, sorted
+fn record_columns(tbl: &LuaTable) -> Option<Vec<String>> {
+    let len = tbl.raw_len();
+
+    if len == 0 || tbl.pairs::<LuaValue, LuaValue>().count() != len {
+        return None;
+    }
+
+    let mut columns: Option<Vec<String>> = None;
+
+    for record in tbl.sequence_values::<LuaValue>() {
+        let LuaValue::Table(record) = record.ok()? else {
+            return None;
+        };
+
+        let mut keys = record
+            .pairs::<LuaValue, LuaValue>()
+            .map(|pair| match pair {
+                Ok((LuaValue::String(key), _)) => Some(key.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        keys.sort();
+
+        match &columns {
+            Some(columns) if *columns != keys => return None,
+            Some(_) => {}
+            None => columns = Some(keys),
+        }
+    }
+
+    columns.filter(|columns| !columns.is_empty())
+}
+
+/// One row per record and one column per key, for tables `record_columns` accepts
+#[allow(clippy::too_many_arguments)]
+fn comfy_records(
+    tbl: &LuaTable,
+    header: String,
+    columns: &[String],
+    options: &ComfyOptions,
+    limits: InspectLimits,
+    visited: &mut HashMap<usize, usize>,
+    depth: usize,
+    width: Option<usize>,
+) -> LuaResult<String> {
+    // the index column keeps its header, the rest is shared evenly by the keys
+    let cell_width = width.map(|width| {
+        (width.saturating_sub(visible_width(&header) + CELL_FRAME + 1) / columns.len())
+            .saturating_sub(CELL_FRAME)
+    });
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL_CONDENSED);
+    table.set_header([header].into_iter().chain(columns.iter().cloned()));
+    constrain(&mut table, width);
+
+    let mut records = tbl
+        .sequence_values::<LuaTable>()
+        .flatten()
+        .enumerate()
+        .collect::<Vec<_>>();
+
+    if let Some(column) = options
+        .sort_by
+        .as_ref()
+        .filter(|column| columns.contains(column))
+    {
+        records.sort_by_cached_key(|(_, record)| {
+            SortValue(record.raw_get(column.as_str()).unwrap_or(LuaValue::Nil))
+        });
+    }
+
+    let mut records = records.into_iter().enumerate();
+
+    while let Some((shown, (i, record))) = records.next() {
+        if limits.is_too_long(shown) {
+            table.add_row(vec![format!("... ({} more)", 1 + records.count())]);
+            break;
+        }
+
+        let id = visited.len();
+        visited.entry(record.to_pointer() as usize).or_insert(id);
+
+        let mut row = vec![value_cell(
+            &LuaValue::Integer(i as i64 + 1),
+            options,
+            limits,
+        )];
+
+        for (key, value) in table_pairs(
+            &record,
+            InspectLimits {
+                sort_keys: true,
+                ..limits
+            },
+        ) {
+            row.push(if is_redacted(&key, limits) {
+                Cell::new(redacted(false))
+            } else {
+                match value {
+                    LuaValue::Table(sub) => Cell::new(comfy_cell(
+                        sub,
+                        options,
+                        limits,
+                        visited,
+                        depth + 2,
+                        cell_width,
+                    )?),
+                    value => value_cell(&value, options, limits),
+                }
+            });
+        }
+
+        table.add_row(row);
+    }
+
+    Ok(table.to_string())
+}
+
+/// Prints `tbl` with comfy_table, fitted to the terminal width when there is one
+pub fn comfy_table(
+    tbl: &LuaTable,
+    options: &ComfyOptions,
+    limits: InspectLimits,
+) -> LuaResult<String> {
+    let mut visited = HashMap::new();
+    comfy_table_inner(tbl, options, limits, &mut visited, 0, terminal_width())
+}
+
+/// One Markdown table row, with the `|` that would end a cell early escaped
+fn markdown_row(cells: impl IntoIterator<Item = String>) -> String {
+    let cells = cells
+        .into_iter()
+        .map(|cell| cell.replace('|', "\\|"))
+        .collect::<Vec<_>>();
+
+    format!("| {} |", cells.join(" | "))
+}
+
+/// A cell holding `value`, nested tables written on one line
+fn markdown_value(
+    key: &LuaValue,
+    value: &LuaValue,
+    limits: InspectLimits,
+    seen: &mut HashMap<usize, usize>,
+) -> String {
+    if is_redacted(key, limits) {
+        return redacted(false);
+    }
+
+    let LuaValue::Table(tbl) = value else {
+        return display_limited(value, false, limits);
+    };
+
+    let mut added = HashMap::new();
+
+    match compact_table(tbl, false, limits, seen, &mut added, 1, usize::MAX) {
+        Some(line) => {
+            seen.extend(added);
+            line
+        }
+        None => display_basic(value, false),
+    }
+}
+
+/// `tbl` as a GitHub-flavored Markdown table, with a column per key for arrays of
+/// records and a `key | value` row per entry otherwise
+pub fn markdown_table(tbl: &LuaTable, limits: InspectLimits) -> String {
+    if let Some(text) = custom_format(tbl, false) {
+        return text;
+    }
+
+    let mut seen = HashMap::from([(tbl.to_pointer() as usize, 0)]);
+    let mut lines = Vec::new();
+
+    if let Some(columns) = record_columns(tbl) {
+        lines.push(markdown_row(
+            [String::from("#")]
+                .into_iter()
+                .chain(columns.iter().cloned()),
+        ));
+        lines.push(markdown_row(vec![String::from("---"); columns.len() + 1]));
+
+        let mut records = tbl.sequence_values::<LuaTable>().flatten().enumerate();
+
+        while let Some((i, record)) = records.next() {
+            if limits.is_too_long(i) {
+                lines.push(markdown_row([format!(
+                    "... ({} more)",
+                    1 + records.count()
+                )]));
+                break;
+            }
+
+            let id = seen.len();
+            seen.entry(record.to_pointer() as usize).or_insert(id);
+
+            let sorted = InspectLimits {
+                sort_keys: true,
+                ..limits
+            };
+            let cells = table_pairs(&record, sorted)
+                .map(|(key, value)| markdown_value(&key, &value, limits, &mut seen))
+                .collect::<Vec<_>>();
+
+            lines.push(markdown_row([(i + 1).to_string()].into_iter().chain(cells)));
+        }
+
+        return lines.join("\n");
+    }
+
+    let mut pairs = table_pairs(tbl, limits);
+
+    while let Some((key, value)) = pairs.next() {
+        if limits.is_too_long(lines.len()) {
+            lines.push(markdown_row([
+                format!("... ({} more)", 1 + pairs.count()),
+                String::new(),
+            ]));
+            break;
+        }
+
+        let value = markdown_value(&key, &value, limits, &mut seen);
+        lines.push(markdown_row([display_basic(&key, false), value]));
+    }
+
+    if lines.is_empty() {
+        return String::from("{}");
+    }
+
+    let header = [
+        markdown_row([String::from("key"), String::from("value")]),
+        markdown_row([String::from("---"), String::from("---")]),
+    ];
+
+    header
+        .into_iter()
+        .chain(lines)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl TableFormat {
+    pub fn format(
+        &self,
+        tbl: &LuaTable,
+        colorize: bool,
+        limits: InspectLimits,
+    ) -> LuaResult<String> {
+        match self {
+            TableFormat::Address => Ok(display_basic(&LuaValue::Table(tbl.clone()), colorize)),
+            TableFormat::Inspect => {
+                display_table(tbl, colorize, limits).map_err(LuaError::external)
+            }
+            TableFormat::ComfyTable => comfy_table(tbl, &ComfyOptions::default(), limits),
+            TableFormat::Markdown => Ok(markdown_table(tbl, limits)),
+        }
+    }
+
+    /// `format` written to `out`, as it is produced for `TableFormat::Inspect`
+    pub fn format_to(
+        &self,
+        out: &mut impl io::Write,
+        tbl: &LuaTable,
+        colorize: bool,
+        limits: InspectLimits,
+    ) -> LuaResult<()> {
+        match self {
+            TableFormat::Inspect => {
+                inspect_to(out, &LuaValue::Table(tbl.clone()), colorize, limits)
+            }
+            format => out
+                .write_all(format.format(tbl, colorize, limits)?.as_bytes())
+                .map_err(LuaError::external),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FORMATTER_CODE;
+
+    #[test]
+    fn formatters() {
+        let lua = Lua::new();
+        let formatters = lua.load(FORMATTER_CODE).eval().unwrap();
+        set_formatters(&lua, formatters);
+
+        let (point, broken, meta): (LuaTable, LuaTable, LuaTable) = lua
+            .load(
+                r#"
+                local Point = {}
+                manen.formatter(Point, function(p) return 'Point(' .. p.x .. ')' end)
+
+                local Broken = {}
+                manen.formatter(Broken, function() error('no way') end)
+
+                return setmetatable({ x = 1 }, Point), setmetatable({}, Broken), Point
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let print = |tbl: &LuaTable| {
+            with_thread_debug(Some(&lua), || {
+                inspect(
+                    &LuaValue::Table(tbl.clone()),
+                    false,
+                    InspectLimits::default(),
+                )
+            })
+            .unwrap()
+        };
+
+        assert_eq!("Point(1)", print(&point));
+        assert!(print(&broken).starts_with("<formatter error: "));
+        assert!(print(&broken).contains("no way"));
+
+        // the metatable itself is left as it was
+        assert_eq!(0, meta.pairs::<LuaValue, LuaValue>().count());
+
+        // without the state's formatters the table prints as any other
+        let plain = inspect(&LuaValue::Table(point), false, InspectLimits::default()).unwrap();
+        assert!(plain.contains("x = 1"));
+    }
+
+    #[cfg(any(feature = "lua54", feature = "lua53", feature = "lua52"))]
+    #[test]
+    fn function_parameters() {
+        let lua = unsafe { Lua::unsafe_new() };
+        set_thread_debug(&lua);
+
+        // an environment whose `debug` is not the real one
+        let f: LuaFunction = lua
+            .load(
+                r#"
+                local env = { debug = { getinfo = function() error('not this one') end } }
+                return load('return function(a, b, ...) end', '=t', 't', env)()
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let f = LuaValue::Function(f);
+
+        let shown = with_thread_debug(Some(&lua), || display_basic(&f, false));
+        assert!(shown.contains("(a, b, ...) [t:1]"), "{shown}");
+
+        // nothing to ask outside of the state's own printing
+        assert!(!display_basic(&f, false).contains("(a, b"));
+    }
+
+    #[test]
+    fn streamed_output() {
+        let lua = Lua::new();
+        let tbl: LuaTable = lua
+            .load("return { name = 'manen', list = { 1, 2, { deep = true } } }")
+            .eval()
+            .unwrap();
+        let limits = InspectLimits {
+            sort_keys: true,
+            ..InspectLimits::default()
+        };
+        let value = LuaValue::Table(tbl.clone());
+
+        let mut out = Vec::new();
+        inspect_to(&mut out, &value, false, limits).unwrap();
+
+        assert_eq!(
+            inspect(&value, false, limits).unwrap(),
+            String::from_utf8(out).unwrap()
+        );
+
+        for format in [
+            TableFormat::Inspect,
+            TableFormat::Address,
+            TableFormat::ComfyTable,
+            TableFormat::Markdown,
+        ] {
+            let mut out = Vec::new();
+            format.format_to(&mut out, &tbl, false, limits).unwrap();
+
+            assert_eq!(
+                format.format(&tbl, false, limits).unwrap(),
+                String::from_utf8(out).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn markdown() {
+        let lua = Lua::new();
+        let limits = InspectLimits {
+            sort_keys: true,
+            ..InspectLimits::default()
+        };
+        let table = |code: &str| lua.load(code).eval::<LuaTable>().unwrap();
+
+        assert_eq!(
+            "| # | n | name |\n| --- | --- | --- |\n| 1 | 1 | \"a\" |\n| 2 | 2 | \"b\" |",
+            markdown_table(
+                &table("return { { name = 'a', n = 1 }, { name = 'b', n = 2 } }"),
+                limits
+            )
+        );
+        assert_eq!(
+            "| key | value |\n| --- | --- |\n| \"pipe\" | \"x\\|y\" |",
+            markdown_table(&table("return { pipe = 'x|y' }"), limits)
+        );
+        assert_eq!(
+            "| key | value |\n| --- | --- |\n| 1 | 10 |\n| ... (2 more) |  |",
+            markdown_table(
+                &table("return { 10, 20, 30 }"),
+                InspectLimits {
+                    max_items: Some(1),
+                    ..limits
+                }
+            )
+        );
+        assert_eq!("{}", markdown_table(&table("return {}"), limits));
+    }
+
+    #[test]
+    fn string_literals() {
+        let bytes = b"a\x01\xffb";
+
+        assert_eq!(r#""a\001\255b""#, lua_string_literal(bytes));
+        assert_eq!(
+            r#""a\1\xFFb""#,
+            format_string_escaped(bytes, false, ByteEscape::Hex)
+        );
+
+        assert_eq!(r#""it's""#, lua_string_literal(b"it's"));
+        assert_eq!(r#"'say "hi"'"#, lua_string_literal(b"say \"hi\""));
+        assert_eq!(r#""it's \"x\"""#, lua_string_literal(b"it's \"x\""));
+
+        // every byte, and a real U+FFFD, reads back the same in generated code
+        let mut all = (0..=255).collect::<Vec<u8>>();
+        all.extend("\u{FFFD}".as_bytes());
+
+        let lua = Lua::new();
+        let read: LuaString = lua
+            .load(format!("return {}", lua_string_literal(&all)))
+            .eval()
+            .unwrap();
+
+        assert_eq!(all, read.as_bytes().to_vec());
+    }
+
+    #[test]
+    fn inheritance_chain() {
+        let lua = Lua::new();
+        let (rex, looped, plain): (LuaTable, LuaTable, LuaTable) = lua
+            .load(
+                r#"
+                local Animal = { __name = 'Animal' }
+                local Dog = setmetatable({ __name = 'Dog' }, { __index = Animal })
+
+                local Self = { __name = 'Self' }
+                Self.__index = Self
+                setmetatable(Self, Self)
+
+                return setmetatable({}, { __index = Dog }), setmetatable({}, Self), {}
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(
+            Some(String::from(": extends Dog < Animal")),
+            inheritance(&rex, false)
+        );
+        // a class that is its own metatable is named once
+        assert_eq!(
+            Some(String::from(": extends Self")),
+            inheritance(&looped, false)
+        );
+        assert_eq!(None, inheritance(&plain, false));
+    }
+
+    #[test]
+    fn thread_debug() {
+        let lua = unsafe { Lua::unsafe_new() };
+        set_thread_debug(&lua);
+
+        let thread: LuaThread = lua
+            .load(
+                r#"
+                local co = coroutine.create(function()
+                   coroutine.yield()
+                end)
+                coroutine.resume(co)
+                return co
+                "#,
+            )
+            .set_name("=co")
+            .eval()
+            .unwrap();
+        let thread = LuaValue::Thread(thread);
+
+        let shown = with_thread_debug(Some(&lua), || {
+            // a state without `debug` leaves it out while its values print
+            let inner = with_thread_debug(None, || display_basic(&thread, false));
+            assert!(inner.ends_with("(suspended)"), "{inner}");
+
+            display_basic(&thread, false)
+        });
+
+        assert!(shown.ends_with("(suspended at co:3)"), "{shown}");
+        // and what was set before is back afterwards
+        assert!(display_basic(&thread, false).ends_with("(suspended)"));
     }
 }
@@ -0,0 +1,177 @@
+use std::{
+    env,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Names `manen.executor = 'auto'` looks for, LuaJIT comes late as it can't be cancelled
+pub const DEFAULT_PREFERENCE: [&str; 10] = [
+    "lua5.4", "lua54", "lua5.3", "lua53", "lua", "lua5.2", "lua52", "luajit", "lua5.1", "lua51",
+];
+
+/// Version of the embedded VM as `--lua` names it, builds link exactly one
+pub const EMBEDDED_VERSION: &str = if cfg!(any(feature = "luajit", feature = "luajit52")) {
+    "luajit"
+} else if cfg!(feature = "lua51") {
+    "5.1"
+} else if cfg!(feature = "lua52") {
+    "5.2"
+} else if cfg!(feature = "lua53") {
+    "5.3"
+} else {
+    "5.4"
+};
+
+/// Versions `--lua` and `manen.lua_version` accept
+pub const VERSIONS: [&str; 6] = ["5.1", "5.2", "5.3", "5.4", "luajit", "luau"];
+
+/// Names interpreters of `version` are installed as
+pub fn version_names(version: &str) -> Vec<String> {
+    let names: &[&str] = match version {
+        "5.1" => &["lua5.1", "lua51"],
+        "5.2" => &["lua5.2", "lua52"],
+        "5.3" => &["lua5.3", "lua53"],
+        "5.4" => &["lua5.4", "lua54"],
+        "luajit" => &["luajit"],
+        "luau" => &["luau"],
+        _ => &[],
+    };
+
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// If the `-v` banner of an interpreter, as `Interpreter::version` shortens it, is of
+/// `version` as `--lua` names it
+pub fn is_version(banner: &str, version: &str) -> bool {
+    match version {
+        "luajit" => banner.starts_with("LuaJIT"),
+        "luau" => banner.starts_with("Luau"),
+        version => banner
+            .strip_prefix("Lua ")
+            .is_some_and(|rest| rest == version || rest.starts_with(&format!("{version}."))),
+    }
+}
+
+/// A Lua interpreter found on the `PATH`
+pub struct Interpreter {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// First executable named `name` in the directories of `PATH`
+pub fn find(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+/// Interpreters of `preference` that are installed, in its order
+pub fn detect(preference: &[String]) -> Vec<Interpreter> {
+    preference
+        .iter()
+        .filter_map(|name| {
+            find(name).map(|path| Interpreter {
+                name: name.clone(),
+                path,
+            })
+        })
+        .collect()
+}
+
+/// `Lua 5.4.6` of `Lua 5.4.6  Copyright (C) 1994-2023 Lua.org, PUC-Rio`
+fn short_version(banner: &str) -> &str {
+    let line = banner
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("");
+
+    line.split("Copyright")
+        .next()
+        .unwrap_or(line)
+        .trim_end_matches([' ', '-'])
+        .trim()
+}
+
+impl Interpreter {
+    /// From `-v`, which Lua 5.1 prints to stderr
+    pub fn version(&self) -> Option<String> {
+        let output = Command::new(&self.path).arg("-v").output().ok()?;
+
+        [output.stdout, output.stderr]
+            .iter()
+            .map(|text| short_version(&String::from_utf8_lossy(text)).to_string())
+            .find(|version| !version.is_empty())
+    }
+}
+
+/// Prints every interpreter of `preference` that is installed, marking the one picked
+pub fn list(preference: &[String]) {
+    let found = detect(preference);
+
+    if found.is_empty() {
+        println!("no Lua interpreter found, tried {}", preference.join(", "));
+        return;
+    }
+
+    let width = found
+        .iter()
+        .map(|found| found.name.len())
+        .max()
+        .unwrap_or(0);
+
+    for (i, interpreter) in found.iter().enumerate() {
+        println!(
+            "{} {:width$}  {}  {}",
+            if i == 0 { '*' } else { ' ' },
+            interpreter.name,
+            interpreter
+                .version()
+                .unwrap_or_else(|| String::from("unknown version")),
+            interpreter.path.display(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_banners() {
+        assert_eq!(
+            "Lua 5.4.6",
+            short_version("Lua 5.4.6  Copyright (C) 1994-2023 Lua.org, PUC-Rio\n")
+        );
+        assert_eq!(
+            "LuaJIT 2.1.1700008891",
+            short_version(
+                "LuaJIT 2.1.1700008891 -- Copyright (C) 2005-2023 Mike Pall. https://luajit.org/\n"
+            )
+        );
+        assert_eq!("", short_version(""));
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(["lua5.1", "lua51"], version_names("5.1").as_slice());
+        assert_eq!(["luajit"], version_names("luajit").as_slice());
+        assert_eq!(["luau"], version_names("luau").as_slice());
+        assert!(version_names("5.5").is_empty());
+
+        assert!(is_version("Lua 5.4.6", "5.4"));
+        assert!(!is_version("Lua 5.4.6", "5.1"));
+        assert!(!is_version("Lua 5.10", "5.1"));
+        assert!(is_version("LuaJIT 2.1.1700008891", "luajit"));
+        assert!(!is_version("LuaJIT 2.1.1700008891", "5.1"));
+        assert!(is_version("Luau 0.650", "luau"));
+        assert!(!is_version("Luau 0.650", "5.1"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::inspect::lua_string_literal;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Language {
+    Lua,
+    #[cfg(feature = "fennel")]
+    Fennel,
+    #[cfg(feature = "teal")]
+    Teal,
+}
+
+impl Language {
+    /// Language implied by a file extension, if it is one manen was built with
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "lua" => Some(Language::Lua),
+            #[cfg(feature = "fennel")]
+            "fnl" => Some(Language::Fennel),
+            #[cfg(feature = "teal")]
+            "tl" => Some(Language::Teal),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Lua => "lua",
+            #[cfg(feature = "fennel")]
+            Language::Fennel => "fennel",
+            #[cfg(feature = "teal")]
+            Language::Teal => "teal",
+        }
+    }
+
+    /// Lua code defining the helpers `wrap` and `compile` call into
+    pub fn prelude(&self) -> Option<&'static str> {
+        match self {
+            #[cfg(feature = "teal")]
+            Language::Teal => Some(include_str!("../lua/teal.lua")),
+            _ => None,
+        }
+    }
+
+    /// Wraps source code into a Lua chunk that compiles and runs it inside the executor
+    ///
+    /// Compilers are loaded with `require` so macros and globals see the live session
+    #[cfg_attr(
+        not(any(feature = "fennel", feature = "teal")),
+        allow(unused_variables)
+    )]
+    pub fn wrap(&self, code: &str, name: &str) -> String {
+        match self {
+            Language::Lua => code.to_string(),
+            #[cfg(feature = "fennel")]
+            Language::Fennel => format!(
+                "return require('fennel').eval({}, {{ filename = {} }})",
+                lua_string_literal(code.as_bytes()),
+                lua_string_literal(name.as_bytes())
+            ),
+            #[cfg(feature = "teal")]
+            Language::Teal => format!(
+                "return __manen_teal({}, {})",
+                lua_string_literal(code.as_bytes()),
+                lua_string_literal(name.as_bytes())
+            ),
+        }
+    }
+
+    /// Lua chunk returning the compiled Lua source for `code`
+    pub fn compile(&self, code: &str) -> String {
+        match self {
+            Language::Lua => format!("return {}", lua_string_literal(code.as_bytes())),
+            #[cfg(feature = "fennel")]
+            Language::Fennel => format!(
+                "return require('fennel').compileString({})",
+                lua_string_literal(code.as_bytes())
+            ),
+            #[cfg(feature = "teal")]
+            Language::Teal => format!(
+                "return __manen_teal_gen({})",
+                lua_string_literal(code.as_bytes())
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::Lua;
+
+    use super::*;
+
+    #[test]
+    fn languages() {
+        assert!(Language::from_path(Path::new("init.lua")) == Some(Language::Lua));
+        assert!(Language::from_path(Path::new("notes.txt")).is_none());
+        assert!(Language::from_path(Path::new("Makefile")).is_none());
+
+        assert_eq!("lua", Language::Lua.name());
+        assert!(Language::Lua.prelude().is_none());
+        assert_eq!("x = 1", Language::Lua.wrap("x = 1", "repl"));
+
+        // Lua compiles to itself
+        let compiled = Lua::new()
+            .load(Language::Lua.compile("print('\"hi\"')\n"))
+            .eval::<String>()
+            .unwrap();
+
+        assert_eq!("print('\"hi\"')\n", compiled);
+    }
+}
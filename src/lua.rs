@@ -1,15 +1,24 @@
 use std::{
-    io::Write,
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    path::PathBuf,
     process::Command,
     sync::{
-        Arc, RwLock,
-        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+        atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering},
     },
+    thread,
+    time::{Duration, Instant},
 };
 
 use mlua::prelude::*;
 use nix::{
-    sys::signal::{Signal, kill},
+    sys::{
+        signal::{Signal, kill},
+        wait::{WaitPidFlag, WaitStatus, waitpid},
+    },
     unistd::Pid,
 };
 use rexpect::session::{PtySession, spawn_command};
@@ -17,42 +26,139 @@ use send_wrapper::SendWrapper;
 use tempfile::NamedTempFile;
 use thiserror::Error;
 
-use crate::inspect::format_string_bytes;
+use crate::{
+    agent,
+    completion::resolve_path,
+    inspect::{self, lua_string_literal},
+    msgpack, serialize, share,
+};
 
 pub trait LuaExecutor: Send + Sync {
-    fn exec(&self, code: &str) -> LuaResult<LuaValue>;
+    /// Every value `code` returns, run as is since internal code calls it too. Input gets
+    /// its trailing expression returned and `_` set by `Editor`
+    fn exec(&self, code: &str) -> LuaResult<LuaMultiValue>;
     fn globals(&self) -> LuaResult<LuaTable>;
     fn cancel(&self);
+
+    /// First value `code` returns, `nil` when it returns nothing
+    fn eval(&self, code: &str) -> LuaResult<LuaValue> {
+        Ok(self.exec(code)?.into_iter().next().unwrap_or(LuaValue::Nil))
+    }
+
+    /// Bytes in use and garbage collections seen so far, readable while `exec` runs
+    fn memory(&self) -> Option<(usize, u64)> {
+        None
+    }
+
+    /// Value at a key path of globals, enough of it to complete the fields of tables
+    fn resolve(&self, path: &[String]) -> Option<LuaValue> {
+        resolve_path(self.globals().ok()?, path)
+    }
+
+    /// Applies the globals saved before the process was restarted again, with their count
+    /// and how long before now they were saved, `None` when nothing was saved
+    fn restore(&self) -> LuaResult<Option<(usize, Duration)>> {
+        Ok(None)
+    }
+
+    /// Count of the globals `restore` would apply and when they were saved
+    fn saved_globals(&self) -> Option<(usize, Instant)> {
+        None
+    }
+
+    /// Starts the process of the session again, also once the restart policy gave up
+    fn restart(&self) -> LuaResult<()> {
+        Err(LuaError::runtime("only a system Lua can be restarted"))
+    }
+
+    /// If calls no longer wait for the executor to start, the highlighter and hinter
+    /// leave it alone until then so a keystroke isn't what starts it
+    fn is_started(&self) -> bool {
+        true
+    }
+
+    /// State the returned values belong to when it lives in this process, so printing
+    /// them can look into its coroutines
+    fn lua(&self) -> Option<&Lua> {
+        None
+    }
+}
+
+// sampled from the hook since the Lua state is locked while code runs
+#[derive(Default)]
+struct MemoryStats {
+    used: AtomicUsize,
+    collections: AtomicU64,
+    /// Set once the sentinel was collected, the hook makes the next one
+    rearm: AtomicBool,
+}
+
+/// Unreferenced userdata, dropped by the collector when a cycle finishes, which is how
+/// collections are counted as Lua has no count of its own
+struct GcSentinel(Arc<MemoryStats>);
+
+impl LuaUserData for GcSentinel {}
+
+impl Drop for GcSentinel {
+    fn drop(&mut self) {
+        self.0.collections.fetch_add(1, Ordering::Relaxed);
+        self.0.rearm.store(true, Ordering::Relaxed);
+    }
 }
 
 pub struct MluaExecutor {
     lua: Lua,
     cancelled: Arc<AtomicBool>,
+    memory: Arc<MemoryStats>,
 }
 
 impl MluaExecutor {
     pub fn new() -> Self {
         let lua = Lua::new();
         let cancelled = Arc::new(AtomicBool::new(false));
+        let memory = Arc::new(MemoryStats::default());
 
         let inner_cancelled = cancelled.clone();
-        lua.set_hook(LuaHookTriggers::EVERY_LINE, move |_lua, _debug| {
+        let inner_memory = memory.clone();
+        lua.set_hook(LuaHookTriggers::EVERY_LINE, move |lua, _debug| {
             if inner_cancelled.load(Ordering::Relaxed) {
                 inner_cancelled.store(false, Ordering::Relaxed);
 
                 return Err(LuaError::runtime("cancelled"));
             }
 
+            inner_memory
+                .used
+                .store(lua.used_memory(), Ordering::Relaxed);
+
+            if inner_memory.rearm.swap(false, Ordering::Relaxed) {
+                lua.create_userdata(GcSentinel(inner_memory.clone()))?;
+            }
+
             Ok(LuaVmState::Continue)
         });
 
-        Self { lua, cancelled }
+        let _ = lua.create_userdata(GcSentinel(memory.clone()));
+
+        inspect::set_thread_debug(&lua);
+
+        if let Ok(serialize) =
+            lua.create_function(|_, value: LuaValue| serialize::serialize(&value))
+        {
+            let _ = lua.globals().raw_set("serialize", serialize);
+        }
+
+        Self {
+            lua,
+            cancelled,
+            memory,
+        }
     }
 }
 
 impl LuaExecutor for MluaExecutor {
-    fn exec(&self, code: &str) -> LuaResult<LuaValue> {
-        self.lua.load(code).set_name("=repl").eval()
+    fn exec(&self, code: &str) -> LuaResult<LuaMultiValue> {
+        self.lua.load(code).set_name("=repl").call(())
     }
 
     fn globals(&self) -> LuaResult<LuaTable> {
@@ -62,16 +168,130 @@ impl LuaExecutor for MluaExecutor {
     fn cancel(&self) {
         self.cancelled.store(true, Ordering::Relaxed);
     }
+
+    fn lua(&self) -> Option<&Lua> {
+        Some(&self.lua)
+    }
+
+    fn memory(&self) -> Option<(usize, u64)> {
+        Some((
+            self.memory.used.load(Ordering::Relaxed),
+            self.memory.collections.load(Ordering::Relaxed),
+        ))
+    }
+}
+
+type Start = Box<dyn FnOnce() -> LuaResult<Arc<dyn LuaExecutor>> + Send>;
+
+/// Executor started by the first call made to it rather than when constructed
+pub struct DeferredExecutor {
+    start: Mutex<Option<Start>>,
+    executor: OnceLock<Result<Arc<dyn LuaExecutor>, String>>,
+}
+
+impl DeferredExecutor {
+    pub fn new(start: impl FnOnce() -> LuaResult<Arc<dyn LuaExecutor>> + Send + 'static) -> Self {
+        Self {
+            start: Mutex::new(Some(Box::new(start))),
+            executor: OnceLock::new(),
+        }
+    }
+
+    fn get(&self) -> LuaResult<&Arc<dyn LuaExecutor>> {
+        let executor = self.executor.get_or_init(|| {
+            let start = self.start.lock().unwrap().take();
+
+            match start {
+                Some(start) => start().map_err(|e| e.to_string()),
+                None => Err(String::from("executor failed to start")),
+            }
+        });
+
+        // the error is kept as text and raised again by every later call
+        executor
+            .as_ref()
+            .map_err(|e| LuaError::RuntimeError(format!("failed to start executor: {e}")))
+    }
+}
+
+impl LuaExecutor for DeferredExecutor {
+    fn exec(&self, code: &str) -> LuaResult<LuaMultiValue> {
+        self.get()?.exec(code)
+    }
+
+    fn globals(&self) -> LuaResult<LuaTable> {
+        self.get()?.globals()
+    }
+
+    // nothing runs before it started, so there is nothing to cancel
+    fn cancel(&self) {
+        if let Some(Ok(executor)) = self.executor.get() {
+            executor.cancel();
+        }
+    }
+
+    fn memory(&self) -> Option<(usize, u64)> {
+        match self.executor.get() {
+            Some(Ok(executor)) => executor.memory(),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, path: &[String]) -> Option<LuaValue> {
+        self.get().ok()?.resolve(path)
+    }
+
+    fn restore(&self) -> LuaResult<Option<(usize, Duration)>> {
+        self.get()?.restore()
+    }
+
+    // nothing was saved before it started
+    fn saved_globals(&self) -> Option<(usize, Instant)> {
+        match self.executor.get() {
+            Some(Ok(executor)) => executor.saved_globals(),
+            _ => None,
+        }
+    }
+
+    fn restart(&self) -> LuaResult<()> {
+        self.get()?.restart()
+    }
+
+    fn is_started(&self) -> bool {
+        self.executor.get().is_some()
+    }
+
+    fn lua(&self) -> Option<&Lua> {
+        self.executor.get()?.as_ref().ok()?.lua()
+    }
 }
 
 pub struct SystemLuaExecutor {
     session: RwLock<SendWrapper<PtySession>>,
     program: String,
+    host: Host,
+    options: ProcessOptions,
     lua: Lua,
 
-    cancellation_file: RwLock<Option<NamedTempFile>>,
+    cancellation_file: RwLock<Option<CancelFile>>,
     pid: AtomicI32,
     is_stopping: AtomicBool,
+    /// Restarts after crashes since the last answer, for `max_restarts` and the backoff
+    restarts: AtomicUsize,
+    /// How the process ended when the policy left it stopped
+    stopped: Mutex<Option<String>>,
+    snapshot: Mutex<Option<Snapshot>>,
+    /// Last snapshot of the process before it was restarted, for `.restore`
+    before_restart: Mutex<Option<Snapshot>>,
+}
+
+/// How often globals are saved, the first `exec` after the interval takes a snapshot
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Plain globals of the system Lua, from `rpc.snapshot` of `lua/rpc.lua`
+struct Snapshot {
+    globals: LuaTable,
+    taken: Instant,
 }
 
 #[derive(Debug, Error)]
@@ -82,59 +302,336 @@ pub enum SystemLuaError {
     Expect(#[from] rexpect::error::Error),
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
-    #[error("restarted system Lua")]
-    Restarted,
-    #[error("runtime error")]
+    #[error("system Lua {0}, restarted it, `.restore` applies the globals saved before")]
+    Restarted(String),
+    #[error("system Lua {0} and was not restarted, `.restart` starts it again")]
+    Stopped(String),
+    #[error("agent closed the connection")]
+    Disconnected,
+    #[error("{0}")]
     RuntimeError(String),
 }
 
 enum RpcCommand {
     Globals,
     Exec(String),
+    Fields(Vec<String>),
     Prepare(String),
+    Snapshot,
+    /// A file and its arguments, read by the system Lua itself
+    Run(String, Vec<String>),
 }
 
 impl RpcCommand {
     pub fn to_lua(&self) -> String {
         match self {
             Self::Globals => String::from("globals"),
-            Self::Exec(code) => format!("exec:{}", format_string_bytes(code.as_bytes(), false)),
+            Self::Exec(code) => format!("exec:{}", lua_string_literal(code.as_bytes())),
+            Self::Fields(path) => {
+                let keys = path
+                    .iter()
+                    .map(|key| lua_string_literal(key.as_bytes()))
+                    .collect::<Vec<_>>();
+
+                format!("fields:{{{}}}", keys.join(","))
+            }
             Self::Prepare(file) => format!("prepare:{file}"),
+            Self::Snapshot => String::from("snapshot"),
+            Self::Run(path, args) => {
+                let words = std::iter::once(path)
+                    .chain(args)
+                    .map(|word| lua_string_literal(word.as_bytes()))
+                    .collect::<Vec<_>>();
+
+                format!("run:{{{}}}", words.join(","))
+            }
+        }
+    }
+}
+
+pub const RPC_CODE: &str = include_str!("../lua/rpc.lua");
+
+/// Where the system Lua is started
+enum Host {
+    Local,
+    /// Over `ssh`, to a host that logs in without prompting
+    Ssh(String),
+    /// In a new container of `image`, named so it can be reached again while it runs
+    Docker {
+        image: String,
+        container: String,
+    },
+}
+
+/// How the system Lua is started, from the `system_env`, `system_cwd` and `system_args`
+/// config options
+#[derive(Clone, Default)]
+pub struct ProcessOptions {
+    pub env: HashMap<String, String>,
+    /// On the host the system Lua runs on
+    pub cwd: Option<PathBuf>,
+    /// Given to the interpreter before the `-e` that loads `lua/rpc.lua`
+    pub args: Vec<String>,
+    pub restart: RestartPolicy,
+}
+
+/// What happens when the system Lua dies on its own, one killed by Ctrl-C is always
+/// started again
+#[derive(Clone)]
+pub struct RestartPolicy {
+    pub on_crash: bool,
+    /// Crashes in a row that are restarted, `None` for no limit
+    pub max_restarts: Option<usize>,
+    /// Wait before restarting after a crash, doubled for each one in a row
+    pub backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            on_crash: true,
+            max_restarts: Some(5),
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Wait before the restart of the `restarts`-th crash in a row, `None` when the
+    /// process is left stopped instead
+    fn delay(&self, restarts: usize) -> Option<Duration> {
+        if !self.on_crash || self.max_restarts.is_some_and(|max| restarts > max) {
+            return None;
+        }
+
+        // capped so a long run of crashes doesn't wait for hours
+        Some(self.backoff * (1 << restarts.saturating_sub(1).min(6)))
+    }
+}
+
+impl ProcessOptions {
+    /// Sorted so the command line is the same on every start
+    fn env_pairs(&self) -> Vec<(&String, &String)> {
+        let mut pairs = self.env.iter().collect::<Vec<_>>();
+        pairs.sort();
+        pairs
+    }
+}
+
+impl Host {
+    fn command(&self, program: &str, options: &ProcessOptions) -> Command {
+        match self {
+            Host::Local => {
+                let mut cmd = Command::new(program);
+
+                cmd.envs(&options.env);
+
+                if let Some(cwd) = &options.cwd {
+                    cmd.current_dir(cwd);
+                }
+
+                cmd.args(&options.args);
+                cmd.arg("-e");
+                cmd.arg(RPC_CODE);
+                cmd
+            }
+            Host::Ssh(host) => {
+                let mut cmd = ssh_command(host);
+                let mut line = String::new();
+
+                if let Some(cwd) = &options.cwd {
+                    let _ = write!(line, "cd {} && ", shell_quote(&cwd.to_string_lossy()));
+                }
+
+                if !options.env.is_empty() {
+                    line.push_str("env ");
+
+                    for (key, value) in options.env_pairs() {
+                        let _ = write!(line, "{} ", shell_quote(&format!("{key}={value}")));
+                    }
+                }
+
+                line.push_str(program);
+
+                for arg in &options.args {
+                    let _ = write!(line, " {}", shell_quote(arg));
+                }
+
+                let _ = write!(line, " -e {}", shell_quote(RPC_CODE));
+
+                cmd.arg(line);
+                cmd
+            }
+            Host::Docker { image, container } => {
+                let mut cmd = Command::new("docker");
+
+                cmd.args(["run", "-i", "--rm", "--name", container]);
+
+                if let Some(cwd) = &options.cwd {
+                    cmd.arg("-w");
+                    cmd.arg(cwd);
+                }
+
+                for (key, value) in options.env_pairs() {
+                    cmd.arg("-e");
+                    cmd.arg(format!("{key}={value}"));
+                }
+
+                cmd.args([image, program]);
+                cmd.args(&options.args);
+                cmd.arg("-e");
+                cmd.arg(RPC_CODE);
+                cmd
+            }
+        }
+    }
+
+    /// Runs `sh` code next to the system Lua, `false` locally where files are written directly
+    fn shell(&self, code: &str) -> bool {
+        let mut cmd = match self {
+            Host::Local => return false,
+            Host::Ssh(host) => ssh_command(host),
+            Host::Docker { container, .. } => {
+                let mut cmd = Command::new("docker");
+
+                cmd.args(["exec", container, "sh", "-c"]);
+                cmd
+            }
+        };
+
+        cmd.arg(code).status().is_ok_and(|status| status.success())
+    }
+
+    /// Removes what outlives the local process once it is killed, so the next start
+    /// can reuse its name
+    fn stop(&self) {
+        if let Host::Docker { container, .. } = self {
+            let _ = Command::new("docker")
+                .args(["rm", "-f", container])
+                .output();
+        }
+    }
+}
+
+/// File the running code polls for `stop`, on the host the system Lua runs on
+enum CancelFile {
+    Local(NamedTempFile),
+    Remote(String),
+}
+
+impl CancelFile {
+    fn path(&self) -> String {
+        match self {
+            CancelFile::Local(file) => file.path().to_string_lossy().to_string(),
+            CancelFile::Remote(path) => path.clone(),
+        }
+    }
+
+    fn stop(&mut self, host: &Host) -> bool {
+        match self {
+            CancelFile::Local(file) => file.write_all(b"stop").is_ok() && file.flush().is_ok(),
+            // the path is made of hex digits, so it needs no quoting
+            CancelFile::Remote(path) => host.shell(&format!("printf stop > {path}")),
+        }
+    }
+}
+
+fn ssh_command(host: &str) -> Command {
+    let mut cmd = Command::new("ssh");
+
+    // fail instead of waiting on a password prompt that is never shown, and a host
+    // starting with `-` is still a host
+    cmd.args(["-o", "BatchMode=yes", "--", host]);
+
+    cmd
+}
+
+/// How the process of `pid` ended, waited for so it isn't left a zombie. One still
+/// running, as when only writing to its terminal failed, is killed rather than waited on
+fn exit_status(pid: i32) -> String {
+    let pid = Pid::from_raw(pid);
+
+    let status = match waitpid(pid, Some(WaitPidFlag::WNOHANG)) {
+        Ok(WaitStatus::StillAlive) => {
+            let _ = kill(pid, Signal::SIGKILL);
+
+            waitpid(pid, None)
         }
+        status => status,
+    };
+
+    match status {
+        Ok(WaitStatus::Exited(_, code)) => format!("exited with status {code}"),
+        Ok(WaitStatus::Signaled(_, signal, _)) => format!("was killed by {}", signal.as_str()),
+        _ => String::from("exited"),
     }
 }
 
-const RPC_CODE: &str = include_str!("../lua/rpc.lua");
+/// `text` as a single word for the POSIX shell that runs commands sent by `ssh`
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
 
 impl SystemLuaExecutor {
-    pub fn new(program: &str) -> Result<Self, SystemLuaError> {
-        let (session, file) = Self::obtain_session(program)?;
+    pub fn new(program: &str, options: ProcessOptions) -> Result<Self, SystemLuaError> {
+        Self::start(program, Host::Local, options)
+    }
+
+    /// `program` started on `host` over SSH, which must log in without prompting
+    pub fn ssh(host: &str, program: &str, options: ProcessOptions) -> Result<Self, SystemLuaError> {
+        Self::start(program, Host::Ssh(host.to_string()), options)
+    }
+
+    /// `program` started in a container of `image`, which must have it on its `PATH`
+    pub fn docker(
+        image: &str,
+        program: &str,
+        options: ProcessOptions,
+    ) -> Result<Self, SystemLuaError> {
+        Self::start(
+            program,
+            Host::Docker {
+                image: image.to_string(),
+                container: format!("manen-{}", share::random_token()),
+            },
+            options,
+        )
+    }
+
+    fn start(program: &str, host: Host, options: ProcessOptions) -> Result<Self, SystemLuaError> {
+        let (session, file) = Self::obtain_session(program, &host, &options)?;
         let pid = session.process.child_pid.as_raw();
 
         Ok(Self {
             session: RwLock::new(SendWrapper::new(session)),
             program: program.to_string(),
-            lua: unsafe { Lua::unsafe_new() },
+            host,
+            options,
+            lua: Lua::new(),
             cancellation_file: RwLock::new(file),
             pid: AtomicI32::new(pid),
             is_stopping: AtomicBool::new(false),
+            restarts: AtomicUsize::new(0),
+            stopped: Mutex::new(None),
+            snapshot: Mutex::new(None),
+            before_restart: Mutex::new(None),
         })
     }
 
     fn obtain_session(
         program: &str,
-    ) -> Result<(PtySession, Option<NamedTempFile>), SystemLuaError> {
-        let mut cmd = Command::new(program);
-
-        cmd.arg("-e");
-        cmd.arg(RPC_CODE);
-
-        let mut session = spawn_command(cmd, None)?;
+        host: &Host,
+        options: &ProcessOptions,
+    ) -> Result<(PtySession, Option<CancelFile>), SystemLuaError> {
+        let mut session = spawn_command(host.command(program, options), None)?;
 
         // TODO; should this be in our cache/run dir?
-        let file = NamedTempFile::new()?;
+        let file = match host {
+            Host::Local => CancelFile::Local(NamedTempFile::new()?),
+            _ => CancelFile::Remote(format!("/tmp/manen-cancel-{}", share::random_token())),
+        };
 
-        let prepare = RpcCommand::Prepare(file.path().to_string_lossy().to_string());
+        let prepare = RpcCommand::Prepare(file.path());
 
         let cmd = prepare.to_lua();
         session.send_line(&cmd)?;
@@ -144,8 +641,8 @@ impl SystemLuaExecutor {
         loop {
             let code = session.read_line()?;
 
-            if let Ok(prepare_result) = lua.load(&code).eval::<LuaTable>() {
-                if prepare_result.get::<bool>("data")? {
+            if let Some(prepare_result) = msgpack::decode_frame(&lua, &code) {
+                if LuaTable::from_lua(prepare_result?, &lua)?.get::<bool>("data")? {
                     return Ok((session, Some(file)));
                 } else {
                     return Ok((session, None));
@@ -154,8 +651,47 @@ impl SystemLuaExecutor {
         }
     }
 
+    /// Every value the file at `path` returns, run with `args` as its `arg` table so
+    /// `arg[0]`, `require` relative to the working directory and chunk names are those
+    /// of the standalone interpreter
+    pub fn run_file(&self, path: &str, args: &[String]) -> LuaResult<LuaMultiValue> {
+        returned_values(
+            self.request(RpcCommand::Run(path.to_string(), args.to_vec()))
+                .map_err(LuaError::external)?
+                .get("data")?,
+        )
+    }
+
+    fn save_snapshot(&self) -> Result<(), SystemLuaError> {
+        let due = self
+            .snapshot
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|snapshot| snapshot.taken.elapsed() >= SNAPSHOT_INTERVAL);
+
+        if due {
+            let globals = self.request(RpcCommand::Snapshot)?.get("data")?;
+
+            *self.snapshot.lock().unwrap() = Some(Snapshot {
+                globals,
+                taken: Instant::now(),
+            });
+        }
+
+        Ok(())
+    }
+
     fn restart_process(&self, session: &mut SendWrapper<PtySession>) -> Result<(), SystemLuaError> {
-        let (pty, file) = Self::obtain_session(&self.program)?;
+        // a container that exited on its own may not be removed yet
+        self.host.stop();
+
+        // kept from a restart before when nothing was saved since
+        if let Some(snapshot) = self.snapshot.lock().unwrap().take() {
+            *self.before_restart.lock().unwrap() = Some(snapshot);
+        }
+
+        let (pty, file) = Self::obtain_session(&self.program, &self.host, &self.options)?;
         self.pid
             .store(pty.process.child_pid.as_raw(), Ordering::Relaxed);
 
@@ -170,7 +706,34 @@ impl SystemLuaExecutor {
         Ok(())
     }
 
+    /// Error for a process that ended, started again when it was killed by Ctrl-C or the
+    /// restart policy allows it
+    fn recover(&self, session: &mut SendWrapper<PtySession>) -> SystemLuaError {
+        let status = exit_status(self.pid.load(Ordering::Relaxed));
+
+        if !self.is_stopping.load(Ordering::Relaxed) {
+            let restarts = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+
+            let Some(delay) = self.options.restart.delay(restarts) else {
+                *self.stopped.lock().unwrap() = Some(status.clone());
+
+                return SystemLuaError::Stopped(status);
+            };
+
+            thread::sleep(delay);
+        }
+
+        match self.restart_process(session) {
+            Ok(()) => SystemLuaError::Restarted(status),
+            Err(e) => e,
+        }
+    }
+
     fn request(&self, command: RpcCommand) -> Result<LuaTable, SystemLuaError> {
+        if let Some(status) = self.stopped.lock().unwrap().clone() {
+            return Err(SystemLuaError::Stopped(status));
+        }
+
         self.is_stopping.store(false, Ordering::Relaxed);
 
         let mut session = self.session.write().expect("write process");
@@ -178,41 +741,100 @@ impl SystemLuaExecutor {
         let cmd = command.to_lua();
 
         if session.send_line(&cmd).is_err() {
-            // killed
-            self.restart_process(&mut session)?;
-
-            return Err(SystemLuaError::Restarted);
+            return Err(self.recover(&mut session));
         }
 
         loop {
             let code = match session.read_line() {
                 Ok(code) => code,
                 Err(rexpect::error::Error::EOF { .. }) => {
-                    self.restart_process(&mut session)?;
-
-                    return Err(SystemLuaError::Restarted);
+                    return Err(self.recover(&mut session));
                 }
                 x => x?,
             };
 
-            if let Ok(res) = self.lua.load(&code).eval::<LuaTable>() {
-                if res.get::<String>("ty")? == "error" {
-                    return Err(SystemLuaError::RuntimeError(res.get("data")?));
-                };
+            if let Some(res) = response(&self.lua, &code)? {
+                self.restarts.store(0, Ordering::Relaxed);
 
                 return Ok(res);
-            } else {
-                println!("{}", &code);
             }
         }
     }
 }
 
+/// Values of an `exec` response, packed with their count since `nil` ones are left out
+fn returned_values(data: LuaTable) -> LuaResult<LuaMultiValue> {
+    let count: usize = data.get("n")?;
+
+    (1..=count).map(|i| data.raw_get(i)).collect()
+}
+
+/// Table answering an RPC command, `None` for output of the code, shown as it comes
+fn response(lua: &Lua, line: &str) -> Result<Option<LuaTable>, SystemLuaError> {
+    // written past `rpc.capture_output`, such as by C code
+    let Some(res) = msgpack::decode_frame(lua, line) else {
+        println!("{line}");
+        return Ok(None);
+    };
+
+    let res = LuaTable::from_lua(res?, lua)?;
+
+    match res.get::<String>("command")?.as_str() {
+        "error" => Err(SystemLuaError::RuntimeError(res.get("data")?)),
+        "stdout" => {
+            let mut stdout = io::stdout();
+
+            stdout.write_all(&res.get::<LuaString>("data")?.as_bytes())?;
+            stdout.flush()?;
+
+            Ok(None)
+        }
+        "stderr" => {
+            io::stderr().write_all(&res.get::<LuaString>("data")?.as_bytes())?;
+
+            Ok(None)
+        }
+        _ => Ok(Some(res)),
+    }
+}
+
+/// Value of a `fields` response, with stand-ins for the fields of tables
+fn resolve_fields(lua: &Lua, data: LuaTable) -> Option<LuaValue> {
+    let fields: LuaTable = match data.get::<String>("type").ok()?.as_str() {
+        "table" => data.get("fields").ok()?,
+        "string" => return lua.create_string("").ok().map(LuaValue::String),
+        _ => return data.get("value").ok(),
+    };
+
+    // stand-ins only need the right type for descriptions and method filtering
+    let tbl = lua.create_table().ok()?;
+
+    for (name, field) in fields.pairs::<String, LuaTable>().flatten() {
+        let value = match field.get::<String>("type").ok()?.as_str() {
+            "function" => LuaValue::Function(lua.create_function(|_, ()| Ok(())).ok()?),
+            "table" => LuaValue::Table(lua.create_table().ok()?),
+            "number" | "boolean" | "string" => field.get("value").ok()?,
+            _ => LuaValue::LightUserData(LuaLightUserData(std::ptr::null_mut())),
+        };
+
+        tbl.raw_set(name, value).ok()?;
+    }
+
+    Some(LuaValue::Table(tbl))
+}
+
 impl LuaExecutor for SystemLuaExecutor {
-    fn exec(&self, code: &str) -> LuaResult<LuaValue> {
-        self.request(RpcCommand::Exec(code.to_string()))
-            .map_err(LuaError::external)?
-            .get("data")
+    fn exec(&self, code: &str) -> LuaResult<LuaMultiValue> {
+        let values = returned_values(
+            self.request(RpcCommand::Exec(code.to_string()))
+                .map_err(LuaError::external)?
+                .get("data")?,
+        )?;
+
+        // a missed snapshot only makes the next `.restore` older
+        let _ = self.save_snapshot();
+
+        Ok(values)
     }
 
     fn globals(&self) -> LuaResult<LuaTable> {
@@ -221,6 +843,17 @@ impl LuaExecutor for SystemLuaExecutor {
             .get("data")
     }
 
+    // only the fields are sent back, rather than every global with its contents
+    fn resolve(&self, path: &[String]) -> Option<LuaValue> {
+        let data: LuaTable = self
+            .request(RpcCommand::Fields(path.to_vec()))
+            .ok()?
+            .get("data")
+            .ok()?;
+
+        resolve_fields(&self.lua, data)
+    }
+
     fn cancel(&self) {
         let mut cancellation_file = self
             .cancellation_file
@@ -231,7 +864,7 @@ impl LuaExecutor for SystemLuaExecutor {
             self.is_stopping.store(true, Ordering::Relaxed);
 
             if let Some(file) = cancellation_file.as_mut() {
-                if file.write_all(b"stop").is_ok() && file.flush().is_ok() {
+                if file.stop(&self.host) {
                     return;
                 }
             }
@@ -241,4 +874,267 @@ impl LuaExecutor for SystemLuaExecutor {
         let pid = self.pid.load(Ordering::Relaxed);
         let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
     }
+
+    fn restart(&self) -> LuaResult<()> {
+        let mut session = self.session.write().expect("write process");
+
+        // a stopped process was already waited for, its pid may belong to another by now
+        if self.stopped.lock().unwrap().take().is_none() {
+            exit_status(self.pid.load(Ordering::Relaxed));
+        }
+
+        self.restarts.store(0, Ordering::Relaxed);
+        self.restart_process(&mut session)
+            .map_err(LuaError::external)
+    }
+
+    fn restore(&self) -> LuaResult<Option<(usize, Duration)>> {
+        let Some(snapshot) = self.before_restart.lock().unwrap().take() else {
+            return Ok(None);
+        };
+
+        let count = snapshot.globals.pairs::<LuaValue, LuaValue>().count();
+        let source = serialize::serialize(&LuaValue::Table(snapshot.globals))?;
+
+        // globals sharing tables still share them once applied
+        self.exec(&format!(
+            "for name, value in pairs((function()\n{source}\nend)()) do _G[name] = value end"
+        ))?;
+
+        Ok(Some((count, snapshot.taken.elapsed())))
+    }
+
+    fn saved_globals(&self) -> Option<(usize, Instant)> {
+        let before_restart = self.before_restart.lock().unwrap();
+        let snapshot = before_restart.as_ref()?;
+
+        Some((
+            snapshot.globals.pairs::<LuaValue, LuaValue>().count(),
+            snapshot.taken,
+        ))
+    }
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+/// Lua state of a `manen agent`, reached over TCP with the same commands as the system Lua
+pub struct RemoteExecutor {
+    target: String,
+    connection: Mutex<Option<Connection>>,
+    /// Writer of the connection kept apart, so a cancel isn't stuck behind the request
+    canceller: Mutex<Option<TcpStream>>,
+    lua: Lua,
+}
+
+impl RemoteExecutor {
+    /// Connects to the agent at `token@host[:port]`
+    pub fn new(target: &str) -> Result<Self, SystemLuaError> {
+        let executor = Self {
+            target: target.to_string(),
+            connection: Mutex::new(None),
+            canceller: Mutex::new(None),
+            lua: Lua::new(),
+        };
+
+        *executor.connection.lock().unwrap() = Some(executor.connect()?);
+
+        Ok(executor)
+    }
+
+    fn connect(&self) -> Result<Connection, SystemLuaError> {
+        let (token, address) = share::parse_target(&self.target, agent::DEFAULT_PORT)?;
+
+        let mut writer = TcpStream::connect(address)?;
+        writer.write_all(format!("{token}\n").as_bytes())?;
+
+        let mut reader = BufReader::new(writer.try_clone()?);
+        let mut greeting = String::new();
+        reader.read_line(&mut greeting)?;
+
+        if greeting.trim_end() != "connected" {
+            return Err(SystemLuaError::Io(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("agent refused the connection: {}", greeting.trim_end()),
+            )));
+        }
+
+        *self.canceller.lock().unwrap() = Some(writer.try_clone()?);
+
+        Ok(Connection { reader, writer })
+    }
+
+    fn request(&self, command: RpcCommand) -> Result<LuaTable, SystemLuaError> {
+        let mut connection = self.connection.lock().unwrap();
+
+        // the agent keeps its state, a dropped connection is opened again
+        let current = match connection.take() {
+            Some(current) => current,
+            None => self.connect()?,
+        };
+
+        let result = self.exchange(connection.insert(current), &command);
+
+        if matches!(
+            result,
+            Err(SystemLuaError::Io(_) | SystemLuaError::Disconnected)
+        ) {
+            *connection = None;
+        }
+
+        result
+    }
+
+    fn exchange(
+        &self,
+        connection: &mut Connection,
+        command: &RpcCommand,
+    ) -> Result<LuaTable, SystemLuaError> {
+        connection
+            .writer
+            .write_all(format!("{}\n", command.to_lua()).as_bytes())?;
+
+        loop {
+            let mut line = String::new();
+
+            if connection.reader.read_line(&mut line)? == 0 {
+                return Err(SystemLuaError::Disconnected);
+            }
+
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if let Some(res) = response(&self.lua, line)? {
+                return Ok(res);
+            }
+        }
+    }
+}
+
+impl LuaExecutor for RemoteExecutor {
+    fn exec(&self, code: &str) -> LuaResult<LuaMultiValue> {
+        returned_values(
+            self.request(RpcCommand::Exec(code.to_string()))
+                .map_err(LuaError::external)?
+                .get("data")?,
+        )
+    }
+
+    fn globals(&self) -> LuaResult<LuaTable> {
+        self.request(RpcCommand::Globals)
+            .map_err(LuaError::external)?
+            .get("data")
+    }
+
+    fn resolve(&self, path: &[String]) -> Option<LuaValue> {
+        let data: LuaTable = self
+            .request(RpcCommand::Fields(path.to_vec()))
+            .ok()?
+            .get("data")
+            .ok()?;
+
+        resolve_fields(&self.lua, data)
+    }
+
+    // stops the code at its next line, the agent itself keeps running
+    fn cancel(&self) {
+        if let Some(writer) = self.canceller.lock().unwrap().as_mut() {
+            let _ = writer.write_all(b"cancel\n");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_depth() {
+        let lua = Lua::new();
+        let rpc: LuaTable = lua.load(RPC_CODE).set_name("=rpc").call("module").unwrap();
+
+        lua.globals().set("rpc", rpc).unwrap();
+
+        let taken: LuaTable = lua
+            .load(
+                "local taken
+                rpc.respond = function(_, globals) taken = globals end
+
+                local function nested(depth)
+                    local t = {}
+                    for _ = 2, depth do t = { t } end
+                    return t
+                end
+
+                -- with the snapshot around them, 180 tables deep and one more
+                kept, deep = nested(179), nested(180)
+                rpc.snapshot()
+
+                return taken",
+            )
+            .eval()
+            .unwrap();
+
+        assert!(taken.contains_key("kept").unwrap());
+        assert!(!taken.contains_key("deep").unwrap());
+        assert!(serialize::serialize(&LuaValue::Table(taken)).is_ok());
+    }
+
+    #[test]
+    fn restart_policy() {
+        let policy = RestartPolicy {
+            on_crash: true,
+            max_restarts: Some(3),
+            backoff: Duration::from_millis(100),
+        };
+
+        assert_eq!(Some(Duration::from_millis(100)), policy.delay(1));
+        assert_eq!(Some(Duration::from_millis(400)), policy.delay(3));
+        assert_eq!(None, policy.delay(4));
+
+        let unlimited = RestartPolicy {
+            max_restarts: None,
+            ..policy.clone()
+        };
+
+        // doubles for six crashes in a row, then stays
+        assert_eq!(Some(Duration::from_millis(6400)), unlimited.delay(7));
+        assert_eq!(Some(Duration::from_millis(6400)), unlimited.delay(100));
+
+        let off = RestartPolicy {
+            on_crash: false,
+            ..policy
+        };
+
+        assert_eq!(None, off.delay(1));
+    }
+
+    #[test]
+    fn counted_collections() {
+        let executor = MluaExecutor::new();
+
+        executor
+            .exec("for _ = 1, 3 do\n  collectgarbage()\nend")
+            .unwrap();
+
+        let (used, collections) = executor.memory().unwrap();
+
+        assert!(used > 0);
+        assert!(collections >= 2);
+    }
+
+    #[test]
+    fn exit_statuses() {
+        let child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        let pid = child.id() as i32;
+
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!("exited with status 3", exit_status(pid));
+
+        // still running, so killed instead of waited on forever
+        let child = Command::new("sleep").arg("60").spawn().unwrap();
+
+        assert_eq!("was killed by SIGKILL", exit_status(child.id() as i32));
+    }
 }
@@ -1,26 +1,49 @@
 use std::{
     fs,
-    io::{Read, stdin},
-    path::{Path, PathBuf},
+    io::{BufWriter, IsTerminal, Read, Write, stdin, stdout},
+    net::IpAddr,
+    path::{self, Path, PathBuf},
     process,
 };
 
 use clap::{Parser, Subcommand};
 use editor::Editor;
 use emmylua_parser::{LuaParser, ParserConfig};
+use language::Language;
 use mlua::prelude::*;
-use reedline::Highlighter;
 
-use inspect::{comfy_table, inspect};
-use parse::LuaHighlighter;
+use config::{Config, Overrides, PrintNil};
+use inspect::{ComfyOptions, Indent, InspectLimits, comfy_table, display_basic};
+use lua::{LuaExecutor, SystemLuaExecutor};
 
+mod agent;
+mod browse;
+mod calculator;
+mod check;
+mod commands;
 mod completion;
 mod config;
+mod crypt;
+mod diff;
 mod editor;
+#[cfg(feature = "fennel")]
+mod fennel;
 mod hinter;
 mod inspect;
+mod interpreters;
+mod language;
 mod lua;
+mod msgpack;
+mod palette;
 mod parse;
+mod report;
+mod serialize;
+mod share;
+mod signature;
+mod snippets;
+mod startup;
+mod tasks;
+mod transcript;
 mod validator;
 
 #[derive(Parser)]
@@ -29,65 +52,391 @@ mod validator;
 struct Cli {
     #[command(subcommand)]
     command: Option<Command>,
+
+    /// Source language of the REPL input and run files, by default `lua` or what the
+    /// extension of a run file says
+    #[arg(long, value_enum, global = true)]
+    language: Option<Language>,
+
+    /// Seed `math.random` so random results can be reproduced
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Run input without `io`, `os.execute`, `loadfile` and the rest `manen.sandbox` removes
+    #[arg(long, global = true)]
+    sandbox: bool,
+
+    /// Lua of the embedded executor, an installed interpreter when the build has another
+    #[arg(long, global = true, value_parser = interpreters::VERSIONS)]
+    lua: Option<String>,
+
+    /// Print how long each step of starting the REPL took
+    #[arg(long, global = true)]
+    startup_profile: bool,
+}
+
+impl Cli {
+    fn overrides(&self) -> Overrides {
+        Overrides {
+            seed: self.seed,
+            sandbox: self.sandbox,
+            lua_version: self.lua.clone(),
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Command {
     /// Enter an interactive REPL session
     Repl,
-    /// Run a Lua file
+    /// Run a Lua file, or a Fennel/Teal file when built with those features
     Run {
         /// Path to Lua file
         path: PathBuf,
+        /// Print returned values without the configured depth and item limits
+        #[arg(long)]
+        full: bool,
+        /// Arguments given to the file as `arg` and `...`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Report syntax errors in Lua files
+    Check {
+        /// Files or directories to check
+        #[arg(default_value = ".")]
+        paths: Vec<PathBuf>,
+        /// Re-check changed files and the files requiring them until interrupted
+        #[arg(long)]
+        watch: bool,
     },
     /// Highlight a Lua file
     Highlight {
         /// Path to Lua file (default: stdin)
         path: Option<PathBuf>,
+        /// Print spans with their capture names as JSON instead of ANSI colors
+        #[arg(long)]
+        json: bool,
+        /// Read Markdown, highlighting only the code of ```lua blocks
+        #[arg(long)]
+        markdown: bool,
+    },
+    /// Open the REPL session to other manen instances
+    Share {
+        /// Address to listen on, `0.0.0.0` lets other machines join
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+        /// Port to listen on
+        #[arg(long, default_value_t = share::DEFAULT_PORT)]
+        port: u16,
+        /// Guests see inputs and outputs but can't evaluate
+        #[arg(long)]
+        read_only: bool,
+    },
+    /// Join a session opened with `manen share`
+    Join {
+        /// `token@host[:port]` as printed by `manen share`
+        target: String,
+    },
+    /// Serve a Lua state for REPLs on other hosts to attach to with the remote executor
+    Agent {
+        /// Address to listen on, `0.0.0.0` lets other machines attach
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
+        /// Port to listen on
+        #[arg(long, default_value_t = agent::DEFAULT_PORT)]
+        port: u16,
+    },
+    /// List the Lua interpreters `manen.executor = 'auto'` can pick, first one marked
+    Executors,
+    /// Bundle version, config, terminal and recent failure details for a bug report
+    Report {
+        /// Where to write the report, a tar archive
+        #[arg(default_value = "manen-report.tar")]
+        output: PathBuf,
     },
+    /// Compare the tables returned by two Lua files
+    Diff {
+        /// File returning the original table
+        a: PathBuf,
+        /// File returning the changed table
+        b: PathBuf,
+    },
+    /// Print a history file or export written with `encrypt_history`
+    Decrypt { path: PathBuf },
     /// DEBUG: Parse a Lua file with emmylua_parser
     Parse { path: PathBuf },
 }
 
-fn eval_lua(file: String, path: &Path) -> LuaResult<()> {
+/// Runs the file in the system Lua, which reads it itself so `arg`, relative `require`
+/// and `debug.getinfo` see it as `lua path args...` would
+fn run_system(
+    config: &Config,
+    program: &Path,
+    path: &Path,
+    args: &[String],
+) -> LuaResult<LuaMultiValue> {
+    let executor = SystemLuaExecutor::new(&program.to_string_lossy(), config.process_options())
+        .map_err(LuaError::external)?;
+
+    executor.exec(config::FORMATTER_CODE)?;
+    executor.exec(config::LIBRARY_CODE)?;
+
+    if let Some(seed) = config.seed {
+        executor.exec(&config::seed_code(seed))?;
+    }
+
+    if config.sandbox {
+        executor.exec(&config::sandbox_code(&config.sandbox_allow))?;
+    }
+
+    // the process runs in `system_cwd`, while the path was typed relative to this one
+    let path = match &config.system_cwd {
+        Some(_) => path::absolute(path).map_err(LuaError::external)?,
+        None => path.to_path_buf(),
+    };
+
+    executor.run_file(&path.to_string_lossy(), args)
+}
+
+fn print_values(
+    config: &Config,
+    res: LuaResult<LuaMultiValue>,
+    limits: InspectLimits,
+) -> LuaResult<()> {
+    match res {
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+        Ok(values) => {
+            // a script without a return value is a statement
+            if values.is_empty() && config.print_nil == PrintNil::Always {
+                println!("{}", display_basic(&LuaValue::Nil, config.color_output));
+            }
+
+            // written as they are produced, huge tables are never held as one string
+            let mut out = BufWriter::new(stdout().lock());
+
+            for value in values {
+                if value.is_nil() && config.print_nil == PrintNil::Never {
+                    continue;
+                }
+
+                match value {
+                    LuaValue::Table(tbl) => config.table_format.format_to(
+                        &mut out,
+                        &tbl,
+                        config.color_output,
+                        limits,
+                    )?,
+                    value => inspect::inspect_to(&mut out, &value, config.color_output, limits)?,
+                }
+
+                writeln!(out).map_err(LuaError::external)?;
+            }
+
+            out.flush().map_err(LuaError::external)
+        }
+    }
+}
+
+fn eval_lua(
+    file: String,
+    path: &Path,
+    args: &[String],
+    language: Language,
+    overrides: &Overrides,
+    full: bool,
+) -> LuaResult<()> {
+    let mut config = Config::load()?;
+    overrides.apply(&mut config);
+    config.check_lua_version()?;
+
+    config.apply_color_support();
+    inspect::set_value_colors(config.colors);
+    inspect::set_redacted_keys(&config.redact_keys);
+    inspect::set_number_format(config.numbers);
+    inspect::set_byte_escape(config.byte_escape);
+
+    let limits = if full {
+        InspectLimits {
+            max_depth: None,
+            max_items: None,
+            max_string: None,
+            ..config.limits()
+        }
+    } else {
+        config.limits()
+    };
+
+    // set for the system and auto executors, or a `lua_version` that isn't embedded
+    if let (Some(program), Language::Lua) = (config.system_program(), language) {
+        let res = run_system(&config, &program, path, args);
+
+        return print_values(&config, res, limits);
+    }
+
     let lua = Lua::new();
+    inspect::set_thread_debug(&lua);
+
     let globals = lua.globals();
 
+    let formatters = lua.load(config::FORMATTER_CODE).eval()?;
+    inspect::set_formatters(&lua, formatters);
+
     globals.raw_set(
         "inspect",
-        lua.create_function(|_, (value, colorize): (LuaValue, Option<bool>)| {
-            println!("{}", inspect(&value, colorize.unwrap_or(true))?);
+        lua.create_function(
+            move |lua, (value, options, depth): (LuaValue, LuaValue, Option<usize>)| {
+                // `inspect(x, 2)` stops at depth 2, `inspect(x, false, 2)` also drops colors
+                // and `inspect(x, { colors = false, depth = 2, indent = '\t' })` sets any of them
+                let mut indent = limits.indent;
+
+                let (colorize, max_depth) = match options {
+                    LuaValue::Nil => (true, depth),
+                    LuaValue::Boolean(colorize) => (colorize, depth),
+                    LuaValue::Table(options) => {
+                        let value = options.get::<LuaValue>("indent")?;
+
+                        if !value.is_nil() {
+                            indent = Indent::from_value(&value).ok_or_else(|| {
+                                LuaError::RuntimeError(String::from(
+                                    "expected number of spaces or string of one repeated character for indent",
+                                ))
+                            })?;
+                        }
+
+                        (
+                            options.get::<Option<bool>>("colors")?.unwrap_or(true),
+                            options.get::<Option<usize>>("depth")?,
+                        )
+                    }
+                    options => match options.as_usize() {
+                        Some(depth) => (true, Some(depth)),
+                        None => {
+                            return Err(LuaError::RuntimeError(String::from(
+                                "expected boolean, depth or options table as second argument to inspect",
+                            )));
+                        }
+                    },
+                };
+
+                // the configured limits, so redacted keys stay hidden
+                let limits = InspectLimits {
+                    max_depth,
+                    indent,
+                    ..limits
+                };
+
+                let mut out = stdout().lock();
+
+                inspect::with_thread_debug(Some(lua), || {
+                    inspect::inspect_to(&mut out, &value, colorize, limits)
+                })?;
+                writeln!(out).map_err(LuaError::external)
+            },
+        )?,
+    )?;
+
+    globals.raw_set(
+        "diff",
+        lua.create_function(move |lua, (a, b): (LuaValue, LuaValue)| {
+            let changes =
+                inspect::with_thread_debug(Some(lua), || diff::diff(&a, &b, true, limits))?;
+
+            if !changes.is_empty() {
+                println!("{changes}");
+            }
+
             Ok(())
         })?,
     )?;
 
+    let colorize = config.color_output;
+
     globals.raw_set(
-        "comfytable",
-        lua.create_function(|_, (table, recursive): (LuaTable, Option<bool>)| {
-            println!("{}", comfy_table(&table, recursive.unwrap_or(true))?);
+        "hexdump",
+        lua.create_function(move |_, s: LuaString| {
+            println!("{}", inspect::hexdump(&s.as_bytes(), colorize));
 
             Ok(())
         })?,
     )?;
 
-    let res = lua
-        .load(file)
-        .set_name(format!("@{}", path.to_string_lossy()))
-        .eval::<LuaMultiValue>();
+    globals.raw_set(
+        "serialize",
+        lua.create_function(|_, value: LuaValue| serialize::serialize(&value))?,
+    )?;
 
-    match res {
-        Err(e) => {
-            eprintln!("{e}");
-            process::exit(1);
-        }
-        Ok(values) => {
-            for value in values {
-                println!("{}", inspect(&value, true)?);
-            }
+    globals.raw_set(
+        "comfytable",
+        lua.create_function(move |_, (table, options): (LuaTable, LuaValue)| {
+            // `comfytable(t, false)` skips nested tables and
+            // `comfytable(t, { align_numbers = true, sort_by = 'name' })` sets any option
+            let mut limits = limits;
+
+            let options = match options {
+                LuaValue::Nil => ComfyOptions::default(),
+                LuaValue::Boolean(recursive) => ComfyOptions {
+                    recursive,
+                    ..ComfyOptions::default()
+                },
+                LuaValue::Table(options) => {
+                    if let Some(sort_keys) = options.get::<Option<bool>>("sort_keys")? {
+                        limits.sort_keys = sort_keys;
+                    }
+
+                    ComfyOptions {
+                        recursive: options.get::<Option<bool>>("recursive")?.unwrap_or(true),
+                        align_numbers: options
+                            .get::<Option<bool>>("align_numbers")?
+                            .unwrap_or(false),
+                        sort_by: options.get::<Option<String>>("sort_by")?,
+                    }
+                }
+                _ => {
+                    return Err(LuaError::RuntimeError(String::from(
+                        "expected boolean or options table as second argument to comfytable",
+                    )));
+                }
+            };
+
+            println!("{}", comfy_table(&table, &options, limits)?);
 
             Ok(())
-        }
+        })?,
+    )?;
+
+    // after the helpers above, so `manen.inspect` is the one set there
+    lua.load(config::LIBRARY_CODE).exec()?;
+
+    if let Some(prelude) = language.prelude() {
+        lua.load(prelude).exec()?;
+    }
+
+    if let Some(seed) = config.seed {
+        lua.load(config::seed_code(seed)).exec()?;
+    }
+
+    // the file is read by manen, so `loadfile` isn't needed to run it
+    if config.sandbox {
+        lua.load(config::sandbox_code(&config.sandbox_allow))
+            .exec()?;
     }
+
+    let name = path.to_string_lossy();
+
+    // as the standalone interpreter sets it
+    let arg = lua.create_sequence_from(args.iter().map(String::as_str))?;
+    arg.raw_set(0, name.as_ref())?;
+    globals.raw_set("arg", arg)?;
+
+    let res = lua
+        .load(language.wrap(&file, &name))
+        .set_name(format!("@{name}"))
+        .call::<LuaMultiValue>(args.iter().map(String::as_str).collect::<LuaVariadic<_>>());
+
+    inspect::with_thread_debug(Some(&lua), || print_values(&config, res, limits))
 }
 
 fn main() -> color_eyre::Result<()> {
@@ -96,11 +445,38 @@ fn main() -> color_eyre::Result<()> {
     let cli = Cli::parse();
 
     match &cli.command {
-        None | Some(Command::Repl) => Editor::new()?.run(),
-        Some(Command::Run { path }) => {
-            eval_lua(fs::read_to_string(path)?, path)?;
+        None | Some(Command::Repl) => Editor::new(
+            cli.language.unwrap_or(Language::Lua),
+            &cli.overrides(),
+            cli.startup_profile,
+        )?
+        .run(),
+        Some(Command::Run { path, full, args }) => {
+            // an explicit `--language` wins over the extension
+            let language = cli
+                .language
+                .or_else(|| Language::from_path(path))
+                .unwrap_or(Language::Lua);
+
+            eval_lua(
+                fs::read_to_string(path)?,
+                path,
+                args,
+                language,
+                &cli.overrides(),
+                *full,
+            )?;
+        }
+        Some(Command::Check { paths, watch }) => {
+            if !check::check(paths.clone(), *watch, stdout().is_terminal()) {
+                process::exit(1);
+            }
         }
-        Some(Command::Highlight { path }) => {
+        Some(Command::Highlight {
+            path,
+            json,
+            markdown,
+        }) => {
             let file = if let Some(path) = path {
                 fs::read_to_string(path)?
             } else {
@@ -110,9 +486,84 @@ fn main() -> color_eyre::Result<()> {
                 buffer
             };
 
-            let text = LuaHighlighter.highlight(&file, 0);
+            if *json {
+                let spans = if *markdown {
+                    parse::markdown_spans(&file)
+                } else {
+                    parse::highlight_spans(&file)
+                };
+
+                println!("{}", parse::spans_to_json(&spans));
+            } else {
+                let config = Config::load()?;
+
+                config.apply_color_support();
+                parse::set_syntax_colors(config.syntax_colors);
+                parse::set_rainbow_brackets(config.rainbow_brackets);
+
+                if *markdown {
+                    print!("{}", parse::highlight_markdown(&file));
+                } else {
+                    println!("{}", parse::highlight(&file).render_simple());
+                }
+            }
+        }
+        Some(Command::Share {
+            bind,
+            port,
+            read_only,
+        }) => Editor::new(
+            cli.language.unwrap_or(Language::Lua),
+            &cli.overrides(),
+            cli.startup_profile,
+        )?
+        .share(*bind, *port, *read_only)?
+        .run(),
+        Some(Command::Join { target }) => share::join(target, stdout().is_terminal())?,
+        Some(Command::Agent { bind, port }) => agent::run(*bind, *port)?,
+        Some(Command::Executors) => interpreters::list(&Config::load()?.lua_preference),
+        Some(Command::Report { output }) => {
+            report::report(output)?;
+
+            println!("wrote {}, attach it to the issue", output.display());
+        }
+        Some(Command::Diff { a, b }) => {
+            let config = Config::load()?;
+            config.apply_color_support();
+            inspect::set_value_colors(config.colors);
+            inspect::set_redacted_keys(&config.redact_keys);
+            inspect::set_number_format(config.numbers);
+            inspect::set_byte_escape(config.byte_escape);
+
+            let lua = Lua::new();
+            let a = lua
+                .load(fs::read_to_string(a)?)
+                .set_name(format!("@{}", a.display()));
+            let b = lua
+                .load(fs::read_to_string(b)?)
+                .set_name(format!("@{}", b.display()));
+
+            let changes = diff::diff(
+                &a.eval()?,
+                &b.eval()?,
+                config.color_output && stdout().is_terminal(),
+                config.limits(),
+            )?;
+
+            if !changes.is_empty() {
+                println!("{changes}");
+                process::exit(1);
+            }
+        }
+        Some(Command::Decrypt { path }) => {
+            let data = fs::read(path)?;
+            let plaintext = crypt::decrypt(&crypt::passphrase()?, &data)?;
 
-            println!("{}", text.render_simple());
+            // history entries are separated by NUL
+            println!(
+                "{}",
+                String::from_utf8_lossy(&plaintext).replace('\0', "\n")
+            );
         }
         Some(Command::Parse { path }) => {
             let code = fs::read_to_string(path)?;
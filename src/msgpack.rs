@@ -0,0 +1,347 @@
+use mlua::prelude::*;
+
+/// First byte of a line framing a message from `lua/rpc.lua`
+pub const FRAME_START: char = '\x1e';
+
+/// Extension type of a table already decoded, by the order tables were reached in
+const EXT_REF: i8 = 1;
+/// Extension type of a function, sent as its `tostring`
+const EXT_FUNCTION: i8 = 2;
+/// Extension type of userdata, threads and cdata, sent as their `tostring`
+const EXT_OPAQUE: i8 = 3;
+
+/// Tables nested deeper than this are refused rather than overflowing the stack, about
+/// as deep as Lua code can nest constructors
+const MAX_DEPTH: usize = 200;
+
+fn base64_digit(c: u8) -> Option<u32> {
+    Some(match c {
+        b'A'..=b'Z' => c - b'A',
+        b'a'..=b'z' => c - b'a' + 26,
+        b'0'..=b'9' => c - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return None,
+    } as u32)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let chunks = text.as_bytes().chunks_exact(4);
+
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+
+    for chunk in chunks {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+
+        if padding > 2 {
+            return None;
+        }
+
+        let mut n = 0;
+
+        for c in &chunk[..4 - padding] {
+            n = n << 6 | base64_digit(*c)?;
+        }
+
+        n <<= 6 * padding as u32;
+
+        bytes.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+
+    Some(bytes)
+}
+
+struct Decoder<'a> {
+    lua: &'a Lua,
+    bytes: &'a [u8],
+    pos: usize,
+    /// Tables in the order they started, for references to them
+    tables: Vec<LuaTable>,
+    /// Tables being decoded around the current value
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> LuaResult<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| LuaError::runtime("truncated message"))?;
+
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> LuaResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap_or([0; N]))
+    }
+
+    /// Length of the given size in bytes
+    fn length(&mut self, size: usize) -> LuaResult<usize> {
+        Ok(match size {
+            1 => self.take_array::<1>()?[0] as usize,
+            2 => u16::from_be_bytes(self.take_array()?) as usize,
+            _ => u32::from_be_bytes(self.take_array()?) as usize,
+        })
+    }
+
+    fn string(&mut self, len: usize) -> LuaResult<LuaValue> {
+        let bytes = self.take(len)?;
+
+        self.lua.create_string(bytes).map(LuaValue::String)
+    }
+
+    fn table(&mut self, len: usize, is_map: bool) -> LuaResult<LuaValue> {
+        if self.depth >= MAX_DEPTH {
+            return Err(LuaError::RuntimeError(format!(
+                "message nests tables deeper than {MAX_DEPTH} levels"
+            )));
+        }
+
+        let tbl = self.lua.create_table()?;
+
+        self.tables.push(tbl.clone());
+        self.depth += 1;
+
+        for i in 1..=len {
+            if is_map {
+                let key = self.value()?;
+                let value = self.value()?;
+
+                // `nil` and NaN can't be keys, the encoder never sends them
+                if !key.is_nil() {
+                    tbl.raw_set(key, value)?;
+                }
+            } else {
+                tbl.raw_set(i, self.value()?)?;
+            }
+        }
+
+        self.depth -= 1;
+
+        Ok(LuaValue::Table(tbl))
+    }
+
+    fn ext(&mut self, len: usize) -> LuaResult<LuaValue> {
+        let kind = self.take_array::<1>()?[0] as i8;
+        let data = self.take(len)?;
+
+        match kind {
+            EXT_REF => {
+                let index = data
+                    .try_into()
+                    .map(u32::from_be_bytes)
+                    .map_err(|_| LuaError::runtime("invalid table reference"))?;
+
+                self.tables
+                    .get(index as usize)
+                    .cloned()
+                    .map(LuaValue::Table)
+                    .ok_or_else(|| LuaError::runtime("invalid table reference"))
+            }
+            // stand-ins only need the right type for descriptions and completion
+            EXT_FUNCTION => self
+                .lua
+                .create_function(|_, ()| Ok(()))
+                .map(LuaValue::Function),
+            EXT_OPAQUE => self.lua.create_string(data).map(LuaValue::String),
+            kind => Err(LuaError::RuntimeError(format!(
+                "unknown extension type {kind}"
+            ))),
+        }
+    }
+
+    fn value(&mut self) -> LuaResult<LuaValue> {
+        let byte = self.take_array::<1>()?[0];
+
+        Ok(match byte {
+            0x00..=0x7f => LuaValue::Integer(byte as i64),
+            0x80..=0x8f => return self.table((byte & 0x0f) as usize, true),
+            0x90..=0x9f => return self.table((byte & 0x0f) as usize, false),
+            0xa0..=0xbf => return self.string((byte & 0x1f) as usize),
+            0xc0 => LuaValue::Nil,
+            0xc2 => LuaValue::Boolean(false),
+            0xc3 => LuaValue::Boolean(true),
+            0xc4 | 0xd9 => {
+                let len = self.length(1)?;
+                return self.string(len);
+            }
+            0xc5 | 0xda => {
+                let len = self.length(2)?;
+                return self.string(len);
+            }
+            0xc6 | 0xdb => {
+                let len = self.length(4)?;
+                return self.string(len);
+            }
+            0xc7 => {
+                let len = self.length(1)?;
+                return self.ext(len);
+            }
+            0xc8 => {
+                let len = self.length(2)?;
+                return self.ext(len);
+            }
+            0xc9 => {
+                let len = self.length(4)?;
+                return self.ext(len);
+            }
+            0xca => LuaValue::Number(f32::from_be_bytes(self.take_array()?) as f64),
+            0xcb => LuaValue::Number(f64::from_be_bytes(self.take_array()?)),
+            0xcc => LuaValue::Integer(self.take_array::<1>()?[0] as i64),
+            0xcd => LuaValue::Integer(u16::from_be_bytes(self.take_array()?) as i64),
+            0xce => LuaValue::Integer(u32::from_be_bytes(self.take_array()?) as i64),
+            0xcf => {
+                let n = u64::from_be_bytes(self.take_array()?);
+
+                i64::try_from(n).map_or(LuaValue::Number(n as f64), LuaValue::Integer)
+            }
+            0xd0 => LuaValue::Integer(self.take_array::<1>()?[0] as i8 as i64),
+            0xd1 => LuaValue::Integer(i16::from_be_bytes(self.take_array()?) as i64),
+            0xd2 => LuaValue::Integer(i32::from_be_bytes(self.take_array()?) as i64),
+            0xd3 => LuaValue::Integer(i64::from_be_bytes(self.take_array()?)),
+            0xd4 => return self.ext(1),
+            0xd5 => return self.ext(2),
+            0xd6 => return self.ext(4),
+            0xd7 => return self.ext(8),
+            0xd8 => return self.ext(16),
+            0xdc => {
+                let len = self.length(2)?;
+                return self.table(len, false);
+            }
+            0xdd => {
+                let len = self.length(4)?;
+                return self.table(len, false);
+            }
+            0xde => {
+                let len = self.length(2)?;
+                return self.table(len, true);
+            }
+            0xdf => {
+                let len = self.length(4)?;
+                return self.table(len, true);
+            }
+            0xe0..=0xff => LuaValue::Integer(byte as i8 as i64),
+            0xc1 => return Err(LuaError::runtime("invalid MessagePack byte 0xc1")),
+        })
+    }
+}
+
+/// Value of MessagePack `bytes`, with the extension types `lua/rpc.lua` writes
+pub fn decode(lua: &Lua, bytes: &[u8]) -> LuaResult<LuaValue> {
+    let mut decoder = Decoder {
+        lua,
+        bytes,
+        pos: 0,
+        tables: Vec::new(),
+        depth: 0,
+    };
+
+    let value = decoder.value()?;
+
+    if decoder.pos != bytes.len() {
+        return Err(LuaError::runtime("trailing bytes after message"));
+    }
+
+    Ok(value)
+}
+
+/// Value of a line framed by `rpc.frame`, `None` when the line isn't one
+///
+/// Frames are the start byte, the length of the rest, `:` and the message as base64, so
+/// the PTY can't turn its newlines into `\r\n` and a cut off message is noticed
+pub fn decode_frame(lua: &Lua, line: &str) -> Option<LuaResult<LuaValue>> {
+    let frame = line.trim_end_matches('\r').strip_prefix(FRAME_START)?;
+    let (len, payload) = frame.split_once(':')?;
+
+    if len.parse::<usize>().ok()? != payload.len() {
+        return Some(Err(LuaError::runtime("truncated message")));
+    }
+
+    Some(
+        decode_base64(payload)
+            .ok_or_else(|| LuaError::runtime("invalid base64 in message"))
+            .and_then(|bytes| decode(lua, &bytes)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::RPC_CODE;
+
+    #[test]
+    fn rpc_frames() {
+        let lua = Lua::new();
+        let rpc: LuaTable = lua.load(RPC_CODE).call("module").unwrap();
+
+        let value = lua
+            .load(
+                "local t = { 1, -2, 2^40, -2^40, 0.5, -1/0, 'a\\0b\\n', true, name = 'x' }
+                t.self = t
+                t.fn = print
+                t.list = { t, { n = 3, nil, false } }
+                return t",
+            )
+            .eval::<LuaValue>()
+            .unwrap();
+
+        let line: String = rpc
+            .get::<LuaFunction>("frame")
+            .unwrap()
+            .call(value)
+            .unwrap();
+
+        assert!(!line.contains('\n'));
+
+        let decoded =
+            LuaTable::from_lua(decode_frame(&lua, &line).unwrap().unwrap(), &lua).unwrap();
+
+        assert!(decoded.get::<LuaTable>("self").unwrap() == decoded);
+        assert_eq!(-2, decoded.get::<i64>(2).unwrap());
+        assert_eq!(1 << 40, decoded.get::<i64>(3).unwrap());
+        assert_eq!(-(1 << 40), decoded.get::<i64>(4).unwrap());
+        assert_eq!(0.5, decoded.get::<f64>(5).unwrap());
+        assert_eq!(f64::NEG_INFINITY, decoded.get::<f64>(6).unwrap());
+        assert_eq!(
+            b"a\0b\n",
+            &decoded.get::<LuaString>(7).unwrap().as_bytes()[..]
+        );
+        assert!(decoded.get::<LuaFunction>("fn").is_ok());
+
+        let list: LuaTable = decoded.get("list").unwrap();
+
+        assert!(list.get::<LuaTable>(1).unwrap() == decoded);
+        assert_eq!(3, list.get::<LuaTable>(2).unwrap().get::<i64>("n").unwrap());
+
+        assert!(decode_frame(&lua, "plain output").is_none());
+        assert!(
+            decode_frame(&lua, &line[..line.len() - 4])
+                .unwrap()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let lua = Lua::new();
+
+        // `{ { { ... { nil } ... } } }`, one-element arrays around a nil
+        let nested = |depth: usize| {
+            let mut bytes = vec![0x91; depth];
+            bytes.push(0xc0);
+            bytes
+        };
+
+        assert!(decode(&lua, &nested(MAX_DEPTH)).is_ok());
+
+        let error = decode(&lua, &nested(1_000_000)).unwrap_err().to_string();
+
+        assert!(error.contains("deeper than"));
+    }
+}
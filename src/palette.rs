@@ -0,0 +1,226 @@
+use std::{env, sync::RwLock};
+
+use lazy_static::lazy_static;
+use nu_ansi_term::{Color, Style};
+
+lazy_static! {
+    static ref COLOR_SUPPORT: RwLock<ColorSupport> = RwLock::new(ColorSupport::TrueColor);
+}
+
+/// Colors the terminal can show, styles are mapped down to the nearest it has
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSupport {
+    TrueColor,
+    Ansi256,
+    /// The 8 colors and their light variants
+    Ansi16,
+    Ansi8,
+    /// Bold, underline and such are kept
+    None,
+}
+
+/// Colors of the 16 color palette as xterm shows them by default, light ones last
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Purple, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::LightGray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightPurple, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Levels of each channel in the 6x6x6 cube of the 256 color palette
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+
+    channel(r1, r2) + channel(g1, g2) + channel(b1, b2)
+}
+
+fn nearest_level(channel: u8) -> usize {
+    (0..CUBE.len())
+        .min_by_key(|i| (CUBE[*i] as i32 - channel as i32).abs())
+        .unwrap_or_default()
+}
+
+/// Channels of a color of the 256 color palette
+fn fixed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..16 => ANSI[index as usize].1,
+        16..232 => {
+            let index = index - 16;
+
+            (
+                CUBE[(index / 36) as usize],
+                CUBE[(index / 6 % 6) as usize],
+                CUBE[(index % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + (index - 232) * 10;
+
+            (level, level, level)
+        }
+    }
+}
+
+/// Nearest color of the 256 color palette, from the cube or the grays after it
+fn to_fixed(rgb: (u8, u8, u8)) -> u8 {
+    let (r, g, b) = (
+        nearest_level(rgb.0),
+        nearest_level(rgb.1),
+        nearest_level(rgb.2),
+    );
+    let cube = 16 + 36 * r as u8 + 6 * g as u8 + b as u8;
+
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray = 232 + (average.saturating_sub(8) / 10).min(23) as u8;
+
+    if distance(rgb, fixed_rgb(gray)) < distance(rgb, fixed_rgb(cube)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+fn to_ansi(rgb: (u8, u8, u8), colors: usize) -> Color {
+    ANSI[..colors]
+        .iter()
+        .min_by_key(|(_, ansi)| distance(rgb, *ansi))
+        .map_or(Color::Default, |(color, _)| *color)
+}
+
+impl ColorSupport {
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorSupport::TrueColor => "truecolor",
+            ColorSupport::Ansi256 => "256",
+            ColorSupport::Ansi16 => "16",
+            ColorSupport::Ansi8 => "8",
+            ColorSupport::None => "none",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            ColorSupport::TrueColor,
+            ColorSupport::Ansi256,
+            ColorSupport::Ansi16,
+            ColorSupport::Ansi8,
+            ColorSupport::None,
+        ]
+        .into_iter()
+        .find(|support| support.name() == name)
+    }
+
+    /// From `NO_COLOR`, `COLORTERM` and `TERM`, terminals that set neither, like those
+    /// of Windows, are assumed to have every color
+    pub fn detect() -> Self {
+        if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return ColorSupport::None;
+        }
+
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorSupport::TrueColor;
+        }
+
+        let term = env::var("TERM").unwrap_or_default();
+
+        match term.as_str() {
+            "" => ColorSupport::TrueColor,
+            "dumb" => ColorSupport::None,
+            "linux" | "cons25" => ColorSupport::Ansi8,
+            term if term.starts_with("vt") => ColorSupport::Ansi8,
+            term if term.contains("256color") => ColorSupport::Ansi256,
+            _ => ColorSupport::Ansi16,
+        }
+    }
+
+    fn color(self, color: Color) -> Option<Color> {
+        let rgb = match color {
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+            Color::Fixed(index) => Some(fixed_rgb(index)),
+            _ => None,
+        };
+
+        Some(match (self, color, rgb) {
+            (ColorSupport::None, _, _) => return None,
+            (ColorSupport::TrueColor, color, _) => color,
+            (ColorSupport::Ansi256, Color::Rgb(r, g, b), _) => Color::Fixed(to_fixed((r, g, b))),
+            (ColorSupport::Ansi256, color, _) => color,
+            (ColorSupport::Ansi16, _, Some(rgb)) => to_ansi(rgb, 16),
+            (ColorSupport::Ansi8, _, Some(rgb)) => to_ansi(rgb, 8),
+            (ColorSupport::Ansi16, color, None) => color,
+            (ColorSupport::Ansi8, color, None) => match color {
+                Color::LightRed => Color::Red,
+                Color::LightGreen => Color::Green,
+                Color::LightYellow => Color::Yellow,
+                Color::LightBlue => Color::Blue,
+                Color::LightPurple | Color::Magenta | Color::LightMagenta => Color::Purple,
+                Color::LightCyan => Color::Cyan,
+                Color::DarkGray | Color::White => Color::LightGray,
+                color => color,
+            },
+        })
+    }
+
+    pub fn style(self, style: Style) -> Style {
+        Style {
+            foreground: style.foreground.and_then(|color| self.color(color)),
+            background: style.background.and_then(|color| self.color(color)),
+            ..style
+        }
+    }
+}
+
+pub fn set_color_support(support: ColorSupport) {
+    *COLOR_SUPPORT.write().unwrap() = support;
+}
+
+/// `style` with colors the terminal can show
+pub fn downgrade(style: Style) -> Style {
+    COLOR_SUPPORT.read().unwrap().style(style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_colors() {
+        let orange = Color::Rgb(254, 128, 25);
+
+        assert_eq!(Some(Color::Fixed(208)), ColorSupport::Ansi256.color(orange));
+        assert_eq!(
+            Some(Color::Fixed(244)),
+            ColorSupport::Ansi256.color(Color::Rgb(128, 128, 128))
+        );
+        assert_eq!(
+            Some(Color::Red),
+            ColorSupport::Ansi16.color(Color::Rgb(200, 10, 10))
+        );
+        assert_eq!(Some(Color::Red), ColorSupport::Ansi8.color(Color::LightRed));
+        assert_eq!(
+            Some(Color::LightYellow),
+            ColorSupport::Ansi16.color(Color::LightYellow)
+        );
+
+        let underline = ColorSupport::None.style(Color::Red.underline());
+
+        assert_eq!(None, underline.foreground);
+        assert!(underline.is_underline);
+    }
+}
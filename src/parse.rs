@@ -1,10 +1,25 @@
+use std::{
+    iter::Peekable,
+    ops::Range,
+    str::Chars,
+    sync::{Mutex, RwLock},
+};
+
 use emmylua_parser::{
-    LuaAst, LuaAstNode, LuaKind, LuaLanguageLevel, LuaParser, LuaSyntaxKind, LuaSyntaxNode,
-    LuaSyntaxToken, LuaSyntaxTree, LuaTokenKind, ParserConfig,
+    LuaAst, LuaAstNode, LuaCallExpr, LuaExpr, LuaIndexExpr, LuaKind, LuaLanguageLevel, LuaParser,
+    LuaStat, LuaSyntaxKind, LuaSyntaxNode, LuaSyntaxToken, LuaSyntaxTree, LuaTokenKind,
+    ParserConfig,
 };
+use lazy_static::lazy_static;
+use mlua::prelude::*;
 use nu_ansi_term::{Color, Style};
 use reedline::StyledText;
-use rowan::WalkEvent;
+use rowan::{TextRange, WalkEvent};
+
+use crate::{
+    completion::{self, Binding, GlobalsCache},
+    palette, validator,
+};
 
 #[cfg(feature = "lua54")]
 pub fn config<'cache>() -> ParserConfig<'cache> {
@@ -36,6 +51,53 @@ pub fn config<'cache>() -> ParserConfig<'cache> {
     ParserConfig::with_level(LuaLanguageLevel::LuaJIT)
 }
 
+fn parses(code: &str) -> bool {
+    LuaParser::parse(code, config()).get_errors().is_empty()
+}
+
+/// Returns the values of a trailing expression or call, so `x = 1; x + 1` gives `2`
+///
+/// Code that is neither valid as is nor with a `return` before one of its statements
+/// is left alone for the runtime to report
+pub fn auto_return(code: &str) -> String {
+    let tree = LuaParser::parse(code, config());
+    let is_valid = tree.get_errors().is_empty();
+
+    let stats = tree
+        .get_chunk_node()
+        .get_block()
+        .map(|block| block.get_stats().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    if is_valid {
+        return match stats.last() {
+            Some(LuaStat::CallExprStat(call)) => {
+                let start = usize::from(call.syntax().text_range().start());
+                format!("{}return {}", &code[..start], &code[start..])
+            }
+            _ => code.to_string(),
+        };
+    }
+
+    let expression = format!("return {code}");
+
+    if parses(&expression) {
+        return expression;
+    }
+
+    // the trailing expression can be split across what the parser recovered as statements
+    for stat in stats.iter().rev() {
+        let start = usize::from(stat.syntax().text_range().start());
+        let wrapped = format!("{}return {}", &code[..start], &code[start..]);
+
+        if parses(&wrapped) {
+            return wrapped;
+        }
+    }
+
+    code.to_string()
+}
+
 fn node_name(node: &LuaAst) -> Option<&'static str> {
     match node {
         LuaAst::LuaChunk(_) => Some("chunk"),
@@ -133,7 +195,297 @@ pub fn debug_tree(tree: &LuaSyntaxTree) {
     }
 }
 
-fn default_token_color(token: &LuaSyntaxToken) -> Color {
+lazy_static! {
+    static ref SYNTAX_COLORS: RwLock<SyntaxColors> = RwLock::new(SyntaxColors::default());
+    static ref RAINBOW_BRACKETS: RwLock<bool> = RwLock::new(false);
+}
+
+/// What a piece of highlighted code is, named after tree-sitter captures
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capture {
+    Keyword,
+    KeywordOperator,
+    Constant,
+    Number,
+    Operator,
+    Variable,
+    Upvalue,
+    Global,
+    Builtin,
+    UnknownGlobal,
+    String,
+    Escape,
+    StringSpecial,
+    Comment,
+    Annotation,
+    Type,
+    Parameter,
+    Function,
+    UnknownField,
+    MatchingBracket,
+    SyntaxError,
+}
+
+impl Capture {
+    const ALL: [Capture; 21] = [
+        Capture::Keyword,
+        Capture::KeywordOperator,
+        Capture::Constant,
+        Capture::Number,
+        Capture::Operator,
+        Capture::Variable,
+        Capture::Upvalue,
+        Capture::Global,
+        Capture::Builtin,
+        Capture::UnknownGlobal,
+        Capture::String,
+        Capture::Escape,
+        Capture::StringSpecial,
+        Capture::Comment,
+        Capture::Annotation,
+        Capture::Type,
+        Capture::Parameter,
+        Capture::Function,
+        Capture::UnknownField,
+        Capture::MatchingBracket,
+        Capture::SyntaxError,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Capture::Keyword => "keyword",
+            Capture::KeywordOperator => "keyword.operator",
+            Capture::Constant => "constant.builtin",
+            Capture::Number => "number",
+            Capture::Operator => "operator",
+            Capture::Variable => "variable",
+            Capture::Upvalue => "variable.upvalue",
+            Capture::Global => "variable.global",
+            Capture::Builtin => "variable.builtin",
+            Capture::UnknownGlobal => "variable.unknown",
+            Capture::String => "string",
+            Capture::Escape => "string.escape",
+            Capture::StringSpecial => "string.special",
+            Capture::Comment => "comment",
+            Capture::Annotation => "attribute",
+            Capture::Type => "type",
+            Capture::Parameter => "variable.parameter",
+            Capture::Function => "function",
+            Capture::UnknownField => "field.unknown",
+            Capture::MatchingBracket => "bracket.match",
+            Capture::SyntaxError => "error",
+        }
+    }
+
+    pub fn style(self) -> Style {
+        palette::downgrade(SYNTAX_COLORS.read().unwrap().captures[self as usize])
+    }
+}
+
+/// Styles of highlighted code for each capture and colors of nested brackets, set from
+/// `manen.theme` and `manen.syntax_colors`
+#[derive(Clone, Copy)]
+pub struct SyntaxColors {
+    captures: [Style; Capture::ALL.len()],
+    /// Colors of nested brackets from the outermost in, repeating after the last
+    rainbow: [Color; 4],
+}
+
+impl SyntaxColors {
+    fn from_colors(rainbow: [Color; 4], colors: impl Fn(Capture) -> Style) -> Self {
+        Self {
+            captures: Capture::ALL.map(colors),
+            rainbow,
+        }
+    }
+
+    pub fn light() -> Self {
+        let rainbow = [Color::Purple, Color::Blue, Color::Red, Color::Cyan];
+
+        Self::from_colors(rainbow, |capture| {
+            let color = match capture {
+                Capture::Keyword => Color::Purple,
+                Capture::KeywordOperator => Color::Blue,
+                Capture::Constant => Color::Red,
+                Capture::Number => Color::Blue,
+                Capture::Operator | Capture::Variable => Color::Default,
+                Capture::Upvalue => return Style::new().fg(Color::Default).italic(),
+                Capture::Global => Color::Cyan,
+                Capture::Builtin => return Style::new().fg(Color::Blue).bold(),
+                Capture::UnknownGlobal => return Style::new().fg(Color::Red).underline(),
+                Capture::String => Color::Green,
+                Capture::Escape | Capture::StringSpecial => Color::Cyan,
+                Capture::Comment => Color::DarkGray,
+                Capture::Annotation => Color::Purple,
+                Capture::Type => Color::Cyan,
+                Capture::Parameter => Color::Red,
+                Capture::Function => Color::Blue,
+                Capture::UnknownField => return Style::new().fg(Color::Red).underline(),
+                Capture::MatchingBracket => return Style::new().fg(Color::Blue).bold().underline(),
+                Capture::SyntaxError => return Style::new().fg(Color::Red).underline(),
+            };
+
+            Style::new().fg(color)
+        })
+    }
+
+    /// Sets the style of the capture called `name`, as in `manen highlight --json`, or
+    /// the color of `bracket.rainbow.1` to `bracket.rainbow.4`, false if there is none
+    pub fn set(&mut self, name: &str, style: Style) -> bool {
+        if let Some(level) = name
+            .strip_prefix("bracket.rainbow.")
+            .and_then(|level| level.parse::<usize>().ok())
+            .filter(|level| (1..=self.rainbow.len()).contains(level))
+        {
+            self.rainbow[level - 1] = style.foreground.unwrap_or(Color::Default);
+            return true;
+        }
+
+        match Capture::ALL.iter().find(|capture| capture.name() == name) {
+            Some(capture) => {
+                self.captures[*capture as usize] = style;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for SyntaxColors {
+    fn default() -> Self {
+        let rainbow = [Color::Yellow, Color::Purple, Color::Blue, Color::Cyan];
+
+        Self::from_colors(rainbow, |capture| {
+            let color = match capture {
+                Capture::Keyword => Color::Purple,
+                Capture::KeywordOperator => Color::Cyan,
+                Capture::Constant => Color::Red,
+                Capture::Number => Color::LightYellow,
+                Capture::Operator | Capture::Variable => Color::LightGray,
+                Capture::Upvalue => return Style::new().fg(Color::LightGray).italic(),
+                Capture::Global => Color::LightCyan,
+                Capture::Builtin => Color::LightBlue,
+                Capture::UnknownGlobal => return Style::new().fg(Color::Yellow).underline(),
+                Capture::String => Color::Green,
+                Capture::Escape | Capture::StringSpecial => Color::Cyan,
+                Capture::Comment => Color::DarkGray,
+                Capture::Annotation => Color::LightMagenta,
+                Capture::Type => Color::Yellow,
+                Capture::Parameter => Color::Red,
+                Capture::Function => Color::Blue,
+                Capture::UnknownField => return Style::new().fg(Color::Yellow).underline(),
+                Capture::MatchingBracket => {
+                    return Style::new().fg(Color::LightCyan).bold().underline();
+                }
+                Capture::SyntaxError => return Style::new().fg(Color::LightRed).underline(),
+            };
+
+            Style::new().fg(color)
+        })
+    }
+}
+
+pub fn set_syntax_colors(colors: SyntaxColors) {
+    *SYNTAX_COLORS.write().unwrap() = colors;
+}
+
+pub fn set_rainbow_brackets(enabled: bool) {
+    *RAINBOW_BRACKETS.write().unwrap() = enabled;
+}
+
+/// Byte offsets of the bracket pair around `cursor`, given the offset and character of
+/// each bracket of the code in order
+///
+/// A bracket right at the cursor is matched first, then one just before it, otherwise
+/// the innermost pair enclosing it. Unbalanced brackets are never matched
+pub fn matching_brackets(
+    brackets: impl IntoIterator<Item = (usize, char)>,
+    cursor: usize,
+) -> Option<(usize, usize)> {
+    let mut open = Vec::new();
+    let mut pairs = Vec::new();
+
+    for (offset, bracket) in brackets {
+        let opening = match bracket {
+            '(' | '{' | '[' => {
+                open.push((offset, bracket));
+                continue;
+            }
+            ')' => '(',
+            '}' => '{',
+            ']' => '[',
+            _ => continue,
+        };
+
+        match open.last() {
+            Some((start, c)) if *c == opening => {
+                pairs.push((*start, offset));
+                open.pop();
+            }
+            _ => open.clear(),
+        }
+    }
+
+    let touching = |position: usize| {
+        pairs
+            .iter()
+            .find(|(open, close)| *open == position || *close == position)
+            .copied()
+    };
+
+    touching(cursor)
+        .or_else(|| cursor.checked_sub(1).and_then(touching))
+        .or_else(|| {
+            pairs
+                .iter()
+                .filter(|(open, close)| *open < cursor && cursor <= *close)
+                .max_by_key(|(open, _)| *open)
+                .copied()
+        })
+}
+
+/// Styles `()`, `{}` and `[]` by how deeply they are nested, when `manen.rainbow_brackets`
+/// is set
+pub struct Rainbow {
+    enabled: bool,
+    depth: usize,
+    colors: [Color; 4],
+}
+
+impl Default for Rainbow {
+    fn default() -> Self {
+        Self {
+            enabled: *RAINBOW_BRACKETS.read().unwrap(),
+            depth: 0,
+            colors: SYNTAX_COLORS.read().unwrap().rainbow,
+        }
+    }
+}
+
+impl Rainbow {
+    /// Style of the next bracket of the code, `style` for anything else
+    pub fn style(&mut self, text: &str, style: Style) -> Style {
+        if !self.enabled {
+            return style;
+        }
+
+        match text {
+            "(" | "{" | "[" => {
+                self.depth += 1;
+                palette::downgrade(style.fg(self.colors[(self.depth - 1) % self.colors.len()]))
+            }
+            // unbalanced closing brackets keep the outermost color
+            ")" | "}" | "]" => {
+                let depth = self.depth.saturating_sub(1);
+                self.depth = depth;
+                palette::downgrade(style.fg(self.colors[depth % self.colors.len()]))
+            }
+            _ => style,
+        }
+    }
+}
+
+fn default_token_capture(token: &LuaSyntaxToken) -> Option<Capture> {
     let kind = match token.kind() {
         LuaKind::Syntax(_) => unreachable!(),
         LuaKind::Token(kind) => kind,
@@ -144,7 +496,7 @@ fn default_token_color(token: &LuaSyntaxToken) -> Color {
         | LuaTokenKind::TkEndOfLine
         | LuaTokenKind::TkEof
         | LuaTokenKind::TkUnknown
-        | LuaTokenKind::None => Color::Default,
+        | LuaTokenKind::None => None,
 
         LuaTokenKind::TkBreak
         | LuaTokenKind::TkDo
@@ -162,13 +514,19 @@ fn default_token_color(token: &LuaSyntaxToken) -> Color {
         | LuaTokenKind::TkThen
         | LuaTokenKind::TkUntil
         | LuaTokenKind::TkWhile
-        | LuaTokenKind::TkGlobal => Color::Purple,
+        | LuaTokenKind::TkGlobal => Some(Capture::Keyword),
 
-        LuaTokenKind::TkOr | LuaTokenKind::TkNot | LuaTokenKind::TkAnd => Color::Cyan,
+        LuaTokenKind::TkOr | LuaTokenKind::TkNot | LuaTokenKind::TkAnd => {
+            Some(Capture::KeywordOperator)
+        }
 
-        LuaTokenKind::TkFalse | LuaTokenKind::TkTrue | LuaTokenKind::TkNil => Color::Red,
+        LuaTokenKind::TkFalse | LuaTokenKind::TkTrue | LuaTokenKind::TkNil => {
+            Some(Capture::Constant)
+        }
 
-        LuaTokenKind::TkInt | LuaTokenKind::TkFloat | LuaTokenKind::TkComplex => Color::LightYellow,
+        LuaTokenKind::TkInt | LuaTokenKind::TkFloat | LuaTokenKind::TkComplex => {
+            Some(Capture::Number)
+        }
 
         LuaTokenKind::TkPlus
         | LuaTokenKind::TkMinus
@@ -202,14 +560,14 @@ fn default_token_color(token: &LuaSyntaxToken) -> Color {
         | LuaTokenKind::TkLeftParen
         | LuaTokenKind::TkRightParen
         | LuaTokenKind::TkLeftBrace
-        | LuaTokenKind::TkRightBrace => Color::LightGray,
+        | LuaTokenKind::TkRightBrace => Some(Capture::Operator),
 
-        LuaTokenKind::TkName => Color::LightGray,
+        LuaTokenKind::TkName => Some(Capture::Variable),
 
-        LuaTokenKind::TkString | LuaTokenKind::TkLongString => Color::Green,
+        LuaTokenKind::TkString | LuaTokenKind::TkLongString => Some(Capture::String),
 
         LuaTokenKind::TkShortComment | LuaTokenKind::TkLongComment | LuaTokenKind::TkShebang => {
-            Color::DarkGray
+            Some(Capture::Comment)
         }
 
         // EmmyLua
@@ -241,13 +599,13 @@ fn default_token_color(token: &LuaSyntaxToken) -> Color {
         | LuaTokenKind::TkTagNamespace
         | LuaTokenKind::TkTagUsing
         | LuaTokenKind::TkTagSource
-        | LuaTokenKind::TkTagReturnCast => Color::LightMagenta,
-        LuaTokenKind::TkDocVisibility => Color::Purple,
-        _ => Color::DarkGray,
+        | LuaTokenKind::TkTagReturnCast => Some(Capture::Annotation),
+        LuaTokenKind::TkDocVisibility => Some(Capture::Keyword),
+        _ => Some(Capture::Comment),
     }
 }
 
-fn modify_token_color(token: &LuaSyntaxToken, parent: &LuaSyntaxNode) -> Option<Color> {
+fn modify_token_capture(token: &LuaSyntaxToken, parent: &LuaSyntaxNode) -> Option<Capture> {
     let tk_kind = match token.kind() {
         LuaKind::Syntax(_) => unreachable!(),
         LuaKind::Token(kind) => kind,
@@ -259,9 +617,9 @@ fn modify_token_color(token: &LuaSyntaxToken, parent: &LuaSyntaxNode) -> Option<
     };
 
     match (tk_kind, node_kind) {
-        (LuaTokenKind::TkName, LuaSyntaxKind::TypeName) => Some(Color::Yellow),
-        (LuaTokenKind::TkName, LuaSyntaxKind::DocTagParam) => Some(Color::Red),
-        (LuaTokenKind::TkName, LuaSyntaxKind::ParamName) => Some(Color::Red),
+        (LuaTokenKind::TkName, LuaSyntaxKind::TypeName) => Some(Capture::Type),
+        (LuaTokenKind::TkName, LuaSyntaxKind::DocTagParam) => Some(Capture::Parameter),
+        (LuaTokenKind::TkName, LuaSyntaxKind::ParamName) => Some(Capture::Parameter),
         (LuaTokenKind::TkName, _) => {
             let parent_kind = if let Some(p) = parent.parent() {
                 match p.kind() {
@@ -273,9 +631,9 @@ fn modify_token_color(token: &LuaSyntaxToken, parent: &LuaSyntaxNode) -> Option<
             };
 
             match (node_kind, parent_kind) {
-                (_, LuaSyntaxKind::CallExpr) => Some(Color::Blue),
-                (_, LuaSyntaxKind::LocalFuncStat) => Some(Color::Blue),
-                (LuaSyntaxKind::IndexExpr, LuaSyntaxKind::FuncStat) => Some(Color::Blue),
+                (_, LuaSyntaxKind::CallExpr) => Some(Capture::Function),
+                (_, LuaSyntaxKind::LocalFuncStat) => Some(Capture::Function),
+                (LuaSyntaxKind::IndexExpr, LuaSyntaxKind::FuncStat) => Some(Capture::Function),
                 _ => None,
             }
         }
@@ -283,9 +641,202 @@ fn modify_token_color(token: &LuaSyntaxToken, parent: &LuaSyntaxNode) -> Option<
     }
 }
 
+/// How a string literal is read by the `string` function it is passed to
+#[derive(Clone, Copy)]
+enum StringUse {
+    Pattern,
+    /// Replacement of `gsub`, where `%1` inserts a capture
+    Replacement,
+    Format,
+}
+
+const PATTERN_FUNCTIONS: [&str; 4] = ["find", "gmatch", "gsub", "match"];
+
+/// Use of the string literal `token` in calls like `string.match(s, '%d+')`,
+/// `s:gsub('a', '%0')` and `('%d'):format(1)`
+fn string_use(token: &LuaSyntaxToken) -> Option<StringUse> {
+    let literal = token.parent()?;
+    let parent = literal.parent()?;
+
+    if parent.kind() == LuaKind::Syntax(LuaSyntaxKind::ParenExpr) {
+        let index = LuaIndexExpr::cast(parent.parent()?)?;
+
+        let is_format = index.token_by_kind(LuaTokenKind::TkColon).is_some()
+            && index.get_name_token()?.get_name_text() == "format";
+
+        return is_format.then_some(StringUse::Format);
+    }
+
+    if parent.kind() != LuaKind::Syntax(LuaSyntaxKind::CallArgList) {
+        return None;
+    }
+
+    let call = LuaCallExpr::cast(parent.parent()?)?;
+
+    let LuaExpr::IndexExpr(index) = call.get_prefix_expr()? else {
+        return None;
+    };
+
+    let mut argument = call
+        .get_args_list()?
+        .get_args()
+        .position(|arg| *arg.syntax() == literal)?;
+
+    // counted as if called through `string.`, where the string comes first
+    if index.token_by_kind(LuaTokenKind::TkColon).is_some() {
+        argument += 1;
+    } else {
+        let LuaExpr::NameExpr(prefix) = index.get_prefix_expr()? else {
+            return None;
+        };
+
+        if prefix.get_name_text()? != "string" {
+            return None;
+        }
+    }
+
+    match (index.get_name_token()?.get_name_text(), argument) {
+        ("format", 0) => Some(StringUse::Format),
+        ("gsub", 2) => Some(StringUse::Replacement),
+        (name, 1) if PATTERN_FUNCTIONS.contains(&name) => Some(StringUse::Pattern),
+        _ => None,
+    }
+}
+
+/// Pushes the rest of a pattern set like `[^%s]`, up to and including its `]`
+fn push_set(chars: &mut Peekable<Chars<'_>>, set: &mut String) {
+    set.extend(chars.next_if_eq(&'['));
+    set.extend(chars.next_if_eq(&'^'));
+    // a `]` right after the opening is part of the set
+    set.extend(chars.next_if_eq(&']'));
+
+    while let Some(c) = chars.next() {
+        set.push(c);
+
+        match c {
+            '%' => set.extend(chars.next()),
+            ']' => break,
+            _ => {}
+        }
+    }
+}
+
+/// Splits text of a string at pattern items like `%d`, `[^%s]` and `(`, `%1` of a
+/// replacement or format specifiers like `%5.2f`, which are special
+fn special_pieces(text: &str, string_use: StringUse) -> Vec<(Capture, String)> {
+    let mut pieces = Vec::new();
+    let mut plain = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let mut special = String::from(c);
+
+        match (string_use, c) {
+            (StringUse::Pattern, '%') => {
+                let class = chars.next();
+                special.extend(class);
+
+                match class {
+                    // `%b()` matches balanced pairs, `%f[%w]` is a frontier
+                    Some('b') => special.extend(chars.by_ref().take(2)),
+                    Some('f') if chars.peek() == Some(&'[') => push_set(&mut chars, &mut special),
+                    _ => {}
+                }
+            }
+            (StringUse::Pattern, '[') => push_set(&mut chars, &mut special),
+            (StringUse::Pattern, '(' | ')' | '.' | '*' | '+' | '-' | '?' | '^' | '$') => {}
+            (StringUse::Replacement, '%') => special.extend(chars.next()),
+            (StringUse::Format, '%') => {
+                while let Some(c) = chars.next_if(|c| "-+ #0.".contains(*c) || c.is_ascii_digit()) {
+                    special.push(c);
+                }
+
+                special.extend(chars.next());
+            }
+            _ => {
+                plain.push(c);
+                continue;
+            }
+        }
+
+        if !plain.is_empty() {
+            pieces.push((Capture::String, std::mem::take(&mut plain)));
+        }
+
+        pieces.push((Capture::StringSpecial, special));
+    }
+
+    if !plain.is_empty() {
+        pieces.push((Capture::String, plain));
+    }
+
+    pieces
+}
+
+/// Opening delimiter, contents and closing delimiter of a string token, a quote or a
+/// long bracket like `[==[`, the closing one empty while the string is unfinished
+fn string_delimiters(text: &str) -> (&str, &str, &str) {
+    let open = match text.chars().next() {
+        Some('"' | '\'') => 1,
+        Some('[') => text[1..]
+            .find(|c: char| c != '=')
+            .map_or(text.len(), |level| level + 2)
+            .min(text.len()),
+        _ => 0,
+    };
+
+    let close = match &text[..open] {
+        "" => String::new(),
+        quote @ ("\"" | "'") => quote.to_string(),
+        bracket => bracket.replace('[', "]"),
+    };
+
+    let rest = &text[open..];
+
+    match rest.strip_suffix(close.as_str()) {
+        Some(body) if !close.is_empty() => (&text[..open], body, &rest[body.len()..]),
+        _ => (&text[..open], rest, ""),
+    }
+}
+
+/// Pieces of a string token, the escapes of a quoted one and, when it is used as a
+/// pattern, replacement or format, the special items between its delimiters
+fn literal_pieces(text: &str, long: bool, string_use: Option<StringUse>) -> Vec<(Capture, String)> {
+    // long strings have no escapes
+    let contents = |text: &str| {
+        if long {
+            vec![(Capture::String, text.to_string())]
+        } else {
+            string_pieces(text)
+        }
+    };
+
+    let Some(string_use) = string_use else {
+        return contents(text);
+    };
+
+    // so quotes and the brackets of `[[...]]` aren't taken for pattern items
+    let (open, body, close) = string_delimiters(text);
+
+    let mut pieces = vec![(Capture::String, open.to_string())];
+
+    pieces.extend(
+        contents(body)
+            .into_iter()
+            .flat_map(|(capture, text)| match capture {
+                Capture::String => special_pieces(&text, string_use),
+                _ => vec![(capture, text)],
+            }),
+    );
+    pieces.push((Capture::String, close.to_string()));
+    pieces.retain(|(_, text)| !text.is_empty());
+
+    pieces
+}
+
 // this function is rubbish but it works
-fn highlight_string(text: &str) -> StyledText {
-    let mut styled = StyledText::new();
+fn string_pieces(text: &str) -> Vec<(Capture, String)> {
+    let mut styled = Vec::new();
 
     let mut chars = text.chars();
     let mut current = String::new();
@@ -296,14 +847,14 @@ fn highlight_string(text: &str) -> StyledText {
             continue;
         }
 
-        styled.push((Style::new().fg(Color::Green), current.clone()));
+        styled.push((Capture::String, current.clone()));
         current.clear();
 
         let modifier = if let Some(c) = chars.next() {
             c
         } else {
             // incomplete string
-            styled.push((Style::new().fg(Color::Cyan), String::from("\\")));
+            styled.push((Capture::Escape, String::from("\\")));
             break;
         };
 
@@ -311,10 +862,7 @@ fn highlight_string(text: &str) -> StyledText {
             let hex1 = chars.next().map(|c| c.to_string()).unwrap_or_default();
             let hex2 = chars.next().map(|c| c.to_string()).unwrap_or_default();
 
-            styled.push((
-                Style::new().fg(Color::Cyan),
-                format!("\\{modifier}{hex1}{hex2}"),
-            ));
+            styled.push((Capture::Escape, format!("\\{modifier}{hex1}{hex2}")));
         } else if modifier == 'u' || modifier == 'U' {
             for c in chars.by_ref() {
                 current.push(c);
@@ -323,54 +871,622 @@ fn highlight_string(text: &str) -> StyledText {
                 }
             }
 
-            styled.push((Style::new().fg(Color::Cyan), format!("\\u{current}")));
+            styled.push((Capture::Escape, format!("\\u{current}")));
             current.clear();
         } else {
-            styled.push((Style::new().fg(Color::Cyan), format!("\\{modifier}")));
+            styled.push((Capture::Escape, format!("\\{modifier}")));
         }
     }
 
-    styled.push((Style::new().fg(Color::Green), current.clone()));
+    styled.push((Capture::String, current.clone()));
 
     styled
 }
 
-pub struct LuaHighlighter;
+/// Text of every token in order with what it highlights as, strings split at escapes
+///
+/// `marks` restyle the tokens at their ranges, names of variables or anything for
+/// warnings about unknown names
+fn pieces(tree: &LuaSyntaxTree, marks: &[(TextRange, Capture)]) -> Vec<(Option<Capture>, String)> {
+    let root = tree.get_red_root();
 
-impl reedline::Highlighter for LuaHighlighter {
-    fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
-        let tree = LuaParser::parse(line, config());
-        let root = tree.get_red_root();
+    let mut pieces = Vec::new();
 
-        let mut text = StyledText::new();
+    for token in root
+        .descendants_with_tokens()
+        .filter_map(|d| d.into_token())
+    {
+        let mut capture = default_token_capture(&token);
 
-        for token in root
-            .descendants_with_tokens()
-            .filter_map(|d| d.into_token())
-        {
-            let mut color = default_token_color(&token);
+        if let Some(parent) = token.parent() {
+            if let Some(new_capture) = modify_token_capture(&token, &parent) {
+                capture = Some(new_capture);
+            }
+        }
+
+        match token.kind() {
+            LuaKind::Syntax(_) => unreachable!(),
+            LuaKind::Token(kind) => {
+                if let LuaTokenKind::TkString | LuaTokenKind::TkLongString = kind {
+                    let long = kind == LuaTokenKind::TkLongString;
 
-            if let Some(parent) = token.parent() {
-                if let Some(new_color) = modify_token_color(&token, &parent) {
-                    color = new_color;
+                    pieces.extend(
+                        literal_pieces(token.text(), long, string_use(&token))
+                            .into_iter()
+                            .map(|(capture, text)| (Some(capture), text)),
+                    );
+                    continue;
                 }
             }
+        }
+
+        let mark = marks
+            .iter()
+            .find(|(range, _)| *range == token.text_range())
+            .map(|(_, mark)| *mark);
 
-            match token.kind() {
-                LuaKind::Syntax(_) => unreachable!(),
-                LuaKind::Token(kind) => {
-                    if let LuaTokenKind::TkString = kind {
-                        let styled = highlight_string(token.text());
+        match mark {
+            Some(mark @ (Capture::UnknownField | Capture::UnknownGlobal)) => capture = Some(mark),
+            // calls of `print` and such are builtins rather than just functions
+            Some(Capture::Builtin) if capture == Some(Capture::Function) => {
+                capture = Some(Capture::Builtin)
+            }
+            Some(mark) if capture == Some(Capture::Variable) => capture = Some(mark),
+            _ => {}
+        }
+
+        pieces.push((capture, token.text().to_string()));
+    }
+
+    pieces
+}
+
+/// Byte range of highlighted text and the name of what it is, such as `keyword`
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub capture: &'static str,
+}
+
+/// Highlighting as spans instead of ANSI styles, for tools applying their own theme
+pub fn highlight_spans(code: &str) -> Vec<HighlightSpan> {
+    let tree = LuaParser::parse(code, config());
+    let mut spans = Vec::new();
+    let mut start = 0;
+
+    for (capture, piece) in pieces(&tree, &[]) {
+        let end = start + piece.len();
+
+        if let Some(capture) = capture.filter(|_| end > start) {
+            spans.push(HighlightSpan {
+                start,
+                end,
+                capture: capture.name(),
+            });
+        }
+
+        start = end;
+    }
+
+    spans
+}
 
-                        text.buffer.extend(styled.buffer);
-                        continue;
-                    }
+/// Byte ranges of the code inside ```` ```lua ```` and `~~~lua` fenced blocks of
+/// `markdown`, a block left open runs to the end
+pub fn lua_blocks(markdown: &str) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    // fence character, its length, where the code starts and if it is Lua, kept for
+    // every block so a fence inside another language's block isn't taken for one
+    let mut open: Option<(char, usize, usize, bool)> = None;
+    let mut offset = 0;
+
+    for line in markdown.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+
+        let trimmed = line.trim_start_matches(' ');
+
+        // fences are indented by at most 3 spaces
+        if line.len() - trimmed.len() > 3 {
+            continue;
+        }
+
+        let Some(fence) = trimmed.chars().next().filter(|c| *c == '`' || *c == '~') else {
+            continue;
+        };
+
+        let len = trimmed.chars().take_while(|c| *c == fence).count();
+        let info = trimmed[len..].trim();
+
+        match open {
+            Some((c, open_len, code, lua)) if c == fence && len >= open_len && info.is_empty() => {
+                if lua {
+                    blocks.push(code..start);
                 }
+
+                open = None;
+            }
+            None if len >= 3 => {
+                let language = info.split_whitespace().next().unwrap_or_default();
+
+                open = Some((fence, len, offset, language.eq_ignore_ascii_case("lua")));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((_, _, code, true)) = open {
+        blocks.push(code..markdown.len());
+    }
+
+    blocks
+}
+
+/// `markdown` with only the code of its Lua blocks highlighted
+pub fn highlight_markdown(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut prose = 0;
+
+    for block in lua_blocks(markdown) {
+        out.push_str(&markdown[prose..block.start]);
+        out.push_str(&highlight(&markdown[block.clone()]).render_simple());
+        prose = block.end;
+    }
+
+    out.push_str(&markdown[prose..]);
+    out
+}
+
+/// Spans of the Lua blocks of `markdown`, relative to the start of the document
+pub fn markdown_spans(markdown: &str) -> Vec<HighlightSpan> {
+    lua_blocks(markdown)
+        .into_iter()
+        .flat_map(|block| {
+            highlight_spans(&markdown[block.clone()])
+                .into_iter()
+                .map(move |span| HighlightSpan {
+                    start: span.start + block.start,
+                    end: span.end + block.start,
+                    capture: span.capture,
+                })
+        })
+        .collect()
+}
+
+/// `[{"start":0,"end":5,"capture":"keyword"}, ...]`, capture names never need escaping
+pub fn spans_to_json(spans: &[HighlightSpan]) -> String {
+    let spans = spans
+        .iter()
+        .map(|span| {
+            format!(
+                "{{\"start\":{},\"end\":{},\"capture\":\"{}\"}}",
+                span.start, span.end, span.capture
+            )
+        })
+        .collect::<Vec<_>>();
+
+    format!("[{}]", spans.join(","))
+}
+
+/// Whether `code` can only begin a new statement, rather than continue the expression
+/// ending the line before like `(f)()` or `.. x` would
+fn starts_statement(code: &str) -> bool {
+    let word = code
+        .trim_start()
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .unwrap_or_default();
+
+    !word.is_empty()
+        && !word.starts_with(|c: char| c.is_ascii_digit())
+        && word != "and"
+        && word != "or"
+}
+
+/// Byte offset and index into `pieces` of each line starting a top-level statement
+///
+/// Offsets are relative to the start of `code` plus `offset`, the first line is skipped
+/// as nothing before it could be reused
+fn statement_lines(
+    tree: &LuaSyntaxTree,
+    code: &str,
+    pieces: &[(Option<Capture>, String)],
+    offset: usize,
+    first_piece: usize,
+) -> Vec<(usize, usize)> {
+    let mut piece_starts = Vec::with_capacity(pieces.len());
+    let mut start = 0;
+
+    for (_, piece) in pieces {
+        piece_starts.push(start);
+        start += piece.len();
+    }
+
+    let Some(block) = tree.get_chunk_node().get_block() else {
+        return Vec::new();
+    };
+
+    block
+        .get_stats()
+        .filter_map(|stat| {
+            let start = usize::from(stat.syntax().text_range().start());
+            let line = code[..start].rfind('\n')? + 1;
+
+            if !code[line..start].trim().is_empty() {
+                return None;
+            }
+
+            let index = piece_starts.binary_search(&line).ok()?;
+
+            Some((line + offset, index + first_piece))
+        })
+        .collect()
+}
+
+/// Pieces of the last highlighted input
+///
+/// While typing, lines before the edit that end a top-level statement keep their pieces
+/// and only the code from there on is parsed again
+#[derive(Default)]
+struct Highlighted {
+    code: String,
+    cursor: usize,
+    pieces: Vec<(Option<Capture>, String)>,
+    lines: Vec<(usize, usize)>,
+    /// Top-level locals with the offset each is visible from, for the code after them
+    locals: Vec<(usize, String)>,
+    /// Range of the token where the code stops being valid Lua
+    error: Option<Range<usize>>,
+}
+
+impl Highlighted {
+    /// Names and fields are checked against `globals`, the session's when given
+    fn update(&mut self, code: &str, cursor: usize, globals: Option<&LuaTable>) {
+        if self.code == code && self.cursor == cursor {
+            return;
+        }
+
+        let common = self
+            .code
+            .bytes()
+            .zip(code.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        // a field is only spared from the underline while the cursor ends it
+        let cursor_after =
+            |line: usize| globals.is_none() || (cursor >= line && self.cursor >= line);
+
+        let (line, first_piece) = self
+            .lines
+            .iter()
+            .rev()
+            .find(|(line, _)| {
+                *line <= common && cursor_after(*line) && starts_statement(&code[*line..])
+            })
+            .copied()
+            .unwrap_or_default();
+
+        let rest = &code[line..];
+        let tree = LuaParser::parse(rest, config());
+
+        self.locals.retain(|(start, _)| *start <= line);
+
+        let mut marks = Vec::new();
+
+        if let Some(globals) = globals {
+            let cursor = cursor.saturating_sub(line) as u32;
+
+            let outer = self
+                .locals
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect::<Vec<_>>();
+
+            for range in completion::unknown_fields(globals, &tree, cursor) {
+                marks.push((range, Capture::UnknownField));
+            }
+
+            for (range, binding) in completion::bindings(globals, &tree, &outer, cursor) {
+                let capture = match binding {
+                    Binding::Local => continue,
+                    Binding::Upvalue => Capture::Upvalue,
+                    Binding::Global => Capture::Global,
+                    Binding::Builtin => Capture::Builtin,
+                    Binding::UnknownGlobal => Capture::UnknownGlobal,
+                };
+
+                marks.push((range, capture));
             }
 
-            text.push((Style::new().fg(color), token.text().to_string()));
+            self.locals.extend(
+                completion::chunk_locals(&tree)
+                    .into_iter()
+                    .map(|(start, name)| (start as usize + line, name)),
+            );
+        }
+
+        let rest_pieces = pieces(&tree, &marks);
+
+        self.lines.retain(|(start, _)| *start <= line && *start > 0);
+        self.lines.extend(statement_lines(
+            &tree,
+            rest,
+            &rest_pieces,
+            line,
+            first_piece,
+        ));
+
+        self.pieces.truncate(first_piece);
+        self.pieces.extend(rest_pieces);
+
+        self.code = code.to_string();
+        self.cursor = cursor;
+    }
+
+    /// Pieces with their styles, also marking the brackets matched around `cursor`
+    fn styled(&self, cursor: Option<usize>) -> StyledText {
+        let mut offsets = Vec::with_capacity(self.pieces.len());
+        let mut offset = 0;
+
+        for (_, piece) in &self.pieces {
+            offsets.push(offset);
+            offset += piece.len();
+        }
+
+        let brackets = self
+            .pieces
+            .iter()
+            .zip(&offsets)
+            .filter(|((capture, _), _)| *capture == Some(Capture::Operator))
+            .filter_map(|((_, piece), offset)| match piece.as_str() {
+                "(" | ")" | "{" | "}" | "[" | "]" => Some((*offset, piece.chars().next()?)),
+                _ => None,
+            });
+
+        let matched = cursor.and_then(|cursor| matching_brackets(brackets, cursor));
+
+        let mut text = StyledText::new();
+        let mut rainbow = Rainbow::default();
+
+        for ((capture, piece), offset) in self.pieces.iter().zip(offsets) {
+            let mut style = match capture {
+                Some(Capture::Operator) => rainbow.style(piece, Capture::Operator.style()),
+                Some(capture) => capture.style(),
+                None => Style::new(),
+            };
+
+            if matched.is_some_and(|(open, close)| offset == open || offset == close) {
+                style = Capture::MatchingBracket.style();
+            }
+
+            let end = offset + piece.len();
+
+            if self
+                .error
+                .as_ref()
+                .is_some_and(|error| offset < error.end && error.start < end)
+            {
+                style = Capture::SyntaxError.style();
+            }
+
+            text.push((style, piece.clone()));
         }
 
         text
     }
 }
+
+/// Highlights `code` outside of the prompt, where there is no cursor to match brackets at
+pub fn highlight(code: &str) -> StyledText {
+    let mut highlighted = Highlighted::default();
+
+    highlighted.update(code, 0, None);
+    highlighted.styled(None)
+}
+
+#[derive(Default)]
+pub struct LuaHighlighter {
+    last: Mutex<Highlighted>,
+}
+
+impl reedline::Highlighter for LuaHighlighter {
+    fn highlight(&self, line: &str, cursor: usize) -> StyledText {
+        let mut last = self.last.lock().unwrap();
+
+        last.update(line, 0, None);
+        last.styled(Some(cursor))
+    }
+}
+
+/// Highlighter for the prompt, also underlines fields missing from the session's tables
+/// and syntax errors that more input could not fix
+pub struct LiveHighlighter {
+    globals: GlobalsCache,
+    /// Only compiles the input, separate from the session so it is never blocked
+    lua: Lua,
+    last: Mutex<Highlighted>,
+}
+
+impl LiveHighlighter {
+    pub fn new(globals: GlobalsCache) -> Self {
+        Self {
+            globals,
+            lua: Lua::new_with(LuaStdLib::NONE, LuaOptions::new()).unwrap(),
+            last: Mutex::default(),
+        }
+    }
+}
+
+impl reedline::Highlighter for LiveHighlighter {
+    fn highlight(&self, line: &str, cursor: usize) -> StyledText {
+        let mut last = self.last.lock().unwrap();
+
+        if last.code != line {
+            last.error = validator::syntax_error(&self.lua, line);
+        }
+
+        // fields are checked once the executor was started by something else and its
+        // globals were fetched, never waiting on them
+        let globals = self.globals.get();
+
+        last.update(line, cursor, globals.as_ref());
+        last.styled(Some(cursor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_expressions() {
+        assert_eq!("return 1 + 1", auto_return("1 + 1"));
+        assert_eq!("x = 1; return x + 1", auto_return("x = 1; x + 1"));
+        assert_eq!("x = 1\nreturn print(x)", auto_return("x = 1\nprint(x)"));
+        assert_eq!("local x = 1", auto_return("local x = 1"));
+        assert_eq!("return x", auto_return("return x"));
+        assert_eq!("x = = 1", auto_return("x = = 1"));
+    }
+
+    #[test]
+    fn incremental_highlighting() {
+        let edits = [
+            "local t = {}\nprint(t)\n",
+            "local t = {}\nprint(t)\nt.x = 'a\\n'",
+            "local t = {}\nprint(t)\n(t.x)",
+            "local t = {}\nprint(t)\n.. 'b'",
+            "local t = {}\nprin",
+            "--[[ t = {}\nprint(t)\n",
+        ];
+
+        let mut last = Highlighted::default();
+
+        for code in edits {
+            last.update(code, 0, None);
+
+            let mut fresh = Highlighted::default();
+            fresh.update(code, 0, None);
+
+            assert_eq!(fresh.pieces, last.pieces, "{code}");
+            assert_eq!(
+                code,
+                last.pieces
+                    .iter()
+                    .map(|(_, piece)| piece.as_str())
+                    .collect::<String>()
+            );
+        }
+    }
+
+    #[test]
+    fn rainbow_brackets() {
+        let mut colors = SyntaxColors::default();
+        assert!(colors.set("bracket.rainbow.4", Style::new().fg(Color::Red)));
+        assert!(!colors.set("bracket.rainbow.5", Style::new().fg(Color::Red)));
+
+        let mut rainbow = Rainbow {
+            enabled: true,
+            depth: 0,
+            colors: colors.rainbow,
+        };
+
+        let styles = "({[(])})]"
+            .chars()
+            .map(|c| rainbow.style(&c.to_string(), Style::new()).foreground)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Some(Color::Yellow),
+                Some(Color::Purple),
+                Some(Color::Blue),
+                Some(Color::Red),
+                Some(Color::Red),
+                Some(Color::Blue),
+                Some(Color::Purple),
+                Some(Color::Yellow),
+                Some(Color::Yellow),
+            ],
+            styles
+        );
+    }
+
+    #[test]
+    fn bracket_pairs() {
+        let brackets = |code: &str| {
+            code.char_indices()
+                .filter(|(_, c)| "(){}[]".contains(*c))
+                .collect::<Vec<_>>()
+        };
+
+        let code = "f({ x[1] }, (2))";
+
+        assert_eq!(Some((1, 15)), matching_brackets(brackets(code), 1));
+        assert_eq!(Some((5, 7)), matching_brackets(brackets(code), 8));
+        assert_eq!(Some((2, 9)), matching_brackets(brackets(code), 4));
+        assert_eq!(Some((12, 14)), matching_brackets(brackets(code), 13));
+        assert_eq!(None, matching_brackets(brackets("(]"), 1));
+    }
+
+    #[test]
+    fn string_patterns() {
+        let special = |code: &str| {
+            highlight_spans(code)
+                .into_iter()
+                .filter(|span| span.capture == "string.special")
+                .map(|span| code[span.start..span.end].to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            special("s:match('^(%d+)[^%]a]%b()$')"),
+            ["^", "(", "%d", "+", ")", "[^%]a]", "%b()", "$"]
+        );
+        assert_eq!(special("string.gsub(s, '.', '%1%%')"), [".", "%1", "%%"]);
+        assert_eq!(special("('%-5.2f%%'):format(1)"), ["%-5.2f", "%%"]);
+        assert!(special("print('%d', s.match('.'))").is_empty());
+        assert_eq!(special("s:find([[%a]])"), ["%a"]);
+        assert_eq!(special("s:find([=[[x]]=])"), ["[x]"]);
+        assert_eq!(special("s:find('[x')"), ["[x"]);
+    }
+
+    #[test]
+    fn markdown_blocks() {
+        let markdown = "# Title\n\n```lua\nprint(1)\n```\n\n```sh\nls\n```\n\n~~~~ Lua\n```\nx = 1\n~~~~\n\n````md\n```lua\nquoted\n```\n````\n\n```lua\nopen";
+
+        let blocks = lua_blocks(markdown)
+            .into_iter()
+            .map(|block| &markdown[block])
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["print(1)\n", "```\nx = 1\n", "open"], blocks);
+    }
+
+    #[test]
+    fn spans() {
+        let code = "local s = 'a\\n'";
+
+        let spans = highlight_spans(code)
+            .into_iter()
+            .map(|span| (&code[span.start..span.end], span.capture))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                ("local", "keyword"),
+                ("s", "variable"),
+                ("=", "operator"),
+                ("'a", "string"),
+                ("\\n", "string.escape"),
+                ("'", "string"),
+            ],
+            spans
+        );
+
+        assert_eq!(
+            r#"[{"start":0,"end":5,"capture":"keyword"}]"#,
+            spans_to_json(&highlight_spans("local")[..1])
+        );
+    }
+}
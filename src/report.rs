@@ -0,0 +1,513 @@
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use directories::{BaseDirs, ProjectDirs};
+use mlua::prelude::*;
+
+use crate::{
+    config::{Config, Executor, PrintNil, Profile},
+    crypt,
+    inspect::{ByteEscape, IntegerBase, TableFormat},
+};
+
+// how many of the latest history entries are included
+const HISTORY_LINES: usize = 20;
+
+// size of tar headers, and of the blocks file contents are padded to
+const BLOCK: usize = 512;
+
+// lines mentioning these have their string values replaced
+const SECRET_WORDS: &[&str] = &["token", "secret", "password", "passwd", "api_key", "apikey"];
+
+fn data_dir() -> Option<PathBuf> {
+    ProjectDirs::from("gay.gayest", "", "Manen").map(|dirs| dirs.data_dir().to_path_buf())
+}
+
+/// Remembers the last input that failed so `manen report` can include it
+pub fn record_failure(input: &str, output: &str) {
+    if let Some(dir) = data_dir() {
+        let _ = fs::create_dir_all(&dir);
+        let _ = fs::write(
+            dir.join("last_failure"),
+            format!("{input}\n--- output ---\n{output}\n"),
+        );
+    }
+}
+
+/// Replaces the home directory with `~` and quoted values on lines that look like
+/// they hold credentials
+fn scrub(text: &str) -> String {
+    let home = BaseDirs::new().map(|dirs| dirs.home_dir().to_string_lossy().to_string());
+
+    text.lines()
+        .map(|line| {
+            let lower = line.to_lowercase();
+
+            let line = if SECRET_WORDS.iter().any(|word| lower.contains(word)) {
+                redact_strings(line)
+            } else {
+                line.to_string()
+            };
+
+            match &home {
+                Some(home) if !home.is_empty() => line.replace(home.as_str(), "~"),
+                _ => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn redact_strings(line: &str) -> String {
+    let mut redacted = String::new();
+    let mut quote = None;
+
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => {
+                redacted.push_str("<redacted>");
+                redacted.push(c);
+                quote = None;
+            }
+            Some(_) => {}
+            None => {
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+
+                redacted.push(c);
+            }
+        }
+    }
+
+    redacted
+}
+
+fn features() -> Vec<&'static str> {
+    [
+        ("vendored", cfg!(feature = "vendored")),
+        ("lua51", cfg!(feature = "lua51")),
+        ("lua52", cfg!(feature = "lua52")),
+        ("lua53", cfg!(feature = "lua53")),
+        ("lua54", cfg!(feature = "lua54")),
+        ("luajit", cfg!(feature = "luajit")),
+        ("luajit52", cfg!(feature = "luajit52")),
+        ("fennel", cfg!(feature = "fennel")),
+        ("teal", cfg!(feature = "teal")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+fn version_section() -> String {
+    let lua_version = Lua::new()
+        .globals()
+        .get::<String>("_VERSION")
+        .unwrap_or_default();
+
+    format!(
+        "manen {}\nembedded {lua_version}\nfeatures: {}\nplatform: {} {}",
+        env!("CARGO_PKG_VERSION"),
+        features().join(", "),
+        env::consts::OS,
+        env::consts::ARCH,
+    )
+}
+
+fn config_section() -> String {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => return format!("config.lua failed to load:\n{e}"),
+    };
+
+    let optional = |value: Option<usize>| value.map_or(String::from("nil"), |v| v.to_string());
+
+    let entries = [
+        (
+            "executor",
+            match config.executor {
+                Executor::Embedded => String::from("embedded"),
+                Executor::System => String::from("system"),
+                Executor::Auto => String::from("auto"),
+                Executor::Remote => String::from("remote"),
+                Executor::Ssh => String::from("ssh"),
+                Executor::Docker => String::from("docker"),
+            },
+        ),
+        (
+            "system_lua",
+            config
+                .system_lua
+                .as_ref()
+                .map_or(String::from("nil"), |path| {
+                    path.to_string_lossy().to_string()
+                }),
+        ),
+        (
+            "system_cwd",
+            config
+                .system_cwd
+                .as_ref()
+                .map_or(String::from("nil"), |path| {
+                    path.to_string_lossy().to_string()
+                }),
+        ),
+        ("system_args", config.system_args.join(" ")),
+        (
+            "restart_on_crash",
+            config.restart_policy.on_crash.to_string(),
+        ),
+        ("max_restarts", optional(config.restart_policy.max_restarts)),
+        (
+            "restart_backoff",
+            config.restart_policy.backoff.as_millis().to_string(),
+        ),
+        ("lua_preference", config.lua_preference.join(", ")),
+        (
+            "lua_version",
+            config
+                .lua_version
+                .clone()
+                .unwrap_or_else(|| String::from("nil")),
+        ),
+        ("sandbox", config.sandbox.to_string()),
+        ("sandbox_allow", config.sandbox_allow.join(", ")),
+        (
+            // the token lets anyone run code in the agent
+            "remote_agent",
+            config
+                .remote_agent
+                .as_ref()
+                .and_then(|target| target.split_once('@'))
+                .map_or(String::from("nil"), |(_, host)| format!("<token>@{host}")),
+        ),
+        (
+            "ssh_host",
+            config
+                .ssh_host
+                .clone()
+                .unwrap_or_else(|| String::from("nil")),
+        ),
+        (
+            "docker_image",
+            config
+                .docker_image
+                .clone()
+                .unwrap_or_else(|| String::from("nil")),
+        ),
+        (
+            "table_format",
+            String::from(match config.table_format {
+                TableFormat::Inspect => "inspect",
+                TableFormat::Address => "address",
+                TableFormat::ComfyTable => "comfytable",
+                TableFormat::Markdown => "markdown",
+            }),
+        ),
+        ("max_depth", optional(config.max_depth)),
+        ("max_items", optional(config.max_items)),
+        ("max_string_length", optional(config.max_string_length)),
+        ("redact_keys", config.redact_keys.join(", ")),
+        ("sort_keys", config.sort_keys.to_string()),
+        (
+            "indent",
+            match config.indent.fill {
+                ' ' => config.indent.width.to_string(),
+                fill => format!("{:?}", fill.to_string().repeat(config.indent.width)),
+            },
+        ),
+        ("compact_tables", config.compact_tables.to_string()),
+        ("hexdump_binary", config.hexdump_binary.to_string()),
+        (
+            "byte_escape",
+            String::from(match config.byte_escape {
+                ByteEscape::Hex => "hex",
+                ByteEscape::Decimal => "decimal",
+            }),
+        ),
+        ("table_summary", config.table_summary.to_string()),
+        ("show_inheritance", config.show_inheritance.to_string()),
+        ("float_precision", optional(config.numbers.precision)),
+        (
+            "scientific_threshold",
+            config
+                .numbers
+                .scientific_threshold
+                .map_or(String::from("nil"), |t| t.to_string()),
+        ),
+        (
+            "integer_base",
+            String::from(match config.numbers.integer_base {
+                IntegerBase::Decimal => "decimal",
+                IntegerBase::Hex => "hex",
+                IntegerBase::Binary => "binary",
+            }),
+        ),
+        ("history_size", config.history_size.to_string()),
+        ("color_output", config.color_output.to_string()),
+        (
+            "color_support",
+            String::from(
+                config
+                    .color_support
+                    .map_or("auto", |support| support.name()),
+            ),
+        ),
+        ("rainbow_brackets", config.rainbow_brackets.to_string()),
+        (
+            "print_nil",
+            String::from(match config.print_nil {
+                PrintNil::Always => "always",
+                PrintNil::Expressions => "expressions",
+                PrintNil::Never => "never",
+            }),
+        ),
+        ("fuzzy_completion", config.fuzzy_completion.to_string()),
+        (
+            "smart_case_completion",
+            config.smart_case_completion.to_string(),
+        ),
+        ("max_completions", optional(config.max_completions)),
+        (
+            "min_completion_length",
+            config.min_completion_length.to_string(),
+        ),
+        (
+            "dedupe_shadowed_locals",
+            config.dedupe_shadowed_locals.to_string(),
+        ),
+        (
+            "confirm_side_effects",
+            config.confirm_side_effects.to_string(),
+        ),
+        ("encrypt_history", config.encrypt_history.to_string()),
+        ("calculator", config.calculator.to_string()),
+        ("report_globals", config.report_globals.to_string()),
+        ("status_line", config.status_line.to_string()),
+        (
+            "async_loop",
+            config
+                .async_loop
+                .map_or(String::from("nil"), |l| l.name().to_string()),
+        ),
+        (
+            "luv_interval",
+            optional(config.luv_interval.map(|i| i.as_millis() as usize)),
+        ),
+        (
+            "profile",
+            String::from(match config.profile {
+                Some(Profile::OpenResty) => "openresty",
+                Some(Profile::Love) => "love",
+                Some(Profile::Neovim) => "neovim",
+                None => "nil",
+            }),
+        ),
+        (
+            "seed",
+            config.seed.map_or(String::from("nil"), |s| s.to_string()),
+        ),
+    ];
+
+    let mut section = String::new();
+
+    for (name, value) in entries {
+        let _ = writeln!(section, "{name} = {value}");
+    }
+
+    // expansions are left out, they are user code that may embed anything
+    let mut aliases = config.alias.keys().cloned().collect::<Vec<_>>();
+    aliases.sort();
+
+    let _ = writeln!(section, "alias names: {}", aliases.join(", "));
+
+    // values are left out like expansions, variables often carry credentials
+    let mut variables = config.system_env.keys().cloned().collect::<Vec<_>>();
+    variables.sort();
+
+    let _ = write!(section, "system_env names: {}", variables.join(", "));
+
+    scrub(&section)
+}
+
+fn terminal_section() -> String {
+    let var = |name: &str| env::var(name).unwrap_or_else(|_| String::from("unset"));
+
+    format!(
+        "TERM={}\nCOLORTERM={}\nNO_COLOR={}\nLANG={}\nstdin is a terminal: {}\nstdout is a terminal: {}",
+        var("TERM"),
+        var("COLORTERM"),
+        var("NO_COLOR"),
+        var("LANG"),
+        io::stdin().is_terminal(),
+        io::stdout().is_terminal(),
+    )
+}
+
+/// Entries of the history the REPL keeps, decrypted with the passphrase when
+/// `encrypt_history` is set
+fn history_entries() -> Result<Vec<String>, String> {
+    let Some(dir) = data_dir() else {
+        return Ok(Vec::new());
+    };
+
+    let encrypted = Config::load().is_ok_and(|config| config.encrypt_history);
+
+    if !encrypted {
+        let history = fs::read_to_string(dir.join("history")).unwrap_or_default();
+
+        return Ok(history.lines().map(String::from).collect());
+    }
+
+    let Ok(data) = fs::read(dir.join("history.enc")) else {
+        return Ok(Vec::new());
+    };
+
+    let passphrase = crypt::passphrase().map_err(|e| e.to_string())?;
+    let history = crypt::decrypt(&passphrase, &data).map_err(|e| e.to_string())?;
+
+    // entries are separated by NUL since they can span lines
+    Ok(String::from_utf8_lossy(&history)
+        .split('\0')
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn history_section() -> String {
+    match history_entries() {
+        Ok(entries) => {
+            let recent = &entries[entries.len().saturating_sub(HISTORY_LINES)..];
+
+            scrub(&recent.join("\n"))
+        }
+        Err(e) => format!("encrypted history could not be read: {e}"),
+    }
+}
+
+fn failure_section() -> String {
+    data_dir()
+        .and_then(|dir| fs::read_to_string(dir.join("last_failure")).ok())
+        .map_or(String::from("none recorded"), |failure| scrub(&failure))
+}
+
+/// Fills a tar header field with `value` as zero padded octal, but for its last byte
+fn octal_field(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+
+    field[..field.len() - 1].copy_from_slice(digits.as_bytes());
+}
+
+/// Appends a file of a ustar archive, a header block followed by the padded contents
+fn tar_entry(archive: &mut Vec<u8>, name: &str, contents: &[u8], mtime: u64) {
+    let mut header = [0; BLOCK];
+
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal_field(&mut header[100..108], 0o644);
+    octal_field(&mut header[108..116], 0);
+    octal_field(&mut header[116..124], 0);
+    octal_field(&mut header[124..136], contents.len() as u64);
+    octal_field(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // the checksum is taken with its own field as spaces
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|byte| u64::from(*byte)).sum::<u64>();
+    octal_field(&mut header[148..155], checksum);
+    header[154] = 0;
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(contents);
+    archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+}
+
+/// A tar archive of a directory named `root` holding each of `files`
+fn tar(root: &str, files: &[(&str, String)], mtime: u64) -> Vec<u8> {
+    let mut archive = Vec::new();
+
+    for (name, contents) in files {
+        tar_entry(
+            &mut archive,
+            &format!("{root}/{name}"),
+            contents.as_bytes(),
+            mtime,
+        );
+    }
+
+    // two empty blocks end the archive
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    archive
+}
+
+/// Writes everything useful for a bug report into a tar archive at `path`, a text file
+/// for each part so they can be read or left out one by one
+pub fn report(path: &Path) -> io::Result<()> {
+    let sections = [
+        ("version.txt", version_section()),
+        ("config.txt", config_section()),
+        ("terminal.txt", terminal_section()),
+        ("history.txt", history_section()),
+        ("last_failure.txt", failure_section()),
+    ];
+
+    let files = sections.map(|(name, body)| (name, format!("{}\n", body.trim_end())));
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    fs::write(path, tar("manen-report", &files, mtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrubbed() {
+        assert_eq!(
+            "local api_key = '<redacted>'\nprint('hi')",
+            scrub("local api_key = 'abc123'\nprint('hi')")
+        );
+        assert_eq!(
+            "auth(\"<redacted>\", \"<redacted>\") -- password",
+            redact_strings("auth(\"user\", \"hunter2\") -- password")
+        );
+    }
+
+    #[test]
+    fn archive() {
+        let files = [("a.txt", String::from("hello\n")), ("b.txt", String::new())];
+        let archive = tar("report", &files, 0);
+
+        // a header and a block of contents, a header alone and the end marker
+        assert_eq!(5 * BLOCK, archive.len());
+        assert!(archive.starts_with(b"report/a.txt\0"));
+        assert_eq!(b"00000000006\0", &archive[124..136]);
+        assert_eq!(b"ustar\0", &archive[257..263]);
+        assert_eq!(b"hello\n", &archive[BLOCK..BLOCK + 6]);
+        assert!(archive[2 * BLOCK..].starts_with(b"report/b.txt\0"));
+        assert!(archive[3 * BLOCK..].iter().all(|byte| *byte == 0));
+
+        let header = &archive[..BLOCK];
+        let checksum = header[..148]
+            .iter()
+            .chain(b"        ")
+            .chain(&header[156..])
+            .map(|byte| u64::from(*byte))
+            .sum::<u64>();
+        let stored = std::str::from_utf8(&header[148..154]).unwrap();
+
+        assert_eq!(checksum, u64::from_str_radix(stored, 8).unwrap());
+    }
+}
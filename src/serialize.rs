@@ -0,0 +1,298 @@
+use std::{collections::HashMap, fmt::Write};
+
+use mlua::prelude::*;
+
+use crate::inspect::{display_path_key, is_valid_identifier, key_order, lua_string_literal};
+
+const INDENT: &str = "   ";
+
+// Lua refuses to load more than about 200 nested syntax levels, the statements around
+// the tables take a few
+const MAX_DEPTH: usize = 180;
+
+fn too_deep() -> LuaError {
+    LuaError::RuntimeError(format!(
+        "cannot serialize tables nested more than {MAX_DEPTH} levels deep"
+    ))
+}
+
+/// Tables reached more than once from `value`, in the order they were first reached
+fn shared_tables(value: &LuaValue) -> LuaResult<Vec<LuaTable>> {
+    fn visit(
+        tbl: &LuaTable,
+        counts: &mut HashMap<usize, usize>,
+        order: &mut Vec<LuaTable>,
+        depth: usize,
+    ) -> LuaResult<()> {
+        if depth > MAX_DEPTH {
+            return Err(too_deep());
+        }
+
+        let count = counts.entry(tbl.to_pointer() as usize).or_default();
+        *count += 1;
+
+        if *count > 1 {
+            return Ok(());
+        }
+
+        order.push(tbl.clone());
+
+        for (key, value) in tbl.pairs::<LuaValue, LuaValue>().flatten() {
+            for value in [key, value] {
+                if let LuaValue::Table(inner) = value {
+                    visit(&inner, counts, order, depth + 1)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    let LuaValue::Table(tbl) = value else {
+        return Ok(Vec::new());
+    };
+
+    let mut counts = HashMap::new();
+    let mut order = Vec::new();
+
+    visit(tbl, &mut counts, &mut order, 0)?;
+
+    Ok(order
+        .into_iter()
+        .filter(|tbl| counts[&(tbl.to_pointer() as usize)] > 1)
+        .collect())
+}
+
+fn sorted_pairs(tbl: &LuaTable) -> Vec<(LuaValue, LuaValue)> {
+    let mut pairs = tbl
+        .pairs::<LuaValue, LuaValue>()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    pairs.sort_by(|(a, _), (b, _)| key_order(a, b));
+    pairs
+}
+
+/// Length of the run of keys from 1 without holes, written without their keys
+fn sequence_len(tbl: &LuaTable) -> i64 {
+    let mut len = 0;
+
+    while tbl
+        .raw_get::<LuaValue>(len + 1)
+        .is_ok_and(|value| !value.is_nil())
+    {
+        len += 1;
+    }
+
+    len
+}
+
+struct Serializer {
+    /// Index in `refs` of each shared table, by pointer
+    refs: HashMap<usize, usize>,
+}
+
+impl Serializer {
+    fn value(&self, value: &LuaValue, path: &str, indent: usize) -> LuaResult<String> {
+        Ok(match value {
+            LuaValue::Nil => String::from("nil"),
+            LuaValue::Boolean(b) => b.to_string(),
+            LuaValue::Integer(i) if *i == i64::MIN => String::from("math.mininteger"),
+            LuaValue::Integer(i) => i.to_string(),
+            LuaValue::Number(n) if n.is_nan() => String::from("0/0"),
+            LuaValue::Number(n) if n.is_infinite() && *n > 0.0 => String::from("math.huge"),
+            LuaValue::Number(n) if n.is_infinite() => String::from("-math.huge"),
+            LuaValue::Number(n) => format!("{n:?}"),
+            LuaValue::String(s) => lua_string_literal(&s.as_bytes()),
+            LuaValue::Table(tbl) => match self.refs.get(&(tbl.to_pointer() as usize)) {
+                Some(index) => format!("refs[{index}]"),
+                None => self.literal(tbl, path, indent)?,
+            },
+            value => {
+                return Err(LuaError::RuntimeError(format!(
+                    "cannot serialize a {} at {}",
+                    value.type_name(),
+                    if path.is_empty() { "the root" } else { path }
+                )));
+            }
+        })
+    }
+
+    /// `key = ` or `[key] = ` inside a table constructor
+    fn key(&self, key: &LuaValue, path: &str, indent: usize) -> LuaResult<String> {
+        if let LuaValue::String(s) = key {
+            if let Ok(name) = s.to_str() {
+                if is_valid_identifier(&name) {
+                    return Ok(format!("{name} = "));
+                }
+            }
+        }
+
+        Ok(format!("[{}] = ", self.value(key, path, indent)?))
+    }
+
+    fn literal(&self, tbl: &LuaTable, path: &str, indent: usize) -> LuaResult<String> {
+        if indent >= MAX_DEPTH {
+            return Err(too_deep());
+        }
+
+        let pairs = sorted_pairs(tbl);
+
+        if pairs.is_empty() {
+            return Ok(String::from("{}"));
+        }
+
+        let len = sequence_len(tbl);
+        let inner = INDENT.repeat(indent + 1);
+        let mut literal = String::from("{\n");
+
+        for i in 1..=len {
+            let path = format!("{path}[{i}]");
+            let value = self.value(&tbl.raw_get(i)?, &path, indent + 1)?;
+
+            let _ = writeln!(literal, "{inner}{value},");
+        }
+
+        for (key, value) in pairs {
+            // already written in order above
+            let index = match key {
+                LuaValue::Integer(i) => Some(i as f64),
+                LuaValue::Number(n) => Some(n),
+                _ => None,
+            };
+
+            if index.is_some_and(|i| i.fract() == 0.0 && i >= 1.0 && i <= len as f64) {
+                continue;
+            }
+
+            let path = format!("{path}{}", display_path_key(&key, false));
+            let key = self.key(&key, &path, indent + 1)?;
+            let value = self.value(&value, &path, indent + 1)?;
+
+            let _ = writeln!(literal, "{inner}{key}{value},");
+        }
+
+        literal.push_str(&INDENT.repeat(indent));
+        literal.push('}');
+
+        Ok(literal)
+    }
+
+    /// Statements filling a shared table created empty in the prelude
+    fn fill(&self, tbl: &LuaTable, index: usize, out: &mut String) -> LuaResult<()> {
+        let path = format!("refs[{index}]");
+
+        for (key, value) in sorted_pairs(tbl) {
+            let target = match &key {
+                LuaValue::String(s) if s.to_str().is_ok_and(|name| is_valid_identifier(&name)) => {
+                    format!("{path}.{}", s.to_string_lossy())
+                }
+                key => format!("{path}[{}]", self.value(key, &path, 0)?),
+            };
+
+            let value = self.value(&value, &target, 0)?;
+
+            let _ = writeln!(out, "{target} = {value}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Lua source that rebuilds `value` when run, tables reached more than once, including
+/// through cycles, are created first and shared the same way once loaded
+pub fn serialize(value: &LuaValue) -> LuaResult<String> {
+    let shared = shared_tables(value)?;
+
+    let serializer = Serializer {
+        refs: shared
+            .iter()
+            .enumerate()
+            .map(|(i, tbl)| (tbl.to_pointer() as usize, i + 1))
+            .collect(),
+    };
+
+    let mut out = String::new();
+
+    if !shared.is_empty() {
+        out.push_str("local refs = {}\n");
+
+        for index in 1..=shared.len() {
+            let _ = writeln!(out, "refs[{index}] = {{}}");
+        }
+
+        for (i, tbl) in shared.iter().enumerate() {
+            serializer.fill(tbl, i + 1, &mut out)?;
+        }
+    }
+
+    let _ = write!(out, "return {}", serializer.value(value, "", 0)?);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let lua = Lua::new();
+
+        let value = lua
+            .load(
+                "local t = { 1, 2, name = 'a\\0b', ['not an identifier'] = { x = 1.5 } }
+                t.self = t
+                t.list = { t[ 'not an identifier' ] }
+                return t",
+            )
+            .eval::<LuaValue>()
+            .unwrap();
+
+        let source = serialize(&value).unwrap();
+        let loaded = lua.load(source.as_str()).eval::<LuaTable>().unwrap();
+
+        assert!(loaded.get::<LuaTable>("self").unwrap() == loaded);
+        assert_eq!(2, loaded.raw_len());
+        assert_eq!(
+            b"a\0b",
+            &loaded.get::<LuaString>("name").unwrap().as_bytes()[..]
+        );
+
+        let inner = loaded.get::<LuaTable>("not an identifier").unwrap();
+
+        assert_eq!(1.5, inner.get::<f64>("x").unwrap());
+        assert!(
+            loaded
+                .get::<LuaTable>("list")
+                .unwrap()
+                .get::<LuaTable>(1)
+                .unwrap()
+                == inner
+        );
+
+        let function = lua.load("return { f = print }").eval::<LuaValue>().unwrap();
+
+        assert!(serialize(&function).is_err());
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let lua = Lua::new();
+
+        let nested = |depth: usize| {
+            lua.load(format!(
+                "local t = {{}}
+                for _ = 1, {depth} do t = {{ t }} end
+                return t"
+            ))
+            .eval::<LuaValue>()
+            .unwrap()
+        };
+
+        let source = serialize(&nested(MAX_DEPTH - 1)).unwrap();
+        assert!(lua.load(source.as_str()).eval::<LuaTable>().is_ok());
+
+        assert!(serialize(&nested(100_000)).is_err());
+    }
+}
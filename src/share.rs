@@ -0,0 +1,422 @@
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, Shutdown, TcpListener, TcpStream},
+    process,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, SyncSender},
+    },
+    thread,
+    time::Duration,
+};
+
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use nu_ansi_term::Color;
+use reedline::{DefaultPrompt, DefaultPromptSegment, Reedline, Signal};
+
+pub const DEFAULT_PORT: u16 = 7377;
+
+/// Time a connection has to send its handshake before it is dropped
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time a write to a connection may block before the peer is taken as gone
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Messages queued for a guest that reads slower than the session talks, before it is
+/// dropped
+const GUEST_BACKLOG: usize = 256;
+
+/// Runs the input of the guest named by the first argument, which prints and broadcasts
+/// its output, returning the error
+pub type Evaluate = dyn Fn(&str, &str) -> Result<(), String> + Send + Sync;
+
+/// What a message carries, the first field of each line on the wire
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Input,
+    Output,
+    Error,
+    Info,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Input => "input",
+            Kind::Output => "output",
+            Kind::Error => "error",
+            Kind::Info => "info",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "input" => Some(Kind::Input),
+            "output" => Some(Kind::Output),
+            "error" => Some(Kind::Error),
+            "info" => Some(Kind::Info),
+            _ => None,
+        }
+    }
+}
+
+// messages are one line each, so newlines in the text are escaped
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+fn encode(kind: Kind, author: &str, text: &str) -> String {
+    format!("{}\t{}\t{}\n", kind.name(), escape(author), escape(text))
+}
+
+fn decode(line: &str) -> Option<(Kind, String, String)> {
+    let mut fields = line.trim_end_matches(['\r', '\n']).splitn(3, '\t');
+
+    let kind = Kind::from_name(fields.next()?)?;
+    let author = unescape(fields.next()?);
+    let text = unescape(fields.next()?);
+
+    Some((kind, author, text))
+}
+
+/// `text` without the control characters another participant could move the cursor
+/// or restyle the terminal with, keeping newlines
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c == '\n' || !c.is_control())
+        .collect()
+}
+
+/// Prints a message from another participant, clearing the prompt line first
+fn print_message(kind: Kind, author: &str, text: &str, color: bool) {
+    let (author, text) = (sanitize(author), sanitize(text));
+    let text = text.as_str();
+
+    let label = format!("[{author}]");
+    let label = if color {
+        Color::DarkGray.paint(label).to_string()
+    } else {
+        label
+    };
+
+    let body = match kind {
+        Kind::Input => format!("> {text}"),
+        Kind::Error if color => Color::Red.paint(text).to_string(),
+        _ => text.to_string(),
+    };
+
+    for line in body.lines() {
+        println!("\r\x1b[2K{label} {line}");
+    }
+
+    let _ = io::stdout().flush();
+}
+
+/// Shows a guest's input to the host, as the main thread evaluates it
+pub fn print_input(author: &str, input: &str, color: bool) {
+    print_message(Kind::Input, author, input, color);
+}
+
+/// 128 bits from the OS as hex, what `token@host` joins and attaches with
+pub fn random_token() -> String {
+    let mut bytes = [0; 16];
+
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// If `given` is `token`, taking as long wherever they differ
+pub fn token_matches(given: &str, token: &str) -> bool {
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Host others reach `bind` as, the host name when it listens on every interface
+pub fn advertised_host(bind: IpAddr) -> String {
+    if bind.is_unspecified() {
+        env::var("HOSTNAME").unwrap_or_else(|_| String::from("localhost"))
+    } else {
+        bind.to_string()
+    }
+}
+
+/// A session opened to other manen instances with `manen share`
+pub struct Share {
+    token: String,
+    read_only: bool,
+    color: bool,
+    /// Queues written to each guest by a thread of its own, so a stalled guest never
+    /// blocks the host
+    guests: Mutex<Vec<SyncSender<String>>>,
+}
+
+impl Share {
+    /// Listens on `bind` and `port` and evaluates guest inputs with `evaluate` unless
+    /// `read_only`
+    pub fn start(
+        bind: IpAddr,
+        port: u16,
+        read_only: bool,
+        color: bool,
+        evaluate: Arc<Evaluate>,
+    ) -> io::Result<Arc<Self>> {
+        let listener = TcpListener::bind((bind, port))?;
+
+        let share = Arc::new(Self {
+            token: random_token(),
+            read_only,
+            color,
+            guests: Mutex::new(Vec::new()),
+        });
+
+        let inner = share.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let share = inner.clone();
+                let evaluate = evaluate.clone();
+
+                thread::spawn(move || share.serve(stream, evaluate));
+            }
+        });
+
+        Ok(share)
+    }
+
+    /// Command for others to run, with the address they can reach
+    pub fn join_command(&self, bind: IpAddr, port: u16) -> String {
+        format!("manen join {}@{}:{port}", self.token, advertised_host(bind))
+    }
+
+    /// Queues a message for every guest, dropping those that disconnected or fell too
+    /// far behind
+    fn send(&self, kind: Kind, author: &str, text: &str) {
+        let message = encode(kind, author, text);
+
+        self.guests
+            .lock()
+            .unwrap()
+            .retain(|guest| guest.try_send(message.clone()).is_ok());
+    }
+
+    /// Writes what is queued for a guest until it is dropped or a write fails, then
+    /// closes its connection
+    fn add_guest(&self, mut stream: TcpStream) {
+        let (sender, receiver) = mpsc::sync_channel::<String>(GUEST_BACKLOG);
+
+        thread::spawn(move || {
+            for message in receiver {
+                if stream.write_all(message.as_bytes()).is_err() {
+                    break;
+                }
+            }
+
+            let _ = stream.shutdown(Shutdown::Both);
+        });
+
+        self.guests.lock().unwrap().push(sender);
+    }
+
+    pub fn broadcast_input(&self, author: &str, input: &str) {
+        self.send(Kind::Input, author, input);
+    }
+
+    pub fn broadcast_output(&self, author: &str, output: &str) {
+        self.send(Kind::Output, author, output);
+    }
+
+    pub fn broadcast_error(&self, author: &str, error: &str) {
+        self.send(Kind::Error, author, error);
+    }
+
+    /// Handshake of token and name, then one evaluation per input line
+    fn serve(&self, stream: TcpStream, evaluate: Arc<Evaluate>) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+
+        if stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).is_err() {
+            return;
+        }
+
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+
+        let mut lines = BufReader::new(reader).lines();
+
+        let (Some(Ok(token)), Some(Ok(name))) = (lines.next(), lines.next()) else {
+            return;
+        };
+
+        if !token_matches(token.trim(), &self.token) {
+            let _ = writer.write_all(encode(Kind::Error, "host", "invalid token").as_bytes());
+            return;
+        }
+
+        // guests may idle as long as they like once joined, but not stop reading
+        if stream.set_read_timeout(None).is_err()
+            || stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err()
+        {
+            return;
+        }
+
+        let name = unescape(name.trim());
+        let mode = if self.read_only {
+            "read-only"
+        } else {
+            "read-write"
+        };
+
+        let _ = writer
+            .write_all(encode(Kind::Info, "host", &format!("joined {mode} session")).as_bytes());
+
+        if let Ok(guest) = writer.try_clone() {
+            self.add_guest(guest);
+        }
+
+        self.send(Kind::Info, "host", &format!("{name} joined"));
+        print_message(Kind::Info, "host", &format!("{name} joined"), self.color);
+
+        for line in lines.map_while(Result::ok) {
+            let input = unescape(&line);
+
+            if self.read_only {
+                let _ = writer
+                    .write_all(encode(Kind::Error, "host", "this session is read-only").as_bytes());
+                continue;
+            }
+
+            self.broadcast_input(&name, &input);
+
+            // printed for the host by the main thread, with the output
+            if let Err(error) = evaluate(&name, &input) {
+                self.broadcast_error(&name, &error);
+            }
+        }
+
+        self.send(Kind::Info, "host", &format!("{name} left"));
+        print_message(Kind::Info, "host", &format!("{name} left"), self.color);
+    }
+}
+
+/// Token and address of `token@host[:port]`, with `default_port` when it has none
+pub fn parse_target(target: &str, default_port: u16) -> io::Result<(&str, String)> {
+    let (token, address) = target.split_once('@').ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("expected token@host[:port], got '{target}'"),
+        )
+    })?;
+
+    let address = if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{address}:{default_port}")
+    };
+
+    Ok((token, address))
+}
+
+/// Connects to a shared session at `token@host[:port]` and relays input until Ctrl-D
+pub fn join(target: &str, color: bool) -> io::Result<()> {
+    let (token, address) = parse_target(target, DEFAULT_PORT)?;
+
+    let mut stream = TcpStream::connect(address)?;
+    let name = env::var("USER").unwrap_or_else(|_| String::from("guest"));
+
+    stream.write_all(format!("{token}\n{}\n", escape(&name)).as_bytes())?;
+
+    let reader = BufReader::new(stream.try_clone()?);
+
+    thread::spawn(move || {
+        for line in reader.lines().map_while(Result::ok) {
+            if let Some((kind, author, text)) = decode(&line) {
+                print_message(kind, &author, &text, color);
+            }
+        }
+
+        println!("\r\x1b[2Kdisconnected");
+        process::exit(0);
+    });
+
+    let mut editor = Reedline::create();
+    let prompt = DefaultPrompt::new(
+        DefaultPromptSegment::Basic(String::from("shared")),
+        DefaultPromptSegment::Empty,
+    );
+
+    loop {
+        match editor.read_line(&prompt) {
+            Ok(Signal::Success(line)) => {
+                stream.write_all(format!("{}\n", escape(&line)).as_bytes())?;
+            }
+            Err(e) => return Err(e),
+            _ => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let line = encode(Kind::Output, "ana", "{\n  a = 1\n} \\n");
+
+        assert_eq!(1, line.lines().count());
+
+        let (kind, author, text) = decode(&line).unwrap();
+
+        assert!(kind == Kind::Output);
+        assert_eq!("ana", author);
+        assert_eq!("{\n  a = 1\n} \\n", text);
+    }
+
+    #[test]
+    fn tokens() {
+        let token = random_token();
+
+        assert_eq!(32, token.len());
+        assert_ne!(token, random_token());
+        assert!(token_matches(&token, &token));
+        assert!(!token_matches(&token[1..], &token));
+        assert!(!token_matches("", &token));
+        assert!(!token_matches(&token.replace(&token[..1], "g"), &token));
+    }
+
+    #[test]
+    fn sanitized() {
+        assert_eq!("ana\n[2Jb", sanitize("ana\n\x1b[2J\rb\x07"));
+        assert_eq!("é ok", sanitize("é ok"));
+    }
+}
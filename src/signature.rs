@@ -0,0 +1,363 @@
+use emmylua_parser::{LuaAstNode, LuaCallExpr, LuaExpr, LuaKind, LuaSyntaxTree, LuaTokenKind};
+use mlua::prelude::*;
+use nu_ansi_term::{Color, Style};
+
+use crate::{
+    completion::{LuaCompleter, resolve_path},
+    lua::LuaExecutor,
+};
+
+// C functions carry no debug info for their parameters, sorted by name so lookups
+// are a binary search over static data rather than a table built at startup
+const STDLIB: &[(&str, &str)] = &[
+    ("assert", "v, message"),
+    ("collectgarbage", "opt, arg"),
+    ("coroutine.close", "co"),
+    ("coroutine.create", "f"),
+    ("coroutine.isyieldable", "co"),
+    ("coroutine.resume", "co, ..."),
+    ("coroutine.running", ""),
+    ("coroutine.status", "co"),
+    ("coroutine.wrap", "f"),
+    ("coroutine.yield", "..."),
+    ("debug.getinfo", "thread, f, what"),
+    ("debug.getlocal", "thread, f, local"),
+    ("debug.sethook", "thread, hook, mask, count"),
+    ("debug.traceback", "thread, message, level"),
+    ("dofile", "filename"),
+    ("error", "message, level"),
+    ("getmetatable", "object"),
+    ("io.close", "file"),
+    ("io.input", "file"),
+    ("io.lines", "filename, ..."),
+    ("io.open", "filename, mode"),
+    ("io.output", "file"),
+    ("io.popen", "prog, mode"),
+    ("io.read", "..."),
+    ("io.type", "obj"),
+    ("io.write", "..."),
+    ("ipairs", "t"),
+    ("load", "chunk, chunkname, mode, env"),
+    ("loadfile", "filename, mode, env"),
+    ("math.abs", "x"),
+    ("math.acos", "x"),
+    ("math.asin", "x"),
+    ("math.atan", "y, x"),
+    ("math.ceil", "x"),
+    ("math.cos", "x"),
+    ("math.exp", "x"),
+    ("math.floor", "x"),
+    ("math.fmod", "x, y"),
+    ("math.log", "x, base"),
+    ("math.max", "x, ..."),
+    ("math.min", "x, ..."),
+    ("math.modf", "x"),
+    ("math.random", "m, n"),
+    ("math.randomseed", "x, y"),
+    ("math.sin", "x"),
+    ("math.sqrt", "x"),
+    ("math.tan", "x"),
+    ("math.tointeger", "x"),
+    ("math.type", "x"),
+    ("math.ult", "m, n"),
+    ("next", "table, index"),
+    ("os.clock", ""),
+    ("os.date", "format, time"),
+    ("os.difftime", "t2, t1"),
+    ("os.execute", "command"),
+    ("os.exit", "code, close"),
+    ("os.getenv", "varname"),
+    ("os.remove", "filename"),
+    ("os.rename", "oldname, newname"),
+    ("os.time", "table"),
+    ("os.tmpname", ""),
+    ("pairs", "t"),
+    ("pcall", "f, ..."),
+    ("print", "..."),
+    ("rawequal", "v1, v2"),
+    ("rawget", "table, index"),
+    ("rawlen", "v"),
+    ("rawset", "table, index, value"),
+    ("require", "modname"),
+    ("select", "index, ..."),
+    ("setmetatable", "table, metatable"),
+    ("string.byte", "s, i, j"),
+    ("string.char", "..."),
+    ("string.dump", "function, strip"),
+    ("string.find", "s, pattern, init, plain"),
+    ("string.format", "formatstring, ..."),
+    ("string.gmatch", "s, pattern, init"),
+    ("string.gsub", "s, pattern, repl, n"),
+    ("string.len", "s"),
+    ("string.lower", "s"),
+    ("string.match", "s, pattern, init"),
+    ("string.pack", "fmt, v1, v2, ..."),
+    ("string.packsize", "fmt"),
+    ("string.rep", "s, n, sep"),
+    ("string.reverse", "s"),
+    ("string.sub", "s, i, j"),
+    ("string.unpack", "fmt, s, pos"),
+    ("string.upper", "s"),
+    ("table.concat", "list, sep, i, j"),
+    ("table.insert", "list, pos, value"),
+    ("table.move", "a1, f, e, t, a2"),
+    ("table.pack", "..."),
+    ("table.remove", "list, pos"),
+    ("table.sort", "list, comp"),
+    ("table.unpack", "list, i, j"),
+    ("tonumber", "e, base"),
+    ("tostring", "v"),
+    ("type", "v"),
+    ("utf8.char", "..."),
+    ("utf8.codepoint", "s, i, j, lax"),
+    ("utf8.codes", "s, lax"),
+    ("utf8.len", "s, i, j, lax"),
+    ("utf8.offset", "s, n, i"),
+    ("xpcall", "f, msgh, ..."),
+];
+
+fn stdlib_parameters(name: &str) -> Option<&'static str> {
+    STDLIB
+        .binary_search_by_key(&name, |(function, _)| function)
+        .ok()
+        .map(|i| STDLIB[i].1)
+}
+
+/// Call whose argument list contains the cursor
+pub struct Call {
+    /// Full name of the callee such as `["string", "format"]`
+    pub path: Vec<String>,
+    /// Index of the argument under the cursor
+    pub argument: usize,
+    /// Called with `:`, the first parameter is passed implicitly
+    pub is_method: bool,
+}
+
+/// Innermost parenthesized call around `cursor` with a callee made of names
+pub fn call_at(tree: &LuaSyntaxTree, cursor: u32) -> Option<Call> {
+    let (call, args) = tree
+        .get_chunk_node()
+        .descendants::<LuaCallExpr>()
+        .filter_map(|call| {
+            let args = call.get_args_list()?;
+            let open = args.token_by_kind(LuaTokenKind::TkLeftParen)?;
+
+            if cursor <= u32::from(open.get_range().start()) {
+                return None;
+            }
+
+            let closed = args
+                .token_by_kind(LuaTokenKind::TkRightParen)
+                .is_some_and(|close| cursor > u32::from(close.get_range().start()));
+
+            if closed {
+                return None;
+            }
+
+            Some((call, args))
+        })
+        .last()?;
+
+    let (path, is_method) = match call.get_prefix_expr()? {
+        LuaExpr::NameExpr(name) => (vec![name.get_name_text()?], false),
+        LuaExpr::IndexExpr(index) => {
+            let mut path = LuaCompleter::index_path(&index)?;
+            path.push(index.get_name_token()?.get_name_text().to_string());
+
+            (path, index.token_by_kind(LuaTokenKind::TkColon).is_some())
+        }
+        _ => return None,
+    };
+
+    let argument = args
+        .syntax()
+        .children_with_tokens()
+        .filter_map(|element| element.into_token())
+        .filter(|token| {
+            token.kind() == LuaKind::Token(LuaTokenKind::TkComma)
+                && u32::from(token.text_range().start()) < cursor
+        })
+        .count();
+
+    Some(Call {
+        path,
+        argument,
+        is_method,
+    })
+}
+
+/// Parameter names of the callee, from `_MANEN_SIGNATURES`, the bundled standard
+/// library signatures or the debug info of Lua functions
+pub fn parameters(lua_executor: &dyn LuaExecutor, call: &Call) -> Option<Vec<String>> {
+    let globals = lua_executor.globals().ok()?;
+    let mut name = call.path.join(".");
+
+    // `s:rep(` on a string calls `string.rep`
+    if call.is_method {
+        let (method, receiver) = call.path.split_last()?;
+
+        if let Some(LuaValue::String(_)) = resolve_path(globals.clone(), receiver) {
+            name = format!("string.{method}");
+        }
+    }
+
+    let known = globals
+        .raw_get::<LuaTable>("_MANEN_SIGNATURES")
+        .and_then(|signatures| signatures.raw_get::<String>(name.as_str()))
+        .ok()
+        .or_else(|| stdlib_parameters(&name).map(String::from));
+
+    let mut params = match known {
+        Some(params) => params
+            .split(',')
+            .map(|param| param.trim().to_string())
+            .filter(|param| !param.is_empty())
+            .collect(),
+        None => debug_parameters(&globals, &call.path)?,
+    };
+
+    if call.is_method && !params.is_empty() {
+        params.remove(0);
+    }
+
+    Some(params)
+}
+
+fn debug_parameters(globals: &LuaTable, path: &[String]) -> Option<Vec<String>> {
+    let LuaValue::Function(function) = resolve_path(globals.clone(), path)? else {
+        return None;
+    };
+
+    let debug: LuaTable = globals.raw_get("debug").ok()?;
+    let getinfo: LuaFunction = debug.raw_get("getinfo").ok()?;
+    let getlocal: LuaFunction = debug.raw_get("getlocal").ok()?;
+
+    let info: LuaTable = getinfo.call((function.clone(), "Su")).ok()?;
+
+    if info.raw_get::<String>("what").ok()? == "C" {
+        return None;
+    }
+
+    let mut params = Vec::new();
+
+    while let Ok(Some(param)) =
+        getlocal.call::<Option<String>>((function.clone(), params.len() + 1))
+    {
+        params.push(param);
+    }
+
+    if info.raw_get::<bool>("isvararg").unwrap_or_default() {
+        params.push(String::from("..."));
+    }
+
+    Some(params)
+}
+
+/// `name(a, b)` with the parameter under the cursor stressed, trailing varargs take
+/// every remaining argument
+pub fn format(call: &Call, params: &[String], colorize: bool) -> String {
+    let current = if call.argument >= params.len() && params.last().is_some_and(|p| p == "...") {
+        params.len() - 1
+    } else {
+        call.argument
+    };
+
+    let dim = Style::new().fg(Color::DarkGray);
+    let mut buffer = call.path.join(".");
+
+    if let (true, Some(dot)) = (call.is_method, buffer.rfind('.')) {
+        buffer.replace_range(dot..=dot, ":");
+    }
+
+    buffer.push('(');
+
+    if colorize {
+        buffer = dim.paint(buffer).to_string();
+    }
+
+    for (i, param) in params.iter().enumerate() {
+        let param = match (i == current, colorize) {
+            (true, true) => dim.bold().underline().paint(param).to_string(),
+            (true, false) => format!("*{param}*"),
+            (false, true) => dim.paint(param).to_string(),
+            (false, false) => param.to_string(),
+        };
+
+        if i > 0 {
+            buffer.push_str(&if colorize {
+                dim.paint(", ").to_string()
+            } else {
+                String::from(", ")
+            });
+        }
+
+        buffer.push_str(&param);
+    }
+
+    if colorize {
+        buffer.push_str(&dim.paint(")").to_string());
+    } else {
+        buffer.push(')');
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use emmylua_parser::LuaParser;
+
+    use crate::parse;
+
+    use super::*;
+
+    fn call(code: &str) -> Option<Call> {
+        let tree = LuaParser::parse(code, parse::config());
+
+        call_at(&tree, code.len() as u32)
+    }
+
+    #[test]
+    fn current_argument() {
+        let format = call("string.format('%d', ").unwrap();
+
+        assert_eq!(vec!["string", "format"], format.path);
+        assert_eq!(1, format.argument);
+        assert!(!format.is_method);
+
+        let nested = call("print(tostring(1), ").unwrap();
+
+        assert_eq!(vec!["print"], nested.path);
+        assert_eq!(1, nested.argument);
+
+        let inner = call("print(tostring(").unwrap();
+
+        assert_eq!(vec!["tostring"], inner.path);
+        assert_eq!(0, inner.argument);
+
+        assert!(call("s:rep(").unwrap().is_method);
+        assert!(call("print(1)").is_none());
+    }
+
+    #[test]
+    fn stdlib_sorted() {
+        assert!(STDLIB.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(Some("s, n, sep"), stdlib_parameters("string.rep"));
+        assert_eq!(None, stdlib_parameters("string.missing"));
+    }
+
+    #[test]
+    fn formatted() {
+        let call = Call {
+            path: vec![String::from("string"), String::from("format")],
+            argument: 3,
+            is_method: false,
+        };
+        let params = vec![String::from("formatstring"), String::from("...")];
+
+        assert_eq!(
+            "string.format(formatstring, *...*)",
+            format(&call, &params, false)
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use directories::ProjectDirs;
+
+/// Returned by the placeholder keybinding in place of a submitted line
+pub const NEXT_PLACEHOLDER: &str = "\0next-placeholder";
+
+/// Where snippets are stored, one `<name>.lua` file each
+pub fn dir() -> Option<PathBuf> {
+    ProjectDirs::from("gay.gayest", "", "Manen").map(|dirs| dirs.data_dir().join("snippets"))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn invalid_name(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid snippet name '{name}', use letters, digits, '_' and '-'"),
+    )
+}
+
+pub fn save(dir: &Path, name: &str, code: &str) -> io::Result<()> {
+    if !is_valid_name(name) {
+        return Err(invalid_name(name));
+    }
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(format!("{name}.lua")), code)
+}
+
+pub fn load(dir: &Path, name: &str) -> io::Result<String> {
+    if !is_valid_name(name) {
+        return Err(invalid_name(name));
+    }
+
+    fs::read_to_string(dir.join(format!("{name}.lua"))).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => io::Error::new(e.kind(), format!("no snippet named '{name}'")),
+        _ => e,
+    })
+}
+
+/// Names of saved snippets, sorted
+pub fn names(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut names = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+
+            if path.extension()? != "lua" {
+                return None;
+            }
+
+            Some(path.file_stem()?.to_string_lossy().to_string())
+        })
+        .collect::<Vec<_>>();
+
+    names.sort();
+    names
+}
+
+/// Byte range of the lowest numbered `$n` placeholder left in `code`
+pub fn next_placeholder(code: &str) -> Option<Range<usize>> {
+    let mut best: Option<(u32, Range<usize>)> = None;
+
+    for (start, _) in code.match_indices('$') {
+        let digits = code[start + 1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(code.len() - start - 1);
+
+        let Ok(number) = code[start + 1..start + 1 + digits].parse::<u32>() else {
+            continue;
+        };
+
+        if best.as_ref().is_none_or(|(lowest, _)| number < *lowest) {
+            best = Some((number, start..start + 1 + digits));
+        }
+    }
+
+    best.map(|(_, range)| range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        let code = "for $2, $1 in pairs($10) do\n  $3\nend";
+
+        assert_eq!(Some(8..10), next_placeholder(code));
+        assert_eq!(
+            Some(4..6),
+            next_placeholder("for $2, x in pairs($10) do end")
+        );
+        assert_eq!(None, next_placeholder("print('$')"));
+    }
+
+    #[test]
+    fn saved() {
+        let dir = tempfile::tempdir().unwrap();
+
+        save(dir.path(), "loop", "for i = 1, $1 do\n  $2\nend").unwrap();
+        save(dir.path(), "class", "local $1 = {}").unwrap();
+
+        assert_eq!(vec!["class", "loop"], names(dir.path()));
+        assert_eq!("local $1 = {}", load(dir.path(), "class").unwrap());
+        assert!(load(dir.path(), "missing").is_err());
+        assert!(save(dir.path(), "../escape", "").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+use nu_ansi_term::Color;
+
+/// Time spent in each step of starting the REPL, printed with `--startup-profile`
+pub struct StartupProfile {
+    enabled: bool,
+    start: Instant,
+    last: Instant,
+    steps: Vec<(&'static str, Duration)>,
+}
+
+impl StartupProfile {
+    pub fn new(enabled: bool) -> Self {
+        let now = Instant::now();
+
+        Self {
+            enabled,
+            start: now,
+            last: now,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Records the time since the previous step under `name`
+    pub fn step(&mut self, name: &'static str) {
+        let now = Instant::now();
+
+        self.steps.push((name, now - self.last));
+        self.last = now;
+    }
+
+    pub fn print(&self, color: bool) {
+        if !self.enabled {
+            return;
+        }
+
+        let width = self
+            .steps
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default();
+
+        for (name, elapsed) in &self.steps {
+            println!("{name:<width$}  {:>9.3}ms", elapsed.as_secs_f64() * 1000.0);
+        }
+
+        let total = format!(
+            "{:<width$}  {:>9.3}ms",
+            "total",
+            self.start.elapsed().as_secs_f64() * 1000.0
+        );
+
+        if color {
+            println!("{}", Color::Default.bold().paint(total));
+        } else {
+            println!("{total}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        let mut profile = StartupProfile::new(false);
+
+        profile.step("config");
+        std::thread::sleep(Duration::from_millis(2));
+        profile.step("prompt");
+
+        let names = profile
+            .steps
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(["config", "prompt"], names.as_slice());
+        assert!(profile.steps[1].1 >= Duration::from_millis(2));
+
+        // steps are measured one after another, so together they fit in the total
+        let steps = profile
+            .steps
+            .iter()
+            .map(|(_, elapsed)| *elapsed)
+            .sum::<Duration>();
+        assert!(steps <= profile.start.elapsed());
+    }
+}
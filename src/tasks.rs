@@ -0,0 +1,158 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, mpsc},
+};
+
+use crossterm::event::Event;
+use nix::{
+    sys::signal::{Signal, kill},
+    unistd::Pid,
+};
+use reedline::{EditMode, PromptEditMode, ReedlineEvent, ReedlineRawEvent};
+
+/// Line `read_line` returns when tasks are waiting, the buffer is kept for the next read
+pub const WAKE: &str = "\0wake";
+
+/// Work other threads leave to the main thread, the only one using the executor
+pub enum Task {
+    /// Input of a `manen join` guest, answered with its error once evaluated
+    Guest {
+        name: String,
+        input: String,
+        reply: mpsc::Sender<Result<(), String>>,
+    },
+    /// Runs the luv loop once, queued every `luv_interval`
+    LuvTick,
+}
+
+/// Tasks waiting for the main thread
+#[derive(Clone, Default)]
+pub struct Tasks(Arc<Mutex<VecDeque<Task>>>);
+
+impl Tasks {
+    /// Queues `task`, waking `read_line` so it runs without waiting for a key
+    pub fn push(&self, task: Task) {
+        self.0.lock().unwrap().push_back(task);
+
+        wake();
+    }
+
+    /// Queues `Task::LuvTick` unless one is still waiting, so ticks don't pile up while
+    /// the main thread is busy
+    pub fn push_luv_tick(&self) {
+        let mut tasks = self.0.lock().unwrap();
+
+        if tasks.iter().any(|task| matches!(task, Task::LuvTick)) {
+            return;
+        }
+
+        tasks.push_back(Task::LuvTick);
+        drop(tasks);
+
+        wake();
+    }
+
+    pub fn pop(&self) -> Option<Task> {
+        self.0.lock().unwrap().pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+fn wake() {
+    // crossterm reads SIGWINCH as a resize, which `WakingEditMode` turns into `WAKE`
+    let _ = kill(Pid::this(), Signal::SIGWINCH);
+}
+
+/// Edit mode returning `WAKE` from `read_line` on the resize `Tasks::push` causes
+pub struct WakingEditMode {
+    inner: Box<dyn EditMode>,
+    tasks: Tasks,
+}
+
+impl WakingEditMode {
+    pub fn new(inner: Box<dyn EditMode>, tasks: Tasks) -> Self {
+        Self { inner, tasks }
+    }
+}
+
+impl EditMode for WakingEditMode {
+    fn parse_event(&mut self, event: ReedlineRawEvent) -> ReedlineEvent {
+        let event = Event::from(event);
+
+        // keys are never taken, so typing goes on while tasks wait
+        if matches!(event, Event::Resize(..)) && !self.tasks.is_empty() {
+            return ReedlineEvent::ExecuteHostCommand(String::from(WAKE));
+        }
+
+        match ReedlineRawEvent::try_from(event) {
+            Ok(event) => self.inner.parse_event(event),
+            Err(()) => ReedlineEvent::None,
+        }
+    }
+
+    fn edit_mode(&self) -> PromptEditMode {
+        self.inner.edit_mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reedline::{Emacs, default_emacs_keybindings};
+
+    use super::*;
+
+    #[test]
+    fn queued_tasks() {
+        let tasks = Tasks::default();
+
+        assert!(tasks.is_empty());
+
+        tasks.push_luv_tick();
+        tasks.push_luv_tick();
+
+        let (reply, replies) = mpsc::channel();
+        tasks.push(Task::Guest {
+            name: String::from("ana"),
+            input: String::from("1 + 1"),
+            reply,
+        });
+
+        // a tick still waiting isn't queued again, and tasks run in order
+        assert!(matches!(tasks.pop(), Some(Task::LuvTick)));
+
+        let Some(Task::Guest { name, input, reply }) = tasks.pop() else {
+            panic!("expected the guest input");
+        };
+
+        assert_eq!(("ana", "1 + 1"), (name.as_str(), input.as_str()));
+        reply.send(Ok(())).unwrap();
+        assert_eq!(Ok(()), replies.recv().unwrap());
+
+        assert!(tasks.pop().is_none());
+        assert!(tasks.is_empty());
+    }
+
+    #[test]
+    fn waking() {
+        let tasks = Tasks::default();
+        let emacs = Emacs::new(default_emacs_keybindings());
+        let mut mode = WakingEditMode::new(Box::new(emacs), tasks.clone());
+        let resize = || ReedlineRawEvent::try_from(Event::Resize(80, 24)).unwrap();
+
+        // without tasks a resize is only a resize
+        assert!(matches!(
+            mode.parse_event(resize()),
+            ReedlineEvent::Resize(80, 24)
+        ));
+
+        tasks.push_luv_tick();
+
+        assert!(matches!(
+            mode.parse_event(resize()),
+            ReedlineEvent::ExecuteHostCommand(command) if command == WAKE
+        ));
+    }
+}
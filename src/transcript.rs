@@ -0,0 +1,326 @@
+use std::{fmt::Write, fs, io, path::Path};
+
+use crate::parse;
+
+pub struct TranscriptEntry {
+    pub input: String,
+    pub output: String,
+}
+
+pub struct Transcript {
+    entries: Vec<TranscriptEntry>,
+    seed: Option<u64>,
+}
+
+const ANSI_COLORS: [&str; 8] = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+];
+
+const ANSI_BRIGHT_COLORS: [&str; 8] = [
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+];
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn fixed_color(n: u8) -> String {
+    match n {
+        0..=7 => ANSI_COLORS[n as usize].to_string(),
+        8..=15 => ANSI_BRIGHT_COLORS[n as usize - 8].to_string(),
+        16..=231 => {
+            let n = n - 16;
+            let scale = |c: u8| if c == 0 { 0 } else { c * 40 + 55 };
+
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                scale(n / 36),
+                scale((n / 6) % 6),
+                scale(n % 6)
+            )
+        }
+        232..=255 => {
+            let gray = (n - 232) * 10 + 8;
+            format!("#{gray:02x}{gray:02x}{gray:02x}")
+        }
+    }
+}
+
+#[derive(Default, Clone, PartialEq)]
+struct SpanStyle {
+    color: Option<String>,
+    bold: bool,
+    dimmed: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl SpanStyle {
+    fn apply(&mut self, params: &str) {
+        let mut codes = params.split(';').map(|c| c.parse::<u8>().unwrap_or(0));
+
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                2 => self.dimmed = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => (self.bold, self.dimmed) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.color = Some(ANSI_COLORS[code as usize - 30].to_string()),
+                90..=97 => self.color = Some(ANSI_BRIGHT_COLORS[code as usize - 90].to_string()),
+                39 => self.color = None,
+                38 => match codes.next() {
+                    Some(5) => self.color = codes.next().map(fixed_color),
+                    Some(2) => {
+                        let (r, g, b) = (codes.next(), codes.next(), codes.next());
+                        self.color = Some(format!(
+                            "#{:02x}{:02x}{:02x}",
+                            r.unwrap_or(0),
+                            g.unwrap_or(0),
+                            b.unwrap_or(0)
+                        ));
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn css(&self) -> String {
+        let mut css = String::new();
+
+        if let Some(color) = &self.color {
+            let _ = write!(css, "color:{color};");
+        }
+
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+
+        if self.dimmed {
+            css.push_str("opacity:0.6;");
+        }
+
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+
+        if self.underline {
+            css.push_str("text-decoration:underline;");
+        }
+
+        css
+    }
+}
+
+pub fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("\x1b[") {
+        stripped.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        match rest.find(|c: char| c.is_ascii_alphabetic()) {
+            Some(end) => rest = &rest[end + 1..],
+            None => rest = "",
+        }
+    }
+
+    stripped.push_str(rest);
+
+    stripped
+}
+
+/// Converts SGR escape sequences into inline-styled `<span>`s
+pub fn ansi_to_html(text: &str) -> String {
+    let mut html = String::new();
+    let mut style = SpanStyle::default();
+    let mut plain = String::new();
+    let mut rest = text;
+
+    let flush = |plain: &mut String, style: &SpanStyle, html: &mut String| {
+        if plain.is_empty() {
+            return;
+        }
+
+        let css = style.css();
+
+        if css.is_empty() {
+            html.push_str(&escape_html(plain));
+        } else {
+            let _ = write!(html, "<span style=\"{css}\">{}</span>", escape_html(plain));
+        }
+
+        plain.clear();
+    };
+
+    while let Some(start) = rest.find("\x1b[") {
+        plain.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let Some(end) = rest.find(|c: char| c.is_ascii_alphabetic()) else {
+            break;
+        };
+
+        if rest.as_bytes()[end] == b'm' {
+            flush(&mut plain, &style, &mut html);
+            style.apply(&rest[..end]);
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    plain.push_str(rest);
+    flush(&mut plain, &style, &mut html);
+
+    html
+}
+
+impl Transcript {
+    pub fn new(seed: Option<u64>) -> Self {
+        Self {
+            entries: Vec::new(),
+            seed,
+        }
+    }
+
+    pub fn push_input(&mut self, input: &str) {
+        self.entries.push(TranscriptEntry {
+            input: input.to_string(),
+            output: String::new(),
+        });
+    }
+
+    pub fn push_output(&mut self, output: &str) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.output.push_str(output);
+            entry.output.push('\n');
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::new();
+
+        if let Some(seed) = self.seed {
+            let _ = writeln!(markdown, "Random seed: `{seed}`\n");
+        }
+
+        for entry in &self.entries {
+            let _ = writeln!(markdown, "```lua\n{}\n```\n", entry.input);
+
+            let output = strip_ansi(entry.output.trim_end());
+
+            if !output.is_empty() {
+                let _ = writeln!(markdown, "```\n{output}\n```\n");
+            }
+        }
+
+        markdown
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+            "<title>manen transcript</title>\n<style>\n",
+            "body { background: #1e1e1e; color: #e5e5e5; font-family: monospace; }\n",
+            "pre { padding: 0.5em; margin: 0 0 1em 0; white-space: pre-wrap; }\n",
+            "pre.input { border-left: 3px solid #3b8eea; }\n",
+            "</style>\n</head>\n<body>\n",
+        ));
+
+        if let Some(seed) = self.seed {
+            let _ = writeln!(html, "<p>Random seed: {seed}</p>");
+        }
+
+        for entry in &self.entries {
+            let input = parse::highlight(&entry.input).render_simple();
+
+            let _ = writeln!(html, "<pre class=\"input\">{}</pre>", ansi_to_html(&input));
+
+            if !entry.output.trim_end().is_empty() {
+                let _ = writeln!(
+                    html,
+                    "<pre class=\"output\">{}</pre>",
+                    ansi_to_html(entry.output.trim_end())
+                );
+            }
+        }
+
+        html.push_str("</body>\n</html>\n");
+
+        html
+    }
+
+    /// HTML when `path` ends in `.html` or `.htm`, Markdown otherwise
+    pub fn render(&self, path: &Path) -> String {
+        let is_html = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm"));
+
+        if is_html {
+            self.to_html()
+        } else {
+            self.to_markdown()
+        }
+    }
+
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.render(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi_colors() {
+        assert_eq!("#cd3131", ANSI_COLORS[1]);
+        assert_eq!("#ff0000", fixed_color(196));
+        assert_eq!("#080808", fixed_color(232));
+        assert_eq!("#f14c4c", fixed_color(9));
+
+        assert_eq!(
+            "red and plain",
+            strip_ansi("\x1b[1;31mred\x1b[0m and plain")
+        );
+        assert_eq!(
+            "<span style=\"color:#cd3131;font-weight:bold;\">&lt;a&gt;</span> &amp; b",
+            ansi_to_html("\x1b[1;31m<a>\x1b[0m & b")
+        );
+        assert_eq!(
+            "<span style=\"color:#0a141e;\">x</span>",
+            ansi_to_html("\x1b[38;2;10;20;30mx")
+        );
+    }
+
+    #[test]
+    fn exports() {
+        let mut transcript = Transcript::new(Some(7));
+
+        transcript.push_input("1 + 1");
+        transcript.push_output("\x1b[33m2\x1b[0m");
+        transcript.push_input("x = 1");
+
+        assert_eq!(
+            "Random seed: `7`\n\n```lua\n1 + 1\n```\n\n```\n2\n```\n\n```lua\nx = 1\n```\n\n",
+            transcript.render(Path::new("session.md"))
+        );
+
+        let html = transcript.render(Path::new("session.HTML"));
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<p>Random seed: 7</p>"));
+        assert_eq!(2, html.matches("<pre class=\"input\">").count());
+        // the statement printed nothing, so it has no output block
+        assert_eq!(1, html.matches("<pre class=\"output\">").count());
+    }
+}
@@ -1,6 +1,11 @@
+use std::ops::Range;
+
+use emmylua_parser::LuaParser;
 use mlua::prelude::*;
 use reedline::{ValidationResult, Validator};
 
+use crate::parse;
+
 // TODO; we should instead rely on the parser to determine incomplete input
 pub struct LuaValidator {
     lua: Lua,
@@ -20,6 +25,57 @@ fn load_lua(lua: &Lua, code: &str) -> LuaResult<LuaFunction> {
     lua.load(code).into_function()
 }
 
+/// If `code` loads as an expression or as statements
+pub fn is_valid(code: &str) -> bool {
+    let lua = Lua::new_with(LuaStdLib::NONE, LuaOptions::new()).unwrap();
+
+    load_lua(&lua, code).is_ok()
+}
+
+/// Byte range of the token where `code` stops being valid Lua, `None` if it is valid or
+/// only incomplete so far
+///
+/// Lua only reports the line of an error, so the token is found by loading ever longer
+/// prefixes of `code` ending at a token, the first failing with more than unexpected
+/// end of input contains the error
+pub fn syntax_error(lua: &Lua, code: &str) -> Option<Range<usize>> {
+    let is_error = |code: &str| {
+        matches!(
+            lua.load(code).into_function(),
+            Err(LuaError::SyntaxError {
+                incomplete_input: false,
+                ..
+            })
+        )
+    };
+
+    if code.starts_with('.') || load_lua(lua, code).is_ok() || !is_error(code) {
+        return None;
+    }
+
+    let tokens = LuaParser::parse(code, parse::config())
+        .get_red_root()
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .filter(|token| !token.text().trim().is_empty())
+        .map(|token| {
+            let range = token.text_range();
+            usize::from(range.start())..usize::from(range.end())
+        })
+        .collect::<Vec<_>>();
+
+    let first_error = tokens.partition_point(|token| !is_error(&code[..token.end]));
+
+    tokens.get(first_error).cloned()
+}
+
+/// If `code` is an expression rather than a list of statements
+pub fn is_expression(code: &str) -> bool {
+    let lua = Lua::new_with(LuaStdLib::NONE, LuaOptions::new()).unwrap();
+
+    lua.load(format!("return ({code})")).into_function().is_ok()
+}
+
 impl Validator for LuaValidator {
     fn validate(&self, line: &str) -> ValidationResult {
         if line.starts_with(".") {